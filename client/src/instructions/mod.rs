@@ -2,3 +2,5 @@ pub mod token_instructions;
 pub use token_instructions::*;
 pub mod rpc;
 pub use rpc::*;
+pub mod quote;
+pub use quote::*;
@@ -0,0 +1,39 @@
+use anyhow::{format_err, Result};
+
+/// Basis points of a whole, the unit of slippage tolerances.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// `amount` less `slippage_bps`, rounded down so the guard never exceeds the tolerance.
+pub fn apply_slippage_bps(amount: u64, slippage_bps: u64) -> Result<u64> {
+    if slippage_bps > BPS_DENOMINATOR {
+        return Err(format_err!(
+            "slippage {} bps is more than {} bps",
+            slippage_bps,
+            BPS_DENOMINATOR
+        ));
+    }
+    let kept = u128::from(amount) * u128::from(BPS_DENOMINATOR - slippage_bps);
+    Ok((kept / u128::from(BPS_DENOMINATOR)) as u64)
+}
+
+/// Minimum amount out a swap base in of `amount_in` should accept, the output the on-chain
+/// curve pays at the current reserves less `slippage_bps`. See
+/// `makidex_amm::math::get_amount_out` for the curve and fee math.
+pub fn compute_min_out(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+    slippage_bps: u64,
+) -> Result<u64> {
+    let expected_amount_out = makidex_amm::math::get_amount_out(
+        amount_in,
+        reserve_in,
+        reserve_out,
+        fee_numerator,
+        fee_denominator,
+    )
+    .map_err(|e| format_err!("cannot quote the swap: {}", e))?;
+    apply_slippage_bps(expected_amount_out, slippage_bps)
+}
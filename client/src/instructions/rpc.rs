@@ -1,16 +1,52 @@
 use anyhow::{anyhow, Result};
+use solana_address_lookup_table_program::state::AddressLookupTable;
 use solana_client::{
-    rpc_client::RpcClient,
-    rpc_config::RpcSendTransactionConfig,
-    rpc_request::RpcRequest,
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::{RpcClient, SerializableTransaction},
+    rpc_config::{RpcSendTransactionConfig, RpcSimulateTransactionConfig},
+    rpc_request::{RpcError, RpcRequest},
     rpc_response::{RpcResult, RpcSimulateTransactionResult},
 };
 use solana_sdk::{
-    account::Account, commitment_config::CommitmentConfig, program_pack::Pack as TokenPack,
-    pubkey::Pubkey, signature::Signature, transaction::Transaction,
-    commitment_config::CommitmentLevel,
+    account::Account,
+    address_lookup_table_account::AddressLookupTableAccount,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    program_pack::Pack as TokenPack,
+    pubkey::Pubkey,
+    signature::Signature,
+    signers::Signers,
+    transaction::{Transaction, TransactionError, VersionedTransaction},
 };
-use std::convert::Into;
+use std::{convert::Into, thread, time::Duration};
+
+/// How transactions of a command are sent.
+#[derive(Clone, Copy, Debug)]
+pub struct SendConfig {
+    /// print the simulated logs instead of sending
+    pub simulate: bool,
+    /// resubmissions after a dropped or expired transaction, 0 sends once
+    pub retries: u32,
+    /// delay before the first resubmission, doubled for each following one
+    pub retry_base_delay: Duration,
+    /// send without the rpc node simulating the transaction first
+    pub skip_preflight: bool,
+}
+
+/// The commitment named by `--commitment`, the client reads, confirms and simulates at it.
+pub fn parse_commitment(s: &str) -> Result<CommitmentConfig, String> {
+    match s {
+        "processed" => Ok(CommitmentConfig::processed()),
+        "confirmed" => Ok(CommitmentConfig::confirmed()),
+        "finalized" => Ok(CommitmentConfig::finalized()),
+        _ => Err(format!(
+            "unknown commitment {}, expected processed, confirmed or finalized",
+            s
+        )),
+    }
+}
 
 pub fn simulate_transaction(
     client: &RpcClient,
@@ -27,26 +63,206 @@ pub fn simulate_transaction(
     )
 }
 
-pub fn send_txn(client: &RpcClient, txn: &Transaction, wait_confirm: bool) -> Result<Signature> {
+pub fn send_txn(
+    client: &RpcClient,
+    txn: &impl SerializableTransaction,
+    wait_confirm: bool,
+    skip_preflight: bool,
+) -> Result<Signature> {
     Ok(client.send_and_confirm_transaction_with_spinner_and_config(
         txn,
         if wait_confirm {
-            CommitmentConfig::confirmed()
+            client.commitment()
         } else {
             CommitmentConfig::processed()
         },
         RpcSendTransactionConfig {
-            skip_preflight: true,
+            skip_preflight,
             max_retries: Some(5),
-            preflight_commitment: Some(CommitmentLevel::Processed),
+            preflight_commitment: Some(client.commitment().commitment),
             ..RpcSendTransactionConfig::default()
         },
     )?)
 }
 
+/// Errors after which the transaction may be submitted again: transport failures, and a
+/// blockhash that expired before the transaction landed.
+pub fn is_retryable(err: &ClientError) -> bool {
+    matches!(
+        err.kind(),
+        ClientErrorKind::Io(_)
+            | ClientErrorKind::Reqwest(_)
+            | ClientErrorKind::TransactionError(TransactionError::BlockhashNotFound)
+            // the spinner gives up once the blockhash expired without confirmation
+            | ClientErrorKind::RpcError(RpcError::ForUser(_))
+    )
+}
+
+/// Delay before resubmission `attempt`, starting at 1.
+pub fn retry_delay(base_delay: Duration, attempt: u32) -> Duration {
+    base_delay.saturating_mul(1u32 << (attempt - 1).min(16))
+}
+
+/// `send_txn`, resubmitting up to `retries` times with exponential backoff after retryable
+/// errors. The transaction is only re-signed with a fresh blockhash once its blockhash
+/// expired, so an earlier submission can never land next to the new one.
+pub fn send_txn_with_retry<S: Signers + ?Sized>(
+    client: &RpcClient,
+    txn: Transaction,
+    signers: &S,
+    config: &SendConfig,
+) -> Result<Signature> {
+    send_with_retry(client, txn, config, |txn, recent_blockhash| {
+        Ok(txn.try_sign(signers, recent_blockhash)?)
+    })
+}
+
+/// The retry loop of `send_txn_with_retry`, `resign` signs the transaction again over a new
+/// blockhash.
+fn send_with_retry<T: SerializableTransaction>(
+    client: &RpcClient,
+    mut txn: T,
+    config: &SendConfig,
+    mut resign: impl FnMut(&mut T, Hash) -> Result<()>,
+) -> Result<Signature> {
+    let mut attempt = 0;
+    loop {
+        let err = match send_txn(client, &txn, true, config.skip_preflight) {
+            Ok(signature) => return Ok(signature),
+            Err(err) => err,
+        };
+        let retryable = err
+            .downcast_ref::<ClientError>()
+            .map_or(false, is_retryable);
+        if !retryable || attempt == config.retries {
+            return Err(err);
+        }
+        attempt += 1;
+        let delay = retry_delay(config.retry_base_delay, attempt);
+        eprintln!(
+            "send attempt {}/{} failed: {}, retrying in {:?}",
+            attempt,
+            config.retries + 1,
+            err,
+            delay
+        );
+        thread::sleep(delay);
+        let signature = *txn.get_signature();
+        if let Ok(Some(Ok(()))) = client.get_signature_status(&signature) {
+            return Ok(signature);
+        }
+        // expiry is judged at the tip of the chain whatever the configured commitment
+        if !client.is_blockhash_valid(txn.get_recent_blockhash(), CommitmentConfig::processed())? {
+            resign(&mut txn, client.get_latest_blockhash()?)?;
+        }
+    }
+}
+
+/// Simulate the transaction and print its logs when `simulate` is set, otherwise send it and
+/// return the signature.
+pub fn send_or_simulate_txn<S: Signers + ?Sized>(
+    client: &RpcClient,
+    txn: Transaction,
+    signers: &S,
+    config: &SendConfig,
+) -> Result<Option<Signature>> {
+    if config.simulate {
+        let result = simulate_transaction(client, &txn, false, client.commitment())?;
+        print_simulation(result.value)?;
+        Ok(None)
+    } else {
+        Ok(Some(send_txn_with_retry(client, txn, signers, config)?))
+    }
+}
+
+/// Prints the logs and compute units of a simulation, failing with its error if it did not
+/// succeed.
+pub fn print_simulation(result: RpcSimulateTransactionResult) -> Result<()> {
+    println!("Simulate :");
+    for log in result.logs.unwrap_or_default() {
+        println!("   log :  {}", log);
+    }
+    if let Some(units_consumed) = result.units_consumed {
+        println!("   units consumed :  {}", units_consumed);
+    }
+    match result.err {
+        Some(error) => Err(anyhow!("Transaction simulation failed: {:?}", error)),
+        None => Ok(()),
+    }
+}
+
+/// The addresses stored in the lookup table at `address`.
+pub fn get_lookup_table(client: &RpcClient, address: &Pubkey) -> Result<AddressLookupTableAccount> {
+    let account = client.get_account(address)?;
+    let table = AddressLookupTable::deserialize(&account.data)
+        .map_err(|e| anyhow!("{} is not an address lookup table: {}", address, e))?;
+    Ok(AddressLookupTableAccount {
+        key: *address,
+        addresses: table.addresses.to_vec(),
+    })
+}
+
+/// A v0 transaction of `instrs` paid by `payer`. Accounts found in `lookup_tables` are
+/// referenced by their index in the table instead of by their address.
+pub fn compile_versioned_txn<S: Signers + ?Sized>(
+    payer: &Pubkey,
+    instrs: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+    signers: &S,
+) -> Result<VersionedTransaction> {
+    let message = v0::Message::try_compile(payer, instrs, lookup_tables, recent_blockhash)?;
+    Ok(VersionedTransaction::try_new(
+        VersionedMessage::V0(message),
+        signers,
+    )?)
+}
+
+/// `send_or_simulate_txn` for a v0 transaction of `instrs` compressed with `lookup_tables`,
+/// recompiled over a new blockhash when a resubmission needs one.
+pub fn send_or_simulate_versioned_txn<S: Signers + ?Sized>(
+    client: &RpcClient,
+    payer: &Pubkey,
+    instrs: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    signers: &S,
+    config: &SendConfig,
+) -> Result<Option<Signature>> {
+    let txn = compile_versioned_txn(
+        payer,
+        instrs,
+        lookup_tables,
+        client.get_latest_blockhash()?,
+        signers,
+    )?;
+    if config.simulate {
+        let result = client.simulate_transaction_with_config(
+            &txn,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                commitment: Some(client.commitment()),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )?;
+        print_simulation(result.value)?;
+        Ok(None)
+    } else {
+        Ok(Some(send_with_retry(
+            client,
+            txn,
+            config,
+            |txn, recent_blockhash| {
+                *txn =
+                    compile_versioned_txn(payer, instrs, lookup_tables, recent_blockhash, signers)?;
+                Ok(())
+            },
+        )?))
+    }
+}
+
 pub fn get_token_account<T: TokenPack>(client: &RpcClient, addr: &Pubkey) -> Result<T> {
     let account = client
-    .get_account_with_commitment(addr, CommitmentConfig::processed())?
+    .get_account_with_commitment(addr, client.commitment())?
     .value
         .map_or(Err(anyhow!("Account not found")), Ok)?;
     T::unpack_from_slice(&account.data).map_err(Into::into)
@@ -1,4 +1,4 @@
-use super::super::{read_keypair_file, ClientConfig};
+use super::super::{load_keypair, ClientConfig};
 use anchor_client::{Client, Cluster};
 use anyhow::Result;
 use solana_client::rpc_client::RpcClient;
@@ -26,7 +26,7 @@ pub fn create_and_init_mint_instr(
     extension_init_params: Vec<ExtensionInitializationParams>,
     decimals: u8,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -69,7 +69,7 @@ pub fn create_account_rent_exmpt_instr(
     owner: Pubkey,
     data_size: usize,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -95,7 +95,7 @@ pub fn create_ata_token_account_instr(
     mint: &Pubkey,
     owner: &Pubkey,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -116,15 +116,16 @@ pub fn create_ata_token_account_instr(
 
 pub fn create_and_init_auxiliary_token(
     config: &ClientConfig,
+    rpc_client: &RpcClient,
     new_account_key: &Pubkey,
     mint: &Pubkey,
     owner: &Pubkey,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
-    let mint_account = &mut RpcClient::new(config.http_url.to_string()).get_account(&mint)?;
+    let mint_account = &mut rpc_client.get_account(&mint)?;
     // Client.
-    let client = Client::new(url, Rc::new(payer));
+    let client = Client::new_with_options(url, Rc::new(payer), rpc_client.commitment());
     let (program, space) = if mint_account.owner == spl_token::id() {
         (
             client.program(spl_token::id())?,
@@ -178,7 +179,7 @@ pub fn close_token_account(
     destination: &Pubkey,
     owner: &Keypair,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -204,7 +205,7 @@ pub fn spl_token_transfer_instr(
     amount: u64,
     from_authority: &Keypair,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -232,7 +233,7 @@ pub fn spl_token_mint_to_instr(
     amount: u64,
     mint_authority: &Keypair,
 ) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     // Client.
     let client = Client::new(url, Rc::new(payer));
@@ -257,7 +258,7 @@ pub fn spl_token_mint_to_instr(
 }
 
 pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruction>> {
-    let payer = read_keypair_file(&config.payer_path)?;
+    let payer = load_keypair(&config.payer_path)?;
     let wallet_key = payer.pubkey();
     let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
     let wsol_mint = Pubkey::from_str("So11111111111111111111111111111111111111112")?;
@@ -289,3 +290,63 @@ pub fn wrap_sol_instr(config: &ClientConfig, amount: u64) -> Result<Vec<Instruct
         .instructions()?;
     Ok(instructions)
 }
+
+/// Wraps `swap_instr` so that a swap touching the native mint needs no prepared wSOL account.
+/// The native side of the swap must use the owner's wSOL associated token account, which is
+/// created if missing, funded with `amount_in` when it is the source, and closed back to SOL
+/// after the swap. Swaps between two non native mints are returned unchanged.
+pub fn wrap_native_swap_instr(
+    config: &ClientConfig,
+    owner: &Keypair,
+    source_mint: &Pubkey,
+    destination_mint: &Pubkey,
+    amount_in: u64,
+    swap_instr: Instruction,
+) -> Result<Vec<Instruction>> {
+    let native_mint = spl_token::native_mint::id();
+    let wrap_source = *source_mint == native_mint;
+    let wrap_destination = *destination_mint == native_mint;
+    if !wrap_source && !wrap_destination {
+        return Ok(vec![swap_instr]);
+    }
+    let payer = load_keypair(&config.payer_path)?;
+    let url = Cluster::Custom(config.http_url.clone(), config.ws_url.clone());
+    let wsol_account =
+        spl_associated_token_account::get_associated_token_address(&owner.pubkey(), &native_mint);
+    // Client.
+    let client = Client::new(url, Rc::new(payer));
+    let program = client.program(spl_token::id())?;
+
+    let mut request = program.request().instruction(
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &program.payer(),
+            &owner.pubkey(),
+            &native_mint,
+            &program.id(),
+        ),
+    );
+    if wrap_source {
+        request = request
+            .instruction(system_instruction::transfer(
+                &owner.pubkey(),
+                &wsol_account,
+                amount_in,
+            ))
+            .instruction(spl_token::instruction::sync_native(
+                &program.id(),
+                &wsol_account,
+            )?);
+    }
+    let instructions = request
+        .instruction(swap_instr)
+        .instruction(spl_token::instruction::close_account(
+            &program.id(),
+            &wsol_account,
+            &owner.pubkey(),
+            &owner.pubkey(),
+            &[],
+        )?)
+        .signer(owner)
+        .instructions()?;
+    Ok(instructions)
+}
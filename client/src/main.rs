@@ -7,10 +7,11 @@ use clap::Parser;
 use configparser::ini::Ini;
 use rand::rngs::OsRng;
 use solana_account_decoder::{
-    parse_token::{TokenAccountType, UiAccountState},
-    UiAccountData, UiAccountEncoding,
+    parse_token::{TokenAccountType, UiAccountState, UiTokenAccount},
+    UiAccountData, UiAccountEncoding, UiDataSliceConfig,
 };
 use solana_client::{
+    pubsub_client::PubsubClient,
     rpc_client::RpcClient,
     rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionConfig},
     rpc_filter::{Memcmp, RpcFilterType},
@@ -18,9 +19,10 @@ use solana_client::{
     rpc_response::{RpcResult, RpcSimulateTransactionResult, Response},
 };
 use solana_sdk::{
+    account_info::IntoAccountInfo,
     commitment_config::CommitmentConfig,
-    commitment_config::CommitmentLevel::Confirmed,
     compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
     message::Message,
     program_pack::Pack,
     pubkey::Pubkey,
@@ -31,13 +33,20 @@ use solana_transaction_status::UiTransactionEncoding;
 use std::path::Path;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use std::{collections::VecDeque, convert::identity, mem::size_of};
 
 mod instructions;
+mod output;
 use bincode::serialize;
 use instructions::rpc::*;
 use instructions::token_instructions::*;
-use spl_associated_token_account::get_associated_token_address;
+use output::*;
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
 use spl_token_2022::{
     extension::StateWithExtensionsMut,
     state::Mint,
@@ -45,6 +54,8 @@ use spl_token_2022::{
 };
 use spl_token_client::token::ExtensionInitializationParams;
 use makidex_amm::instruction::*;
+use makidex_amm::math::PoolReserves;
+use makidex_amm::state::{AmmConfig, AmmInfo, Loadable};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ClientConfig {
@@ -71,106 +82,68 @@ pub struct ClientConfig {
 
 fn load_cfg(client_config: &String) -> Result<ClientConfig> {
     let mut config = Ini::new();
-    let _map = config.load(client_config).unwrap();
-    let http_url = config.get("Global", "http_url").unwrap();
-    if http_url.is_empty() {
-        panic!("http_url must not be empty");
-    }
-    let ws_url = config.get("Global", "ws_url").unwrap();
-    if ws_url.is_empty() {
-        panic!("ws_url must not be empty");
-    }
-    let payer_path = config.get("Global", "payer_path").unwrap();
-    if payer_path.is_empty() {
-        panic!("payer_path must not be empty");
-    }
-    let admin_path = config.get("Global", "admin_path").unwrap();
-    if admin_path.is_empty() {
-        panic!("admin_path must not be empty");
-    }
-    let withdrawer_path = config.get("Global", "withdrawer_path").unwrap();
-    if withdrawer_path.is_empty() {
-        panic!("withdrawer_path must not be empty");
-    }
-
-    let raydium_program_str = config.get("Global", "raydium_program").unwrap();
-    if raydium_program_str.is_empty() {
-        panic!("raydium_program must not be empty");
-    }
-    let raydium_program = Pubkey::from_str(&raydium_program_str).unwrap();
-
-    let pnl_owner_str = config.get("Global", "pnl_owner").unwrap();
-    if pnl_owner_str.is_empty() {
-        panic!("pnl_owner must not be empty");
-    }
-    let pnl_owner = Pubkey::from_str(&pnl_owner_str).unwrap();
-
-    let withdrawer_str = config.get("Global", "withdrawer").unwrap();
-    if withdrawer_str.is_empty() {
-        panic!("withdrawer must not be empty");
-    }
-    let withdrawer = Pubkey::from_str(&withdrawer_str).unwrap();
-
-    let admin_key_str = config.get("Global", "admin_key").unwrap();
-    if admin_key_str.is_empty() {
-        panic!("admin_key must not be empty");
-    }
-    let admin_key = Pubkey::from_str(&admin_key_str).unwrap();
-    
-    let amm_pool_str = config.get("Withdraw", "amm_pool").unwrap();
-    let mut amm_pool;
-    if amm_pool_str.is_empty() {
-        panic!("amm_pool must not be empty");
-    } else {
-        amm_pool = Pubkey::from_str(&amm_pool_str).unwrap();
-    }
-
-    let amm_open_orders_str = config.get("Withdraw", "amm_open_orders").unwrap();
-    let mut amm_open_orders;
-    if amm_open_orders_str.is_empty() {
-        panic!("amm_open_orders must not be empty");
-    } else {
-        amm_open_orders = Pubkey::from_str(&amm_open_orders_str).unwrap();
-    }
+    config
+        .load(client_config)
+        .map_err(|e| format_err!("failed to load {}: {}", client_config, e))?;
+    parse_cfg(&config)
+}
 
-    let amm_coin_vault_str = config.get("Withdraw", "amm_coin_vault").unwrap();
-    let mut amm_coin_vault;
-    if amm_coin_vault_str.is_empty() {
-        panic!("amm_coin_vault must not be empty");
-    } else {
-        amm_coin_vault = Pubkey::from_str(&amm_coin_vault_str).unwrap();
-    }
+fn parse_cfg(config: &Ini) -> Result<ClientConfig> {
+    let mut errors: Vec<String> = Vec::new();
+    let mut get_str = |section: &str, key: &str| -> String {
+        match config.get(section, key) {
+            Some(value) if !value.is_empty() => value,
+            _ => {
+                errors.push(format!("{}.{} is missing or empty", section, key));
+                String::new()
+            }
+        }
+    };
 
-    let amm_pc_vault_str = config.get("Withdraw", "amm_pc_vault").unwrap();
-    let mut amm_pc_vault;
-    if amm_pc_vault_str.is_empty() {
-        panic!("amm_pc_vault must not be empty");
-    } else {
-        amm_pc_vault = Pubkey::from_str(&amm_pc_vault_str).unwrap();
-    }
+    let http_url = get_str("Global", "http_url");
+    let ws_url = get_str("Global", "ws_url");
+    let payer_path = get_str("Global", "payer_path");
+    let admin_path = get_str("Global", "admin_path");
+    let withdrawer_path = get_str("Global", "withdrawer_path");
+    let raydium_program_str = get_str("Global", "raydium_program");
+    let pnl_owner_str = get_str("Global", "pnl_owner");
+    let withdrawer_str = get_str("Global", "withdrawer");
+    let admin_key_str = get_str("Global", "admin_key");
 
-    let amm_target_orders_str = config.get("Withdraw", "amm_target_orders").unwrap();
-    let mut amm_target_orders;
-    if amm_target_orders_str.is_empty() {
-        panic!("amm_target_orders must not be empty");
-    } else {
-        amm_target_orders = Pubkey::from_str(&amm_target_orders_str).unwrap();
-    }
+    let amm_pool_str = get_str("Withdraw", "amm_pool");
+    let amm_open_orders_str = get_str("Withdraw", "amm_open_orders");
+    let amm_coin_vault_str = get_str("Withdraw", "amm_coin_vault");
+    let amm_pc_vault_str = get_str("Withdraw", "amm_pc_vault");
+    let amm_target_orders_str = get_str("Withdraw", "amm_target_orders");
+    let coin_mint_str = get_str("Withdraw", "coin_mint");
+    let pc_mint_str = get_str("Withdraw", "pc_mint");
 
-    let coin_mint_str = config.get("Withdraw", "coin_mint").unwrap();
-    let mut coin_mint;
-    if coin_mint_str.is_empty() {
-        panic!("coin_mint must not be empty");
-    } else {
-        coin_mint = Pubkey::from_str(&coin_mint_str).unwrap();
-    }
+    let mut get_pubkey = |key: &str, value: &str| -> Pubkey {
+        if value.is_empty() {
+            return Pubkey::default();
+        }
+        Pubkey::from_str(value).unwrap_or_else(|_| {
+            errors.push(format!("{} is not a valid pubkey: {}", key, value));
+            Pubkey::default()
+        })
+    };
+    let raydium_program = get_pubkey("raydium_program", &raydium_program_str);
+    let pnl_owner = get_pubkey("pnl_owner", &pnl_owner_str);
+    let withdrawer = get_pubkey("withdrawer", &withdrawer_str);
+    let admin_key = get_pubkey("admin_key", &admin_key_str);
+    let amm_pool = get_pubkey("amm_pool", &amm_pool_str);
+    let amm_open_orders = get_pubkey("amm_open_orders", &amm_open_orders_str);
+    let amm_coin_vault = get_pubkey("amm_coin_vault", &amm_coin_vault_str);
+    let amm_pc_vault = get_pubkey("amm_pc_vault", &amm_pc_vault_str);
+    let amm_target_orders = get_pubkey("amm_target_orders", &amm_target_orders_str);
+    let coin_mint = get_pubkey("coin_mint", &coin_mint_str);
+    let pc_mint = get_pubkey("pc_mint", &pc_mint_str);
 
-    let pc_mint_str = config.get("Withdraw", "pc_mint").unwrap();
-    let mut pc_mint;
-    if pc_mint_str.is_empty() {
-        panic!("pc_mint must not be empty");
-    } else {
-        pc_mint = Pubkey::from_str(&pc_mint_str).unwrap();
+    if !errors.is_empty() {
+        return Err(format_err!(
+            "invalid client config:\n  {}",
+            errors.join("\n  ")
+        ));
     }
 
     Ok(ClientConfig {
@@ -193,6 +166,34 @@ fn load_cfg(client_config: &String) -> Result<ClientConfig> {
         pc_mint
     })
 }
+
+/// Keypair of an ini `*_path` value. `env:VAR` reads the secret key from the environment
+/// variable VAR, either base58 or the JSON byte array written by solana-keygen, anything
+/// else is the path of a keypair file.
+fn load_keypair(s: &str) -> Result<Keypair> {
+    match s.strip_prefix("env:") {
+        Some(var) => {
+            let secret = std::env::var(var)
+                .map_err(|_| format_err!("environment variable {} is not set", var))?;
+            keypair_from_secret(&secret)
+                .map_err(|err| format_err!("failed to read keypair from ${}: {}", var, err))
+        }
+        None => read_keypair_file(s),
+    }
+}
+
+fn keypair_from_secret(secret: &str) -> Result<Keypair> {
+    let secret = secret.trim();
+    let bytes = if secret.starts_with('[') {
+        serde_json::from_str::<Vec<u8>>(secret).map_err(|_| format_err!("not a JSON byte array"))?
+    } else {
+        bs58::decode(secret)
+            .into_vec()
+            .map_err(|_| format_err!("not base58"))?
+    };
+    Keypair::from_bytes(&bytes).map_err(|_| format_err!("not a 64 byte keypair"))
+}
+
 fn read_keypair_file(s: &str) -> Result<Keypair> {
     solana_sdk::signature::read_keypair_file(s)
         .map_err(|_| format_err!("failed to read keypair from {}", s))
@@ -204,10 +205,757 @@ fn write_keypair_file(keypair: &Keypair, outfile: &str) -> Result<String> {
 fn path_is_exist(path: &str) -> bool {
     Path::new(path).exists()
 }
+fn parse_u64_hex_or_dec(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    }
+    .map_err(|_| format_err!("invalid number {}", s))
+}
+/// Convert a human amount such as `1.5` to base units of a mint with `decimals` decimals.
+fn parse_ui_amount(s: &str, decimals: u8) -> Result<u64> {
+    let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (integer.is_empty() && fraction.is_empty()) || !is_digits(integer) || !is_digits(fraction) {
+        return Err(format_err!("invalid amount {}", s));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format_err!(
+            "amount {} has more than {} decimal places",
+            s,
+            decimals
+        ));
+    }
+    let overflow = || format_err!("amount {} overflows u64 at {} decimals", s, decimals);
+    let integer = if integer.is_empty() {
+        0
+    } else {
+        integer.parse::<u64>().map_err(|_| overflow())?
+    };
+    let fraction_units = if fraction.is_empty() {
+        0
+    } else {
+        10u64
+            .checked_pow(decimals as u32 - fraction.len() as u32)
+            .and_then(|scale| fraction.parse::<u64>().ok()?.checked_mul(scale))
+            .ok_or_else(overflow)?
+    };
+    10u64
+        .checked_pow(decimals.into())
+        .and_then(|scale| integer.checked_mul(scale))
+        .and_then(|units| units.checked_add(fraction_units))
+        .ok_or_else(overflow)
+}
+/// Convert a human amount to base units of `mint`, fetching its decimals on-chain.
+fn ui_amount_to_base_units(rpc_client: &RpcClient, mint: &Pubkey, s: &str) -> Result<u64> {
+    let mut mint_account = rpc_client.get_account(mint)?;
+    let decimals = StateWithExtensionsMut::<Mint>::unpack(&mut mint_account.data)?
+        .base
+        .decimals;
+    parse_ui_amount(s, decimals)
+}
+
+/// The only market of `market_program` trading `coin_mint` against `pc_mint`.
+fn find_market(
+    rpc_client: &RpcClient,
+    market_program: &Pubkey,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+) -> Result<Pubkey> {
+    // offsets of coin_mint and pc_mint in a market account, after the 5 bytes head padding
+    const COIN_MINT_OFFSET: usize = 53;
+    const PC_MINT_OFFSET: usize = 85;
+    let markets = rpc_client.get_program_accounts_with_config(
+        market_program,
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    COIN_MINT_OFFSET,
+                    coin_mint.as_ref(),
+                )),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(PC_MINT_OFFSET, pc_mint.as_ref())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig {
+                    offset: 0,
+                    length: 0,
+                }),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        },
+    )?;
+    match markets.as_slice() {
+        [(market, _)] => Ok(*market),
+        [] => Err(format_err!(
+            "no market of {} trades {} against {}, pass --market",
+            market_program,
+            coin_mint,
+            pc_mint
+        )),
+        _ => Err(format_err!(
+            "{} markets trade {} against {}, pass one with --market: {}",
+            markets.len(),
+            coin_mint,
+            pc_mint,
+            markets
+                .iter()
+                .map(|(market, _)| market.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// The openbook market accounts a swap instruction takes.
+struct MarketKeys {
+    bids: Pubkey,
+    asks: Pubkey,
+    event_queue: Pubkey,
+    coin_vault: Pubkey,
+    pc_vault: Pubkey,
+    vault_signer: Pubkey,
+}
+
+fn load_market_keys(
+    rpc_client: &RpcClient,
+    market_program: &Pubkey,
+    market: &Pubkey,
+) -> Result<MarketKeys> {
+    let key_of = |words: [u64; 4]| {
+        let mut bytes = [0u8; 32];
+        for (chunk, word) in bytes.chunks_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Pubkey::new_from_array(bytes)
+    };
+    let mut market_account = rpc_client.get_account(market)?;
+    let market_info = (market, &mut market_account).into_account_info();
+    let market_state = serum_dex::state::Market::load(&market_info, market_program, true)
+        .map_err(|e| format_err!("{} is not a market of {}: {:?}", market, market_program, e))?;
+    Ok(MarketKeys {
+        bids: key_of(identity(market_state.bids)),
+        asks: key_of(identity(market_state.asks)),
+        event_queue: key_of(identity(market_state.event_q)),
+        coin_vault: key_of(identity(market_state.coin_vault)),
+        pc_vault: key_of(identity(market_state.pc_vault)),
+        vault_signer: serum_dex::state::gen_vault_signer_key(
+            market_state.vault_signer_nonce,
+            market,
+            market_program,
+        )?,
+    })
+}
+
+/// Initialize2 the pool of `market` with the payer's initial deposit.
+fn initialize_pool(
+    rpc_client: &RpcClient,
+    send_config: &SendConfig,
+    compute_budget_instrs: &[Instruction],
+    raydium_amm: &Pubkey,
+    payer: &Keypair,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+    market: &Pubkey,
+    init_coin_amount: u64,
+    init_pc_amount: u64,
+    open_time: u64,
+    lp_mint_extensions: &[ExtensionInitializationParams],
+    price_bound: Option<InitPriceBound>,
+    lp_decimals: Option<u8>,
+    without_open_orders: bool,
+    stable_amp: Option<u64>,
+) -> Result<InitializePoolOutput> {
+    let market_program = makidex_amm::processor::config_feature::openbook_program::id();
+    let pdas = makidex_amm::processor::get_amm_pdas(raydium_amm, market, coin_mint, pc_mint);
+    let amm_config_key = pdas.amm_config;
+    let (amm_authority_key, nonce) = (pdas.amm_authority, pdas.nonce);
+    let amm_pool_key = pdas.amm_pool;
+    let amm_open_orders_key = pdas.amm_open_orders;
+    let amm_lp_mint_key = pdas.amm_lp_mint;
+    let amm_coin_vault_key = pdas.amm_coin_vault;
+    let amm_pc_vault_key = pdas.amm_pc_vault;
+    let amm_target_orders_key = pdas.amm_target_orders;
+    let lp_mint_extension = lp_mint_extension(lp_mint_extensions, &amm_authority_key)?;
+    let lp_token_program = match lp_mint_extension {
+        Some(_) => spl_token_2022::id(),
+        None => spl_token::id(),
+    };
+
+    let user_token_coin_key = get_associated_token_address(&payer.pubkey(), coin_mint);
+    let user_token_pc_key = get_associated_token_address(&payer.pubkey(), pc_mint);
+    let user_token_lp_key = get_associated_token_address_with_program_id(
+        &payer.pubkey(),
+        &amm_lp_mint_key,
+        &lp_token_program,
+    );
+
+    let mut instrs = compute_budget_instrs.to_vec();
+    for (user_token, mint) in [
+        (&user_token_coin_key, coin_mint),
+        (&user_token_pc_key, pc_mint),
+    ] {
+        if rpc_client.get_account(user_token).is_err() {
+            instrs.push(
+                spl_associated_token_account::instruction::create_associated_token_account(
+                    &payer.pubkey(),
+                    &payer.pubkey(),
+                    mint,
+                    &spl_token::id(),
+                ),
+            );
+        }
+    }
+    instrs.push(initialize2(
+        raydium_amm,
+        &amm_pool_key,
+        &amm_authority_key,
+        &amm_open_orders_key,
+        &amm_lp_mint_key,
+        coin_mint,
+        pc_mint,
+        &amm_coin_vault_key,
+        &amm_pc_vault_key,
+        &amm_target_orders_key,
+        &amm_config_key,
+        &makidex_amm::processor::config_feature::create_pool_fee_address::id(),
+        &market_program,
+        market,
+        &payer.pubkey(),
+        &user_token_coin_key,
+        &user_token_pc_key,
+        &user_token_lp_key,
+        nonce,
+        open_time,
+        init_pc_amount,
+        init_coin_amount,
+        lp_mint_extension,
+        price_bound,
+        lp_decimals,
+        without_open_orders,
+        stable_amp,
+    )?);
+    // send
+    let signers = vec![payer];
+    let recent_hash = rpc_client.get_latest_blockhash()?;
+    let txn =
+        Transaction::new_signed_with_payer(&instrs, Some(&payer.pubkey()), &signers, recent_hash);
+    let signature = send_or_simulate_txn(rpc_client, txn, &signers, send_config)?;
+    Ok(InitializePoolOutput {
+        signature: signature.map(|signature| signature.to_string()),
+        amm_pool: amm_pool_key.to_string(),
+        amm_authority: amm_authority_key.to_string(),
+        amm_open_orders: amm_open_orders_key.to_string(),
+        amm_lp_mint: amm_lp_mint_key.to_string(),
+        amm_coin_vault: amm_coin_vault_key.to_string(),
+        amm_pc_vault: amm_pc_vault_key.to_string(),
+        amm_target_orders: amm_target_orders_key.to_string(),
+        user_token_lp: user_token_lp_key.to_string(),
+    })
+}
+
+/// The lp mint extension asked for by `extensions`. Only a metadata pointer that leaves the
+/// lp mint to the amm authority is accepted by the program.
+fn lp_mint_extension(
+    extensions: &[ExtensionInitializationParams],
+    amm_authority: &Pubkey,
+) -> Result<Option<LpMintExtension>> {
+    match extensions {
+        [] => Ok(None),
+        [ExtensionInitializationParams::MetadataPointer {
+            authority,
+            metadata_address,
+        }] => {
+            if authority.is_some() && *authority != Some(*amm_authority) {
+                return Err(format_err!(
+                    "the lp metadata pointer authority must be unset or the amm authority {}",
+                    amm_authority
+                ));
+            }
+            Ok(Some(LpMintExtension::MetadataPointer {
+                authority: *authority,
+                metadata_address: *metadata_address,
+            }))
+        }
+        [extension] => Err(format_err!(
+            "{:?} can not be initialized on an lp mint",
+            extension.extension()
+        )),
+        _ => Err(format_err!("an lp mint takes at most one extension")),
+    }
+}
+
+/// Genesis hash of mainnet-beta, create-test-pool refuses to run against it.
+const MAINNET_BETA_GENESIS_HASH: &str = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+
+/// Queue and book sizes of a test market, far below the openbook defaults to keep the
+/// rent of the five market accounts low.
+const TEST_MARKET_REQUEST_QUEUE_LEN: usize = 5 + 32 + 10 * 80 + 7;
+const TEST_MARKET_EVENT_QUEUE_LEN: usize = 5 + 32 + 128 * 88 + 7;
+const TEST_MARKET_ORDERBOOK_LEN: usize = 5 + 40 + 128 * 72 + 7;
+
+/// `ui_amount` whole tokens of a mint with `decimals` in base units.
+fn ui_to_base_units(ui_amount: u64, decimals: u8) -> Result<u64> {
+    10u64
+        .checked_pow(decimals.into())
+        .and_then(|unit| ui_amount.checked_mul(unit))
+        .ok_or_else(|| format_err!("{} tokens of {} decimals overflow u64", ui_amount, decimals))
+}
+
+/// The on-chain bound for a ui pc per ui coin price, None without an expected price.
+fn init_price_bound(
+    expected_price: Option<f64>,
+    price_tolerance_bps: u64,
+) -> Result<Option<InitPriceBound>> {
+    let expected_price = match expected_price {
+        Some(expected_price) => expected_price,
+        None => return Ok(None),
+    };
+    let scaled = expected_price * 10f64.powi(makidex_amm::math::SPOT_PRICE_DECIMALS as i32);
+    if !scaled.is_finite() || scaled < 1.0 || scaled >= u128::MAX as f64 {
+        return Err(format_err!("invalid expected price {}", expected_price));
+    }
+    Ok(Some(InitPriceBound {
+        expected_price: scaled.round() as u128,
+        price_tolerance_bps,
+    }))
+}
+
+/// Base units in a lot of 10^-`lot_decimals` tokens of a mint with `decimals`, which can not
+/// be split any finer than its decimals.
+fn lot_size(decimals: u8, lot_decimals: u8) -> Result<u64> {
+    decimals
+        .checked_sub(lot_decimals)
+        .and_then(|exponent| 10u64.checked_pow(exponent.into()))
+        .ok_or_else(|| {
+            format_err!(
+                "no lot of 10^-{} tokens for a mint with {} decimals",
+                lot_decimals,
+                decimals
+            )
+        })
+}
+
+/// Send `instrs` signed by the payer and `signers`, the send config retries apply.
+fn send_instrs(
+    rpc_client: &RpcClient,
+    send_config: &SendConfig,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    instrs: &[Instruction],
+) -> Result<String> {
+    let signers = [&[payer], signers].concat();
+    let recent_hash = rpc_client.get_latest_blockhash()?;
+    let txn =
+        Transaction::new_signed_with_payer(instrs, Some(&payer.pubkey()), &signers, recent_hash);
+    let signature = send_or_simulate_txn(rpc_client, txn, &signers, send_config)?;
+    signature
+        .map(|signature| signature.to_string())
+        .ok_or_else(|| format_err!("transaction was not sent"))
+}
+
+/// Warnings from checking the configured `program_id` against the id the makidex_amm crate
+/// was built for and its deployed `program_account`. None of them stop the client: a custom
+/// deployment is fine as long as it runs this program, and a program that is not deployed
+/// yet only fails the transactions sent to it.
+fn check_program(
+    program_id: &Pubkey,
+    expected_program_id: &Pubkey,
+    program_account: Option<&solana_sdk::account::Account>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if program_id != expected_program_id {
+        warnings.push(format!(
+            "raydium_program {} is not the program id {} the client was built for",
+            program_id, expected_program_id
+        ));
+    }
+    if !program_account.map_or(false, |account| account.executable) {
+        warnings.push(format!(
+            "raydium_program {} is not a deployed program",
+            program_id
+        ));
+    }
+    warnings
+}
+
+/// Close instructions packed in one transaction, each one adds an account to the message.
+const CLOSE_ACCOUNTS_PER_TXN: usize = 20;
+
+/// Whether a token account of `owner`, as returned by a jsonParsed rpc query, can be closed
+/// by `owner`. Frozen accounts and accounts with another close authority are left alone.
+/// Closing a wrapped sol account returns its balance as lamports, so it does not have to
+/// be empty.
+fn close_candidate(account: &UiTokenAccount, owner: &Pubkey) -> bool {
+    if account.state != UiAccountState::Initialized {
+        return false;
+    }
+    if let Some(close_authority) = &account.close_authority {
+        if *close_authority != owner.to_string() {
+            return false;
+        }
+    }
+    account.is_native || account.token_amount.amount == "0"
+}
+
+/// The token accounts of `owner` under `token_program` that `close_candidate` accepts.
+fn find_close_candidates(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    token_program: &Pubkey,
+) -> Result<Vec<(Pubkey, UiTokenAccount)>> {
+    let mut candidates = Vec::new();
+    let keyed_accounts = rpc_client
+        .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(*token_program))?;
+    for keyed_account in keyed_accounts {
+        let parsed = match keyed_account.account.data {
+            UiAccountData::Json(parsed) => parsed.parsed,
+            _ => continue,
+        };
+        let token_account = match serde_json::from_value(parsed)? {
+            TokenAccountType::Account(token_account) => token_account,
+            _ => continue,
+        };
+        if close_candidate(&token_account, owner) {
+            candidates.push((Pubkey::from_str(&keyed_account.pubkey)?, token_account));
+        }
+    }
+    Ok(candidates)
+}
+
+/// Expected output of a swap base in and the minimum_amount_out `slippage_percent` tolerates.
+/// The quote is the on-chain swap math, see `makidex_amm::math::get_amount_out`.
+fn swap_quote(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+    slippage_percent: f64,
+) -> Result<(u64, u64)> {
+    if !(0.0..=100.0).contains(&slippage_percent) {
+        return Err(format_err!(
+            "slippage {}% is not between 0 and 100",
+            slippage_percent
+        ));
+    }
+    let expected_amount_out = makidex_amm::math::get_amount_out(
+        amount_in,
+        reserve_in,
+        reserve_out,
+        fee_numerator,
+        fee_denominator,
+    )
+    .map_err(|e| format_err!("cannot quote the swap: {}", e))?;
+    if expected_amount_out == 0 {
+        return Err(format_err!(
+            "the pool lacks the liquidity to swap {}, it would pay out nothing",
+            amount_in
+        ));
+    }
+    let minimum_amount_out = apply_slippage(expected_amount_out, slippage_percent)?;
+    Ok((expected_amount_out, minimum_amount_out))
+}
+
+/// `amount` less `slippage_percent`, rounded down, see `apply_slippage_bps`.
+fn apply_slippage(amount: u64, slippage_percent: f64) -> Result<u64> {
+    apply_slippage_bps(amount, (slippage_percent * 100.0).round() as u64)
+}
+
+/// Base side, expected lp and the minimum_lp_amount `slippage_percent` tolerates for a
+/// deposit of at most `max_coin_amount` and `max_pc_amount`, see `deposit_amounts`.
+fn deposit_quote(
+    max_coin_amount: u64,
+    max_pc_amount: u64,
+    reserve_coin: u64,
+    reserve_pc: u64,
+    lp_supply: u64,
+    slippage_percent: f64,
+) -> Result<(u64, u64, u64)> {
+    if !(0.0..=100.0).contains(&slippage_percent) {
+        return Err(format_err!(
+            "slippage {}% is not between 0 and 100",
+            slippage_percent
+        ));
+    }
+    let (base_side, _, _, expected_lp_amount) = deposit_amounts(
+        max_coin_amount,
+        max_pc_amount,
+        reserve_coin,
+        reserve_pc,
+        lp_supply,
+    )?;
+    let minimum_lp_amount = apply_slippage(expected_lp_amount, slippage_percent)?;
+    Ok((base_side, expected_lp_amount, minimum_lp_amount))
+}
+
+/// What a deposit of at most `max_coin_amount` and `max_pc_amount` takes from the wallet, as
+/// base side, coin, pc and the lp it mints. The side short of the pool ratio is the base side
+/// and is spent in full, the other side only as far as the ratio needs, rounded up as the
+/// program charges it. This is the on-chain deposit math, see
+/// `makidex_amm::math::lp_tokens_for_deposit`.
+fn deposit_amounts(
+    max_coin_amount: u64,
+    max_pc_amount: u64,
+    reserve_coin: u64,
+    reserve_pc: u64,
+    lp_supply: u64,
+) -> Result<(u64, u64, u64, u64)> {
+    let no_liquidity = || format_err!("the pool has no liquidity to deposit next to");
+    let coin_short = u128::from(max_coin_amount) * u128::from(reserve_pc)
+        <= u128::from(max_pc_amount) * u128::from(reserve_coin);
+    let (base_side, coin_amount, pc_amount) = if coin_short {
+        let pc_amount =
+            makidex_amm::math::deposit_pc_for_coin(max_coin_amount, reserve_coin, reserve_pc)
+                .ok_or_else(no_liquidity)?;
+        (0, max_coin_amount, pc_amount)
+    } else {
+        let coin_amount =
+            makidex_amm::math::deposit_coin_for_pc(max_pc_amount, reserve_coin, reserve_pc)
+                .ok_or_else(no_liquidity)?;
+        (1, coin_amount, max_pc_amount)
+    };
+    let lp_amount = makidex_amm::math::lp_tokens_for_deposit(
+        coin_amount,
+        pc_amount,
+        reserve_coin,
+        reserve_pc,
+        lp_supply,
+    )
+    .ok_or_else(no_liquidity)?;
+    if lp_amount == 0 {
+        return Err(format_err!(
+            "depositing {} coin and {} pc would mint no lp",
+            max_coin_amount,
+            max_pc_amount
+        ));
+    }
+    Ok((base_side, coin_amount, pc_amount, lp_amount))
+}
+
+/// How an existing config differs from the `pnl_owner` and `admin` a deployment intends. An
+/// unset config admin leaves the config to the built-in amm owners.
+fn config_mismatches(amm_config: &AmmConfig, pnl_owner: &Pubkey, admin: &Pubkey) -> Vec<String> {
+    let mut mismatches = vec![];
+    if amm_config.pnl_owner != *pnl_owner {
+        mismatches.push(format!(
+            "config pnl_owner is {}, not {}",
+            amm_config.pnl_owner, pnl_owner
+        ));
+    }
+    if amm_config.admin != Pubkey::default() {
+        if amm_config.admin != *admin {
+            mismatches.push(format!(
+                "config admin is {}, not {}",
+                amm_config.admin, admin
+            ));
+        }
+    } else if *admin != makidex_amm::processor::config_feature::amm_owner::id()
+        && *admin != makidex_amm::processor::config_feature::amm_subscriber::id()
+    {
+        mismatches.push(format!(
+            "config admin is unset, so the built-in amm owners administer it and not {}",
+            admin
+        ));
+    }
+    mismatches
+}
+
+/// Open orders state of raw account data, which the dex pads with a 5 byte head and a 7 byte
+/// tail.
+fn open_orders_from_bytes(data: &[u8]) -> Result<serum_dex::state::OpenOrders> {
+    let state_len = size_of::<serum_dex::state::OpenOrders>();
+    if data.len() != 5 + state_len + 7 {
+        return Err(format_err!(
+            "{} bytes are not an open orders account",
+            data.len()
+        ));
+    }
+    Ok(bytemuck::pod_read_unaligned(&data[5..5 + state_len]))
+}
+
+/// (coin, pc) the program prices swaps and deposits against: the vaults and the open orders
+/// totals, without the pnl owed to the protocol, see `makidex_amm::math::PoolReserves`. Maker
+/// fills still waiting in the event queue are not counted.
+fn pool_reserves(
+    amm: &AmmInfo,
+    coin_vault_amount: u64,
+    pc_vault_amount: u64,
+    open_orders: &serum_dex::state::OpenOrders,
+) -> Result<(u64, u64)> {
+    let (pc_reserve, coin_reserve) =
+        PoolReserves::new(coin_vault_amount, pc_vault_amount, open_orders)
+            .total_without_take_pnl(amm)
+            .map_err(|e| format_err!("the pool reserves do not cover its pnl: {}", e))?;
+    Ok((coin_reserve, pc_reserve))
+}
+
+/// `pool_reserves` of `amm` read over rpc.
+fn fetch_pool_reserves(rpc_client: &RpcClient, amm: &AmmInfo) -> Result<(u64, u64)> {
+    let coin_vault = get_token_account::<Account>(rpc_client, &amm.coin_vault)?;
+    let pc_vault = get_token_account::<Account>(rpc_client, &amm.pc_vault)?;
+    let open_orders = open_orders_from_bytes(&rpc_client.get_account_data(&amm.open_orders)?)?;
+    pool_reserves(amm, coin_vault.amount, pc_vault.amount, &open_orders)
+}
+
+/// What watch-pool tracks of a pool: its state, vault balances and open orders.
+struct WatchedPool {
+    amm: AmmInfo,
+    coin_vault_amount: u64,
+    pc_vault_amount: u64,
+    open_orders: serum_dex::state::OpenOrders,
+}
+
+impl WatchedPool {
+    /// The pool account `pool`, then its vaults and open orders.
+    fn keys(pool: &Pubkey, amm: &AmmInfo) -> [Pubkey; 4] {
+        [*pool, amm.coin_vault, amm.pc_vault, amm.open_orders]
+    }
+
+    /// Reads the accounts of `keys` at one slot, which is returned along.
+    fn fetch(rpc_client: &RpcClient, keys: &[Pubkey; 4]) -> Result<(u64, Self)> {
+        let response =
+            rpc_client.get_multiple_accounts_with_commitment(keys, rpc_client.commitment())?;
+        let mut data = Vec::new();
+        for (key, account) in keys.iter().zip(response.value) {
+            data.push(
+                account
+                    .ok_or_else(|| format_err!("account {} does not exist", key))?
+                    .data,
+            );
+        }
+        let amm = AmmInfo::from_account_data(&data[0])
+            .map_err(|_| format_err!("{} is not an amm pool account", keys[0]))?;
+        let mut watched = WatchedPool {
+            amm,
+            coin_vault_amount: 0,
+            pc_vault_amount: 0,
+            open_orders: open_orders_from_bytes(&data[3])?,
+        };
+        watched.update(&keys[0], &keys[1], &data[1])?;
+        watched.update(&keys[0], &keys[2], &data[2])?;
+        Ok((response.context.slot, watched))
+    }
+
+    /// Applies the new `data` of `key`, the pool account `pool` or one of its accounts.
+    fn update(&mut self, pool: &Pubkey, key: &Pubkey, data: &[u8]) -> Result<()> {
+        if key == pool {
+            self.amm = AmmInfo::from_account_data(data)
+                .map_err(|_| format_err!("{} is no longer an amm pool account", pool))?;
+        } else if *key == self.amm.open_orders {
+            self.open_orders = open_orders_from_bytes(data)?;
+        } else {
+            let amount = Account::unpack(data)
+                .map_err(|e| format_err!("failed to decode vault {}: {}", key, e))?
+                .amount;
+            if *key == self.amm.coin_vault {
+                self.coin_vault_amount = amount;
+            } else {
+                self.pc_vault_amount = amount;
+            }
+        }
+        Ok(())
+    }
+
+    /// `pool_reserves` of the tracked accounts.
+    fn reserves(&self) -> Result<(u64, u64)> {
+        pool_reserves(
+            &self.amm,
+            self.coin_vault_amount,
+            self.pc_vault_amount,
+            &self.open_orders,
+        )
+    }
+}
+
+/// What `watch_account` sends of its subscription.
+enum AccountUpdate {
+    /// slot and data of a change of the account
+    Changed(Pubkey, u64, Vec<u8>),
+    /// subscribed again after the websocket dropped, changes in between were missed
+    Resubscribed(Pubkey),
+}
+
+/// Sends the slot and data of every change of `key` to `updates` until its receiver is
+/// dropped. Subscribes again after `reconnect_delay` whenever the websocket drops, and
+/// reports each new subscription once it is established.
+fn watch_account(
+    ws_url: String,
+    key: Pubkey,
+    commitment: CommitmentConfig,
+    reconnect_delay: Duration,
+    updates: mpsc::Sender<AccountUpdate>,
+) {
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(commitment),
+        ..RpcAccountInfoConfig::default()
+    };
+    let mut resubscribing = false;
+    loop {
+        match PubsubClient::account_subscribe(&ws_url, &key, Some(config.clone())) {
+            Ok((_subscription, receiver)) => {
+                if resubscribing && updates.send(AccountUpdate::Resubscribed(key)).is_err() {
+                    return;
+                }
+                for response in receiver.iter() {
+                    let data = match response.value.data.decode() {
+                        Some(data) => data,
+                        None => {
+                            eprintln!("skipping an undecodable update of {}", key);
+                            continue;
+                        }
+                    };
+                    let update = AccountUpdate::Changed(key, response.context.slot, data);
+                    if updates.send(update).is_err() {
+                        return;
+                    }
+                }
+                eprintln!("websocket of {} dropped, reconnecting", key);
+            }
+            Err(e) => eprintln!("failed to subscribe to {}: {}", key, e),
+        }
+        resubscribing = true;
+        thread::sleep(reconnect_delay);
+    }
+}
 
 
 #[derive(Debug, Parser)]
 pub struct Opts {
+    /// client config file
+    #[clap(long, global = true, default_value = "client_config.ini")]
+    pub config: String,
+    /// compute unit limit requested for each transaction
+    #[clap(long, global = true, default_value_t = 400_000)]
+    pub compute_unit_limit: u32,
+    /// priority fee in micro-lamports per compute unit
+    #[clap(long, global = true, default_value_t = 10_000)]
+    pub compute_unit_price: u64,
+    /// print the simulated logs and compute units instead of sending transactions, failing
+    /// when a simulation fails
+    #[clap(long, global = true)]
+    pub simulate: bool,
+    /// resubmit a dropped or expired transaction up to this many times
+    #[clap(long, global = true, default_value_t = 0)]
+    pub send_retries: u32,
+    /// delay in milliseconds before the first resubmission, doubled for each following one
+    #[clap(long, global = true, default_value_t = 500)]
+    pub retry_base_delay_ms: u64,
+    /// send without preflight simulation by the rpc node
+    #[clap(long, global = true)]
+    pub skip_preflight: bool,
+    /// result format, json prints one object per command for scripting
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    pub output: OutputFormat,
+    /// commitment of every read, confirmation wait and simulation: processed, confirmed or
+    /// finalized
+    #[clap(long, global = true, default_value = "confirmed", value_parser = parse_commitment)]
+    pub commitment: CommitmentConfig,
+    /// skip checking raydium_program against the client build and its deployment
+    #[clap(long, global = true)]
+    pub force: bool,
     #[clap(subcommand)]
     pub command: CommandsName,
 }
@@ -218,19 +966,256 @@ pub enum CommandsName {
         // administrator: Pubkey,
         // amm_config: Pubkey,
         // pnl_owner: Pubkey,
+        /// print the config and succeed when it already exists, instead of failing
+        #[arg(long)]
+        ensure: bool,
     },
     OwnerWithdrawPool {
     },
+    UpdateConfigFees {
+        trade_fee_numerator: u64,
+        trade_fee_denominator: u64,
+        protocol_fee_numerator: u64,
+        protocol_fee_denominator: u64,
+    },
+    /// Copy the current config fees into the pool, signed by the admin
+    SyncPoolFees,
+    InitializePool {
+        coin_mint: Pubkey,
+        pc_mint: Pubkey,
+        /// openbook market of the coin/pc pair
+        market: Pubkey,
+        init_coin_amount: u64,
+        init_pc_amount: u64,
+        #[arg(long, default_value_t = 0)]
+        open_time: u64,
+        /// metadata account the lp mint points wallets to, makes the lp mint a Token-2022 mint
+        #[arg(long)]
+        lp_metadata_address: Option<Pubkey>,
+        /// ui pc per ui coin the initial amounts must imply, the pool is not created otherwise
+        #[arg(long)]
+        expected_price: Option<f64>,
+        /// tolerated deviation from --expected-price
+        #[arg(long, default_value_t = 100)]
+        price_tolerance_bps: u64,
+        /// decimals of the lp mint, at most 9, the coin decimals when unset
+        #[arg(long)]
+        lp_decimals: Option<u8>,
+        /// trade on the pool vaults alone, no open orders account is created and the pool never
+        /// places orders on the market
+        #[arg(long)]
+        without_open_orders: bool,
+        /// price swaps on the stable curve of this amplification instead of x * y = k, needs
+        /// --without-open-orders and mints of the same decimals
+        #[arg(long, requires = "without_open_orders")]
+        stable_amp: Option<u64>,
+    },
+    /// Create two test mints with their supply in the payer's token accounts, list them on a
+    /// new openbook market and initialize its pool with an initial deposit. Amounts are in ui
+    /// units. Refuses to run without --devnet and against mainnet-beta
+    CreateTestPool {
+        #[arg(long, default_value_t = 9)]
+        coin_decimals: u8,
+        #[arg(long, default_value_t = 6)]
+        pc_decimals: u8,
+        /// supply of each mint minted to the payer
+        #[arg(long, default_value_t = 1_000_000)]
+        supply: u64,
+        #[arg(long, default_value_t = 1_000)]
+        init_coin_amount: u64,
+        #[arg(long, default_value_t = 1_000)]
+        init_pc_amount: u64,
+        /// SOL airdropped to the payer first, 0 skips the airdrop
+        #[arg(long, default_value_t = 0)]
+        airdrop: u64,
+        #[arg(long)]
+        devnet: bool,
+    },
+    /// Print the addresses Initialize2 derives for the pool of a market, the market is looked
+    /// up among the openbook markets of the mints when not given
+    DerivePdas {
+        coin_mint: Pubkey,
+        pc_mint: Pubkey,
+        #[arg(long)]
+        market: Option<Pubkey>,
+    },
+    /// Rotate the config pnl_owner, signed by the config admin
+    SetPnlOwner {
+        new_pnl_owner: Pubkey,
+    },
+    /// Set the wallet protocol fees are collected to, signed by the config admin
+    SetFeeRecipient {
+        fee_recipient: Pubkey,
+    },
+    /// Let holders of discount_mint swap at fee_numerator / fee_denominator, signed by the
+    /// config admin. --disable turns the discount off
+    SetSwapDiscount {
+        #[arg(required_unless_present = "disable")]
+        discount_mint: Option<Pubkey>,
+        #[arg(required_unless_present = "disable")]
+        fee_numerator: Option<u64>,
+        #[arg(required_unless_present = "disable")]
+        fee_denominator: Option<u64>,
+        #[arg(long, conflicts_with = "discount_mint")]
+        disable: bool,
+    },
+    /// Print reserves, lp supply, spot price and fees of a pool
+    ShowPool {
+        pool: Pubkey,
+    },
+    /// Print the reserves and spot price of a pool on every change of the pool, its vaults or
+    /// its open orders, until interrupted. Subscribes through the configured ws_url
+    WatchPool {
+        pool: Pubkey,
+        /// wait before subscribing again after the websocket drops
+        #[arg(long, default_value_t = 5)]
+        reconnect_delay_secs: u64,
+    },
+    /// Collect accrued protocol fees to the config fee recipient, the payer wallet must be
+    /// the config pnl_owner
+    CollectProtocolFees {
+        coin_amount: u64,
+        pc_amount: u64,
+    },
+    SetPoolStatus {
+        /// bit 0 disables swap, bit 1 deposit, bit 2 withdraw (hex or decimal)
+        status: String,
+    },
+    /// Close a pool whose lp is all burned and whose vaults only hold uncollected pnl, and
+    /// reclaim the rent of its accounts, signed by the admin. The pnl goes to the config fee
+    /// recipient
+    ClosePool {
+        pool: Pubkey,
+        /// receives the reclaimed lamports, defaults to the admin wallet
+        #[arg(long)]
+        recipient: Option<Pubkey>,
+    },
+    /// Cap the reserves deposits may grow the pool to, 0 removes a cap
+    SetReserveCaps {
+        max_coin_reserve: u64,
+        max_pc_reserve: u64,
+    },
+    /// Charge a swap fee growing from base_fee_numerator to max_fee_numerator as the price
+    /// moves up to max_fee_deviation_bps away from its recent average, fractions of
+    /// fee_denominator
+    SetDynamicFee {
+        base_fee_numerator: u64,
+        max_fee_numerator: u64,
+        fee_denominator: u64,
+        max_fee_deviation_bps: u64,
+        /// go back to the static pool fee
+        #[arg(long)]
+        disable: bool,
+    },
+    /// Reject swaps paying in less than the minimum of the input mint, 0 removes a minimum
+    SetMinTradeAmount {
+        min_coin_amount: u64,
+        min_pc_amount: u64,
+    },
+    /// Swap an ui amount of input_mint through the pool of the config, refusing to receive
+    /// less than the current quote minus the slippage tolerance
+    Swap {
+        input_mint: Pubkey,
+        /// ui amount of input_mint, e.g. 1.5
+        amount: String,
+        /// tolerated shortfall from the quoted output, in percent
+        #[arg(long, default_value_t = 0.5)]
+        slippage: f64,
+        /// address lookup table with the pool and market accounts, sends a v0 transaction
+        /// that references them by index instead of a legacy one
+        #[arg(long)]
+        use_lookup_table: Option<Pubkey>,
+        /// wallet receiving the output in its associated token account, defaults to the
+        /// payer, native output reaches it as wrapped sol
+        #[arg(long)]
+        recipient: Option<Pubkey>,
+    },
+    /// Deposit up to ui amounts of coin and pc into the pool of the config, refusing to mint
+    /// less lp than the current quote minus the slippage tolerance
+    Deposit {
+        /// ui amount of coin, e.g. 1.5
+        coin_amount: String,
+        /// ui amount of pc
+        pc_amount: String,
+        /// tolerated shortfall from the quoted lp, in percent
+        #[arg(long, default_value_t = 0.5)]
+        slippage: f64,
+    },
+    /// Quote how much of a coin and pc budget a deposit into the pool of the config takes,
+    /// the lp it mints and what is left over, without sending anything
+    QuoteDeposit {
+        /// ui amount of coin available, e.g. 1.5
+        coin_amount: String,
+        /// ui amount of pc available
+        pc_amount: String,
+    },
+    /// Restrict swaps to the pool swap whitelist, or open them to everyone again
+    SetSwapPermissioned {
+        #[arg(action = clap::ArgAction::Set)]
+        permissioned: bool,
+    },
+    /// Reject a swap of the pool following another swap of it in the same transaction, or
+    /// allow it again
+    SetSwapGuard {
+        #[arg(action = clap::ArgAction::Set)]
+        guarded: bool,
+    },
+    /// Delegate pausing, reserve caps, the swap whitelist and fees of the pool to a wallet,
+    /// without --pool-admin the pool goes back to the amm owners alone
+    SetPoolAdmin {
+        #[arg(long)]
+        pool_admin: Option<Pubkey>,
+    },
+    /// Add a wallet to the pool swap whitelist, or remove it with --remove
+    UpdateSwapWhitelist {
+        wallet: Pubkey,
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Close the payer token accounts left with a zero balance and reclaim their rent,
+    /// wrapped sol accounts are closed whatever their balance and unwrap to the payer
+    CloseEmptyTokenAccounts {
+        /// only list the accounts that would be closed
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 // #[cfg(not(feature = "async"))]
 fn main() -> Result<()> {
-    println!("Starting...");
-    let client_config = "client_config.ini";
-    let pool_config = load_cfg(&client_config.to_string()).unwrap();
+    let opts = Opts::parse();
+    let output = opts.output;
+    if output == OutputFormat::Human {
+        println!("Starting...");
+    }
+    let client_config = opts.config.clone();
+    let config_path = std::fs::canonicalize(&client_config)
+        .map_err(|e| format_err!("failed to resolve config {}: {}", client_config, e))?;
+    if output == OutputFormat::Human {
+        println!("Loading config from {}", config_path.display());
+    }
+    let pool_config = load_cfg(&client_config)?;
+    let simulate = opts.simulate;
+    let send_config = SendConfig {
+        simulate,
+        retries: opts.send_retries,
+        retry_base_delay: std::time::Duration::from_millis(opts.retry_base_delay_ms),
+        skip_preflight: opts.skip_preflight,
+    };
+    if opts.skip_preflight {
+        // preflight catches a failing transaction before it is sent, without it the
+        // transaction lands and pays its fees even when it fails
+        eprintln!(
+            "warning: preflight is skipped, failing transactions are still sent and charged fees"
+        );
+    }
+    let compute_budget_instrs = vec![
+        ComputeBudgetInstruction::set_compute_unit_limit(opts.compute_unit_limit),
+        ComputeBudgetInstruction::set_compute_unit_price(opts.compute_unit_price),
+    ];
     // Admin and cluster params.
-    let payer = read_keypair_file(&pool_config.payer_path)?;
-    let admin = read_keypair_file(&pool_config.admin_path)?;
-    let withdrawer = read_keypair_file(&pool_config.withdrawer_path)?;
+    let payer = load_keypair(&pool_config.payer_path)?;
+    let admin = load_keypair(&pool_config.admin_path)?;
+    let withdrawer = load_keypair(&pool_config.withdrawer_path)?;
     let raydium_amm = pool_config.raydium_program;
     let pnl_owner = pool_config.pnl_owner;
     let admin_key = pool_config.admin_key;
@@ -241,22 +1226,36 @@ fn main() -> Result<()> {
     let amm_target_orders = pool_config.amm_target_orders;
 
     // solana rpc client
-    let rpc_client = RpcClient::new(pool_config.http_url.to_string());
+    let rpc_client =
+        RpcClient::new_with_commitment(pool_config.http_url.to_string(), opts.commitment);
+
+    if !opts.force {
+        for warning in check_program(
+            &raydium_amm,
+            &makidex_amm::id(),
+            get_multiple_accounts(&rpc_client, &[raydium_amm])?
+                .pop()
+                .flatten()
+                .as_ref(),
+        ) {
+            eprintln!("warning: {}", warning);
+        }
+    }
 
     // anchor client.
     let anchor_config = pool_config.clone();
     let url = Cluster::Custom(anchor_config.http_url, anchor_config.ws_url);
-    let wallet = read_keypair_file(&pool_config.payer_path)?;
-    let anchor_client = Client::new(url, Rc::new(wallet));
+    let wallet = load_keypair(&pool_config.payer_path)?;
+    let anchor_client = Client::new_with_options(url, Rc::new(wallet), opts.commitment);
     let program = anchor_client.program(pool_config.raydium_program)?;
 
-    let opts = Opts::parse();
     match opts.command {
         CommandsName::CreateConfigAccount {
             // amm_program,
             // administrator,
             // amm_config,
             // pnl_owner,
+            ensure,
         } => {
             let program = anchor_client.program(pool_config.raydium_program)?;
             let (amm_config_key, __bump) = Pubkey::find_program_address(
@@ -265,7 +1264,46 @@ fn main() -> Result<()> {
                 ],
                 &program.id(),
             );
-        
+            // the program refuses to create the config twice, say so before paying for it
+            if let Some(amm_config) = get_multiple_accounts(&rpc_client, &[amm_config_key])?
+                .pop()
+                .flatten()
+            {
+                if amm_config.owner == raydium_amm && ensure {
+                    let amm_config_state =
+                        AmmConfig::load_from_bytes(&amm_config.data).map_err(|_| {
+                            format_err!("{} is not an amm config account", amm_config_key)
+                        })?;
+                    for mismatch in config_mismatches(&amm_config_state, &pnl_owner, &admin_key) {
+                        eprintln!("warning: {}", mismatch);
+                    }
+                    print_output(
+                        output,
+                        &ExistingConfigOutput {
+                            amm_config: amm_config_key.to_string(),
+                            pnl_owner: amm_config_state.pnl_owner.to_string(),
+                            cancel_owner: amm_config_state.cancel_owner.to_string(),
+                            admin: amm_config_state.admin.to_string(),
+                            pending_admin: amm_config_state.pending_admin.to_string(),
+                            fee_recipient: amm_config_state.fee_recipient().to_string(),
+                            trade_fee_numerator: amm_config_state.trade_fee_numerator,
+                            trade_fee_denominator: amm_config_state.trade_fee_denominator,
+                            protocol_fee_numerator: amm_config_state.protocol_fee_numerator,
+                            protocol_fee_denominator: amm_config_state.protocol_fee_denominator,
+                            create_pool_fee: amm_config_state.create_pool_fee,
+                        },
+                    )?;
+                    return Ok(());
+                }
+                if amm_config.owner == raydium_amm {
+                    return Err(format_err!(
+                        "config account {} already exists, change it with update-config-fees \
+                         or set-pnl-owner instead of creating it again",
+                        amm_config_key
+                    ));
+                }
+            }
+
             let create_instr = create_config_account(
                 &raydium_amm,
                 &admin_key, // &admin.pubkey(),
@@ -278,13 +1316,19 @@ fn main() -> Result<()> {
             let signers = vec![&payer];
             let recent_hash = rpc_client.get_latest_blockhash()?;
             let txn = Transaction::new_signed_with_payer(
-                &vec![create_instr],
+                &[compute_budget_instrs.clone(), vec![create_instr]].concat(),
                 Some(&payer.pubkey()),
                 &signers,
                 recent_hash,
             );
-            let signature = send_txn(&rpc_client, &txn, true)?;
-            println!("{}", signature);
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &CreateConfigAccountOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_config: amm_config_key.to_string(),
+                },
+            )?;
         }
         CommandsName::OwnerWithdrawPool {
         } => {
@@ -323,14 +1367,13 @@ fn main() -> Result<()> {
             let signers = vec![&payer];
             let recent_hash = rpc_client.get_latest_blockhash()?;
             let txn = Transaction::new_signed_with_payer(
-                &vec![withdraw_instr],
+                &[compute_budget_instrs.clone(), vec![withdraw_instr]].concat(),
                 Some(&payer.pubkey()),
                 &signers,
                 recent_hash,
             );
-            let result : Response<RpcSimulateTransactionResult> = simulate_transaction(&rpc_client, &txn, false, CommitmentConfig{
-                commitment: Confirmed
-            })?;
+            let result: Response<RpcSimulateTransactionResult> =
+                simulate_transaction(&rpc_client, &txn, false, rpc_client.commitment())?;
             let logs = result.value.logs.unwrap();
             println!("Simulate :");
             for log in logs {
@@ -339,11 +1382,1966 @@ fn main() -> Result<()> {
             if let Some(error) = result.value.err {
                 println!("Transaction simulation failed: {:?}", error);
             } else {
-                let signature = send_txn(&rpc_client, &txn, true)?;
-                println!("{}", signature);
+                let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+                print_output(
+                    output,
+                    &OwnerWithdrawPoolOutput {
+                        signature: signature.map(|signature| signature.to_string()),
+                        amm_pool: amm_pool.to_string(),
+                    },
+                )?;
             }
         }
-    }
+        CommandsName::UpdateConfigFees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            protocol_fee_numerator,
+            protocol_fee_denominator,
+        } => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &program.id(),
+            );
 
-    Ok(())
+            let update_instr = update_config_fees(
+                &raydium_amm,
+                &admin.pubkey(),
+                &amm_config_key,
+                trade_fee_numerator,
+                trade_fee_denominator,
+                protocol_fee_numerator,
+                protocol_fee_denominator,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![update_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &UpdateConfigFeesOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_config: amm_config_key.to_string(),
+                },
+            )?;
+        }
+        CommandsName::SyncPoolFees => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let sync_instr =
+                sync_pool_fees(&raydium_amm, &amm_pool, &amm_config_key, &admin.pubkey())?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![sync_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SyncPoolFeesOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                },
+            )?;
+        }
+        CommandsName::InitializePool {
+            coin_mint,
+            pc_mint,
+            market,
+            init_coin_amount,
+            init_pc_amount,
+            open_time,
+            lp_metadata_address,
+            expected_price,
+            price_tolerance_bps,
+            lp_decimals,
+            without_open_orders,
+            stable_amp,
+        } => {
+            if let Some(lp_decimals) = lp_decimals {
+                if lp_decimals > makidex_amm::processor::MAX_LP_DECIMALS {
+                    return Err(format_err!(
+                        "--lp-decimals {} exceeds the maximum of {}",
+                        lp_decimals,
+                        makidex_amm::processor::MAX_LP_DECIMALS
+                    ));
+                }
+            }
+            for (name, mint) in [("coin_mint", &coin_mint), ("pc_mint", &pc_mint)] {
+                if rpc_client.get_account(mint).is_err() {
+                    return Err(format_err!("{} {} does not exist on-chain", name, mint));
+                }
+            }
+            let output_pool = initialize_pool(
+                &rpc_client,
+                &send_config,
+                &compute_budget_instrs,
+                &raydium_amm,
+                &payer,
+                &coin_mint,
+                &pc_mint,
+                &market,
+                init_coin_amount,
+                init_pc_amount,
+                open_time,
+                &lp_metadata_address
+                    .map(|metadata_address| ExtensionInitializationParams::MetadataPointer {
+                        authority: None,
+                        metadata_address: Some(metadata_address),
+                    })
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+                init_price_bound(expected_price, price_tolerance_bps)?,
+                lp_decimals,
+                without_open_orders,
+                stable_amp,
+            )?;
+            print_output(output, &output_pool)?;
+        }
+        CommandsName::CreateTestPool {
+            coin_decimals,
+            pc_decimals,
+            supply,
+            init_coin_amount,
+            init_pc_amount,
+            airdrop,
+            devnet,
+        } => {
+            if !devnet {
+                return Err(format_err!(
+                    "create-test-pool creates throwaway mints, a market and a pool, pass --devnet to run it"
+                ));
+            }
+            if rpc_client.get_genesis_hash()?.to_string() == MAINNET_BETA_GENESIS_HASH {
+                return Err(format_err!("{} is a mainnet-beta rpc", pool_config.http_url));
+            }
+            if send_config.simulate {
+                return Err(format_err!(
+                    "create-test-pool can not be simulated, every step needs the accounts of the previous one"
+                ));
+            }
+            // a lot of 0.001 coin, priced in steps of 0.000001 pc
+            let coin_lot_size = lot_size(coin_decimals, 3)?;
+            let pc_lot_size = lot_size(pc_decimals, 6)?;
+            let mut signatures = Vec::new();
+            if airdrop > 0 {
+                let lamports = airdrop
+                    .checked_mul(solana_sdk::native_token::LAMPORTS_PER_SOL)
+                    .ok_or_else(|| format_err!("airdrop of {} SOL overflows", airdrop))?;
+                let signature = rpc_client.request_airdrop(&payer.pubkey(), lamports)?;
+                rpc_client.poll_for_signature(&signature)?;
+                signatures.push(signature.to_string());
+            }
+
+            // mints, with their supply minted to the payer
+            let coin_mint = Keypair::new();
+            let pc_mint = Keypair::new();
+            let user_token_coin = get_associated_token_address(&payer.pubkey(), &coin_mint.pubkey());
+            let user_token_pc = get_associated_token_address(&payer.pubkey(), &pc_mint.pubkey());
+            let mut instrs = compute_budget_instrs.clone();
+            for (mint, user_token, decimals) in [
+                (&coin_mint, &user_token_coin, coin_decimals),
+                (&pc_mint, &user_token_pc, pc_decimals),
+            ] {
+                instrs.extend(create_and_init_mint_instr(
+                    &pool_config,
+                    spl_token::id(),
+                    &mint.pubkey(),
+                    &payer.pubkey(),
+                    None,
+                    Vec::<ExtensionInitializationParams>::new(),
+                    decimals,
+                )?);
+                instrs.push(
+                    spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                        &payer.pubkey(),
+                        &payer.pubkey(),
+                        &mint.pubkey(),
+                        &spl_token::id(),
+                    ),
+                );
+                instrs.extend(spl_token_mint_to_instr(
+                    &pool_config,
+                    spl_token::id(),
+                    &mint.pubkey(),
+                    user_token,
+                    ui_to_base_units(supply, decimals)?,
+                    &payer,
+                )?);
+            }
+            signatures.push(send_instrs(
+                &rpc_client,
+                &send_config,
+                &payer,
+                &[&coin_mint, &pc_mint],
+                &instrs,
+            )?);
+
+            // openbook market accounts
+            let market_program = makidex_amm::processor::config_feature::openbook_program::id();
+            let market = Keypair::new();
+            let request_queue = Keypair::new();
+            let event_queue = Keypair::new();
+            let bids = Keypair::new();
+            let asks = Keypair::new();
+            let mut instrs = compute_budget_instrs.clone();
+            for (account, len) in [
+                (&market, size_of::<serum_dex::state::MarketState>() + 12),
+                (&request_queue, TEST_MARKET_REQUEST_QUEUE_LEN),
+                (&event_queue, TEST_MARKET_EVENT_QUEUE_LEN),
+                (&bids, TEST_MARKET_ORDERBOOK_LEN),
+                (&asks, TEST_MARKET_ORDERBOOK_LEN),
+            ] {
+                instrs.push(solana_sdk::system_instruction::create_account(
+                    &payer.pubkey(),
+                    &account.pubkey(),
+                    rpc_client.get_minimum_balance_for_rent_exemption(len)?,
+                    len as u64,
+                    &market_program,
+                ));
+            }
+            signatures.push(send_instrs(
+                &rpc_client,
+                &send_config,
+                &payer,
+                &[&market, &request_queue, &event_queue, &bids, &asks],
+                &instrs,
+            )?);
+
+            // market vaults owned by the vault signer, then the market itself
+            let (vault_signer_nonce, vault_signer) = (0..u64::MAX)
+                .find_map(|nonce| {
+                    serum_dex::state::gen_vault_signer_key(nonce, &market.pubkey(), &market_program)
+                        .ok()
+                        .map(|vault_signer| (nonce, vault_signer))
+                })
+                .ok_or_else(|| format_err!("no vault signer for market {}", market.pubkey()))?;
+            let market_coin_vault = get_associated_token_address(&vault_signer, &coin_mint.pubkey());
+            let market_pc_vault = get_associated_token_address(&vault_signer, &pc_mint.pubkey());
+            let mut instrs = compute_budget_instrs.clone();
+            for mint in [&coin_mint, &pc_mint] {
+                instrs.push(
+                    spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                        &payer.pubkey(),
+                        &vault_signer,
+                        &mint.pubkey(),
+                        &spl_token::id(),
+                    ),
+                );
+            }
+            instrs.push(
+                serum_dex::instruction::initialize_market(
+                    &market.pubkey(),
+                    &market_program,
+                    &coin_mint.pubkey(),
+                    &pc_mint.pubkey(),
+                    &market_coin_vault,
+                    &market_pc_vault,
+                    None,
+                    None,
+                    None,
+                    &bids.pubkey(),
+                    &asks.pubkey(),
+                    &request_queue.pubkey(),
+                    &event_queue.pubkey(),
+                    coin_lot_size,
+                    pc_lot_size,
+                    vault_signer_nonce,
+                    pc_lot_size,
+                )
+                .map_err(|e| format_err!("failed to build initialize_market: {:?}", e))?,
+            );
+            signatures.push(send_instrs(&rpc_client, &send_config, &payer, &[], &instrs)?);
+
+            // the pool, with the initial deposit from the payer
+            let pool = initialize_pool(
+                &rpc_client,
+                &send_config,
+                &compute_budget_instrs,
+                &raydium_amm,
+                &payer,
+                &coin_mint.pubkey(),
+                &pc_mint.pubkey(),
+                &market.pubkey(),
+                ui_to_base_units(init_coin_amount, coin_decimals)?,
+                ui_to_base_units(init_pc_amount, pc_decimals)?,
+                0,
+                &[],
+                None,
+                None,
+                false,
+                None,
+            )?;
+            print_output(
+                output,
+                &CreateTestPoolOutput {
+                    signatures,
+                    coin_mint: coin_mint.pubkey().to_string(),
+                    pc_mint: pc_mint.pubkey().to_string(),
+                    user_token_coin: user_token_coin.to_string(),
+                    user_token_pc: user_token_pc.to_string(),
+                    market: market.pubkey().to_string(),
+                    request_queue: request_queue.pubkey().to_string(),
+                    event_queue: event_queue.pubkey().to_string(),
+                    bids: bids.pubkey().to_string(),
+                    asks: asks.pubkey().to_string(),
+                    market_coin_vault: market_coin_vault.to_string(),
+                    market_pc_vault: market_pc_vault.to_string(),
+                    vault_signer: vault_signer.to_string(),
+                    pool,
+                },
+            )?;
+        }
+        CommandsName::DerivePdas {
+            coin_mint,
+            pc_mint,
+            market,
+        } => {
+            let market = match market {
+                Some(market) => market,
+                None => find_market(
+                    &rpc_client,
+                    &makidex_amm::processor::config_feature::openbook_program::id(),
+                    &coin_mint,
+                    &pc_mint,
+                )?,
+            };
+            let pdas =
+                makidex_amm::processor::get_amm_pdas(&raydium_amm, &market, &coin_mint, &pc_mint);
+            print_output(
+                output,
+                &DerivePdasOutput {
+                    market: market.to_string(),
+                    amm_pool: pdas.amm_pool.to_string(),
+                    amm_authority: pdas.amm_authority.to_string(),
+                    nonce: pdas.nonce,
+                    amm_open_orders: pdas.amm_open_orders.to_string(),
+                    amm_target_orders: pdas.amm_target_orders.to_string(),
+                    amm_coin_vault: pdas.amm_coin_vault.to_string(),
+                    amm_pc_vault: pdas.amm_pc_vault.to_string(),
+                    amm_lp_mint: pdas.amm_lp_mint.to_string(),
+                    amm_config: pdas.amm_config.to_string(),
+                },
+            )?;
+        }
+        CommandsName::SetPnlOwner { new_pnl_owner } => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let set_instr =
+                set_pnl_owner(&raydium_amm, &admin.pubkey(), &amm_config_key, &new_pnl_owner)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetPnlOwnerOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_config: amm_config_key.to_string(),
+                    new_pnl_owner: new_pnl_owner.to_string(),
+                },
+            )?;
+        }
+        CommandsName::SetFeeRecipient { fee_recipient } => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let set_instr =
+                set_fee_recipient(&raydium_amm, &admin.pubkey(), &amm_config_key, &fee_recipient)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetFeeRecipientOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_config: amm_config_key.to_string(),
+                    fee_recipient: fee_recipient.to_string(),
+                },
+            )?;
+        }
+        CommandsName::SetSwapDiscount {
+            discount_mint,
+            fee_numerator,
+            fee_denominator,
+            disable: _,
+        } => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            // --disable leaves all three unset, the default mint turns the discount off
+            let discount_mint = discount_mint.unwrap_or_default();
+            let fee_numerator = fee_numerator.unwrap_or_default();
+            let fee_denominator = fee_denominator.unwrap_or_default();
+            let set_instr = set_swap_discount(
+                &raydium_amm,
+                &admin.pubkey(),
+                &amm_config_key,
+                &discount_mint,
+                fee_numerator,
+                fee_denominator,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetSwapDiscountOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_config: amm_config_key.to_string(),
+                    discount_mint: discount_mint.to_string(),
+                    fee_numerator,
+                    fee_denominator,
+                },
+            )?;
+        }
+        CommandsName::ShowPool { pool } => {
+            let pool_account = get_multiple_accounts(&rpc_client, &[pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", pool))?;
+            let (coin_reserve, pc_reserve) = fetch_pool_reserves(&rpc_client, &amm)?;
+            let ui_coin = coin_reserve as f64 / 10f64.powi(amm.coin_decimals as i32);
+            let ui_pc = pc_reserve as f64 / 10f64.powi(amm.pc_decimals as i32);
+            let user_lp_key = get_associated_token_address(&payer.pubkey(), &amm.lp_mint);
+            let user_lp = get_token_account::<Account>(&rpc_client, &user_lp_key)
+                .map(|account| account.amount)
+                .unwrap_or(0);
+
+            print_output(
+                output,
+                &ShowPoolOutput {
+                    pool: pool.to_string(),
+                    status: amm.status,
+                    coin_mint: amm.coin_vault_mint.to_string(),
+                    pc_mint: amm.pc_vault_mint.to_string(),
+                    coin_reserve,
+                    ui_coin_reserve: ui_coin,
+                    pc_reserve,
+                    ui_pc_reserve: ui_pc,
+                    lp_supply: amm.lp_amount,
+                    payer_lp: user_lp,
+                    spot_price: makidex_amm::math::checked_spot_price(
+                        coin_reserve,
+                        pc_reserve,
+                        amm.coin_decimals as u8,
+                        amm.pc_decimals as u8,
+                    ),
+                    swap_fee: (amm.fees.swap_fee_numerator, amm.fees.swap_fee_denominator),
+                    trade_fee: (amm.fees.trade_fee_numerator, amm.fees.trade_fee_denominator),
+                    protocol_fee: (amm.protocol_fee_numerator, amm.protocol_fee_denominator),
+                    pnl: (amm.fees.pnl_numerator, amm.fees.pnl_denominator),
+                },
+            )?;
+        }
+        CommandsName::WatchPool {
+            pool,
+            reconnect_delay_secs,
+        } => {
+            // the subscriptions only report changes, start from the current state
+            let pool_account = get_multiple_accounts(&rpc_client, &[pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", pool))?;
+            let keys = WatchedPool::keys(&pool, &amm);
+            let (mut slot, mut watched) = WatchedPool::fetch(&rpc_client, &keys)?;
+
+            let (updates, receiver) = mpsc::channel();
+            for key in keys {
+                let ws_url = pool_config.ws_url.clone();
+                let commitment = opts.commitment;
+                let updates = updates.clone();
+                thread::spawn(move || {
+                    watch_account(
+                        ws_url,
+                        key,
+                        commitment,
+                        Duration::from_secs(reconnect_delay_secs),
+                        updates,
+                    )
+                });
+            }
+            drop(updates);
+            loop {
+                match watched.reserves() {
+                    Ok((coin_reserve, pc_reserve)) => {
+                        let amm = &watched.amm;
+                        print_output(
+                            output,
+                            &WatchPoolOutput {
+                                slot,
+                                pool: pool.to_string(),
+                                status: amm.status,
+                                coin_reserve,
+                                ui_coin_reserve: coin_reserve as f64
+                                    / 10f64.powi(amm.coin_decimals as i32),
+                                pc_reserve,
+                                ui_pc_reserve: pc_reserve as f64
+                                    / 10f64.powi(amm.pc_decimals as i32),
+                                lp_supply: amm.lp_amount,
+                                spot_price: makidex_amm::math::checked_spot_price(
+                                    coin_reserve,
+                                    pc_reserve,
+                                    amm.coin_decimals as u8,
+                                    amm.pc_decimals as u8,
+                                ),
+                            },
+                        )?;
+                    }
+                    Err(e) => eprintln!("slot {}: {}", slot, e),
+                }
+                match receiver
+                    .recv()
+                    .map_err(|_| format_err!("every subscription of pool {} ended", pool))?
+                {
+                    AccountUpdate::Changed(key, update_slot, data) => {
+                        slot = update_slot;
+                        watched.update(&pool, &key, &data)?;
+                    }
+                    // whatever changed while the websocket was down is read again
+                    AccountUpdate::Resubscribed(key) => {
+                        eprintln!("resubscribed to {}, reading pool {} again", key, pool);
+                        (slot, watched) = WatchedPool::fetch(&rpc_client, &keys)?;
+                    }
+                }
+            }
+        }
+        CommandsName::CollectProtocolFees {
+            coin_amount,
+            pc_amount,
+        } => {
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let (amm_authority_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AUTHORITY_AMM],
+                &raydium_amm,
+            );
+            let config_account = get_multiple_accounts(&rpc_client, &[amm_config_key])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("amm config {} does not exist", amm_config_key))?;
+            let fee_recipient = AmmConfig::load_from_bytes(&config_account.data)
+                .map_err(|_| format_err!("{} is not an amm config account", amm_config_key))?
+                .fee_recipient();
+            let owner_token_coin_key =
+                get_associated_token_address(&fee_recipient, &pool_config.coin_mint);
+            let owner_token_pc_key =
+                get_associated_token_address(&fee_recipient, &pool_config.pc_mint);
+            let create_recipient_atas = [pool_config.coin_mint, pool_config.pc_mint].map(|mint| {
+                spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                    &payer.pubkey(),
+                    &fee_recipient,
+                    &mint,
+                    &spl_token::id(),
+                )
+            });
+
+            let collect_instr = collect_protocol_fees(
+                &raydium_amm,
+                &amm_pool,
+                &amm_config_key,
+                &amm_authority_key,
+                &amm_coin_vault,
+                &amm_pc_vault,
+                &owner_token_coin_key,
+                &owner_token_pc_key,
+                &payer.pubkey(),
+                coin_amount,
+                pc_amount,
+            )?;
+            // send
+            let signers = vec![&payer];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[
+                    compute_budget_instrs.clone(),
+                    create_recipient_atas.to_vec(),
+                    vec![collect_instr],
+                ]
+                .concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            let balance = |token: &Pubkey| {
+                signature.and_then(|_| {
+                    get_token_account::<Account>(&rpc_client, token)
+                        .ok()
+                        .map(|account| account.amount)
+                })
+            };
+            print_output(
+                output,
+                &CollectProtocolFeesOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    owner_token_coin: owner_token_coin_key.to_string(),
+                    owner_token_pc: owner_token_pc_key.to_string(),
+                    owner_coin_balance: balance(&owner_token_coin_key),
+                    owner_pc_balance: balance(&owner_token_pc_key),
+                },
+            )?;
+        }
+        CommandsName::SetPoolStatus { status } => {
+            let status = parse_u64_hex_or_dec(&status)?;
+            let set_status_instr =
+                set_pool_status(&raydium_amm, &amm_pool, &admin.pubkey(), status)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_status_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetPoolStatusOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    status,
+                },
+            )?;
+        }
+        CommandsName::ClosePool { pool, recipient } => {
+            let pool_account = get_multiple_accounts(&rpc_client, &[pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", pool))?;
+            let (amm_authority_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AUTHORITY_AMM],
+                &raydium_amm,
+            );
+            let recipient = recipient.unwrap_or_else(|| admin.pubkey());
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let config_account = get_multiple_accounts(&rpc_client, &[amm_config_key])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("amm config {} does not exist", amm_config_key))?;
+            let fee_recipient = AmmConfig::load_from_bytes(&config_account.data)
+                .map_err(|_| format_err!("{} is not an amm config account", amm_config_key))?
+                .fee_recipient();
+            let recipient_token_coin_key =
+                get_associated_token_address(&fee_recipient, &amm.coin_vault_mint);
+            let recipient_token_pc_key =
+                get_associated_token_address(&fee_recipient, &amm.pc_vault_mint);
+            let create_recipient_atas = [amm.coin_vault_mint, amm.pc_vault_mint].map(|mint| {
+                spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                    &payer.pubkey(),
+                    &fee_recipient,
+                    &mint,
+                    &spl_token::id(),
+                )
+            });
+
+            let close_instr = close_pool(
+                &raydium_amm,
+                &pool,
+                &amm_authority_key,
+                &amm.open_orders,
+                &amm.coin_vault,
+                &amm.pc_vault,
+                &amm.lp_mint,
+                &amm.target_orders,
+                &amm.market_program,
+                &amm.market,
+                &admin.pubkey(),
+                &recipient,
+                &amm_config_key,
+                &recipient_token_coin_key,
+                &recipient_token_pc_key,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[
+                    compute_budget_instrs.clone(),
+                    create_recipient_atas.to_vec(),
+                    vec![close_instr],
+                ]
+                .concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &ClosePoolOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: pool.to_string(),
+                    recipient: recipient.to_string(),
+                },
+            )?;
+        }
+        CommandsName::SetReserveCaps {
+            max_coin_reserve,
+            max_pc_reserve,
+        } => {
+            let set_caps_instr = set_reserve_caps(
+                &raydium_amm,
+                &amm_pool,
+                &admin.pubkey(),
+                max_coin_reserve,
+                max_pc_reserve,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_caps_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetReserveCapsOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    max_coin_reserve,
+                    max_pc_reserve,
+                },
+            )?;
+        }
+        CommandsName::SetDynamicFee {
+            base_fee_numerator,
+            max_fee_numerator,
+            fee_denominator,
+            max_fee_deviation_bps,
+            disable,
+        } => {
+            let set_fee_instr = set_dynamic_fee(
+                &raydium_amm,
+                &amm_pool,
+                &admin.pubkey(),
+                !disable,
+                base_fee_numerator,
+                max_fee_numerator,
+                fee_denominator,
+                max_fee_deviation_bps,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_fee_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetDynamicFeeOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    enabled: !disable,
+                    base_fee_numerator,
+                    max_fee_numerator,
+                    fee_denominator,
+                    max_fee_deviation_bps,
+                },
+            )?;
+        }
+        CommandsName::SetMinTradeAmount {
+            min_coin_amount,
+            min_pc_amount,
+        } => {
+            let set_min_instr = set_min_trade_amount(
+                &raydium_amm,
+                &amm_pool,
+                &admin.pubkey(),
+                min_coin_amount,
+                min_pc_amount,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_min_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetMinTradeAmountOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    min_coin_amount,
+                    min_pc_amount,
+                },
+            )?;
+        }
+        CommandsName::Swap {
+            input_mint,
+            amount,
+            slippage,
+            use_lookup_table,
+            recipient,
+        } => {
+            let recipient = recipient.unwrap_or_else(|| payer.pubkey());
+            let pool_account = get_multiple_accounts(&rpc_client, &[amm_pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", amm_pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", amm_pool))?;
+            let output_mint = if input_mint == amm.coin_vault_mint {
+                amm.pc_vault_mint
+            } else if input_mint == amm.pc_vault_mint {
+                amm.coin_vault_mint
+            } else {
+                return Err(format_err!("{} is not a mint of pool {}", input_mint, amm_pool));
+            };
+            let amount_in = ui_amount_to_base_units(&rpc_client, &input_mint, &amount)?;
+            let (coin_reserve, pc_reserve) = fetch_pool_reserves(&rpc_client, &amm)?;
+            let (reserve_in, reserve_out) = if input_mint == amm.coin_vault_mint {
+                (coin_reserve, pc_reserve)
+            } else {
+                (pc_reserve, coin_reserve)
+            };
+            // a payer holding the config discount mint swaps at the lower of the two fees
+            let (amm_config_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AMM_CONFIG_SEED],
+                &raydium_amm,
+            );
+            let amm_config = get_multiple_accounts(&rpc_client, &[amm_config_key])?
+                .pop()
+                .flatten()
+                .and_then(|account| AmmConfig::load_from_bytes(&account.data).ok())
+                .filter(|amm_config| amm_config.discount_mint != Pubkey::default());
+            let mut swap_fee = (amm.fees.swap_fee_numerator, amm.fees.swap_fee_denominator);
+            let mut user_discount_account = None;
+            if let Some(amm_config) = amm_config {
+                // the discount mint may be a Token-2022 mint, whose token accounts derive from
+                // that program
+                let discount_token_program =
+                    get_multiple_accounts(&rpc_client, &[amm_config.discount_mint])?
+                        .pop()
+                        .flatten()
+                        .map_or(spl_token::id(), |mint| mint.owner);
+                let discount_account = get_associated_token_address_with_program_id(
+                    &payer.pubkey(),
+                    &amm_config.discount_mint,
+                    &discount_token_program,
+                );
+                let discount_balance = get_multiple_accounts(&rpc_client, &[discount_account])?
+                    .pop()
+                    .flatten()
+                    .and_then(|account| Account::unpack_from_slice(&account.data).ok())
+                    .map_or(0, |account| account.amount);
+                if discount_balance > 0 {
+                    user_discount_account = Some(discount_account);
+                    let discount_fee = (
+                        amm_config.discount_fee_numerator,
+                        amm_config.discount_fee_denominator,
+                    );
+                    if u128::from(discount_fee.0) * u128::from(swap_fee.1)
+                        < u128::from(swap_fee.0) * u128::from(discount_fee.1)
+                    {
+                        swap_fee = discount_fee;
+                    }
+                }
+            }
+            let (expected_amount_out, minimum_amount_out) = swap_quote(
+                amount_in,
+                reserve_in,
+                reserve_out,
+                swap_fee.0,
+                swap_fee.1,
+                slippage,
+            )?;
+            if output == OutputFormat::Human {
+                println!(
+                    "expected_amount_out: {}, minimum_amount_out: {} ({}% slippage)",
+                    expected_amount_out, minimum_amount_out, slippage
+                );
+            }
+
+            let market = load_market_keys(&rpc_client, &amm.market_program, &amm.market)?;
+            let (amm_authority_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AUTHORITY_AMM],
+                &raydium_amm,
+            );
+            let user_token_source = get_associated_token_address(&payer.pubkey(), &input_mint);
+            let user_token_destination = get_associated_token_address(&recipient, &output_mint);
+            let mut swap_instr = swap_base_in(
+                &raydium_amm,
+                &amm_pool,
+                &amm_authority_key,
+                &amm.open_orders,
+                &amm.coin_vault,
+                &amm.pc_vault,
+                &amm.market_program,
+                &amm.market,
+                &market.bids,
+                &market.asks,
+                &market.event_queue,
+                &market.coin_vault,
+                &market.pc_vault,
+                &market.vault_signer,
+                &user_token_source,
+                &user_token_destination,
+                &payer.pubkey(),
+                amount_in,
+                minimum_amount_out,
+                u64::MAX,
+                Some(if input_mint == amm.coin_vault_mint {
+                    TradeDirection::CoinToPc
+                } else {
+                    TradeDirection::PcToCoin
+                }),
+            )?;
+            if let Some(discount_account) = user_discount_account {
+                swap_instr.accounts.extend([
+                    AccountMeta::new_readonly(amm_config_key, false),
+                    AccountMeta::new_readonly(discount_account, false),
+                ]);
+            }
+            if amm.swap_permissioned() {
+                let (swap_whitelist, _) = Pubkey::find_program_address(
+                    &[
+                        amm_pool.as_ref(),
+                        makidex_amm::processor::SWAP_WHITELIST_SEED,
+                    ],
+                    &raydium_amm,
+                );
+                swap_instr
+                    .accounts
+                    .push(AccountMeta::new_readonly(swap_whitelist, false));
+            }
+            if amm.swap_guarded() {
+                swap_instr.accounts.push(AccountMeta::new_readonly(
+                    solana_sdk::sysvar::instructions::id(),
+                    false,
+                ));
+            }
+            let mut instrs = compute_budget_instrs.clone();
+            // the payer wsol account is created and closed around the swap by the wrap helper
+            if output_mint != spl_token::native_mint::id() || recipient != payer.pubkey() {
+                instrs.push(
+                    spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                        &payer.pubkey(),
+                        &recipient,
+                        &output_mint,
+                        &spl_token::id(),
+                    ),
+                );
+            }
+            instrs.extend(wrap_native_swap_instr(
+                &pool_config,
+                &payer,
+                &input_mint,
+                &output_mint,
+                amount_in,
+                swap_instr,
+            )?);
+            // send
+            let signers = vec![&payer];
+            let signature = match use_lookup_table {
+                Some(lookup_table) => send_or_simulate_versioned_txn(
+                    &rpc_client,
+                    &payer.pubkey(),
+                    &instrs,
+                    &[get_lookup_table(&rpc_client, &lookup_table)?],
+                    &signers,
+                    &send_config,
+                )?,
+                None => {
+                    let recent_hash = rpc_client.get_latest_blockhash()?;
+                    let txn = Transaction::new_signed_with_payer(
+                        &instrs,
+                        Some(&payer.pubkey()),
+                        &signers,
+                        recent_hash,
+                    );
+                    send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?
+                }
+            };
+            print_output(
+                output,
+                &SwapOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    input_mint: input_mint.to_string(),
+                    output_mint: output_mint.to_string(),
+                    recipient: recipient.to_string(),
+                    amount_in,
+                    expected_amount_out,
+                    minimum_amount_out,
+                },
+            )?;
+        }
+        CommandsName::QuoteDeposit {
+            coin_amount,
+            pc_amount,
+        } => {
+            let pool_account = get_multiple_accounts(&rpc_client, &[amm_pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", amm_pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", amm_pool))?;
+            let max_coin_amount =
+                ui_amount_to_base_units(&rpc_client, &amm.coin_vault_mint, &coin_amount)?;
+            let max_pc_amount = ui_amount_to_base_units(&rpc_client, &amm.pc_vault_mint, &pc_amount)?;
+            let (coin_reserve, pc_reserve) = fetch_pool_reserves(&rpc_client, &amm)?;
+            let (base_side, coin_used, pc_used, lp_amount) = deposit_amounts(
+                max_coin_amount,
+                max_pc_amount,
+                coin_reserve,
+                pc_reserve,
+                amm.lp_amount,
+            )?;
+            print_output(
+                output,
+                &QuoteDepositOutput {
+                    amm_pool: amm_pool.to_string(),
+                    base_side,
+                    coin_amount: coin_used,
+                    pc_amount: pc_used,
+                    lp_amount,
+                    coin_leftover: max_coin_amount - coin_used,
+                    pc_leftover: max_pc_amount - pc_used,
+                },
+            )?;
+        }
+        CommandsName::Deposit {
+            coin_amount,
+            pc_amount,
+            slippage,
+        } => {
+            let pool_account = get_multiple_accounts(&rpc_client, &[amm_pool])?
+                .pop()
+                .flatten()
+                .ok_or_else(|| format_err!("pool account {} does not exist", amm_pool))?;
+            let amm = AmmInfo::from_account_data(&pool_account.data)
+                .map_err(|_| format_err!("{} is not an amm pool account", amm_pool))?;
+            let max_coin_amount =
+                ui_amount_to_base_units(&rpc_client, &amm.coin_vault_mint, &coin_amount)?;
+            let max_pc_amount = ui_amount_to_base_units(&rpc_client, &amm.pc_vault_mint, &pc_amount)?;
+            let (coin_reserve, pc_reserve) = fetch_pool_reserves(&rpc_client, &amm)?;
+            let (base_side, expected_lp_amount, minimum_lp_amount) = deposit_quote(
+                max_coin_amount,
+                max_pc_amount,
+                coin_reserve,
+                pc_reserve,
+                amm.lp_amount,
+                slippage,
+            )?;
+            if output == OutputFormat::Human {
+                println!(
+                    "expected_lp_amount: {}, minimum_lp_amount: {} ({}% slippage)",
+                    expected_lp_amount, minimum_lp_amount, slippage
+                );
+            }
+
+            let market = load_market_keys(&rpc_client, &amm.market_program, &amm.market)?;
+            let (amm_authority_key, __bump) = Pubkey::find_program_address(
+                &[&makidex_amm::processor::AUTHORITY_AMM],
+                &raydium_amm,
+            );
+            let lp_token_program = rpc_client.get_account(&amm.lp_mint)?.owner;
+            let user_token_lp = get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &amm.lp_mint,
+                &lp_token_program,
+            );
+            let mut deposit_instr = deposit(
+                &raydium_amm,
+                &amm_pool,
+                &amm_authority_key,
+                &amm.open_orders,
+                &amm.target_orders,
+                &amm.lp_mint,
+                &amm.coin_vault,
+                &amm.pc_vault,
+                &amm.market,
+                &market.event_queue,
+                &get_associated_token_address(&payer.pubkey(), &amm.coin_vault_mint),
+                &get_associated_token_address(&payer.pubkey(), &amm.pc_vault_mint),
+                &user_token_lp,
+                &payer.pubkey(),
+                max_coin_amount,
+                max_pc_amount,
+                base_side,
+                minimum_lp_amount,
+            )?;
+            if lp_token_program == spl_token_2022::id() {
+                deposit_instr
+                    .accounts
+                    .push(AccountMeta::new_readonly(lp_token_program, false));
+            }
+            let mut instrs = compute_budget_instrs.clone();
+            instrs.push(
+                spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                    &payer.pubkey(),
+                    &payer.pubkey(),
+                    &amm.lp_mint,
+                    &lp_token_program,
+                ),
+            );
+            instrs.push(deposit_instr);
+            // send
+            let signers = vec![&payer];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &instrs,
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &DepositOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    max_coin_amount,
+                    max_pc_amount,
+                    base_side,
+                    expected_lp_amount,
+                    minimum_lp_amount,
+                },
+            )?;
+        }
+        CommandsName::SetSwapPermissioned { permissioned } => {
+            let set_permissioned_instr =
+                set_swap_permissioned(&raydium_amm, &amm_pool, &admin.pubkey(), permissioned)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_permissioned_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetSwapPermissionedOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    permissioned,
+                },
+            )?;
+        }
+        CommandsName::SetSwapGuard { guarded } => {
+            let set_guard_instr =
+                set_swap_guard(&raydium_amm, &amm_pool, &admin.pubkey(), guarded)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_guard_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetSwapGuardOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    guarded,
+                },
+            )?;
+        }
+        CommandsName::SetPoolAdmin { pool_admin } => {
+            let pool_admin = pool_admin.unwrap_or_default();
+            let set_admin_instr =
+                set_pool_admin(&raydium_amm, &amm_pool, &admin.pubkey(), &pool_admin)?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![set_admin_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &SetPoolAdminOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    pool_admin: pool_admin.to_string(),
+                },
+            )?;
+        }
+        CommandsName::UpdateSwapWhitelist { wallet, remove } => {
+            let (swap_whitelist, _) = Pubkey::find_program_address(
+                &[
+                    amm_pool.as_ref(),
+                    makidex_amm::processor::SWAP_WHITELIST_SEED,
+                ],
+                &raydium_amm,
+            );
+            let update_whitelist_instr = update_swap_whitelist(
+                &raydium_amm,
+                &amm_pool,
+                &swap_whitelist,
+                &admin.pubkey(),
+                &wallet,
+                !remove,
+            )?;
+            // send
+            let signers = vec![&payer, &admin];
+            let recent_hash = rpc_client.get_latest_blockhash()?;
+            let txn = Transaction::new_signed_with_payer(
+                &[compute_budget_instrs.clone(), vec![update_whitelist_instr]].concat(),
+                Some(&payer.pubkey()),
+                &signers,
+                recent_hash,
+            );
+            let signature = send_or_simulate_txn(&rpc_client, txn, &signers, &send_config)?;
+            print_output(
+                output,
+                &UpdateSwapWhitelistOutput {
+                    signature: signature.map(|signature| signature.to_string()),
+                    amm_pool: amm_pool.to_string(),
+                    swap_whitelist: swap_whitelist.to_string(),
+                    wallet: wallet.to_string(),
+                    added: !remove,
+                },
+            )?;
+        }
+        CommandsName::CloseEmptyTokenAccounts { dry_run } => {
+            let mut candidates = Vec::new();
+            for token_program in [spl_token::id(), spl_token_2022::id()] {
+                for (account, token_account) in
+                    find_close_candidates(&rpc_client, &payer.pubkey(), &token_program)?
+                {
+                    candidates.push((account, token_program, token_account));
+                }
+            }
+            let mut signatures = Vec::new();
+            if !dry_run {
+                for batch in candidates.chunks(CLOSE_ACCOUNTS_PER_TXN) {
+                    let mut instrs = compute_budget_instrs.clone();
+                    for (account, token_program, _) in batch {
+                        instrs.push(spl_token_2022::instruction::close_account(
+                            token_program,
+                            account,
+                            &payer.pubkey(),
+                            &payer.pubkey(),
+                            &[],
+                        )?);
+                    }
+                    let signers = vec![&payer];
+                    let recent_hash = rpc_client.get_latest_blockhash()?;
+                    let txn = Transaction::new_signed_with_payer(
+                        &instrs,
+                        Some(&payer.pubkey()),
+                        &signers,
+                        recent_hash,
+                    );
+                    signatures.extend(send_or_simulate_txn(
+                        &rpc_client,
+                        txn,
+                        &signers,
+                        &send_config,
+                    )?);
+                }
+            }
+            print_output(
+                output,
+                &CloseEmptyTokenAccountsOutput {
+                    signatures: signatures
+                        .iter()
+                        .map(|signature| signature.to_string())
+                        .collect(),
+                    dry_run,
+                    accounts: candidates
+                        .iter()
+                        .map(|(account, _, token_account)| CloseTokenAccountOutput {
+                            account: account.to_string(),
+                            mint: token_account.mint.clone(),
+                            amount: token_account.token_amount.amount.clone(),
+                            native: token_account.is_native,
+                        })
+                        .collect(),
+                },
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cfg_reports_every_missing_field() {
+        let key = Pubkey::new_unique().to_string();
+        let mut config = Ini::new();
+        config
+            .read(format!(
+                "[Global]\n\
+                 http_url = http://127.0.0.1:8899\n\
+                 ws_url = ws://127.0.0.1:8900\n\
+                 payer_path = payer.json\n\
+                 admin_path = admin.json\n\
+                 withdrawer_path = withdrawer.json\n\
+                 raydium_program = {key}\n\
+                 pnl_owner =\n\
+                 admin_key = {key}\n\
+                 [Withdraw]\n\
+                 amm_pool = {key}\n\
+                 amm_open_orders = {key}\n\
+                 amm_coin_vault = {key}\n\
+                 amm_pc_vault = {key}\n\
+                 amm_target_orders = {key}\n\
+                 coin_mint = not_a_pubkey\n\
+                 pc_mint = {key}\n"
+            ))
+            .unwrap();
+
+        let err = parse_cfg(&config).err().unwrap().to_string();
+        assert!(err.contains("Global.pnl_owner is missing or empty"));
+        assert!(err.contains("Global.withdrawer is missing or empty"));
+        assert!(err.contains("coin_mint is not a valid pubkey"));
+        assert!(!err.contains("admin_key"));
+    }
+
+    #[test]
+    fn test_output_json_and_human() {
+        let pool = Pubkey::new_unique().to_string();
+        let simulated = SetPoolStatusOutput {
+            signature: None,
+            amm_pool: pool.clone(),
+            status: 1,
+        };
+        assert_eq!(
+            serde_json::to_value(&simulated).unwrap(),
+            serde_json::json!({"signature": null, "amm_pool": pool, "status": 1})
+        );
+        assert_eq!(simulated.to_string(), "");
+
+        let sent = SetPoolStatusOutput {
+            signature: Some(Signature::default().to_string()),
+            ..simulated
+        };
+        assert_eq!(sent.to_string(), Signature::default().to_string());
+    }
+
+    #[test]
+    fn test_swap_quote() {
+        let (expected, minimum) = swap_quote(1_000, 1_000_000, 2_000_000, 25, 10_000, 1.0).unwrap();
+        assert_eq!(
+            expected,
+            makidex_amm::math::get_amount_out(1_000, 1_000_000, 2_000_000, 25, 10_000).unwrap()
+        );
+        assert_eq!(minimum, expected * 99 / 100);
+        assert_eq!(
+            swap_quote(1_000, 1_000_000, 2_000_000, 25, 10_000, 0.0).unwrap(),
+            (expected, expected)
+        );
+        assert_eq!(
+            swap_quote(1_000, 1_000_000, 2_000_000, 25, 10_000, 100.0).unwrap(),
+            (expected, 0)
+        );
+        assert!(swap_quote(1_000, 1_000_000, 2_000_000, 25, 10_000, 100.5).is_err());
+        // an empty side or a dust reserve cannot fill the trade
+        assert!(swap_quote(1_000, 1_000_000, 0, 25, 10_000, 1.0).is_err());
+        assert!(swap_quote(1, 1_000_000, 1, 25, 10_000, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_deposit_quote() {
+        // pc is short of the 1:2 ratio, so it is the base side and sets the lp
+        let (base_side, expected, minimum) =
+            deposit_quote(10_000, 10_000, 1_000_000, 2_000_000, 500_000, 1.0).unwrap();
+        assert_eq!(base_side, 1);
+        assert_eq!(expected, 2_500);
+        assert_eq!(minimum, expected * 99 / 100);
+        let (base_side, expected, _) =
+            deposit_quote(10_000, 30_000, 1_000_000, 2_000_000, 500_000, 1.0).unwrap();
+        assert_eq!(base_side, 0);
+        assert_eq!(expected, 5_000);
+        assert!(deposit_quote(10_000, 20_000, 1_000_000, 2_000_000, 500_000, 100.5).is_err());
+        // nothing to deposit next to, or too little to mint
+        assert!(deposit_quote(10_000, 20_000, 1_000_000, 2_000_000, 0, 1.0).is_err());
+        assert!(deposit_quote(1, 1, 1_000_000, 2_000_000, 500_000, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_deposit_amounts() {
+        // coin is short of the 1:2 ratio, the pc it needs is taken and the rest left over
+        assert_eq!(
+            deposit_amounts(10_000, 30_000, 1_000_000, 2_000_000, 500_000).unwrap(),
+            (0, 10_000, 20_000, 5_000)
+        );
+        assert_eq!(
+            deposit_amounts(10_000, 10_000, 1_000_000, 2_000_000, 500_000).unwrap(),
+            (1, 5_000, 10_000, 2_500)
+        );
+        // the matching side is rounded up, as the program charges it
+        assert_eq!(
+            deposit_amounts(10, 100, 1_000_003, 2_000_011, 1_000_000).unwrap(),
+            (0, 10, 21, 9)
+        );
+        // the lp matches the deposit quote of the same budget
+        let (_, _, _, lp_amount) =
+            deposit_amounts(12_345, 67_890, 1_000_000, 2_000_000, 500_000).unwrap();
+        let (_, expected, _) =
+            deposit_quote(12_345, 67_890, 1_000_000, 2_000_000, 500_000, 0.0).unwrap();
+        assert_eq!(lp_amount, expected);
+        assert!(deposit_amounts(10_000, 20_000, 1_000_000, 0, 500_000).is_err());
+        assert!(deposit_amounts(10_000, 20_000, 1_000_000, 2_000_000, 0).is_err());
+        assert!(deposit_amounts(1, 1, 1_000_000, 2_000_000, 500_000).is_err());
+    }
+
+    #[test]
+    fn test_config_mismatches() {
+        let (pnl_owner, admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut amm_config = AmmConfig::default();
+        amm_config.pnl_owner = pnl_owner;
+        amm_config.admin = admin;
+        assert!(config_mismatches(&amm_config, &pnl_owner, &admin).is_empty());
+
+        let other = Pubkey::new_unique();
+        assert_eq!(config_mismatches(&amm_config, &other, &admin).len(), 1);
+        assert_eq!(config_mismatches(&amm_config, &other, &other).len(), 2);
+
+        // an unset admin is only what the built-in amm owners intend
+        amm_config.admin = Pubkey::default();
+        let amm_owner = makidex_amm::processor::config_feature::amm_owner::id();
+        assert!(config_mismatches(&amm_config, &pnl_owner, &amm_owner).is_empty());
+        assert_eq!(config_mismatches(&amm_config, &pnl_owner, &admin).len(), 1);
+    }
+
+    #[test]
+    fn test_compute_min_out() {
+        // 1_000 in against 1_000_000 / 2_000_000 at 25 bps: a fee of 3 leaves 997 in,
+        // which pays 1_992 out, 50 bps below that is 1_982.04
+        assert_eq!(
+            compute_min_out(1_000, 1_000_000, 2_000_000, 25, 10_000, 0).unwrap(),
+            1_992
+        );
+        assert_eq!(
+            compute_min_out(1_000, 1_000_000, 2_000_000, 25, 10_000, 50).unwrap(),
+            1_982
+        );
+        // the swap subcommand sets the same guard
+        let (_, minimum) = swap_quote(1_000, 1_000_000, 2_000_000, 25, 10_000, 0.5).unwrap();
+        assert_eq!(minimum, 1_982);
+        assert_eq!(
+            compute_min_out(1_000, 1_000_000, 2_000_000, 25, 10_000, 10_000).unwrap(),
+            0
+        );
+        assert!(compute_min_out(1_000, 1_000_000, 2_000_000, 25, 10_000, 10_001).is_err());
+        assert!(compute_min_out(1_000, 1_000_000, 0, 25, 10_000, 50).is_err());
+    }
+
+    #[test]
+    fn test_send_retry_policy() {
+        use solana_client::client_error::{ClientError, ClientErrorKind};
+        use solana_sdk::transaction::TransactionError;
+        use std::time::Duration;
+
+        let base = Duration::from_millis(500);
+        assert_eq!(retry_delay(base, 1), base);
+        assert_eq!(retry_delay(base, 2), Duration::from_secs(1));
+        assert_eq!(retry_delay(base, 3), Duration::from_secs(2));
+        // the backoff is capped instead of overflowing
+        assert_eq!(retry_delay(base, 100), retry_delay(base, 17));
+
+        let expired: ClientError =
+            ClientErrorKind::TransactionError(TransactionError::BlockhashNotFound).into();
+        assert!(is_retryable(&expired));
+        let failed: ClientError =
+            ClientErrorKind::TransactionError(TransactionError::InsufficientFundsForFee).into();
+        assert!(!is_retryable(&failed));
+        assert!(!is_retryable(
+            &ClientErrorKind::Custom("bad request".to_string()).into()
+        ));
+    }
+
+    #[test]
+    fn test_print_simulation_fails_with_its_error() {
+        use solana_sdk::transaction::TransactionError;
+
+        let simulation = |err| RpcSimulateTransactionResult {
+            err,
+            logs: Some(vec!["Program log: swap".to_string()]),
+            accounts: None,
+            units_consumed: Some(30_000),
+            return_data: None,
+        };
+        assert!(print_simulation(simulation(None)).is_ok());
+        let err = print_simulation(simulation(Some(TransactionError::AccountNotFound)))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("AccountNotFound"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_commitment() {
+        assert_eq!(
+            parse_commitment("processed").unwrap(),
+            CommitmentConfig::processed()
+        );
+        assert_eq!(
+            parse_commitment("confirmed").unwrap(),
+            CommitmentConfig::confirmed()
+        );
+        assert_eq!(
+            parse_commitment("finalized").unwrap(),
+            CommitmentConfig::finalized()
+        );
+        assert!(parse_commitment("max").is_err());
+        assert!(parse_commitment("Finalized").is_err());
+
+        let pool = Pubkey::new_unique().to_string();
+        let opts = Opts::parse_from([
+            "client",
+            "--commitment",
+            "finalized",
+            "show-pool",
+            pool.as_str(),
+        ]);
+        assert_eq!(opts.commitment, CommitmentConfig::finalized());
+        let opts = Opts::parse_from(["client", "show-pool", pool.as_str()]);
+        assert_eq!(opts.commitment, CommitmentConfig::confirmed());
+    }
+
+    #[test]
+    fn test_preflight_on_by_default() {
+        let pool = Pubkey::new_unique().to_string();
+        let opts = Opts::parse_from(["client", "show-pool", pool.as_str()]);
+        assert!(!opts.skip_preflight);
+        let opts = Opts::parse_from(["client", "show-pool", pool.as_str(), "--skip-preflight"]);
+        assert!(opts.skip_preflight);
+    }
+
+    #[test]
+    fn test_parse_ui_amount() {
+        assert_eq!(parse_ui_amount("1.234567", 6).unwrap(), 1_234_567);
+        assert_eq!(parse_ui_amount("1.5", 6).unwrap(), 1_500_000);
+        assert_eq!(parse_ui_amount("2", 6).unwrap(), 2_000_000);
+        assert_eq!(parse_ui_amount(".5", 1).unwrap(), 5);
+        assert_eq!(parse_ui_amount("7", 0).unwrap(), 7);
+        assert!(parse_ui_amount("1.2345678", 6)
+            .unwrap_err()
+            .to_string()
+            .contains("more than 6 decimal places"));
+        assert!(parse_ui_amount("18446744073710", 6)
+            .unwrap_err()
+            .to_string()
+            .contains("overflows u64"));
+        for invalid in ["", ".", "1.2.3", "-1", "1e6", "abc"] {
+            assert!(parse_ui_amount(invalid, 6).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_load_keypair_from_env() {
+        let keypair = Keypair::new();
+        std::env::set_var(
+            "MAKIDEX_TEST_KEY_BASE58",
+            bs58::encode(keypair.to_bytes()).into_string(),
+        );
+        std::env::set_var(
+            "MAKIDEX_TEST_KEY_JSON",
+            format!(
+                " {}\n",
+                serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap()
+            ),
+        );
+        for path in ["env:MAKIDEX_TEST_KEY_BASE58", "env:MAKIDEX_TEST_KEY_JSON"] {
+            assert_eq!(load_keypair(path).unwrap().to_bytes(), keypair.to_bytes());
+        }
+
+        std::env::set_var("MAKIDEX_TEST_KEY_SHORT", "[1,2,3]");
+        std::env::set_var("MAKIDEX_TEST_KEY_GARBAGE", "not-a-key-0OIl");
+        for path in [
+            "env:MAKIDEX_TEST_KEY_UNSET",
+            "env:MAKIDEX_TEST_KEY_SHORT",
+            "env:MAKIDEX_TEST_KEY_GARBAGE",
+        ] {
+            assert!(load_keypair(path).is_err(), "{}", path);
+        }
+        // the secret never ends up in the error
+        let err = load_keypair("env:MAKIDEX_TEST_KEY_GARBAGE").unwrap_err();
+        assert!(!err.to_string().contains("not-a-key"));
+    }
+
+    #[test]
+    fn test_load_keypair_from_path() {
+        let keypair = Keypair::new();
+        let path = std::env::temp_dir().join(format!("makidex-test-{}.json", keypair.pubkey()));
+        let path = path.to_str().unwrap();
+        write_keypair_file(&keypair, path).unwrap();
+        assert_eq!(load_keypair(path).unwrap().to_bytes(), keypair.to_bytes());
+        std::fs::remove_file(path).unwrap();
+        assert!(load_keypair(path).is_err());
+    }
+
+    #[test]
+    fn test_ui_to_base_units() {
+        assert_eq!(ui_to_base_units(1_000, 6).unwrap(), 1_000_000_000);
+        assert_eq!(ui_to_base_units(7, 0).unwrap(), 7);
+        assert_eq!(ui_to_base_units(0, 9).unwrap(), 0);
+        assert!(ui_to_base_units(u64::MAX, 1).is_err());
+        assert!(ui_to_base_units(1, 20).is_err());
+    }
+
+    #[test]
+    fn test_lot_size() {
+        assert_eq!(lot_size(9, 3).unwrap(), 1_000_000);
+        assert_eq!(lot_size(6, 6).unwrap(), 1);
+        assert!(lot_size(2, 3).is_err());
+        assert!(lot_size(u8::MAX, 0).is_err());
+    }
+
+    #[test]
+    fn test_init_price_bound() {
+        assert_eq!(init_price_bound(None, 100).unwrap(), None);
+        assert_eq!(
+            init_price_bound(Some(25.5), 50).unwrap(),
+            Some(InitPriceBound {
+                expected_price: 25_500_000_000_000,
+                price_tolerance_bps: 50,
+            })
+        );
+        for invalid in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(init_price_bound(Some(invalid), 100).is_err());
+        }
+    }
+
+    #[test]
+    fn test_lp_mint_extension() {
+        let amm_authority = Pubkey::new_unique();
+        let metadata_address = Some(Pubkey::new_unique());
+        assert_eq!(lp_mint_extension(&[], &amm_authority).unwrap(), None);
+        for authority in [None, Some(amm_authority)] {
+            assert_eq!(
+                lp_mint_extension(
+                    &[ExtensionInitializationParams::MetadataPointer {
+                        authority,
+                        metadata_address,
+                    }],
+                    &amm_authority
+                )
+                .unwrap(),
+                Some(LpMintExtension::MetadataPointer {
+                    authority,
+                    metadata_address,
+                })
+            );
+        }
+        assert!(lp_mint_extension(
+            &[ExtensionInitializationParams::MetadataPointer {
+                authority: Some(Pubkey::new_unique()),
+                metadata_address,
+            }],
+            &amm_authority
+        )
+        .is_err());
+        assert!(lp_mint_extension(
+            &[ExtensionInitializationParams::MintCloseAuthority {
+                close_authority: Some(amm_authority),
+            }],
+            &amm_authority
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_compile_versioned_txn_with_lookup_table() {
+        use solana_sdk::{
+            address_lookup_table_account::AddressLookupTableAccount, hash::Hash,
+            message::VersionedMessage,
+        };
+
+        let payer = Keypair::new();
+        let keys = (0..15).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let swap = swap_base_in(
+            &Pubkey::new_unique(),
+            &keys[0],
+            &keys[1],
+            &keys[2],
+            &keys[3],
+            &keys[4],
+            &keys[5],
+            &keys[6],
+            &keys[7],
+            &keys[8],
+            &keys[9],
+            &keys[10],
+            &keys[11],
+            &keys[12],
+            &keys[13],
+            &keys[14],
+            &payer.pubkey(),
+            1_000,
+            1,
+            u64::MAX,
+            Some(TradeDirection::CoinToPc),
+        )
+        .unwrap();
+        let instrs = vec![swap];
+        let legacy = Transaction::new_signed_with_payer(
+            &instrs,
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: keys.clone(),
+        };
+        let txn = compile_versioned_txn(
+            &payer.pubkey(),
+            &instrs,
+            &[table],
+            Hash::default(),
+            &[&payer],
+        )
+        .unwrap();
+        assert!(txn.verify_with_results().iter().all(|verified| *verified));
+        let message = match &txn.message {
+            VersionedMessage::V0(message) => message,
+            VersionedMessage::Legacy(_) => panic!("expected a v0 message"),
+        };
+        // only the payer and the programs stay in the static keys
+        assert_eq!(message.address_table_lookups.len(), 1);
+        assert!(!message.account_keys.contains(&keys[0]));
+        assert!(message.account_keys.contains(&payer.pubkey()));
+        assert!(message.account_keys.len() < legacy.message.account_keys.len());
+        assert!(serialize(&txn).unwrap().len() < serialize(&legacy).unwrap().len());
+
+        // a table without any of the accounts compresses nothing
+        let unrelated = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: vec![Pubkey::new_unique()],
+        };
+        let txn = compile_versioned_txn(
+            &payer.pubkey(),
+            &instrs,
+            &[unrelated],
+            Hash::default(),
+            &[&payer],
+        )
+        .unwrap();
+        match &txn.message {
+            VersionedMessage::V0(message) => {
+                assert!(message.address_table_lookups.is_empty());
+                assert_eq!(
+                    message.account_keys.len(),
+                    legacy.message.account_keys.len()
+                );
+            }
+            VersionedMessage::Legacy(_) => panic!("expected a v0 message"),
+        }
+    }
+
+    #[test]
+    fn test_check_program() {
+        let program_id = Pubkey::new_unique();
+        let program = solana_sdk::account::Account {
+            executable: true,
+            ..Default::default()
+        };
+        assert!(check_program(&program_id, &program_id, Some(&program)).is_empty());
+        // another deployment of the program
+        let warnings = check_program(&program_id, &Pubkey::new_unique(), Some(&program));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("the client was built for"));
+
+        // a missing or non executable account
+        let data_account = solana_sdk::account::Account::default();
+        for program_account in [None, Some(&data_account)] {
+            let warnings = check_program(&program_id, &program_id, program_account);
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].contains("is not a deployed program"));
+        }
+    }
+
+    #[test]
+    fn test_close_candidate() {
+        let owner = Pubkey::new_unique();
+        let token_account = |amount: &str, native: bool, state: &str, close: Option<Pubkey>| {
+            let parsed = serde_json::json!({
+                "type": "account",
+                "info": {
+                    "mint": Pubkey::new_unique().to_string(),
+                    "owner": owner.to_string(),
+                    "tokenAmount": {
+                        "uiAmount": null,
+                        "decimals": 9,
+                        "amount": amount,
+                        "uiAmountString": amount,
+                    },
+                    "state": state,
+                    "isNative": native,
+                    "closeAuthority": close.map(|close| close.to_string()),
+                },
+            });
+            match serde_json::from_value(parsed).unwrap() {
+                TokenAccountType::Account(token_account) => token_account,
+                _ => panic!("expected a token account"),
+            }
+        };
+
+        assert!(close_candidate(
+            &token_account("0", false, "initialized", None),
+            &owner
+        ));
+        assert!(!close_candidate(
+            &token_account("1", false, "initialized", None),
+            &owner
+        ));
+        // wrapped sol is unwrapped to the owner on close
+        assert!(close_candidate(
+            &token_account("5000", true, "initialized", None),
+            &owner
+        ));
+        assert!(!close_candidate(
+            &token_account("0", false, "frozen", None),
+            &owner
+        ));
+        assert!(close_candidate(
+            &token_account("0", false, "initialized", Some(owner)),
+            &owner
+        ));
+        assert!(!close_candidate(
+            &token_account("0", false, "initialized", Some(Pubkey::new_unique())),
+            &owner
+        ));
+    }
+
+    #[test]
+    fn test_pool_reserves() {
+        let mut amm = AmmInfo::load_from_bytes(&vec![0u8; size_of::<AmmInfo>()]).unwrap();
+        let mut data = vec![0u8; 5 + size_of::<serum_dex::state::OpenOrders>() + 7];
+        let mut open_orders = open_orders_from_bytes(&data).unwrap();
+        assert_eq!(
+            pool_reserves(&amm, 1_000, 2_000, &open_orders).unwrap(),
+            (1_000, 2_000)
+        );
+        // the funds on the order book count as the program counts them
+        open_orders.native_coin_total = 300;
+        open_orders.native_pc_total = 400;
+        data[5..data.len() - 7].copy_from_slice(bytemuck::bytes_of(&open_orders));
+        let open_orders = open_orders_from_bytes(&data).unwrap();
+        assert_eq!(
+            pool_reserves(&amm, 1_000, 2_000, &open_orders).unwrap(),
+            (1_300, 2_400)
+        );
+        amm.state_data.need_take_pnl_coin = 10;
+        amm.state_data.need_take_pnl_pc = 2_400;
+        assert_eq!(
+            pool_reserves(&amm, 1_000, 2_000, &open_orders).unwrap(),
+            (1_290, 0)
+        );
+        amm.state_data.need_take_pnl_pc = 2_401;
+        assert!(pool_reserves(&amm, 1_000, 2_000, &open_orders).is_err());
+        assert!(open_orders_from_bytes(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn test_watched_pool_update() {
+        let pool = Pubkey::new_unique();
+        let mut amm = AmmInfo::load_from_bytes(&vec![0u8; size_of::<AmmInfo>()]).unwrap();
+        amm.coin_vault = Pubkey::new_unique();
+        amm.pc_vault = Pubkey::new_unique();
+        amm.open_orders = Pubkey::new_unique();
+        let open_orders_data = vec![0u8; 5 + size_of::<serum_dex::state::OpenOrders>() + 7];
+        let mut watched = WatchedPool {
+            amm,
+            coin_vault_amount: 0,
+            pc_vault_amount: 0,
+            open_orders: open_orders_from_bytes(&open_orders_data).unwrap(),
+        };
+        let keys = WatchedPool::keys(&pool, &amm);
+        assert_eq!(keys, [pool, amm.coin_vault, amm.pc_vault, amm.open_orders]);
+
+        for (key, amount) in [(amm.coin_vault, 1_000), (amm.pc_vault, 2_000)] {
+            let mut data = vec![0u8; Account::LEN];
+            Account::pack(
+                Account {
+                    amount,
+                    state: AccountState::Initialized,
+                    ..Account::default()
+                },
+                &mut data,
+            )
+            .unwrap();
+            watched.update(&pool, &key, &data).unwrap();
+        }
+        assert_eq!(watched.reserves().unwrap(), (1_000, 2_000));
+
+        let mut open_orders = watched.open_orders;
+        open_orders.native_coin_total = 300;
+        let mut data = open_orders_data.clone();
+        data[5..data.len() - 7].copy_from_slice(bytemuck::bytes_of(&open_orders));
+        watched.update(&pool, &amm.open_orders, &data).unwrap();
+        assert_eq!(watched.reserves().unwrap(), (1_300, 2_000));
+
+        amm.state_data.need_take_pnl_coin = 10;
+        watched.update(&pool, &pool, &amm.pack()).unwrap();
+        assert_eq!(watched.reserves().unwrap(), (1_290, 2_000));
+        assert!(watched.update(&pool, &pool, &[0u8; 8]).is_err());
+    }
 }
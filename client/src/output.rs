@@ -0,0 +1,599 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+
+/// How subcommands report their result on stdout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// free-form text for people
+    Human,
+    /// one json object per command for scripts
+    Json,
+}
+
+/// Print a command result, nothing is printed in human mode for an empty rendering.
+pub fn print_output<T: Serialize + fmt::Display>(format: OutputFormat, output: &T) -> Result<()> {
+    match format {
+        OutputFormat::Human => {
+            let text = output.to_string();
+            if !text.is_empty() {
+                println!("{}", text);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string(output)?),
+    }
+    Ok(())
+}
+
+/// The transaction signature, None when the transaction was only simulated.
+fn write_signature(f: &mut fmt::Formatter, signature: &Option<String>) -> fmt::Result {
+    match signature {
+        Some(signature) => write!(f, "{}", signature),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateConfigAccountOutput {
+    pub signature: Option<String>,
+    pub amm_config: String,
+}
+
+impl fmt::Display for CreateConfigAccountOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExistingConfigOutput {
+    pub amm_config: String,
+    pub pnl_owner: String,
+    pub cancel_owner: String,
+    pub admin: String,
+    pub pending_admin: String,
+    pub fee_recipient: String,
+    pub trade_fee_numerator: u64,
+    pub trade_fee_denominator: u64,
+    pub protocol_fee_numerator: u64,
+    pub protocol_fee_denominator: u64,
+    pub create_pool_fee: u64,
+}
+
+impl fmt::Display for ExistingConfigOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<16} {} (already exists)",
+            "amm_config", self.amm_config
+        )?;
+        for (name, address) in [
+            ("pnl_owner", &self.pnl_owner),
+            ("cancel_owner", &self.cancel_owner),
+            ("admin", &self.admin),
+            ("pending_admin", &self.pending_admin),
+            ("fee_recipient", &self.fee_recipient),
+        ] {
+            writeln!(f, "{:<16} {}", name, address)?;
+        }
+        writeln!(
+            f,
+            "{:<16} {}/{}",
+            "trade_fee", self.trade_fee_numerator, self.trade_fee_denominator
+        )?;
+        writeln!(
+            f,
+            "{:<16} {}/{}",
+            "protocol_fee", self.protocol_fee_numerator, self.protocol_fee_denominator
+        )?;
+        write!(f, "{:<16} {}", "create_pool_fee", self.create_pool_fee)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnerWithdrawPoolOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+}
+
+impl fmt::Display for OwnerWithdrawPoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateConfigFeesOutput {
+    pub signature: Option<String>,
+    pub amm_config: String,
+}
+
+impl fmt::Display for UpdateConfigFeesOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncPoolFeesOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+}
+
+impl fmt::Display for SyncPoolFeesOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitializePoolOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub amm_authority: String,
+    pub amm_open_orders: String,
+    pub amm_lp_mint: String,
+    pub amm_coin_vault: String,
+    pub amm_pc_vault: String,
+    pub amm_target_orders: String,
+    pub user_token_lp: String,
+}
+
+impl fmt::Display for InitializePoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(signature) = &self.signature {
+            writeln!(f, "{}", signature)?;
+        }
+        write!(f, "amm_pool: {}", self.amm_pool)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTestPoolOutput {
+    pub signatures: Vec<String>,
+    pub coin_mint: String,
+    pub pc_mint: String,
+    pub user_token_coin: String,
+    pub user_token_pc: String,
+    pub market: String,
+    pub request_queue: String,
+    pub event_queue: String,
+    pub bids: String,
+    pub asks: String,
+    pub market_coin_vault: String,
+    pub market_pc_vault: String,
+    pub vault_signer: String,
+    pub pool: InitializePoolOutput,
+}
+
+impl fmt::Display for CreateTestPoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for signature in self.signatures.iter().chain(&self.pool.signature) {
+            writeln!(f, "{}", signature)?;
+        }
+        for (name, address) in [
+            ("coin_mint", &self.coin_mint),
+            ("pc_mint", &self.pc_mint),
+            ("user_token_coin", &self.user_token_coin),
+            ("user_token_pc", &self.user_token_pc),
+            ("market", &self.market),
+            ("request_queue", &self.request_queue),
+            ("event_queue", &self.event_queue),
+            ("bids", &self.bids),
+            ("asks", &self.asks),
+            ("market_coin_vault", &self.market_coin_vault),
+            ("market_pc_vault", &self.market_pc_vault),
+            ("vault_signer", &self.vault_signer),
+            ("amm_pool", &self.pool.amm_pool),
+            ("amm_authority", &self.pool.amm_authority),
+            ("amm_open_orders", &self.pool.amm_open_orders),
+            ("amm_target_orders", &self.pool.amm_target_orders),
+            ("amm_coin_vault", &self.pool.amm_coin_vault),
+            ("amm_pc_vault", &self.pool.amm_pc_vault),
+            ("amm_lp_mint", &self.pool.amm_lp_mint),
+        ] {
+            writeln!(f, "{:<20} {}", name, address)?;
+        }
+        write!(f, "{:<20} {}", "user_token_lp", self.pool.user_token_lp)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DerivePdasOutput {
+    pub market: String,
+    pub amm_pool: String,
+    pub amm_authority: String,
+    pub nonce: u8,
+    pub amm_open_orders: String,
+    pub amm_target_orders: String,
+    pub amm_coin_vault: String,
+    pub amm_pc_vault: String,
+    pub amm_lp_mint: String,
+    pub amm_config: String,
+}
+
+impl fmt::Display for DerivePdasOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<20} {}", "market", self.market)?;
+        writeln!(f, "{:<20} {}", "amm_pool", self.amm_pool)?;
+        writeln!(
+            f,
+            "{:<20} {} (nonce {})",
+            "amm_authority", self.amm_authority, self.nonce
+        )?;
+        writeln!(f, "{:<20} {}", "amm_open_orders", self.amm_open_orders)?;
+        writeln!(f, "{:<20} {}", "amm_target_orders", self.amm_target_orders)?;
+        writeln!(f, "{:<20} {}", "amm_coin_vault", self.amm_coin_vault)?;
+        writeln!(f, "{:<20} {}", "amm_pc_vault", self.amm_pc_vault)?;
+        writeln!(f, "{:<20} {}", "amm_lp_mint", self.amm_lp_mint)?;
+        write!(f, "{:<20} {}", "amm_config", self.amm_config)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetPnlOwnerOutput {
+    pub signature: Option<String>,
+    pub amm_config: String,
+    pub new_pnl_owner: String,
+}
+
+impl fmt::Display for SetPnlOwnerOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetFeeRecipientOutput {
+    pub signature: Option<String>,
+    pub amm_config: String,
+    pub fee_recipient: String,
+}
+
+impl fmt::Display for SetFeeRecipientOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetSwapDiscountOutput {
+    pub signature: Option<String>,
+    pub amm_config: String,
+    pub discount_mint: String,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+}
+
+impl fmt::Display for SetSwapDiscountOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShowPoolOutput {
+    pub pool: String,
+    pub status: u64,
+    pub coin_mint: String,
+    pub pc_mint: String,
+    pub coin_reserve: u64,
+    pub ui_coin_reserve: f64,
+    pub pc_reserve: u64,
+    pub ui_pc_reserve: f64,
+    pub lp_supply: u64,
+    pub payer_lp: u64,
+    pub spot_price: Option<f64>,
+    pub swap_fee: (u64, u64),
+    pub trade_fee: (u64, u64),
+    pub protocol_fee: (u64, u64),
+    pub pnl: (u64, u64),
+}
+
+impl fmt::Display for ShowPoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<20} {}", "pool", self.pool)?;
+        writeln!(f, "{:<20} {}", "status", self.status)?;
+        writeln!(f, "{:<20} {}", "coin_mint", self.coin_mint)?;
+        writeln!(f, "{:<20} {}", "pc_mint", self.pc_mint)?;
+        writeln!(
+            f,
+            "{:<20} {} ({})",
+            "coin_reserve", self.coin_reserve, self.ui_coin_reserve
+        )?;
+        writeln!(
+            f,
+            "{:<20} {} ({})",
+            "pc_reserve", self.pc_reserve, self.ui_pc_reserve
+        )?;
+        writeln!(f, "{:<20} {}", "lp_supply", self.lp_supply)?;
+        writeln!(f, "{:<20} {}", "payer_lp", self.payer_lp)?;
+        match self.spot_price {
+            Some(price) => writeln!(f, "{:<20} {}", "spot_price", price)?,
+            None => writeln!(f, "{:<20} -", "spot_price")?,
+        }
+        writeln!(
+            f,
+            "{:<20} {}/{}",
+            "swap_fee", self.swap_fee.0, self.swap_fee.1
+        )?;
+        writeln!(
+            f,
+            "{:<20} {}/{}",
+            "trade_fee", self.trade_fee.0, self.trade_fee.1
+        )?;
+        writeln!(
+            f,
+            "{:<20} {}/{}",
+            "protocol_fee", self.protocol_fee.0, self.protocol_fee.1
+        )?;
+        write!(f, "{:<20} {}/{}", "pnl", self.pnl.0, self.pnl.1)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchPoolOutput {
+    pub slot: u64,
+    pub pool: String,
+    pub status: u64,
+    pub coin_reserve: u64,
+    pub ui_coin_reserve: f64,
+    pub pc_reserve: u64,
+    pub ui_pc_reserve: f64,
+    pub lp_supply: u64,
+    pub spot_price: Option<f64>,
+}
+
+impl fmt::Display for WatchPoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "slot {} coin_reserve {} ({}) pc_reserve {} ({}) lp_supply {} spot_price ",
+            self.slot,
+            self.coin_reserve,
+            self.ui_coin_reserve,
+            self.pc_reserve,
+            self.ui_pc_reserve,
+            self.lp_supply
+        )?;
+        match self.spot_price {
+            Some(price) => write!(f, "{}", price),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectProtocolFeesOutput {
+    pub signature: Option<String>,
+    pub owner_token_coin: String,
+    pub owner_token_pc: String,
+    /// owner balances after the collection, None when simulated
+    pub owner_coin_balance: Option<u64>,
+    pub owner_pc_balance: Option<u64>,
+}
+
+impl fmt::Display for CollectProtocolFeesOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetPoolStatusOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub status: u64,
+}
+
+impl fmt::Display for SetPoolStatusOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClosePoolOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub recipient: String,
+}
+
+impl fmt::Display for ClosePoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetReserveCapsOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub max_coin_reserve: u64,
+    pub max_pc_reserve: u64,
+}
+
+impl fmt::Display for SetReserveCapsOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetDynamicFeeOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub enabled: bool,
+    pub base_fee_numerator: u64,
+    pub max_fee_numerator: u64,
+    pub fee_denominator: u64,
+    pub max_fee_deviation_bps: u64,
+}
+
+impl fmt::Display for SetDynamicFeeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetMinTradeAmountOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub min_coin_amount: u64,
+    pub min_pc_amount: u64,
+}
+
+impl fmt::Display for SetMinTradeAmountOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DepositOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub max_coin_amount: u64,
+    pub max_pc_amount: u64,
+    pub base_side: u64,
+    pub expected_lp_amount: u64,
+    pub minimum_lp_amount: u64,
+}
+
+impl fmt::Display for DepositOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuoteDepositOutput {
+    pub amm_pool: String,
+    pub base_side: u64,
+    pub coin_amount: u64,
+    pub pc_amount: u64,
+    pub lp_amount: u64,
+    pub coin_leftover: u64,
+    pub pc_leftover: u64,
+}
+
+impl fmt::Display for QuoteDepositOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<14} {}", "coin_amount", self.coin_amount)?;
+        writeln!(f, "{:<14} {}", "pc_amount", self.pc_amount)?;
+        writeln!(f, "{:<14} {}", "lp_amount", self.lp_amount)?;
+        writeln!(f, "{:<14} {}", "coin_leftover", self.coin_leftover)?;
+        write!(f, "{:<14} {}", "pc_leftover", self.pc_leftover)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetSwapPermissionedOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub permissioned: bool,
+}
+
+impl fmt::Display for SetSwapPermissionedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetSwapGuardOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub guarded: bool,
+}
+
+impl fmt::Display for SetSwapGuardOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetPoolAdminOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub pool_admin: String,
+}
+
+impl fmt::Display for SetPoolAdminOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateSwapWhitelistOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub swap_whitelist: String,
+    pub wallet: String,
+    pub added: bool,
+}
+
+impl fmt::Display for UpdateSwapWhitelistOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SwapOutput {
+    pub signature: Option<String>,
+    pub amm_pool: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub recipient: String,
+    pub amount_in: u64,
+    pub expected_amount_out: u64,
+    pub minimum_amount_out: u64,
+}
+
+impl fmt::Display for SwapOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_signature(f, &self.signature)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CloseTokenAccountOutput {
+    pub account: String,
+    pub mint: String,
+    /// balance in base units, only non zero for wrapped sol which is unwrapped on close
+    pub amount: String,
+    pub native: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CloseEmptyTokenAccountsOutput {
+    pub signatures: Vec<String>,
+    pub dry_run: bool,
+    pub accounts: Vec<CloseTokenAccountOutput>,
+}
+
+impl fmt::Display for CloseEmptyTokenAccountsOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for signature in &self.signatures {
+            writeln!(f, "{}", signature)?;
+        }
+        let verb = if self.dry_run {
+            "would close"
+        } else {
+            "closed"
+        };
+        for account in &self.accounts {
+            write!(f, "{} {} mint {}", verb, account.account, account.mint)?;
+            if account.native && account.amount != "0" {
+                write!(f, " unwrapping {} lamports", account.amount)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{} {} token accounts", verb, self.accounts.len())
+    }
+}
@@ -200,17 +200,87 @@ pub enum AmmError {
 
     // 55
     /// RepeatCreateConfigAccount
-    #[error("Repeat create config account")]
+    #[error("Config account already exists")]
     RepeatCreateConfigAccount,
     /// MarketLotSizeIsTooLarge
     #[error("Market lotSize is too large")]
     MarketLotSizeIsTooLarge,
     /// Init lp amount is too less.
-    #[error("Init lp amount is too less(Because 10**lp_decimals amount lp will be locked)")]
+    #[error(
+        "Init lp amount is too less(Because max(10**lp_decimals, 1000) amount lp will be locked)"
+    )]
     InitLpAmountTooLess,
     /// Unknown Amm Error
     #[error("Unknown Amm Error")]
     UnknownAmmError,
+    /// The requested output is not less than the pool reserve.
+    #[error("Insufficient liquidity in the pool")]
+    InsufficientLiquidity,
+
+    // 60
+    /// The trade would move zero tokens on one side.
+    #[error("Zero trading tokens")]
+    ZeroTradingTokens,
+    /// The curve math failed, usually an overflow.
+    #[error("Calculation failure")]
+    CalculationFailure,
+    /// The pool has been paused by the admin.
+    #[error("Pool is paused")]
+    PoolPaused,
+    /// The account was written by a newer program version.
+    #[error("Unsupported state version")]
+    UnsupportedStateVersion,
+    /// The lp mint can be minted or frozen by someone else than the amm authority.
+    #[error("Invalid lp mint authority")]
+    InvalidLpMintAuthority,
+
+    // 65
+    /// The pool still holds liquidity.
+    #[error("Pool is not empty")]
+    PoolNotEmpty,
+    /// The deposit would grow a reserve above its cap.
+    #[error("Reserve cap exceeded")]
+    ReserveCapExceeded,
+    /// The swapper is not on the whitelist of a permissioned pool.
+    #[error("Swapper is not whitelisted")]
+    NotWhitelisted,
+    /// The lp mint supply exceeds the lp amount tracked by the pool.
+    #[error("Lp supply mismatch")]
+    LpSupplyMismatch,
+    /// The coin and pc mints of a pool are the same.
+    #[error("Coin and pc mints are identical")]
+    IdenticalMints,
+
+    // 70
+    /// The discount token account is not a holding of the discount mint by the swapper.
+    #[error("Invalid discount account")]
+    InvalidDiscountAccount,
+    /// The user token accounts of a swap go the other way than the stated direction.
+    #[error("Trade direction mismatch")]
+    TradeDirectionMismatch,
+    /// The initial amounts of a pool imply a price outside of the requested bound.
+    #[error("Initial price out of bounds")]
+    PriceOutOfBounds,
+    /// A user token account is of another mint or owner than the instruction expects.
+    #[error("Invalid user account")]
+    InvalidUserAccount,
+    /// The instruction works the order book of a pool initialized without open orders.
+    #[error("Pool has no open orders")]
+    NoOpenOrders,
+
+    // 75
+    /// The instruction needs the AmmInfoExtension of a pool not yet grown by MigrateState.
+    #[error("Pool not migrated")]
+    PoolNotMigrated,
+    /// A swap paid in less than the minimum trade amount of the pool.
+    #[error("Trade too small")]
+    TradeTooSmall,
+    /// A swap of a pool with the swap guard follows another swap of it in the transaction.
+    #[error("Pool already swapped in this transaction")]
+    RepeatedSwap,
+    /// A swap of a pool with the swap guard did not pass the instructions sysvar.
+    #[error("Instructions sysvar missing")]
+    InstructionsSysvarMissing,
 }
 
 impl From<AmmError> for ProgramError {
@@ -302,8 +372,28 @@ impl PrintProgramError for AmmError {
             AmmError::InvalidConfigAccount => msg!("Error: InvalidConfigAccount"),
             AmmError::RepeatCreateConfigAccount => msg!("Error: RepeatCreateConfigAccount"),
             AmmError::MarketLotSizeIsTooLarge => msg!("Error: Market lotSize is too large"),
-            AmmError::InitLpAmountTooLess => msg!("Error: Init lp amount is too less(Because 10**lp_decimals amount lp will be locked)"),
+            AmmError::InitLpAmountTooLess => msg!("Error: Init lp amount is too less(Because max(10**lp_decimals, 1000) amount lp will be locked)"),
             AmmError::UnknownAmmError => msg!("Error: UnknownAmmError"),
+            AmmError::InsufficientLiquidity => msg!("Error: Insufficient liquidity in the pool"),
+            AmmError::ZeroTradingTokens => msg!("Error: Zero trading tokens"),
+            AmmError::CalculationFailure => msg!("Error: Calculation failure"),
+            AmmError::PoolPaused => msg!("Error: Pool is paused"),
+            AmmError::UnsupportedStateVersion => msg!("Error: Unsupported state version"),
+            AmmError::InvalidLpMintAuthority => msg!("Error: Invalid lp mint authority"),
+            AmmError::PoolNotEmpty => msg!("Error: Pool is not empty"),
+            AmmError::ReserveCapExceeded => msg!("Error: Reserve cap exceeded"),
+            AmmError::NotWhitelisted => msg!("Error: Swapper is not whitelisted"),
+            AmmError::LpSupplyMismatch => msg!("Error: Lp supply mismatch"),
+            AmmError::IdenticalMints => msg!("Error: Coin and pc mints are identical"),
+            AmmError::InvalidDiscountAccount => msg!("Error: Invalid discount account"),
+            AmmError::TradeDirectionMismatch => msg!("Error: Trade direction mismatch"),
+            AmmError::PriceOutOfBounds => msg!("Error: Initial price out of bounds"),
+            AmmError::InvalidUserAccount => msg!("Error: Invalid user account"),
+            AmmError::NoOpenOrders => msg!("Error: Pool has no open orders"),
+            AmmError::PoolNotMigrated => msg!("Error: Pool not migrated"),
+            AmmError::TradeTooSmall => msg!("Error: Trade too small"),
+            AmmError::RepeatedSwap => msg!("Error: Pool already swapped in this transaction"),
+            AmmError::InstructionsSysvarMissing => msg!("Error: Instructions sysvar missing"),
         }
     }
 }
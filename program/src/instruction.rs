@@ -4,7 +4,7 @@
 #![allow(deprecated)]
 
 use crate::state::{AmmParams, Fees, LastOrderDistance, SimulateParams};
-use arrayref::array_ref;
+use arrayref::{array_ref, array_refs};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
@@ -15,6 +15,7 @@ use solana_program::{
 use std::convert::TryInto;
 use std::mem::size_of;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct InitializeInstruction {
@@ -24,6 +25,7 @@ pub struct InitializeInstruction {
     pub open_time: u64,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct InitializeInstruction2 {
@@ -35,8 +37,31 @@ pub struct InitializeInstruction2 {
     pub init_pc_amount: u64,
     /// init token coin amount
     pub init_coin_amount: u64,
+    /// Token-2022 extension of the lp mint, a spl token lp mint is created when unset
+    pub lp_mint_extension: Option<LpMintExtension>,
+    /// price the initial amounts must imply, unchecked when unset
+    pub price_bound: Option<InitPriceBound>,
+    /// decimals of the lp mint, at most 9, the coin decimals when unset
+    pub lp_decimals: Option<u8>,
+    /// the pool trades on its vaults alone, no open orders account is created and the pool
+    /// never places orders on the market
+    pub without_open_orders: bool,
+    /// amplification of the stable swap curve of a pool of pegged tokens, a constant product
+    /// pool when unset. Only a pool without open orders may take it
+    pub stable_amp: Option<u64>,
 }
 
+/// Guard against initial amounts that are off by orders of magnitude.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct InitPriceBound {
+    /// ui pc per ui coin scaled by 10^SPOT_PRICE_DECIMALS, see `checked_spot_price_fixed`
+    pub expected_price: u128,
+    /// tolerated deviation of the initial price from `expected_price`
+    pub price_tolerance_bps: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct PreInitializeInstruction {
@@ -44,6 +69,7 @@ pub struct PreInitializeInstruction {
     pub nonce: u8,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct MonitorStepInstruction {
@@ -53,6 +79,7 @@ pub struct MonitorStepInstruction {
     pub cancel_order_limit: u16,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct DepositInstruction {
@@ -61,8 +88,33 @@ pub struct DepositInstruction {
     pub max_coin_amount: u64,
     pub max_pc_amount: u64,
     pub base_side: u64,
+    /// Minimum lp amount to mint, prevents the pool ratio shifting before the deposit lands.
+    /// Optional trailing field, older clients that omit it get 0
+    pub minimum_lp_amount: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepositSingleSidedInstruction {
+    pub amount: u64,
+    /// the side `amount` is paid in, part of it is swapped to the other side
+    pub side: TradeDirection,
+    pub minimum_lp_amount: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WithdrawSingleSidedInstruction {
+    pub lp_amount: u64,
+    /// the conversion of the withdrawn amounts, the user is paid in its output side
+    pub out_side: TradeDirection,
+    /// Minimum amount paid out, the user bears the fee and price impact of the conversion
+    pub min_out: u64,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct WithdrawInstruction {
@@ -71,22 +123,26 @@ pub struct WithdrawInstruction {
     pub amount: u64,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct SetParamsInstruction {
     pub param: u8,
     pub value: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
     pub new_pubkey: Option<Pubkey>,
     pub fees: Option<Fees>,
     pub last_order_distance: Option<LastOrderDistance>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct WithdrawSrmInstruction {
     pub amount: u64,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct SwapInstructionBaseIn {
@@ -94,8 +150,14 @@ pub struct SwapInstructionBaseIn {
     pub amount_in: u64,
     /// Minimum amount of DESTINATION token to output, prevents excessive slippage
     pub minimum_amount_out: u64,
+    /// Maximum price impact in basis points, u64::MAX disables the check.
+    /// Optional trailing field, older clients that omit it get u64::MAX
+    pub max_impact_bps: u64,
+    /// Optional trailing byte, swaps that omit it take the direction of the user token accounts
+    pub direction: Option<TradeDirection>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct SwapInstructionBaseOut {
@@ -103,8 +165,45 @@ pub struct SwapInstructionBaseOut {
     pub max_amount_in: u64,
     /// Minimum amount of DESTINATION token to output, prevents excessive slippage
     pub amount_out: u64,
+    /// Optional trailing byte, swaps that omit it take the direction of the user token accounts
+    pub direction: Option<TradeDirection>,
+}
+
+/// Side of the pool a swap takes its input from, the user source and destination token
+/// accounts must be of the matching mints.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TradeDirection {
+    CoinToPc = 0,
+    PcToCoin = 1,
+}
+
+impl TradeDirection {
+    pub fn from_u8(direction: u8) -> Result<Self, ProgramError> {
+        match direction {
+            0 => Ok(TradeDirection::CoinToPc),
+            1 => Ok(TradeDirection::PcToCoin),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+/// Token-2022 extension initialized on the lp mint of a new pool. Only extensions that leave
+/// minting and burning of the lp token to $authority alone can be expressed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LpMintExtension {
+    /// Metadata pointer of the lp token, `authority` must be unset or $authority.
+    MetadataPointer {
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+        authority: Option<Pubkey>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
+        metadata_address: Option<Pubkey>,
+    },
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct SimulateInstruction {
@@ -113,6 +212,7 @@ pub struct SimulateInstruction {
     pub swap_base_out_value: Option<SwapInstructionBaseOut>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct AdminCancelOrdersInstruction {
@@ -120,15 +220,150 @@ pub struct AdminCancelOrdersInstruction {
 }
 
 /// Update config acccount params
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct ConfigArgs {
     pub param: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::option"))]
     pub owner: Option<Pubkey>,
     pub create_pool_fee: Option<u64>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProposeNewAdminInstruction {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub new_admin: Pubkey,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateConfigFeesInstruction {
+    pub trade_fee_numerator: u64,
+    pub trade_fee_denominator: u64,
+    pub protocol_fee_numerator: u64,
+    pub protocol_fee_denominator: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetPoolStatusInstruction {
+    pub status: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CollectProtocolFeesInstruction {
+    pub coin_amount: u64,
+    pub pc_amount: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetReserveCapsInstruction {
+    /// 0 removes the cap
+    pub max_coin_reserve: u64,
+    /// 0 removes the cap
+    pub max_pc_reserve: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetSwapPermissionedInstruction {
+    /// non zero restricts swaps to the whitelisted wallets
+    pub permissioned: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetSwapGuardInstruction {
+    /// non zero rejects a swap following another swap of the pool in the same transaction
+    pub guarded: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetDynamicFeeInstruction {
+    /// non zero charges the dynamic fee, zero returns to the static pool fee
+    pub enabled: u64,
+    pub base_fee_numerator: u64,
+    pub max_fee_numerator: u64,
+    pub fee_denominator: u64,
+    /// distance of the spot price from its recent average, in bps of the average, at which
+    /// the fee reaches max_fee_numerator
+    pub max_fee_deviation_bps: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetMinTradeAmountInstruction {
+    /// least coin a swap may pay in, 0 for no minimum
+    pub min_coin_amount: u64,
+    /// least pc a swap may pay in, 0 for no minimum
+    pub min_pc_amount: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateSwapWhitelistInstruction {
+    /// 1 adds the wallet, 0 removes it
+    pub add: u8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub wallet: Pubkey,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetFeeRecipientInstruction {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub fee_recipient: Pubkey,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetSwapDiscountInstruction {
+    /// the default pubkey disables the discount
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub discount_mint: Pubkey,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SetPoolAdminInstruction {
+    /// the default pubkey leaves the pool to the amm owners alone
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub pool_admin: Pubkey,
+}
+
+/// Most amounts a `BatchQuote` prices at once, bounding its compute.
+pub const MAX_BATCH_QUOTE_AMOUNTS: usize = 16;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchQuoteInstruction {
+    pub direction: TradeDirection,
+    /// between 1 and MAX_BATCH_QUOTE_AMOUNTS swap base in amounts
+    pub amounts_in: Vec<u64>,
+}
+
 /// Instructions supported by the AmmInfo program.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum AmmInstruction {
@@ -144,9 +379,9 @@ pub enum AmmInstruction {
     ///   1. `[]` Associated Token program id
     ///   2. `[]` Sys program id
     ///   3. `[]` Rent program id
-    ///   4. `[writable]` New AMM Account to create.
+    ///   4. `[writable]` New AMM Account to create, derived from `derive_pool_address(market, coin_mint, pc_mint)`.
     ///   5. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
-    ///   6. `[writable]` AMM open orders Account
+    ///   6. `[writable]` AMM open orders Account, left uncreated with `without_open_orders`
     ///   7. `[writable]` AMM lp mint Account
     ///   8. `[]` AMM coin mint Account
     ///   9. `[]` AMM pc mint Account
@@ -160,7 +395,8 @@ pub enum AmmInstruction {
     ///   17. `[writable, singer]` User wallet Account
     ///   18. `[]` User token coin Account
     ///   19. '[]` User token pc Account
-    ///   20. `[writable]` User destination lp token ATA Account
+    ///   20. `[writable]` User destination lp token ATA Account, created if it does not exist yet
+    ///   21. `[]` (optional) Token-2022 program id, required when `lp_mint_extension` is set
     Initialize2(InitializeInstruction2),
 
     ///   MonitorStep. To monitor place Amm order state machine turn around step by step.
@@ -204,6 +440,7 @@ pub enum AmmInstruction {
     ///   11. `[writable]` User lp token. To deposit the generated tokens, user is the owner.
     ///   12. '[signer]` User wallet Account
     ///   13. `[]` Market event queue Account.
+    ///   last. `[]` (optional) Token-2022 program id, required when the lp mint is a Token-2022 mint
     Deposit(DepositInstruction),
 
     ///   Withdraw the vault tokens from the pool at the current ratio.
@@ -222,12 +459,13 @@ pub enum AmmInstruction {
     ///   11. `[writable]` Market pc vault Account
     ///   12. '[]` Market vault signer Account
     ///   13. `[writable]` User lp token Account.
-    ///   14. `[writable]` User token coin Account. user Account to credit.
-    ///   15. `[writable]` User token pc Account. user Account to credit.
+    ///   14. `[writable]` User token coin Account. user Account to credit, owned by 16.
+    ///   15. `[writable]` User token pc Account. user Account to credit, owned by 16.
     ///   16. `[singer]` User wallet Account
     ///   17. `[writable]` Market event queue Account
     ///   18. `[writable]` Market bids Account
     ///   19. `[writable]` Market asks Account
+    ///   last. `[]` (optional) Token-2022 program id, required when the lp mint is a Token-2022 mint
     Withdraw(WithdrawInstruction),
 
     ///   Migrate the associated market from Serum to OpenBook.
@@ -272,7 +510,7 @@ pub enum AmmInstruction {
     ///   12. `[writable]` Market event queue Account
     ///   13. `[writable]` Market bids Account
     ///   14. `[writable]` Market asks Account
-    ///   15. `[singer]` Admin Account
+    ///   15. `[singer]` Admin Account, or the pool admin for `AmmParams::Fees`
     ///   16. `[]` (optional) New AMM open orders Account to replace old AMM open orders Account
     SetParams(SetParamsInstruction),
 
@@ -326,8 +564,13 @@ pub enum AmmInstruction {
     ///   13. `[writable]` Market pc vault Account
     ///   14. '[]` Market vault signer Account
     ///   15. `[writable]` User source token Account.
-    ///   16. `[writable]` User destination token Account.
+    ///   16. `[writable]` User destination token Account, may be owned by another wallet than 17
     ///   17. `[singer]` User wallet Account
+    ///   18. `[]` (optional) AMM config Account, passed with 19 to swap at the discount fee
+    ///   19. `[]` (optional) User token Account of the config discount mint, owned by 17
+    ///   20. `[]` Swap whitelist Account, only passed to a permissioned pool
+    ///   21. `[]` Instructions sysvar, only needed by a pool with the swap guard, always the
+    ///       last Account
     SwapBaseIn(SwapInstructionBaseIn),
 
     ///   Continue Initializes a new Amm pool because of compute units limit.
@@ -353,8 +596,13 @@ pub enum AmmInstruction {
     ///   13. `[writable]` Market pc vault Account
     ///   14. '[]` Market vault signer Account
     ///   15. `[writable]` User source token Account.
-    ///   16. `[writable]` User destination token Account.
+    ///   16. `[writable]` User destination token Account, may be owned by another wallet than 17
     ///   17. `[singer]` User wallet Account
+    ///   18. `[]` (optional) AMM config Account, passed with 19 to swap at the discount fee
+    ///   19. `[]` (optional) User token Account of the config discount mint, owned by 17
+    ///   20. `[]` Swap whitelist Account, only passed to a permissioned pool
+    ///   21. `[]` Instructions sysvar, only needed by a pool with the swap guard, always the
+    ///       last Account
     SwapBaseOut(SwapInstructionBaseOut),
 
     SimulateInfo(SimulateInstruction),
@@ -365,9 +613,217 @@ pub enum AmmInstruction {
     CreateConfigAccount,
 
     /// Update amm config account by admin
+    /// param 0 sets pnl_owner, 1 sets cancel_owner, 2 sets create_pool_fee
+    ///
+    ///   0. `[signer]` Admin wallet Account
+    ///   1. `[writable]` AMM config Account
     UpdateConfigAccount(ConfigArgs),
     /// Owner Withdraw
     OwnerWithdraw,
+
+    /// Propose a new admin of the amm config account, signed by the current admin
+    ///
+    ///   0. `[signer]` Current admin wallet Account
+    ///   1. `[writable]` AMM config Account
+    ProposeNewAdmin(ProposeNewAdminInstruction),
+
+    /// Accept the admin of the amm config account, signed by the pending admin
+    ///
+    ///   0. `[signer]` Pending admin wallet Account
+    ///   1. `[writable]` AMM config Account
+    AcceptAdmin,
+
+    /// Update the trade fee and protocol fee used by pools created afterwards, existing pools
+    /// take them with `SyncPoolFees`
+    ///
+    ///   0. `[signer]` Admin wallet Account
+    ///   1. `[writable]` AMM config Account
+    UpdateConfigFees(UpdateConfigFeesInstruction),
+
+    /// Pause or resume swap, deposit and withdraw of a pool by admin, status takes only the
+    /// pause bits of PoolStatusBit and keeps the others
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetPoolStatus(SetPoolStatusInstruction),
+
+    /// Collect up to the requested amounts of the accrued protocol fees, clamped to
+    /// need_take_pnl and the vault balances
+    ///
+    ///   0. `[]` Spl Token program id
+    ///   1. `[writable]` AMM Account
+    ///   2. `[]` AMM config Account, derived from `find_program_address(&[&&AMM_CONFIG_SEED])`.
+    ///   3. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
+    ///   4. `[writable]` AMM coin vault account to collect FROM,
+    ///   5. `[writable]` AMM pc vault account to collect FROM,
+    ///   6. `[writable]` fee recipient coin token Account to collect to
+    ///   7. `[writable]` fee recipient pc token Account to collect to
+    ///   8. `[signer]` pnl owner wallet Account, must be the config pnl_owner
+    CollectProtocolFees(CollectProtocolFeesInstruction),
+
+    /// Upgrade an AMM Account written by an older program to the current state layout. The
+    /// accounts of pools before version 6 are grown to hold the AmmInfoExtension, the admin
+    /// pays the rent of the new bytes
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[writable, signer]` Admin wallet Account
+    ///   2. `[]` System program id
+    MigrateState,
+
+    /// Close a pool once every minted lp token is burned and the vaults hold nothing beyond
+    /// the uncollected pnl. The pnl goes to the token accounts of the config fee recipient,
+    /// the rent of the AMM, vault, open orders and target orders accounts goes to the recipient
+    ///
+    ///   0. `[]` Spl Token program id
+    ///   1. `[writable]` AMM Account
+    ///   2. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
+    ///   3. `[writable]` AMM open orders Account
+    ///   4. `[writable]` AMM coin vault Account
+    ///   5. `[writable]` AMM pc vault Account
+    ///   6. `[]` AMM lp mint Account
+    ///   7. `[writable]` AMM target orders Account
+    ///   8. `[]` market program id
+    ///   9. `[]` market Account, the AMM open orders belong to it
+    ///   10. `[signer]` Admin wallet Account
+    ///   11. `[writable]` recipient Account of the reclaimed lamports
+    ///   12. `[]` AMM config Account, derived from `find_program_address(&[&&AMM_CONFIG_SEED])`.
+    ///   13. `[writable]` fee recipient coin token Account the residual coin is swept to
+    ///   14. `[writable]` fee recipient pc token Account the residual pc is swept to
+    ClosePool,
+
+    /// Cap the reserves deposits may grow a pool to, zero removes a cap
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetReserveCaps(SetReserveCapsInstruction),
+
+    /// Restrict the swaps of a pool to the wallets of its swap whitelist, or open them again
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetSwapPermissioned(SetSwapPermissionedInstruction),
+
+    /// Add a wallet to or remove it from the swap whitelist of a pool, the whitelist
+    /// account is created on first use
+    ///
+    ///   0. `[]` AMM Account
+    ///   1. `[writable]` Swap whitelist Account, derived from `find_program_address(&[amm, SWAP_WHITELIST_SEED])`
+    ///   2. `[writable, signer]` Admin wallet Account or the pool admin, pays the rent of the whitelist account
+    ///   3. `[]` System program id
+    UpdateSwapWhitelist(UpdateSwapWhitelistInstruction),
+
+    /// Set the owner of the token accounts protocol fees are collected to
+    ///
+    ///   0. `[signer]` Admin wallet Account
+    ///   1. `[writable]` AMM config Account
+    SetFeeRecipient(SetFeeRecipientInstruction),
+
+    /// Set the mint whose holders swap at a reduced fee, and that fee
+    ///
+    ///   0. `[signer]` Admin wallet Account
+    ///   1. `[writable]` AMM config Account
+    SetSwapDiscount(SetSwapDiscountInstruction),
+
+    ///   Deposit with a single token. Part of the amount is swapped to the other side within
+    ///   the pool and the output is deposited together with the rest, the dust the ratio
+    ///   leaves over stays in the user token account. See `math::single_sided_deposit`.
+    ///   Not taken by a stable pool.
+    ///
+    ///   0. `[]` Spl Token program id
+    ///   1. `[writable]` AMM Account
+    ///   2. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
+    ///   3. `[]` AMM open_orders Account
+    ///   4. `[writable]` AMM target orders Account. To store plan orders infomations.
+    ///   5. `[writable]` AMM lp mint Account. Owned by $authority.
+    ///   6. `[writable]` AMM coin vault $authority can transfer amount,
+    ///   7. `[writable]` AMM pc vault $authority can transfer amount,
+    ///   8. `[]` Market Account. Market program is the owner.
+    ///   9. `[writable]` User token Account of the deposit side.
+    ///   10. `[writable]` User lp token. To deposit the generated tokens, user is the owner.
+    ///   11. '[signer]` User wallet Account
+    ///   12. `[]` Market event queue Account.
+    ///   13. `[]` Swap whitelist Account, only passed to a permissioned pool
+    ///   last. `[]` (optional) Token-2022 program id, required when the lp mint is a Token-2022 mint
+    DepositSingleSided(DepositSingleSidedInstruction),
+
+    ///   Withdraw to a single token. The lp share of both sides is withdrawn and the input
+    ///   side of `out_side` is swapped to its output side within the pool at the pool fee,
+    ///   the user bears the price impact of that swap. Paid from the vault only, a pool with
+    ///   funds on the order book may need a regular Withdraw instead. Not taken by a stable
+    ///   pool. See `math::single_sided_withdraw`.
+    ///
+    ///   0. `[]` Spl Token program id
+    ///   1. `[writable]` AMM Account
+    ///   2. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
+    ///   3. `[]` AMM open_orders Account
+    ///   4. `[writable]` AMM target orders Account. To store plan orders infomations.
+    ///   5. `[writable]` AMM lp mint Account. Owned by $authority.
+    ///   6. `[writable]` AMM coin vault $authority can transfer amount,
+    ///   7. `[writable]` AMM pc vault $authority can transfer amount,
+    ///   8. `[]` Market Account. Market program is the owner.
+    ///   9. `[writable]` User lp token Account, owned by 11.
+    ///   10. `[writable]` User token Account of the output side, owned by 11.
+    ///   11. '[signer]` User wallet Account
+    ///   12. `[]` Market event queue Account.
+    ///   13. `[]` Swap whitelist Account, only passed to a permissioned pool
+    ///   last. `[]` (optional) Token-2022 program id, required when the lp mint is a Token-2022 mint
+    WithdrawSingleSided(WithdrawSingleSidedInstruction),
+
+    /// Delegate the pool scoped admin instructions of a pool to a wallet, the default pubkey
+    /// takes them back. A pool of an older layout is migrated first.
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account
+    SetPoolAdmin(SetPoolAdminInstruction),
+
+    /// Quote swap base in of each of `amounts_in` against the current reserves at the fee a
+    /// swap would pay now, without moving any tokens. The amounts out are set as return data, little endian
+    /// u64s in the order of `amounts_in`. Each quote is independent of the others, so a
+    /// router can compare the sizes of a split in one call.
+    ///
+    ///   0. `[]` AMM Account
+    ///   1. `[]` $authority derived from `create_program_address(&[AUTHORITY_AMM, &[nonce]])`.
+    ///   2. `[]` AMM open orders Account
+    ///   3. `[]` AMM coin vault Account
+    ///   4. `[]` AMM pc vault Account
+    ///   5. `[]` Market program id
+    ///   6. `[]` Market Account. Market program is the owner.
+    ///   7. `[]` Market event queue Account
+    BatchQuote(BatchQuoteInstruction),
+
+    /// Copy the trade and protocol fees of the config into a pool, which keeps the fees it
+    /// was created with until then. A pool of an older layout is migrated first.
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[]` AMM config Account, derived from `find_program_address(&[&&AMM_CONFIG_SEED])`.
+    ///   2. `[signer]` Admin wallet Account
+    SyncPoolFees,
+
+    /// Charge swaps of a pool a fee between base_fee_numerator and max_fee_numerator that
+    /// grows with the distance of the spot price from its average over the last
+    /// DYNAMIC_FEE_WINDOW or two, see `AmmInfoExtension::swap_fees`. Disabling it returns
+    /// the pool to its static swap fee. The pool has to be migrated by MigrateState first.
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetDynamicFee(SetDynamicFeeInstruction),
+
+    /// Reject swaps of a pool paying in less than the minimum of the input mint, so dust
+    /// trades cannot grind the rounding of the reserves. Zero removes a minimum. The pool has
+    /// to be migrated by MigrateState first.
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetMinTradeAmount(SetMinTradeAmountInstruction),
+
+    /// Reject a swap of a pool that follows another swap of the same pool in the transaction,
+    /// e.g. the front half of a sandwich, or allow it again. Swaps of a guarded pool pass the
+    /// instructions sysvar to show the earlier instructions of the transaction. Swaps through
+    /// another program (CPI) are outside what the sysvar shows.
+    ///
+    ///   0. `[writable]` AMM Account
+    ///   1. `[signer]` Admin wallet Account, or the pool admin
+    SetSwapGuard(SetSwapGuardInstruction),
 }
 
 impl AmmInstruction {
@@ -386,12 +842,19 @@ impl AmmInstruction {
                 let (nonce, rest) = Self::unpack_u8(rest)?;
                 let (open_time, rest) = Self::unpack_u64(rest)?;
                 let (init_pc_amount, rest) = Self::unpack_u64(rest)?;
-                let (init_coin_amount, _reset) = Self::unpack_u64(rest)?;
+                let (init_coin_amount, rest) = Self::unpack_u64(rest)?;
+                let (lp_mint_extension, price_bound, lp_decimals, without_open_orders, stable_amp) =
+                    Self::unpack_init_options(rest)?;
                 Self::Initialize2(InitializeInstruction2 {
                     nonce,
                     open_time,
                     init_pc_amount,
                     init_coin_amount,
+                    lp_mint_extension,
+                    price_bound,
+                    lp_decimals,
+                    without_open_orders,
+                    stable_amp,
                 })
             }
             2 => {
@@ -407,11 +870,17 @@ impl AmmInstruction {
             3 => {
                 let (max_coin_amount, rest) = Self::unpack_u64(rest)?;
                 let (max_pc_amount, rest) = Self::unpack_u64(rest)?;
-                let (base_side, _rest) = Self::unpack_u64(rest)?;
+                let (base_side, rest) = Self::unpack_u64(rest)?;
+                let minimum_lp_amount = if rest.is_empty() {
+                    0
+                } else {
+                    Self::unpack_u64(rest)?.0
+                };
                 Self::Deposit(DepositInstruction {
                     max_coin_amount,
                     max_pc_amount,
                     base_side,
+                    minimum_lp_amount,
                 })
             }
             4 => {
@@ -421,7 +890,9 @@ impl AmmInstruction {
             5 => Self::MigrateToOpenBook,
             6 => {
                 let (param, rest) = Self::unpack_u8(rest)?;
-                match AmmParams::from_u64(param as u64) {
+                match AmmParams::try_from_u64(param as u64)
+                    .ok_or(ProgramError::InvalidInstructionData)?
+                {
                     AmmParams::AmmOwner => {
                         if rest.len() >= 32 {
                             let new_pubkey = array_ref![rest, 0, 32];
@@ -492,10 +963,18 @@ impl AmmInstruction {
             }
             9 => {
                 let (amount_in, rest) = Self::unpack_u64(rest)?;
-                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, rest) = Self::unpack_u64(rest)?;
+                let (max_impact_bps, rest) = if rest.is_empty() {
+                    (u64::MAX, rest)
+                } else {
+                    Self::unpack_u64(rest)?
+                };
+                let direction = Self::unpack_trade_direction(rest)?;
                 Self::SwapBaseIn(SwapInstructionBaseIn {
                     amount_in,
                     minimum_amount_out,
+                    max_impact_bps,
+                    direction,
                 })
             }
             10 => {
@@ -504,15 +983,19 @@ impl AmmInstruction {
             }
             11 => {
                 let (max_amount_in, rest) = Self::unpack_u64(rest)?;
-                let (amount_out, _rest) = Self::unpack_u64(rest)?;
+                let (amount_out, rest) = Self::unpack_u64(rest)?;
+                let direction = Self::unpack_trade_direction(rest)?;
                 Self::SwapBaseOut(SwapInstructionBaseOut {
                     max_amount_in,
                     amount_out,
+                    direction,
                 })
             }
             12 => {
                 let (param, rest) = Self::unpack_u8(rest)?;
-                match SimulateParams::from_u64(param as u64) {
+                match SimulateParams::try_from_u64(param as u64)
+                    .ok_or(ProgramError::InvalidInstructionData)?
+                {
                     SimulateParams::PoolInfo | SimulateParams::RunCrankInfo => {
                         Self::SimulateInfo(SimulateInstruction {
                             param,
@@ -526,6 +1009,8 @@ impl AmmInstruction {
                         let swap_base_in = Some(SwapInstructionBaseIn {
                             amount_in,
                             minimum_amount_out,
+                            max_impact_bps: u64::MAX,
+                            direction: None,
                         });
                         Self::SimulateInfo(SimulateInstruction {
                             param,
@@ -539,6 +1024,7 @@ impl AmmInstruction {
                         let swap_base_out = Some(SwapInstructionBaseOut {
                             max_amount_in,
                             amount_out,
+                            direction: None,
                         });
                         Self::SimulateInfo(SimulateInstruction {
                             param,
@@ -557,6 +1043,9 @@ impl AmmInstruction {
                 let (param, rest) = Self::unpack_u8(rest)?;
                 match param {
                     0 | 1 => {
+                        if rest.len() < 32 {
+                            return Err(ProgramError::InvalidInstructionData.into());
+                        }
                         let pubkey = array_ref![rest, 0, 32];
                         Self::UpdateConfigAccount(ConfigArgs {
                             param,
@@ -578,10 +1067,245 @@ impl AmmInstruction {
                 }
             },
             16=> Self::OwnerWithdraw,
+            17 => {
+                if rest.len() < 32 {
+                    return Err(ProgramError::InvalidInstructionData.into());
+                }
+                let new_admin = array_ref![rest, 0, 32];
+                Self::ProposeNewAdmin(ProposeNewAdminInstruction {
+                    new_admin: Pubkey::new_from_array(*new_admin),
+                })
+            }
+            18 => Self::AcceptAdmin,
+            19 => {
+                let (trade_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (trade_fee_denominator, rest) = Self::unpack_u64(rest)?;
+                let (protocol_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (protocol_fee_denominator, _rest) = Self::unpack_u64(rest)?;
+                Self::UpdateConfigFees(UpdateConfigFeesInstruction {
+                    trade_fee_numerator,
+                    trade_fee_denominator,
+                    protocol_fee_numerator,
+                    protocol_fee_denominator,
+                })
+            }
+            20 => {
+                let (status, _rest) = Self::unpack_u64(rest)?;
+                Self::SetPoolStatus(SetPoolStatusInstruction { status })
+            }
+            21 => {
+                let (coin_amount, rest) = Self::unpack_u64(rest)?;
+                let (pc_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::CollectProtocolFees(CollectProtocolFeesInstruction {
+                    coin_amount,
+                    pc_amount,
+                })
+            }
+            22 => Self::MigrateState,
+            23 => Self::ClosePool,
+            24 => {
+                let (max_coin_reserve, rest) = Self::unpack_u64(rest)?;
+                let (max_pc_reserve, _rest) = Self::unpack_u64(rest)?;
+                Self::SetReserveCaps(SetReserveCapsInstruction {
+                    max_coin_reserve,
+                    max_pc_reserve,
+                })
+            }
+            25 => {
+                let (permissioned, _rest) = Self::unpack_u64(rest)?;
+                Self::SetSwapPermissioned(SetSwapPermissionedInstruction { permissioned })
+            }
+            26 => {
+                let (add, rest) = Self::unpack_u8(rest)?;
+                if rest.len() < 32 {
+                    return Err(ProgramError::InvalidInstructionData.into());
+                }
+                let wallet = array_ref![rest, 0, 32];
+                Self::UpdateSwapWhitelist(UpdateSwapWhitelistInstruction {
+                    add,
+                    wallet: Pubkey::new_from_array(*wallet),
+                })
+            }
+            27 => {
+                if rest.len() < 32 {
+                    return Err(ProgramError::InvalidInstructionData.into());
+                }
+                let fee_recipient = array_ref![rest, 0, 32];
+                Self::SetFeeRecipient(SetFeeRecipientInstruction {
+                    fee_recipient: Pubkey::new_from_array(*fee_recipient),
+                })
+            }
+            28 => {
+                if rest.len() < 32 {
+                    return Err(ProgramError::InvalidInstructionData.into());
+                }
+                let (discount_mint, rest) = rest.split_at(32);
+                let (fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (fee_denominator, _rest) = Self::unpack_u64(rest)?;
+                Self::SetSwapDiscount(SetSwapDiscountInstruction {
+                    discount_mint: Pubkey::new_from_array(*array_ref![discount_mint, 0, 32]),
+                    fee_numerator,
+                    fee_denominator,
+                })
+            }
+            29 => {
+                let (amount, rest) = Self::unpack_u64(rest)?;
+                let (side, rest) = Self::unpack_u8(rest)?;
+                let (minimum_lp_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::DepositSingleSided(DepositSingleSidedInstruction {
+                    amount,
+                    side: TradeDirection::from_u8(side)?,
+                    minimum_lp_amount,
+                })
+            }
+            30 => {
+                let (lp_amount, rest) = Self::unpack_u64(rest)?;
+                let (out_side, rest) = Self::unpack_u8(rest)?;
+                let (min_out, _rest) = Self::unpack_u64(rest)?;
+                Self::WithdrawSingleSided(WithdrawSingleSidedInstruction {
+                    lp_amount,
+                    out_side: TradeDirection::from_u8(out_side)?,
+                    min_out,
+                })
+            }
+            31 => {
+                if rest.len() < 32 {
+                    return Err(ProgramError::InvalidInstructionData.into());
+                }
+                let pool_admin = array_ref![rest, 0, 32];
+                Self::SetPoolAdmin(SetPoolAdminInstruction {
+                    pool_admin: Pubkey::new_from_array(*pool_admin),
+                })
+            }
+            32 => {
+                let (direction, rest) = Self::unpack_u8(rest)?;
+                let (len, mut rest) = Self::unpack_u8(rest)?;
+                if len == 0 || len as usize > MAX_BATCH_QUOTE_AMOUNTS {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                let mut amounts_in = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    let (amount_in, next) = Self::unpack_u64(rest)?;
+                    amounts_in.push(amount_in);
+                    rest = next;
+                }
+                Self::BatchQuote(BatchQuoteInstruction {
+                    direction: TradeDirection::from_u8(direction)?,
+                    amounts_in,
+                })
+            }
+            33 => Self::SyncPoolFees,
+            34 => {
+                let (enabled, rest) = Self::unpack_u64(rest)?;
+                let (base_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (max_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (fee_denominator, rest) = Self::unpack_u64(rest)?;
+                let (max_fee_deviation_bps, _rest) = Self::unpack_u64(rest)?;
+                Self::SetDynamicFee(SetDynamicFeeInstruction {
+                    enabled,
+                    base_fee_numerator,
+                    max_fee_numerator,
+                    fee_denominator,
+                    max_fee_deviation_bps,
+                })
+            }
+            35 => {
+                let (min_coin_amount, rest) = Self::unpack_u64(rest)?;
+                let (min_pc_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::SetMinTradeAmount(SetMinTradeAmountInstruction {
+                    min_coin_amount,
+                    min_pc_amount,
+                })
+            }
+            36 => {
+                let (guarded, _rest) = Self::unpack_u64(rest)?;
+                Self::SetSwapGuard(SetSwapGuardInstruction { guarded })
+            }
             _ => return Err(ProgramError::InvalidInstructionData.into()),
         })
     }
 
+    fn unpack_trade_direction(input: &[u8]) -> Result<Option<TradeDirection>, ProgramError> {
+        if input.is_empty() {
+            return Ok(None);
+        }
+        let (direction, _rest) = Self::unpack_u8(input)?;
+        Ok(Some(TradeDirection::from_u8(direction)?))
+    }
+
+    /// The optional trailing fields of `Initialize2`, each one a tag followed by its value:
+    /// 0 the lp mint metadata pointer, 1 the price bound, 2 the lp decimals, 3 without a value
+    /// for a pool without open orders, 4 the amp of a stable pool.
+    fn unpack_init_options(
+        mut input: &[u8],
+    ) -> Result<
+        (
+            Option<LpMintExtension>,
+            Option<InitPriceBound>,
+            Option<u8>,
+            bool,
+            Option<u64>,
+        ),
+        ProgramError,
+    > {
+        let mut lp_mint_extension = None;
+        let mut price_bound = None;
+        let mut lp_decimals = None;
+        let mut without_open_orders = false;
+        let mut stable_amp = None;
+        while !input.is_empty() {
+            let (tag, rest) = Self::unpack_u8(input)?;
+            input = match tag {
+                0 if lp_mint_extension.is_none() => {
+                    if rest.len() < 64 {
+                        return Err(ProgramError::InvalidInstructionData);
+                    }
+                    let (authority, metadata_address) =
+                        array_refs![array_ref![rest, 0, 64], 32, 32];
+                    let optional = |key: &[u8; 32]| {
+                        Some(Pubkey::new_from_array(*key)).filter(|key| *key != Pubkey::default())
+                    };
+                    lp_mint_extension = Some(LpMintExtension::MetadataPointer {
+                        authority: optional(authority),
+                        metadata_address: optional(metadata_address),
+                    });
+                    &rest[64..]
+                }
+                1 if price_bound.is_none() => {
+                    let (expected_price, rest) = Self::unpack_u128(rest)?;
+                    let (price_tolerance_bps, rest) = Self::unpack_u64(rest)?;
+                    price_bound = Some(InitPriceBound {
+                        expected_price,
+                        price_tolerance_bps,
+                    });
+                    rest
+                }
+                2 if lp_decimals.is_none() => {
+                    let (decimals, rest) = Self::unpack_u8(rest)?;
+                    lp_decimals = Some(decimals);
+                    rest
+                }
+                3 if !without_open_orders => {
+                    without_open_orders = true;
+                    rest
+                }
+                4 if stable_amp.is_none() => {
+                    let (amp, rest) = Self::unpack_u64(rest)?;
+                    stable_amp = Some(amp);
+                    rest
+                }
+                _ => return Err(ProgramError::InvalidInstructionData),
+            };
+        }
+        Ok((
+            lp_mint_extension,
+            price_bound,
+            lp_decimals,
+            without_open_orders,
+            stable_amp,
+        ))
+    }
+
     fn unpack_u8(input: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
         if input.len() >= 1 {
             let (amount, rest) = input.split_at(1);
@@ -624,6 +1348,20 @@ impl AmmInstruction {
         }
     }
 
+    fn unpack_u128(input: &[u8]) -> Result<(u128, &[u8]), ProgramError> {
+        if input.len() >= 16 {
+            let (amount, rest) = input.split_at(16);
+            let amount = amount
+                .get(..16)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u128::from_le_bytes)
+                .ok_or(ProgramError::InvalidInstructionData)?;
+            Ok((amount, rest))
+        } else {
+            Err(ProgramError::InvalidInstructionData.into())
+        }
+    }
+
     /// Packs a [AmmInstruction](enum.AmmInstruction.html) into a byte buffer.
     pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
@@ -638,12 +1376,48 @@ impl AmmInstruction {
                 open_time,
                 init_pc_amount,
                 init_coin_amount,
+                lp_mint_extension,
+                price_bound,
+                lp_decimals,
+                without_open_orders,
+                stable_amp,
             }) => {
                 buf.push(1);
                 buf.push(*nonce);
                 buf.extend_from_slice(&open_time.to_le_bytes());
                 buf.extend_from_slice(&init_pc_amount.to_le_bytes());
                 buf.extend_from_slice(&init_coin_amount.to_le_bytes());
+                match lp_mint_extension {
+                    Some(LpMintExtension::MetadataPointer {
+                        authority,
+                        metadata_address,
+                    }) => {
+                        buf.push(0);
+                        buf.extend_from_slice(&authority.unwrap_or_default().to_bytes());
+                        buf.extend_from_slice(&metadata_address.unwrap_or_default().to_bytes());
+                    }
+                    None => {}
+                }
+                if let Some(InitPriceBound {
+                    expected_price,
+                    price_tolerance_bps,
+                }) = price_bound
+                {
+                    buf.push(1);
+                    buf.extend_from_slice(&expected_price.to_le_bytes());
+                    buf.extend_from_slice(&price_tolerance_bps.to_le_bytes());
+                }
+                if let Some(lp_decimals) = lp_decimals {
+                    buf.push(2);
+                    buf.push(*lp_decimals);
+                }
+                if *without_open_orders {
+                    buf.push(3);
+                }
+                if let Some(amp) = stable_amp {
+                    buf.push(4);
+                    buf.extend_from_slice(&amp.to_le_bytes());
+                }
             }
             Self::MonitorStep(MonitorStepInstruction {
                 plan_order_limit,
@@ -659,11 +1433,13 @@ impl AmmInstruction {
                 max_coin_amount,
                 max_pc_amount,
                 base_side,
+                minimum_lp_amount,
             }) => {
                 buf.push(3);
                 buf.extend_from_slice(&max_coin_amount.to_le_bytes());
                 buf.extend_from_slice(&max_pc_amount.to_le_bytes());
                 buf.extend_from_slice(&base_side.to_le_bytes());
+                buf.extend_from_slice(&minimum_lp_amount.to_le_bytes());
             }
             Self::Withdraw(WithdrawInstruction { amount }) => {
                 buf.push(4);
@@ -681,7 +1457,9 @@ impl AmmInstruction {
             }) => {
                 buf.push(6);
                 buf.push(*param);
-                match AmmParams::from_u64(*param as u64) {
+                match AmmParams::try_from_u64(*param as u64)
+                    .ok_or(ProgramError::InvalidInstructionData)?
+                {
                     AmmParams::AmmOwner => {
                         let new_pubkey = match new_pubkey {
                             Some(a) => a,
@@ -728,10 +1506,16 @@ impl AmmInstruction {
             Self::SwapBaseIn(SwapInstructionBaseIn {
                 amount_in,
                 minimum_amount_out,
+                max_impact_bps,
+                direction,
             }) => {
                 buf.push(9);
                 buf.extend_from_slice(&amount_in.to_le_bytes());
                 buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+                buf.extend_from_slice(&max_impact_bps.to_le_bytes());
+                if let Some(direction) = direction {
+                    buf.push(*direction as u8);
+                }
             }
             Self::PreInitialize(PreInitializeInstruction { nonce }) => {
                 buf.push(10);
@@ -740,10 +1524,14 @@ impl AmmInstruction {
             Self::SwapBaseOut(SwapInstructionBaseOut {
                 max_amount_in,
                 amount_out,
+                direction,
             }) => {
                 buf.push(11);
                 buf.extend_from_slice(&max_amount_in.to_le_bytes());
                 buf.extend_from_slice(&amount_out.to_le_bytes());
+                if let Some(direction) = direction {
+                    buf.push(*direction as u8);
+                }
             }
             Self::SimulateInfo(SimulateInstruction {
                 param,
@@ -752,7 +1540,9 @@ impl AmmInstruction {
             }) => {
                 buf.push(12);
                 buf.push(*param);
-                match SimulateParams::from_u64(*param as u64) {
+                match SimulateParams::try_from_u64(*param as u64)
+                    .ok_or(ProgramError::InvalidInstructionData)?
+                {
                     SimulateParams::PoolInfo | SimulateParams::RunCrankInfo => {}
                     SimulateParams::SwapBaseInInfo => {
                         let swap_base_in = match swap_base_in_value {
@@ -810,6 +1600,135 @@ impl AmmInstruction {
                     _ => return Err(ProgramError::InvalidInstructionData.into()),
                 }
             }
+            Self::ProposeNewAdmin(ProposeNewAdminInstruction { new_admin }) => {
+                buf.push(17);
+                buf.extend_from_slice(&new_admin.to_bytes());
+            }
+            Self::AcceptAdmin => {
+                buf.push(18);
+            }
+            Self::UpdateConfigFees(UpdateConfigFeesInstruction {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                protocol_fee_numerator,
+                protocol_fee_denominator,
+            }) => {
+                buf.push(19);
+                buf.extend_from_slice(&trade_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&trade_fee_denominator.to_le_bytes());
+                buf.extend_from_slice(&protocol_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&protocol_fee_denominator.to_le_bytes());
+            }
+            Self::SetPoolStatus(SetPoolStatusInstruction { status }) => {
+                buf.push(20);
+                buf.extend_from_slice(&status.to_le_bytes());
+            }
+            Self::CollectProtocolFees(CollectProtocolFeesInstruction {
+                coin_amount,
+                pc_amount,
+            }) => {
+                buf.push(21);
+                buf.extend_from_slice(&coin_amount.to_le_bytes());
+                buf.extend_from_slice(&pc_amount.to_le_bytes());
+            }
+            Self::MigrateState => buf.push(22),
+            Self::ClosePool => buf.push(23),
+            Self::SetReserveCaps(SetReserveCapsInstruction {
+                max_coin_reserve,
+                max_pc_reserve,
+            }) => {
+                buf.push(24);
+                buf.extend_from_slice(&max_coin_reserve.to_le_bytes());
+                buf.extend_from_slice(&max_pc_reserve.to_le_bytes());
+            }
+            Self::SetSwapPermissioned(SetSwapPermissionedInstruction { permissioned }) => {
+                buf.push(25);
+                buf.extend_from_slice(&permissioned.to_le_bytes());
+            }
+            Self::UpdateSwapWhitelist(UpdateSwapWhitelistInstruction { add, wallet }) => {
+                buf.push(26);
+                buf.push(*add);
+                buf.extend_from_slice(&wallet.to_bytes());
+            }
+            Self::SetFeeRecipient(SetFeeRecipientInstruction { fee_recipient }) => {
+                buf.push(27);
+                buf.extend_from_slice(&fee_recipient.to_bytes());
+            }
+            Self::SetSwapDiscount(SetSwapDiscountInstruction {
+                discount_mint,
+                fee_numerator,
+                fee_denominator,
+            }) => {
+                buf.push(28);
+                buf.extend_from_slice(&discount_mint.to_bytes());
+                buf.extend_from_slice(&fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&fee_denominator.to_le_bytes());
+            }
+            Self::DepositSingleSided(DepositSingleSidedInstruction {
+                amount,
+                side,
+                minimum_lp_amount,
+            }) => {
+                buf.push(29);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.push(*side as u8);
+                buf.extend_from_slice(&minimum_lp_amount.to_le_bytes());
+            }
+            Self::WithdrawSingleSided(WithdrawSingleSidedInstruction {
+                lp_amount,
+                out_side,
+                min_out,
+            }) => {
+                buf.push(30);
+                buf.extend_from_slice(&lp_amount.to_le_bytes());
+                buf.push(*out_side as u8);
+                buf.extend_from_slice(&min_out.to_le_bytes());
+            }
+            Self::SetPoolAdmin(SetPoolAdminInstruction { pool_admin }) => {
+                buf.push(31);
+                buf.extend_from_slice(&pool_admin.to_bytes());
+            }
+            Self::BatchQuote(BatchQuoteInstruction {
+                direction,
+                amounts_in,
+            }) => {
+                if amounts_in.is_empty() || amounts_in.len() > MAX_BATCH_QUOTE_AMOUNTS {
+                    return Err(ProgramError::InvalidInstructionData);
+                }
+                buf.push(32);
+                buf.push(*direction as u8);
+                buf.push(amounts_in.len() as u8);
+                for amount_in in amounts_in {
+                    buf.extend_from_slice(&amount_in.to_le_bytes());
+                }
+            }
+            Self::SyncPoolFees => buf.push(33),
+            Self::SetDynamicFee(SetDynamicFeeInstruction {
+                enabled,
+                base_fee_numerator,
+                max_fee_numerator,
+                fee_denominator,
+                max_fee_deviation_bps,
+            }) => {
+                buf.push(34);
+                buf.extend_from_slice(&enabled.to_le_bytes());
+                buf.extend_from_slice(&base_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&max_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&fee_denominator.to_le_bytes());
+                buf.extend_from_slice(&max_fee_deviation_bps.to_le_bytes());
+            }
+            Self::SetMinTradeAmount(SetMinTradeAmountInstruction {
+                min_coin_amount,
+                min_pc_amount,
+            }) => {
+                buf.push(35);
+                buf.extend_from_slice(&min_coin_amount.to_le_bytes());
+                buf.extend_from_slice(&min_pc_amount.to_le_bytes());
+            }
+            Self::SetSwapGuard(SetSwapGuardInstruction { guarded }) => {
+                buf.push(36);
+                buf.extend_from_slice(&guarded.to_le_bytes());
+            }
         }
         Ok(buf)
     }
@@ -839,16 +1758,26 @@ pub fn initialize2(
     open_time: u64,
     init_pc_amount: u64,
     init_coin_amount: u64,
+    lp_mint_extension: Option<LpMintExtension>,
+    price_bound: Option<InitPriceBound>,
+    lp_decimals: Option<u8>,
+    without_open_orders: bool,
+    stable_amp: Option<u64>,
 ) -> Result<Instruction, ProgramError> {
     let init_data = AmmInstruction::Initialize2(InitializeInstruction2 {
         nonce,
         open_time,
         init_pc_amount,
         init_coin_amount,
+        lp_mint_extension,
+        price_bound,
+        lp_decimals,
+        without_open_orders,
+        stable_amp,
     });
     let data = init_data.pack()?;
 
-    let accounts = vec![
+    let mut accounts = vec![
         // spl & sys
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(spl_associated_token_account::id(), false),
@@ -875,6 +1804,9 @@ pub fn initialize2(
         AccountMeta::new_readonly(*user_token_pc, false),
         AccountMeta::new(*user_token_lp, false),
     ];
+    if lp_mint_extension.is_some() {
+        accounts.push(AccountMeta::new_readonly(spl_token_2022::id(), false));
+    }
 
     Ok(Instruction {
         program_id: *amm_program,
@@ -902,11 +1834,13 @@ pub fn deposit(
     max_coin_amount: u64,
     max_pc_amount: u64,
     base_side: u64,
+    minimum_lp_amount: u64,
 ) -> Result<Instruction, ProgramError> {
     let data = AmmInstruction::Deposit(DepositInstruction {
         max_coin_amount,
         max_pc_amount,
         base_side,
+        minimum_lp_amount,
     })
     .pack()?;
 
@@ -938,8 +1872,8 @@ pub fn deposit(
     })
 }
 
-/// Creates a 'withdraw' instruction.
-pub fn withdraw(
+/// Creates a 'deposit_single_sided' instruction.
+pub fn deposit_single_sided(
     amm_program: &Pubkey,
     amm_pool: &Pubkey,
     amm_authority: &Pubkey,
@@ -948,41 +1882,147 @@ pub fn withdraw(
     amm_lp_mint: &Pubkey,
     amm_coin_vault: &Pubkey,
     amm_pc_vault: &Pubkey,
-    market_program: &Pubkey,
     market: &Pubkey,
-    market_coin_vault: &Pubkey,
-    market_pc_vault: &Pubkey,
-    market_vault_signer: &Pubkey,
+    market_event_queue: &Pubkey,
+    user_token_source: &Pubkey,
     user_token_lp: &Pubkey,
-    user_token_coin: &Pubkey,
-    user_token_pc: &Pubkey,
     user_owner: &Pubkey,
-    market_event_queue: &Pubkey,
-    market_bids: &Pubkey,
-    market_asks: &Pubkey,
-
-    referrer_pc_account: Option<&Pubkey>,
-
     amount: u64,
+    side: TradeDirection,
+    minimum_lp_amount: u64,
 ) -> Result<Instruction, ProgramError> {
-    let data = AmmInstruction::Withdraw(WithdrawInstruction { amount }).pack()?;
+    let data = AmmInstruction::DepositSingleSided(DepositSingleSidedInstruction {
+        amount,
+        side,
+        minimum_lp_amount,
+    })
+    .pack()?;
 
-    let mut accounts = vec![
+    let accounts = vec![
         // spl token
         AccountMeta::new_readonly(spl_token::id(), false),
         // amm
         AccountMeta::new(*amm_pool, false),
         AccountMeta::new_readonly(*amm_authority, false),
-        AccountMeta::new(*amm_open_orders, false),
+        AccountMeta::new_readonly(*amm_open_orders, false),
         AccountMeta::new(*amm_target_orders, false),
         AccountMeta::new(*amm_lp_mint, false),
         AccountMeta::new(*amm_coin_vault, false),
         AccountMeta::new(*amm_pc_vault, false),
         // market
-        AccountMeta::new_readonly(*market_program, false),
-        AccountMeta::new(*market, false),
-        AccountMeta::new(*market_coin_vault, false),
-        AccountMeta::new(*market_pc_vault, false),
+        AccountMeta::new_readonly(*market, false),
+        // user
+        AccountMeta::new(*user_token_source, false),
+        AccountMeta::new(*user_token_lp, false),
+        AccountMeta::new_readonly(*user_owner, true),
+        AccountMeta::new_readonly(*market_event_queue, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_single_sided' instruction.
+pub fn withdraw_single_sided(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_authority: &Pubkey,
+    amm_open_orders: &Pubkey,
+    amm_target_orders: &Pubkey,
+    amm_lp_mint: &Pubkey,
+    amm_coin_vault: &Pubkey,
+    amm_pc_vault: &Pubkey,
+    market: &Pubkey,
+    market_event_queue: &Pubkey,
+    user_token_lp: &Pubkey,
+    user_token_destination: &Pubkey,
+    user_owner: &Pubkey,
+    lp_amount: u64,
+    out_side: TradeDirection,
+    min_out: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::WithdrawSingleSided(WithdrawSingleSidedInstruction {
+        lp_amount,
+        out_side,
+        min_out,
+    })
+    .pack()?;
+
+    let accounts = vec![
+        // spl token
+        AccountMeta::new_readonly(spl_token::id(), false),
+        // amm
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_authority, false),
+        AccountMeta::new_readonly(*amm_open_orders, false),
+        AccountMeta::new(*amm_target_orders, false),
+        AccountMeta::new(*amm_lp_mint, false),
+        AccountMeta::new(*amm_coin_vault, false),
+        AccountMeta::new(*amm_pc_vault, false),
+        // market
+        AccountMeta::new_readonly(*market, false),
+        // user
+        AccountMeta::new(*user_token_lp, false),
+        AccountMeta::new(*user_token_destination, false),
+        AccountMeta::new_readonly(*user_owner, true),
+        AccountMeta::new_readonly(*market_event_queue, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw' instruction.
+pub fn withdraw(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_authority: &Pubkey,
+    amm_open_orders: &Pubkey,
+    amm_target_orders: &Pubkey,
+    amm_lp_mint: &Pubkey,
+    amm_coin_vault: &Pubkey,
+    amm_pc_vault: &Pubkey,
+    market_program: &Pubkey,
+    market: &Pubkey,
+    market_coin_vault: &Pubkey,
+    market_pc_vault: &Pubkey,
+    market_vault_signer: &Pubkey,
+    user_token_lp: &Pubkey,
+    user_token_coin: &Pubkey,
+    user_token_pc: &Pubkey,
+    user_owner: &Pubkey,
+    market_event_queue: &Pubkey,
+    market_bids: &Pubkey,
+    market_asks: &Pubkey,
+
+    referrer_pc_account: Option<&Pubkey>,
+
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::Withdraw(WithdrawInstruction { amount }).pack()?;
+
+    let mut accounts = vec![
+        // spl token
+        AccountMeta::new_readonly(spl_token::id(), false),
+        // amm
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_authority, false),
+        AccountMeta::new(*amm_open_orders, false),
+        AccountMeta::new(*amm_target_orders, false),
+        AccountMeta::new(*amm_lp_mint, false),
+        AccountMeta::new(*amm_coin_vault, false),
+        AccountMeta::new(*amm_pc_vault, false),
+        // market
+        AccountMeta::new_readonly(*market_program, false),
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*market_coin_vault, false),
+        AccountMeta::new(*market_pc_vault, false),
         AccountMeta::new_readonly(*market_vault_signer, false),
         // user
         AccountMeta::new(*user_token_lp, false),
@@ -1027,10 +2067,14 @@ pub fn swap_base_in(
 
     amount_in: u64,
     minimum_amount_out: u64,
+    max_impact_bps: u64,
+    direction: Option<TradeDirection>,
 ) -> Result<Instruction, ProgramError> {
     let data = AmmInstruction::SwapBaseIn(SwapInstructionBaseIn {
         amount_in,
         minimum_amount_out,
+        max_impact_bps,
+        direction,
     })
     .pack()?;
 
@@ -1088,10 +2132,12 @@ pub fn swap_base_out(
 
     max_amount_in: u64,
     amount_out: u64,
+    direction: Option<TradeDirection>,
 ) -> Result<Instruction, ProgramError> {
     let data = AmmInstruction::SwapBaseOut(SwapInstructionBaseOut {
         max_amount_in,
         amount_out,
+        direction,
     })
     .pack()?;
 
@@ -1523,6 +2569,8 @@ pub fn simulate_swap_base_in(
         swap_base_in_value: Some(SwapInstructionBaseIn {
             amount_in,
             minimum_amount_out: 0,
+            max_impact_bps: u64::MAX,
+            direction: None,
         }),
         swap_base_out_value: None,
     })
@@ -1578,6 +2626,7 @@ pub fn simulate_swap_base_out(
         swap_base_out_value: Some(SwapInstructionBaseOut {
             max_amount_in: 0,
             amount_out,
+            direction: None,
         }),
     })
     .pack()?;
@@ -1762,3 +2811,483 @@ pub fn update_config_account(
         data,
     })
 }
+
+/// Creates an 'update_config_account' instruction that rotates the pnl_owner.
+pub fn set_pnl_owner(
+    amm_program: &Pubkey,
+    admin: &Pubkey,
+    amm_config: &Pubkey,
+    new_pnl_owner: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    update_config_account(
+        amm_program,
+        admin,
+        amm_config,
+        ConfigArgs {
+            param: 0,
+            owner: Some(*new_pnl_owner),
+            create_pool_fee: None,
+        },
+    )
+}
+
+/// Creates a 'propose_new_admin' instruction.
+pub fn propose_new_admin(
+    amm_program: &Pubkey,
+    admin: &Pubkey,
+    amm_config: &Pubkey,
+    new_admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::ProposeNewAdmin(ProposeNewAdminInstruction {
+        new_admin: *new_admin,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*amm_config, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'accept_admin' instruction.
+pub fn accept_admin(
+    amm_program: &Pubkey,
+    pending_admin: &Pubkey,
+    amm_config: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::AcceptAdmin.pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*pending_admin, true),
+        AccountMeta::new(*amm_config, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'update_config_fees' instruction.
+pub fn update_config_fees(
+    amm_program: &Pubkey,
+    admin: &Pubkey,
+    amm_config: &Pubkey,
+    trade_fee_numerator: u64,
+    trade_fee_denominator: u64,
+    protocol_fee_numerator: u64,
+    protocol_fee_denominator: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateConfigFees(UpdateConfigFeesInstruction {
+        trade_fee_numerator,
+        trade_fee_denominator,
+        protocol_fee_numerator,
+        protocol_fee_denominator,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*amm_config, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'sync_pool_fees' instruction.
+pub fn sync_pool_fees(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_config: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SyncPoolFees.pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_config, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fee_recipient' instruction.
+pub fn set_fee_recipient(
+    amm_program: &Pubkey,
+    admin: &Pubkey,
+    amm_config: &Pubkey,
+    fee_recipient: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetFeeRecipient(SetFeeRecipientInstruction {
+        fee_recipient: *fee_recipient,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*amm_config, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_swap_discount' instruction.
+pub fn set_swap_discount(
+    amm_program: &Pubkey,
+    admin: &Pubkey,
+    amm_config: &Pubkey,
+    discount_mint: &Pubkey,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetSwapDiscount(SetSwapDiscountInstruction {
+        discount_mint: *discount_mint,
+        fee_numerator,
+        fee_denominator,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*amm_config, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_pool_status' instruction.
+pub fn set_pool_status(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    status: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetPoolStatus(SetPoolStatusInstruction { status }).pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_pool_admin' instruction.
+pub fn set_pool_admin(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    pool_admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetPoolAdmin(SetPoolAdminInstruction {
+        pool_admin: *pool_admin,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'batch_quote' instruction.
+pub fn batch_quote(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_authority: &Pubkey,
+    amm_open_orders: &Pubkey,
+    amm_coin_vault: &Pubkey,
+    amm_pc_vault: &Pubkey,
+    market_program: &Pubkey,
+    market: &Pubkey,
+    market_event_queue: &Pubkey,
+    direction: TradeDirection,
+    amounts_in: Vec<u64>,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::BatchQuote(BatchQuoteInstruction {
+        direction,
+        amounts_in,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_authority, false),
+        AccountMeta::new_readonly(*amm_open_orders, false),
+        AccountMeta::new_readonly(*amm_coin_vault, false),
+        AccountMeta::new_readonly(*amm_pc_vault, false),
+        AccountMeta::new_readonly(*market_program, false),
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(*market_event_queue, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'collect_protocol_fees' instruction.
+pub fn collect_protocol_fees(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_config: &Pubkey,
+    amm_authority: &Pubkey,
+    amm_coin_vault: &Pubkey,
+    amm_pc_vault: &Pubkey,
+    owner_token_coin: &Pubkey,
+    owner_token_pc: &Pubkey,
+    pnl_owner: &Pubkey,
+    coin_amount: u64,
+    pc_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::CollectProtocolFees(CollectProtocolFeesInstruction {
+        coin_amount,
+        pc_amount,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_config, false),
+        AccountMeta::new_readonly(*amm_authority, false),
+        AccountMeta::new(*amm_coin_vault, false),
+        AccountMeta::new(*amm_pc_vault, false),
+        AccountMeta::new(*owner_token_coin, false),
+        AccountMeta::new(*owner_token_pc, false),
+        AccountMeta::new_readonly(*pnl_owner, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'migrate_state' instruction.
+pub fn migrate_state(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::MigrateState.pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'close_pool' instruction.
+pub fn close_pool(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    amm_authority: &Pubkey,
+    amm_open_orders: &Pubkey,
+    amm_coin_vault: &Pubkey,
+    amm_pc_vault: &Pubkey,
+    amm_lp_mint: &Pubkey,
+    amm_target_orders: &Pubkey,
+    market_program: &Pubkey,
+    market: &Pubkey,
+    admin: &Pubkey,
+    recipient: &Pubkey,
+    amm_config: &Pubkey,
+    recipient_token_coin: &Pubkey,
+    recipient_token_pc: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::ClosePool.pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*amm_authority, false),
+        AccountMeta::new(*amm_open_orders, false),
+        AccountMeta::new(*amm_coin_vault, false),
+        AccountMeta::new(*amm_pc_vault, false),
+        AccountMeta::new_readonly(*amm_lp_mint, false),
+        AccountMeta::new(*amm_target_orders, false),
+        AccountMeta::new_readonly(*market_program, false),
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*recipient, false),
+        AccountMeta::new_readonly(*amm_config, false),
+        AccountMeta::new(*recipient_token_coin, false),
+        AccountMeta::new(*recipient_token_pc, false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_reserve_caps' instruction.
+pub fn set_reserve_caps(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    max_coin_reserve: u64,
+    max_pc_reserve: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetReserveCaps(SetReserveCapsInstruction {
+        max_coin_reserve,
+        max_pc_reserve,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_dynamic_fee' instruction.
+pub fn set_dynamic_fee(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    enabled: bool,
+    base_fee_numerator: u64,
+    max_fee_numerator: u64,
+    fee_denominator: u64,
+    max_fee_deviation_bps: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetDynamicFee(SetDynamicFeeInstruction {
+        enabled: enabled as u64,
+        base_fee_numerator,
+        max_fee_numerator,
+        fee_denominator,
+        max_fee_deviation_bps,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_min_trade_amount' instruction.
+pub fn set_min_trade_amount(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    min_coin_amount: u64,
+    min_pc_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetMinTradeAmount(SetMinTradeAmountInstruction {
+        min_coin_amount,
+        min_pc_amount,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_swap_guard' instruction.
+pub fn set_swap_guard(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    guarded: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetSwapGuard(SetSwapGuardInstruction {
+        guarded: guarded as u64,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_swap_permissioned' instruction.
+pub fn set_swap_permissioned(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    admin: &Pubkey,
+    permissioned: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::SetSwapPermissioned(SetSwapPermissionedInstruction {
+        permissioned: permissioned as u64,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new(*amm_pool, false),
+        AccountMeta::new_readonly(*admin, true),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'update_swap_whitelist' instruction.
+pub fn update_swap_whitelist(
+    amm_program: &Pubkey,
+    amm_pool: &Pubkey,
+    swap_whitelist: &Pubkey,
+    admin: &Pubkey,
+    wallet: &Pubkey,
+    add: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = AmmInstruction::UpdateSwapWhitelist(UpdateSwapWhitelistInstruction {
+        add: add as u8,
+        wallet: *wallet,
+    })
+    .pack()?;
+    let accounts = vec![
+        AccountMeta::new_readonly(*amm_pool, false),
+        AccountMeta::new(*swap_whitelist, false),
+        AccountMeta::new(*admin, true),
+        AccountMeta::new_readonly(solana_program::system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *amm_program,
+        accounts,
+        data,
+    })
+}
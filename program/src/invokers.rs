@@ -41,6 +41,52 @@ impl Invokers {
             &[],
         )
     }
+    /// Issue a associated_spl_token `create_associated_token_account_idempotent` instruction,
+    /// a no-op when `associated_account` already exists for the wallet and mint
+    pub fn create_ata_spl_token_idempotent<'a>(
+        associated_account: AccountInfo<'a>,
+        funding_account: AccountInfo<'a>,
+        wallet_account: AccountInfo<'a>,
+        token_mint_account: AccountInfo<'a>,
+        token_program_account: AccountInfo<'a>,
+        ata_program_account: AccountInfo<'a>,
+        system_program_account: AccountInfo<'a>,
+    ) -> Result<(), ProgramError> {
+        let ix = Self::create_ata_idempotent_instruction(
+            funding_account.key,
+            wallet_account.key,
+            token_mint_account.key,
+            token_program_account.key,
+        );
+        solana_program::program::invoke_signed(
+            &ix,
+            &[
+                associated_account,
+                funding_account,
+                wallet_account,
+                token_mint_account,
+                token_program_account,
+                ata_program_account,
+                system_program_account,
+            ],
+            &[],
+        )
+    }
+    /// The `create_associated_token_account_idempotent` instruction issued by
+    /// `create_ata_spl_token_idempotent`
+    fn create_ata_idempotent_instruction(
+        funding_address: &Pubkey,
+        wallet_address: &Pubkey,
+        token_mint_address: &Pubkey,
+        token_program_id: &Pubkey,
+    ) -> Instruction {
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            funding_address,
+            wallet_address,
+            token_mint_address,
+            token_program_id,
+        )
+    }
     /// Issue a spl_token `Burn` instruction.
     pub fn token_burn<'a>(
         token_program: AccountInfo<'a>,
@@ -49,7 +95,7 @@ impl Invokers {
         owner: AccountInfo<'a>,
         burn_amount: u64,
     ) -> Result<(), ProgramError> {
-        let ix = spl_token::instruction::burn(
+        let ix = spl_token_2022::instruction::burn(
             token_program.key,
             burn_account.key,
             mint.key,
@@ -76,7 +122,7 @@ impl Invokers {
     ) -> Result<(), ProgramError> {
         let authority_signature_seeds = [amm_seed, &[nonce]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::close_account(
+        let ix = spl_token_2022::instruction::close_account(
             token_program.key,
             close_account.key,
             destination_account.key,
@@ -103,7 +149,7 @@ impl Invokers {
     ) -> Result<(), ProgramError> {
         let authority_signature_seeds = [amm_seed, &[nonce]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::burn(
+        let ix = spl_token_2022::instruction::burn(
             token_program.key,
             burn_account.key,
             mint.key,
@@ -131,7 +177,7 @@ impl Invokers {
     ) -> Result<(), ProgramError> {
         let authority_signature_seeds = [amm_seed, &[nonce]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::mint_to(
+        let ix = spl_token_2022::instruction::mint_to(
             token_program.key,
             mint.key,
             destination.key,
@@ -155,7 +201,7 @@ impl Invokers {
         owner: AccountInfo<'a>,
         deposit_amount: u64,
     ) -> Result<(), ProgramError> {
-        let ix = spl_token::instruction::transfer(
+        let ix = spl_token_2022::instruction::transfer(
             token_program.key,
             source.key,
             destination.key,
@@ -182,7 +228,7 @@ impl Invokers {
     ) -> Result<(), ProgramError> {
         let authority_signature_seeds = [amm_seed, &[nonce]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::transfer(
+        let ix = spl_token_2022::instruction::transfer(
             token_program.key,
             source.key,
             destination.key,
@@ -621,3 +667,45 @@ impl Invokers {
         solana_program::program::invoke_signed(&ix, &accounts, signers)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_create_ata_idempotent_instruction() {
+        let funding = Pubkey::new_unique();
+        let wallet = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let token_program = spl_token::id();
+        let associated = spl_associated_token_account::get_associated_token_address(&wallet, &mint);
+
+        let ix =
+            Invokers::create_ata_idempotent_instruction(&funding, &wallet, &mint, &token_program);
+        let expected =
+            spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+                &funding,
+                &wallet,
+                &mint,
+                &token_program,
+            );
+        assert_eq!(ix.program_id, expected.program_id);
+        assert_eq!(ix.data, expected.data);
+        assert_eq!(ix.accounts, expected.accounts);
+
+        // CreateIdempotent, which accepts an account it already created
+        assert_eq!(ix.program_id, spl_associated_token_account::id());
+        assert_eq!(ix.data, vec![1]);
+        assert_eq!(
+            ix.accounts,
+            vec![
+                AccountMeta::new(funding, true),
+                AccountMeta::new(associated, false),
+                AccountMeta::new_readonly(wallet, false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                AccountMeta::new_readonly(token_program, false),
+            ]
+        );
+    }
+}
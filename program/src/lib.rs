@@ -10,6 +10,8 @@ pub mod instruction;
 pub mod invokers;
 pub mod math;
 pub mod processor;
+#[cfg(feature = "serde")]
+pub mod serde_pubkey;
 pub mod state;
 
 // Export current solana-sdk types for downstream users who may also be building with a different solana-sdk version
@@ -17,5 +19,5 @@ use anchor_lang::prelude::*;
 
 #[cfg(feature = "devnet")]
 declare_id!("3Qvevpr9VQp7ECWjAU186oiSGjMhDucjU32oSX8BfxGK");
-#[cfg(not(any(feature = "devnet", feature = "client")))]
+#[cfg(not(feature = "devnet"))]
 declare_id!("3Qvevpr9VQp7ECWjAU186oiSGjMhDucjU32oSX8BfxGK");
@@ -18,6 +18,55 @@ macro_rules! check_assert_eq {
     };
 }
 
+/// The value of a checked arithmetic expression, returns `AmmError::CalculationFailure`
+/// from the enclosing function when it is None.
+#[macro_export]
+macro_rules! checked {
+    ($e:expr) => {
+        match $e {
+            Some(value) => value,
+            None => return Err($crate::error::AmmError::CalculationFailure.into()),
+        }
+    };
+}
+
+/// Log with an `INFO: ` prefix.
+#[macro_export]
+macro_rules! log_info {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        ::solana_program::msg!(concat!("INFO: ", $fmt) $(, $arg)*)
+    };
+}
+
+/// Log with a `WARN: ` prefix.
+#[macro_export]
+macro_rules! log_warn {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        ::solana_program::msg!(concat!("WARN: ", $fmt) $(, $arg)*)
+    };
+}
+
+/// Log with a `DEBUG: ` prefix when the `verbose-logs` feature is enabled.
+#[cfg(feature = "verbose-logs")]
+#[macro_export]
+macro_rules! log_debug {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        ::solana_program::msg!(concat!("DEBUG: ", $fmt) $(, $arg)*)
+    };
+}
+
+/// Compiled out without the `verbose-logs` feature, the arguments are still type checked
+/// but never evaluated so they cost no compute units.
+#[cfg(not(feature = "verbose-logs"))]
+#[macro_export]
+macro_rules! log_debug {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {
+        if false {
+            ::solana_program::msg!(concat!("DEBUG: ", $fmt) $(, $arg)*)
+        }
+    };
+}
+
 pub fn log_keys_mismatch(msg: &str, input: Pubkey, expected: Pubkey) {
     msg!(arrform!(
         LOG_SIZE,
@@ -37,6 +86,7 @@ pub enum LogType {
     Withdraw,
     SwapBaseIn,
     SwapBaseOut,
+    SwapEvent,
 }
 
 impl LogType {
@@ -47,6 +97,7 @@ impl LogType {
             2 => LogType::Withdraw,
             3 => LogType::SwapBaseIn,
             4 => LogType::SwapBaseOut,
+            5 => LogType::SwapEvent,
             _ => unreachable!(),
         }
     }
@@ -58,6 +109,7 @@ impl LogType {
             LogType::Withdraw => 2u8,
             LogType::SwapBaseIn => 3u8,
             LogType::SwapBaseOut => 4u8,
+            LogType::SwapEvent => 5u8,
         }
     }
 }
@@ -144,13 +196,37 @@ pub struct SwapBaseOutLog {
     pub deduct_in: u64,
 }
 
-pub fn encode_ray_log<T: Serialize>(log: T) {
-    // encode
-    let bytes = bincode::serialize(&log).unwrap();
+/// Emitted once by every successful swap, after SwapBaseInLog/SwapBaseOutLog.
+///
+/// Wire format is the bincode encoding of the fields in declaration order
+/// (u8 log_type = 5, 32 byte pool, then little endian u64s), base64 encoded
+/// behind the `ray_log: ` prefix. New fields may only be appended.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SwapEvent {
+    pub log_type: u8,
+    pub pool: Pubkey,
+    pub direction: u64,
+    // amounts moved by the user, amount_in includes the fee
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee: u64,
+    // pool reserves before the swap
+    pub pool_coin: u64,
+    pub pool_pc: u64,
+}
+
+fn encode_ray_log_base64<T: Serialize>(log: &T) -> Vec<u8> {
+    let bytes = bincode::serialize(log).unwrap();
     let mut out_buf = Vec::new();
     out_buf.resize(bytes.len() * 4 / 3 + 4, 0);
     let bytes_written = base64::encode_config_slice(bytes, base64::STANDARD, &mut out_buf);
     out_buf.resize(bytes_written, 0);
+    out_buf
+}
+
+pub fn encode_ray_log<T: Serialize>(log: T) {
+    // encode
+    let out_buf = encode_ray_log_base64(&log);
     let msg_str = unsafe { std::str::from_utf8_unchecked(&out_buf) };
     msg!(arrform!(LOG_SIZE, "ray_log: {}", msg_str).as_str());
 }
@@ -178,5 +254,50 @@ pub fn decode_ray_log(log: &str) {
             let log: SwapBaseOutLog = bincode::deserialize(&bytes).unwrap();
             println!("{:?}", log);
         }
+        LogType::SwapEvent => {
+            let log: SwapEvent = bincode::deserialize(&bytes).unwrap();
+            println!("{:?}", log);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_swap_event_round_trip() {
+        let event = SwapEvent {
+            log_type: LogType::SwapEvent.into_u8(),
+            pool: Pubkey::new_unique(),
+            direction: 1,
+            amount_in: 1_000_000,
+            amount_out: 987_654,
+            fee: 2_500,
+            pool_coin: u64::MAX,
+            pool_pc: 42,
+        };
+        let encoded = encode_ray_log_base64(&event);
+        let msg_str = std::str::from_utf8(&encoded).unwrap();
+        assert!(format!("ray_log: {}", msg_str).len() <= LOG_SIZE);
+
+        let bytes = base64::decode_config(msg_str, base64::STANDARD).unwrap();
+        assert_eq!(bytes.len(), 1 + 32 + 6 * 8);
+        assert_eq!(bytes[0], LogType::SwapEvent.into_u8());
+        let decoded: SwapEvent = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, event);
+    }
+
+    #[test]
+    fn test_log_macros() {
+        let mut evaluated = false;
+        log_info!("info {}", 1);
+        log_warn!("warn {} {}", 1, 2,);
+        log_info!("no arguments");
+        log_debug!("debug {}", {
+            evaluated = true;
+            1
+        });
+        assert_eq!(evaluated, cfg!(feature = "verbose-logs"));
     }
 }
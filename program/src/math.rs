@@ -4,14 +4,17 @@
 #![allow(clippy::unknown_clippy_lints)]
 #![allow(clippy::manual_range_contains)]
 
-use crate::{error::AmmError, state::AmmInfo};
+use crate::{
+    error::AmmError,
+    state::{AmmInfo, Fees, TEN_THOUSAND},
+};
 use num_traits::CheckedDiv;
 use serum_dex::{
     matching::Side,
     state::{EventView, MarketState, OpenOrders, ToAlignedBytes},
 };
 use solana_program::{account_info::AccountInfo, log::sol_log_compute_units, msg};
-use std::{cmp::Eq, convert::identity, convert::TryInto};
+use std::{cmp::Eq, convert::identity, convert::TryFrom};
 use uint::construct_uint;
 
 construct_uint! {
@@ -21,6 +24,54 @@ construct_uint! {
     pub struct U128(2);
 }
 
+macro_rules! impl_round_div {
+    ($name:ident) => {
+        impl $name {
+            /// `self / rhs` rounded in the given direction, None if rhs is zero.
+            pub fn checked_div_round(
+                self,
+                rhs: Self,
+                round_direction: RoundDirection,
+            ) -> Option<Self> {
+                if rhs.is_zero() {
+                    return None;
+                }
+                let quotient = self.checked_div(rhs)?;
+                match round_direction {
+                    RoundDirection::Floor => Some(quotient),
+                    RoundDirection::Ceiling => {
+                        if self.checked_rem(rhs)?.is_zero() {
+                            Some(quotient)
+                        } else {
+                            quotient.checked_add(Self::one())
+                        }
+                    }
+                }
+            }
+
+            /// `self / rhs` rounded down, None if rhs is zero or the result is truncated to u64.
+            pub fn to_u64_floor(self, rhs: Self) -> Option<u64> {
+                Self::checked_to_u64(self.checked_div_round(rhs, RoundDirection::Floor)?)
+            }
+
+            /// `self / rhs` rounded up, None if rhs is zero or the result is truncated to u64.
+            pub fn to_u64_ceil(self, rhs: Self) -> Option<u64> {
+                Self::checked_to_u64(self.checked_div_round(rhs, RoundDirection::Ceiling)?)
+            }
+
+            fn checked_to_u64(val: Self) -> Option<u64> {
+                if val > Self::from(u64::MAX) {
+                    None
+                } else {
+                    Some(val.as_u64())
+                }
+            }
+        }
+    };
+}
+impl_round_div!(U128);
+impl_round_div!(U256);
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u64)]
 pub enum SwapDirection {
@@ -41,16 +92,98 @@ pub enum RoundDirection {
     Ceiling,
 }
 
+/// The balances behind a pool, read once per instruction from its vaults and open orders.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PoolReserves {
+    /// coin vault balance
+    pub coin_vault: u64,
+    /// pc vault balance
+    pub pc_vault: u64,
+    /// coin of the open orders, with the fills still in the event queue when read with the market
+    pub coin_in_orders: u64,
+    /// pc of the open orders, with the fills still in the event queue when read with the market
+    pub pc_in_orders: u64,
+    /// coin the open orders can settle to the coin vault right away
+    pub coin_free: u64,
+    /// pc the open orders can settle to the pc vault right away
+    pub pc_free: u64,
+}
+
+impl PoolReserves {
+    /// Reserves of a pool whose order book is not read, the open orders totals as stored.
+    pub fn new(coin_vault: u64, pc_vault: u64, open_orders: &OpenOrders) -> Self {
+        Self {
+            coin_vault,
+            pc_vault,
+            coin_in_orders: open_orders.native_coin_total,
+            pc_in_orders: open_orders.native_pc_total,
+            coin_free: open_orders.native_coin_free,
+            pc_free: open_orders.native_pc_free,
+        }
+    }
+
+    /// Reserves of a pool without open orders, everything it holds is in its vaults.
+    pub fn from_vaults(coin_vault: u64, pc_vault: u64) -> Self {
+        Self {
+            coin_vault,
+            pc_vault,
+            coin_in_orders: 0,
+            pc_in_orders: 0,
+            coin_free: 0,
+            pc_free: 0,
+        }
+    }
+
+    /// Reserves with the maker fills of the open orders still waiting in the event queue.
+    pub fn with_market<'a>(
+        coin_vault: u64,
+        pc_vault: u64,
+        open_orders: &'a OpenOrders,
+        market_state: &'a Box<MarketState>,
+        event_q_account: &'a AccountInfo,
+        amm_open_account: &'a AccountInfo,
+    ) -> Result<Self, AmmError> {
+        let (pc_in_orders, coin_in_orders) = Calculator::calc_exact_vault_in_serum(
+            open_orders,
+            market_state,
+            event_q_account,
+            amm_open_account,
+        )?;
+        Ok(Self {
+            coin_in_orders,
+            pc_in_orders,
+            ..Self::new(coin_vault, pc_vault, open_orders)
+        })
+    }
+
+    /// (pc, coin) of the pool minus the pnl owed to the pnl owner.
+    pub fn total_without_take_pnl(&self, amm: &AmmInfo) -> Result<(u64, u64), AmmError> {
+        let total_pc_without_take_pnl = self
+            .pc_vault
+            .checked_add(self.pc_in_orders)
+            .ok_or(AmmError::CheckedAddOverflow)?
+            .checked_sub(amm.state_data.need_take_pnl_pc)
+            .ok_or(AmmError::CheckedSubOverflow)?;
+        let total_coin_without_take_pnl = self
+            .coin_vault
+            .checked_add(self.coin_in_orders)
+            .ok_or(AmmError::CheckedAddOverflow)?
+            .checked_sub(amm.state_data.need_take_pnl_coin)
+            .ok_or(AmmError::CheckedSubOverflow)?;
+        Ok((total_pc_without_take_pnl, total_coin_without_take_pnl))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Calculator {}
 
 impl Calculator {
     pub fn to_u128(val: u64) -> Result<u128, AmmError> {
-        val.try_into().map_err(|_| AmmError::ConversionFailure)
+        Ok(to_u128(val))
     }
 
     pub fn to_u64(val: u128) -> Result<u64, AmmError> {
-        val.try_into().map_err(|_| AmmError::ConversionFailure)
+        u64::try_from(val).map_err(|_| AmmError::ConversionFailure)
     }
 
     pub fn calc_x_power(last_x: U256, last_y: U256, current_x: U256, current_y: U256) -> U256 {
@@ -300,24 +433,15 @@ impl Calculator {
         event_q_account: &'a AccountInfo,
         amm_open_account: &'a AccountInfo,
     ) -> Result<(u64, u64), AmmError> {
-        let (pc_total_in_serum, coin_total_in_serum) = Self::calc_exact_vault_in_serum(
+        PoolReserves::with_market(
+            coin_amount,
+            pc_amount,
             open_orders,
             market_state,
             event_q_account,
             amm_open_account,
-        )?;
-
-        let total_pc_without_take_pnl = pc_amount
-            .checked_add(pc_total_in_serum)
-            .ok_or(AmmError::CheckedAddOverflow)?
-            .checked_sub(amm.state_data.need_take_pnl_pc)
-            .ok_or(AmmError::CheckedSubOverflow)?;
-        let total_coin_without_take_pnl = coin_amount
-            .checked_add(coin_total_in_serum)
-            .ok_or(AmmError::CheckedAddOverflow)?
-            .checked_sub(amm.state_data.need_take_pnl_coin)
-            .ok_or(AmmError::CheckedSubOverflow)?;
-        Ok((total_pc_without_take_pnl, total_coin_without_take_pnl))
+        )?
+        .total_without_take_pnl(amm)
     }
 
     pub fn calc_total_without_take_pnl_no_orderbook<'a>(
@@ -326,19 +450,7 @@ impl Calculator {
         open_orders: &'a OpenOrders,
         amm: &'a AmmInfo,
     ) -> Result<(u64, u64), AmmError> {
-        let pc_total_in_serum = open_orders.native_pc_total;
-        let coin_total_in_serum = open_orders.native_coin_total;
-        let total_pc_without_take_pnl = pc_amount
-            .checked_add(pc_total_in_serum)
-            .ok_or(AmmError::CheckedAddOverflow)?
-            .checked_sub(amm.state_data.need_take_pnl_pc)
-            .ok_or(AmmError::CheckedSubOverflow)?;
-        let total_coin_without_take_pnl = coin_amount
-            .checked_add(coin_total_in_serum)
-            .ok_or(AmmError::CheckedAddOverflow)?
-            .checked_sub(amm.state_data.need_take_pnl_coin)
-            .ok_or(AmmError::CheckedSubOverflow)?;
-        Ok((total_pc_without_take_pnl, total_coin_without_take_pnl))
+        PoolReserves::new(coin_amount, pc_amount, open_orders).total_without_take_pnl(amm)
     }
 
     pub fn get_max_buy_size_at_price(price: u64, x: u128, y: u128, amm: &AmmInfo) -> u64 {
@@ -461,6 +573,312 @@ impl Calculator {
     }
 }
 
+/// The result of a constant product swap, all amounts in native token units.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SwapResult {
+    /// New amount of the source token in the pool, fee included
+    pub new_source_reserve: u128,
+    /// New amount of the destination token in the pool
+    pub new_dest_reserve: u128,
+    /// Amount of the destination token paid out to the user
+    pub dest_amount: u128,
+    /// Amount of the source token charged as fee
+    pub fee: u128,
+}
+
+/// The constant product curve calculator, x * y = k.
+pub struct CurveCalculator {}
+
+impl CurveCalculator {
+    /// Divide with an explicit rounding direction, None if divisor is zero.
+    pub fn checked_div_round(
+        dividend: U256,
+        divisor: U256,
+        round_direction: RoundDirection,
+    ) -> Option<U256> {
+        dividend.checked_div_round(divisor, round_direction)
+    }
+
+    /// The pool invariant k = x * y, can't overflow in U256.
+    pub fn invariant(reserve_a: u128, reserve_b: u128) -> U256 {
+        U256::from(reserve_a) * U256::from(reserve_b)
+    }
+
+    /// Swap `source_amount` in, the fee is rounded up and the output is rounded down
+    /// so that the invariant of the pool never decreases.
+    pub fn swap_base_in_out(
+        source_amount: u128,
+        swap_source_reserve: u128,
+        swap_dest_reserve: u128,
+        fee_numerator: u64,
+        fee_denominator: u64,
+    ) -> Option<SwapResult> {
+        if swap_source_reserve == 0 || swap_dest_reserve == 0 || fee_numerator > fee_denominator {
+            return None;
+        }
+        let fee = Self::checked_div_round(
+            U256::from(source_amount).checked_mul(fee_numerator.into())?,
+            fee_denominator.into(),
+            RoundDirection::Ceiling,
+        )?;
+        let source_amount_less_fee = U256::from(source_amount).checked_sub(fee)?;
+        // dest_amount = dest_reserve * source_in / (source_reserve + source_in)
+        let dest_amount = Self::checked_div_round(
+            U256::from(swap_dest_reserve).checked_mul(source_amount_less_fee)?,
+            U256::from(swap_source_reserve).checked_add(source_amount_less_fee)?,
+            RoundDirection::Floor,
+        )?;
+        let new_source_reserve = swap_source_reserve.checked_add(source_amount)?;
+        let new_dest_reserve = swap_dest_reserve.checked_sub(dest_amount.as_u128())?;
+        Some(SwapResult {
+            new_source_reserve,
+            new_dest_reserve,
+            dest_amount: dest_amount.as_u128(),
+            fee: fee.as_u128(),
+        })
+    }
+}
+
+/// Curve a pool prices its swaps on, chosen at initialization.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CurveType {
+    /// x * y = k, see `CurveCalculator`
+    ConstantProduct,
+    /// the StableSwap invariant of a pair of pegged tokens, see `StableSwap`
+    Stable { amp: u64 },
+}
+
+impl CurveType {
+    /// The invariant of the reserves on the curve, `k = x * y` or the stable `d`.
+    pub fn invariant(&self, reserve_a: u128, reserve_b: u128) -> Option<U256> {
+        match *self {
+            CurveType::ConstantProduct => Some(CurveCalculator::invariant(reserve_a, reserve_b)),
+            CurveType::Stable { amp } => StableSwap::compute_d(amp, reserve_a, reserve_b),
+        }
+    }
+
+    /// Swap `source_amount` in on the curve, see `CurveCalculator::swap_base_in_out`.
+    pub fn swap_base_in_out(
+        &self,
+        source_amount: u128,
+        swap_source_reserve: u128,
+        swap_dest_reserve: u128,
+        fee_numerator: u64,
+        fee_denominator: u64,
+    ) -> Option<SwapResult> {
+        match *self {
+            CurveType::ConstantProduct => CurveCalculator::swap_base_in_out(
+                source_amount,
+                swap_source_reserve,
+                swap_dest_reserve,
+                fee_numerator,
+                fee_denominator,
+            ),
+            CurveType::Stable { amp } => StableSwap::swap_base_in_out(
+                amp,
+                source_amount,
+                swap_source_reserve,
+                swap_dest_reserve,
+                fee_numerator,
+                fee_denominator,
+            ),
+        }
+    }
+
+    /// Amount in before fees that takes `amount_out` out of the pool totals, see
+    /// `Calculator::swap_token_amount_base_out`.
+    pub fn swap_base_out_in(
+        &self,
+        amount_out: u64,
+        total_pc_without_take_pnl: u64,
+        total_coin_without_take_pnl: u64,
+        swap_direction: SwapDirection,
+    ) -> Option<U128> {
+        match *self {
+            CurveType::ConstantProduct => Some(Calculator::swap_token_amount_base_out(
+                amount_out.into(),
+                total_pc_without_take_pnl.into(),
+                total_coin_without_take_pnl.into(),
+                swap_direction,
+            )),
+            CurveType::Stable { amp } => {
+                let (reserve_in, reserve_out) = match swap_direction {
+                    SwapDirection::Coin2PC => {
+                        (total_coin_without_take_pnl, total_pc_without_take_pnl)
+                    }
+                    SwapDirection::PC2Coin => {
+                        (total_pc_without_take_pnl, total_coin_without_take_pnl)
+                    }
+                };
+                StableSwap::swap_base_out_in(
+                    amp,
+                    amount_out.into(),
+                    reserve_in.into(),
+                    reserve_out.into(),
+                )
+                .map(U128::from)
+            }
+        }
+    }
+}
+
+/// Least amplification of a stable pool, a pool of amp 1 is still far flatter than x * y = k
+/// around the peg.
+pub const MIN_AMP: u64 = 1;
+/// Largest amplification of a stable pool, bounding the intermediate products of `StableSwap`.
+pub const MAX_AMP: u64 = 1_000_000;
+/// Newton steps `StableSwap` takes at most before giving up on convergence.
+pub const STABLE_SWAP_ITERATIONS: usize = 255;
+
+/// The StableSwap invariant of two tokens of the same decimals,
+/// 4 * amp * (x + y) + d = 4 * amp * d + d^3 / (4 * x * y), which is close to x + y = d
+/// around the peg and bends to x * y = k as the pool tips to one side. The products are
+/// checked U256 math, d^3 of u64 reserves fits with room to spare, and a solve that would
+/// overflow all the same returns None instead.
+pub struct StableSwap {}
+
+impl StableSwap {
+    /// The invariant d of the reserves, solved by Newton's method from d = x + y, None if an
+    /// amount is zero while the other is not or the iteration does not converge.
+    pub fn compute_d(amp: u64, reserve_a: u128, reserve_b: u128) -> Option<U256> {
+        let sum = U256::from(reserve_a).checked_add(reserve_b.into())?;
+        if sum.is_zero() {
+            return Some(U256::zero());
+        }
+        if reserve_a == 0 || reserve_b == 0 {
+            return None;
+        }
+        let leverage = U256::from(amp).checked_mul(U256::from(4u64))?;
+        let mut d = sum;
+        for _ in 0..STABLE_SWAP_ITERATIONS {
+            // d_p = d^3 / (4 * x * y)
+            // in one division, the floors of two lose enough precision to stop the
+            // iteration converging on a tipped pool
+            let d_p = d.checked_mul(d)?.checked_mul(d)?.checked_div(
+                U256::from(reserve_a)
+                    .checked_mul(reserve_b.into())?
+                    .checked_mul(U256::from(4u64))?,
+            )?;
+            let d_prev = d;
+            // d = (leverage * sum + 2 * d_p) * d / ((leverage - 1) * d + 3 * d_p)
+            let numerator = leverage
+                .checked_mul(sum)?
+                .checked_add(d_p.checked_mul(U256::from(2u64))?)?
+                .checked_mul(d)?;
+            let denominator = leverage
+                .checked_sub(U256::from(1u64))?
+                .checked_mul(d)?
+                .checked_add(d_p.checked_mul(U256::from(3u64))?)?;
+            d = numerator.checked_div(denominator)?;
+            if abs_diff(d, d_prev) <= U256::one() {
+                return Some(d);
+            }
+        }
+        None
+    }
+
+    /// The reserve of the other token that keeps the invariant at `d` once one token holds
+    /// `reserve_x`, solved by Newton's method from y = d. None for a zero `reserve_x` or if
+    /// the iteration does not converge.
+    pub fn compute_y(amp: u64, reserve_x: u128, d: U256) -> Option<U256> {
+        if reserve_x == 0 {
+            return None;
+        }
+        let leverage = U256::from(amp).checked_mul(U256::from(4u64))?;
+        // c = d^3 / (4 * x * leverage), b = x + d / leverage
+        let c = d.checked_mul(d)?.checked_mul(d)?.checked_div(
+            U256::from(reserve_x)
+                .checked_mul(leverage)?
+                .checked_mul(U256::from(4u64))?,
+        )?;
+        let b = U256::from(reserve_x).checked_add(d.checked_div(leverage)?)?;
+        let mut y = d;
+        for _ in 0..STABLE_SWAP_ITERATIONS {
+            let y_prev = y;
+            // y = (y^2 + c) / (2 * y + b - d)
+            y = y.checked_mul(y)?.checked_add(c)?.checked_div(
+                y.checked_mul(U256::from(2u64))?
+                    .checked_add(b)?
+                    .checked_sub(d)?,
+            )?;
+            if abs_diff(y, y_prev) <= U256::one() {
+                return Some(y);
+            }
+        }
+        None
+    }
+
+    /// Swap `source_amount` in, the fee is taken from the input and rounded up as on the
+    /// constant product curve, the output is rounded down by one more unit than the solver
+    /// error so that the invariant of the pool never decreases.
+    pub fn swap_base_in_out(
+        amp: u64,
+        source_amount: u128,
+        swap_source_reserve: u128,
+        swap_dest_reserve: u128,
+        fee_numerator: u64,
+        fee_denominator: u64,
+    ) -> Option<SwapResult> {
+        if swap_source_reserve == 0 || swap_dest_reserve == 0 || fee_numerator > fee_denominator {
+            return None;
+        }
+        let fee = CurveCalculator::checked_div_round(
+            U256::from(source_amount).checked_mul(fee_numerator.into())?,
+            fee_denominator.into(),
+            RoundDirection::Ceiling,
+        )?
+        .as_u128();
+        let source_amount_less_fee = source_amount.checked_sub(fee)?;
+        let d = Self::compute_d(amp, swap_source_reserve, swap_dest_reserve)?;
+        let new_dest = Self::compute_y(
+            amp,
+            swap_source_reserve.checked_add(source_amount_less_fee)?,
+            d,
+        )?;
+        let dest_amount = U256::from(swap_dest_reserve)
+            .checked_sub(new_dest)?
+            .saturating_sub(U256::one())
+            .as_u128();
+        Some(SwapResult {
+            new_source_reserve: swap_source_reserve.checked_add(source_amount)?,
+            new_dest_reserve: swap_dest_reserve.checked_sub(dest_amount)?,
+            dest_amount,
+            fee,
+        })
+    }
+
+    /// Source amount, before the fee, that takes `dest_amount` out of the pool, rounded up
+    /// by one more unit than the solver error. None unless `dest_amount` is below the
+    /// destination reserve.
+    pub fn swap_base_out_in(
+        amp: u64,
+        dest_amount: u128,
+        swap_source_reserve: u128,
+        swap_dest_reserve: u128,
+    ) -> Option<u128> {
+        if dest_amount >= swap_dest_reserve {
+            return None;
+        }
+        let d = Self::compute_d(amp, swap_source_reserve, swap_dest_reserve)?;
+        let new_source = Self::compute_y(amp, swap_dest_reserve - dest_amount, d)?;
+        let source_amount = new_source
+            .checked_sub(swap_source_reserve.into())?
+            .checked_add(U256::one())?;
+        if source_amount > U256::from(u128::MAX) {
+            return None;
+        }
+        Some(source_amount.as_u128())
+    }
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
 /// The invariant calculator.
 pub struct InvariantToken {
     /// Token coin
@@ -661,3 +1079,1156 @@ impl CheckedCeilDiv for U128 {
         Some((quotient, rhs))
     }
 }
+
+/// Widens `x` for curve math, lossless unlike narrowing back with `from_u128`.
+pub fn to_u128(x: u64) -> u128 {
+    u128::from(x)
+}
+
+/// Narrows a curve math result back to a token amount, an `x` above `u64::MAX` is an error
+/// instead of a truncated amount.
+pub fn from_u128(x: u128) -> Result<u64, AmmError> {
+    u64::try_from(x).map_err(|_| AmmError::CalculationFailure)
+}
+
+/// `from_u128` of a `U256` intermediate, which `U256::as_u64` would panic on.
+pub fn from_u256(x: U256) -> Result<u64, AmmError> {
+    if x > U256::from(u64::MAX) {
+        return Err(AmmError::CalculationFailure);
+    }
+    Ok(x.as_u64())
+}
+
+/// Price impact in basis points of swapping `amount_in` against the constant product curve,
+/// (spot_price - execution_price) / spot_price rounded up, fees excluded.
+/// The execution price uses the floored output, so dust trades report a large impact.
+/// A trade against an empty reserve can not be filled and is reported as 100% impact.
+pub fn price_impact_bps(amount_in: u64, reserve_in: u64, reserve_out: u64) -> u64 {
+    if amount_in == 0 {
+        return 0;
+    }
+    if reserve_in == 0 || reserve_out == 0 {
+        return TEN_THOUSAND;
+    }
+    let amount_out = U256::from(amount_in) * U256::from(reserve_out)
+        / (U256::from(reserve_in) + U256::from(amount_in));
+    // spot = reserve_out / reserve_in, execution = amount_out / amount_in
+    let spot_value = U256::from(amount_in) * U256::from(reserve_out);
+    let execution_value = amount_out * U256::from(reserve_in);
+    (spot_value - execution_value)
+        .checked_mul(U256::from(TEN_THOUSAND))
+        .unwrap()
+        .checked_div_round(spot_value, RoundDirection::Ceiling)
+        .unwrap()
+        .as_u64()
+}
+
+/// Amount out of a swap base in of `amount_in` against `reserve_in` and `reserve_out`,
+/// charging `fee_numerator / fee_denominator` of the input. This is the math of the
+/// on-chain swap, so other programs can quote a pool without an RPC round trip.
+pub fn get_amount_out(
+    amount_in: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<u64, AmmError> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(AmmError::CheckedEmptyFunds);
+    }
+    if fee_numerator > fee_denominator {
+        return Err(AmmError::InvalidFee);
+    }
+    let fees = Fees {
+        swap_fee_numerator: fee_numerator,
+        swap_fee_denominator: fee_denominator,
+        ..Fees::default()
+    };
+    let swap_fee = fees
+        .trading_fee(amount_in.into())
+        .ok_or(AmmError::InvalidFee)?;
+    let swap_in_after_deduct_fee = U128::from(amount_in)
+        .checked_sub(U128::from(swap_fee))
+        .ok_or(AmmError::CalculationFailure)?;
+    // the coin side stands for the input reserve
+    Calculator::to_u64(
+        Calculator::swap_token_amount_base_in(
+            swap_in_after_deduct_fee,
+            reserve_out.into(),
+            reserve_in.into(),
+            SwapDirection::Coin2PC,
+        )
+        .as_u128(),
+    )
+}
+
+/// Pc a deposit of `coin_amount` has to bring along to keep the pool ratio, rounded up so
+/// the existing lp holders are never diluted. None for an empty pool, whose first deposit
+/// sets the ratio with both sides, or when the amount does not fit in u64.
+pub fn deposit_pc_for_coin(coin_amount: u64, reserve_coin: u64, reserve_pc: u64) -> Option<u64> {
+    deposit_matching_amount(coin_amount, reserve_coin, reserve_pc)
+}
+
+/// Coin a deposit of `pc_amount` has to bring along, see `deposit_pc_for_coin`.
+pub fn deposit_coin_for_pc(pc_amount: u64, reserve_coin: u64, reserve_pc: u64) -> Option<u64> {
+    deposit_matching_amount(pc_amount, reserve_pc, reserve_coin)
+}
+
+fn deposit_matching_amount(amount: u64, reserve_base: u64, reserve_other: u64) -> Option<u64> {
+    if reserve_base == 0 || reserve_other == 0 {
+        return None;
+    }
+    U128::from(amount)
+        .checked_mul(reserve_other.into())?
+        .to_u64_ceil(reserve_base.into())
+}
+
+/// Lp to mint for a deposit of `coin_in` and `pc_in`, the smaller of the two shares of
+/// `lp_supply` the sides are worth, floored as in Uniswap V2. A side brought in excess of
+/// the pool ratio credits nothing. None for a pool without lp supply, whose first deposit
+/// is priced by the initial liquidity instead, for an empty reserve or on overflow.
+pub fn lp_tokens_for_deposit(
+    coin_in: u64,
+    pc_in: u64,
+    reserve_coin: u64,
+    reserve_pc: u64,
+    lp_supply: u64,
+) -> Option<u64> {
+    if lp_supply == 0 || reserve_coin == 0 || reserve_pc == 0 {
+        return None;
+    }
+    let share = |amount: u64, reserve: u64| {
+        U128::from(amount)
+            .checked_mul(lp_supply.into())?
+            .to_u64_floor(reserve.into())
+    };
+    Some(share(coin_in, reserve_coin)?.min(share(pc_in, reserve_pc)?))
+}
+
+/// Part of a single sided deposit of `amount` that is swapped to the other side first, so
+/// that the swap output and the rest of `amount` match the pool ratio after the swap.
+///
+/// Solves `g*s^2 + r*(1+g)*s - amount*r = 0` for `s`, with `r = reserve_in` and
+/// `g = 1 - fee_numerator / fee_denominator`. The quadratic treats the fee as an exact
+/// fraction of the input that stays in the pool, while the swap rounds the fee up and the
+/// protocol share of it leaves the reserves. Together with the floored root this puts `s`
+/// slightly below the exact optimum, so the deposit leaves a little dust of `amount` with
+/// the user. None if the fee takes the whole input or the terms overflow.
+pub fn single_sided_swap_amount(
+    amount: u64,
+    reserve_in: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Option<u64> {
+    // multiplied through by fee_denominator: k*s^2 + r*(d+k)*s - amount*r*d = 0, k = d - n
+    let d = U256::from(fee_denominator);
+    let k = U256::from(fee_denominator.checked_sub(fee_numerator)?);
+    if k.is_zero() {
+        return None;
+    }
+    let r = U256::from(reserve_in);
+    let b = r.checked_mul(d.checked_add(k)?)?;
+    let discriminant = b.checked_mul(b)?.checked_add(
+        U256::from(4u8)
+            .checked_mul(k)?
+            .checked_mul(U256::from(amount))?
+            .checked_mul(r)?
+            .checked_mul(d)?,
+    )?;
+    let s = (discriminant.integer_sqrt().checked_sub(b)?) / (U256::from(2u8) * k);
+    Some(s.min(U256::from(amount)).as_u64())
+}
+
+/// The amounts of a single sided deposit, see `single_sided_deposit`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SingleSidedDeposit {
+    /// part of the input swapped to the other side
+    pub swap_in: u64,
+    /// swap fee charged on `swap_in`, protocol share included
+    pub swap_fee: u64,
+    /// protocol share of `swap_fee`, owed to the pnl owner
+    pub protocol_fee: u64,
+    /// other side bought by the swap, all of it is deposited
+    pub swap_out: u64,
+    /// part of the input deposited next to `swap_out`
+    pub deposit_in: u64,
+    /// lp minted for the deposit
+    pub mint_lp: u64,
+}
+
+/// Swaps part of `amount` against the pool and deposits the output together with the rest
+/// of `amount`, all within the pool. The deposit keeps the ratio rounded in favour of the
+/// pool, `amount - swap_in - deposit_in` is the dust the user keeps.
+pub fn single_sided_deposit(
+    amm: &AmmInfo,
+    amount: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    lp_amount: u64,
+) -> Result<SingleSidedDeposit, AmmError> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return Err(AmmError::CheckedEmptyFunds);
+    }
+    let swap_in = single_sided_swap_amount(
+        amount,
+        reserve_in,
+        amm.fees.swap_fee_numerator,
+        amm.fees.swap_fee_denominator,
+    )
+    .ok_or(AmmError::CalculationFailure)?;
+    let swap_fee = Calculator::to_u64(
+        amm.fees
+            .trading_fee(swap_in.into())
+            .ok_or(AmmError::CalculationFailure)?,
+    )?;
+    let protocol_fee = Calculator::to_u64(
+        amm.owner_trading_fee(swap_fee.into())
+            .ok_or(AmmError::CalculationFailure)?,
+    )?;
+    // the input side stands for the coin side
+    let swap_out = Calculator::swap_token_amount_base_in(
+        U128::from(swap_in - swap_fee),
+        reserve_out.into(),
+        reserve_in.into(),
+        SwapDirection::Coin2PC,
+    )
+    .as_u64();
+    if swap_out >= reserve_out {
+        return Err(AmmError::InsufficientFunds);
+    }
+    let reserve_in = reserve_in
+        .checked_add(swap_in - protocol_fee)
+        .ok_or(AmmError::CheckedAddOverflow)?;
+    let reserve_out = reserve_out - swap_out;
+    let rest = amount - swap_in;
+    let deposit_in = deposit_matching_amount(swap_out, reserve_out, reserve_in)
+        .ok_or(AmmError::CalculationExRateFailure)?
+        .min(rest);
+    // an input short of the ratio mints by the input side, the surplus output stays pooled
+    let mint_lp = lp_tokens_for_deposit(deposit_in, swap_out, reserve_in, reserve_out, lp_amount)
+        .ok_or(AmmError::CalculationExRateFailure)?;
+    Ok(SingleSidedDeposit {
+        swap_in,
+        swap_fee,
+        protocol_fee,
+        swap_out,
+        deposit_in,
+        mint_lp,
+    })
+}
+
+/// The amounts of a single sided withdraw, see `single_sided_withdraw`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SingleSidedWithdraw {
+    /// share of the converted side the lp is worth, swapped to the paid side
+    pub withdraw_in: u64,
+    /// share of the paid side the lp is worth
+    pub withdraw_out: u64,
+    /// swap fee charged on `withdraw_in`, protocol share included
+    pub swap_fee: u64,
+    /// protocol share of `swap_fee`, owed to the pnl owner
+    pub protocol_fee: u64,
+    /// paid side bought with `withdraw_in`
+    pub swap_out: u64,
+    /// paid to the user, `withdraw_out + swap_out`
+    pub amount_out: u64,
+}
+
+/// Withdraws the share of both reserves `lp` is worth and swaps the `reserve_in` side of it
+/// to the `reserve_out` side against the pool left after the withdraw, so the user is paid
+/// in a single token. This is a withdraw followed by a swap base in at the pool fee, the
+/// user bears the fee and the price impact of the swap.
+pub fn single_sided_withdraw(
+    amm: &AmmInfo,
+    lp: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    lp_amount: u64,
+) -> Result<SingleSidedWithdraw, AmmError> {
+    if lp >= lp_amount {
+        return Err(AmmError::NotAllowZeroLP);
+    }
+    let share = |reserve: u64| {
+        InvariantPool {
+            token_input: lp,
+            token_total: lp_amount,
+        }
+        .exchange_pool_to_token(reserve, RoundDirection::Floor)
+        .ok_or(AmmError::CalculationExRateFailure)
+    };
+    let withdraw_in = share(reserve_in)?;
+    let withdraw_out = share(reserve_out)?;
+    let swap_fee = Calculator::to_u64(
+        amm.fees
+            .trading_fee(withdraw_in.into())
+            .ok_or(AmmError::CalculationFailure)?,
+    )?;
+    let protocol_fee = Calculator::to_u64(
+        amm.owner_trading_fee(swap_fee.into())
+            .ok_or(AmmError::CalculationFailure)?,
+    )?;
+    // the converted side stands for the coin side
+    let swap_out = Calculator::swap_token_amount_base_in(
+        U128::from(withdraw_in - swap_fee),
+        (reserve_out - withdraw_out).into(),
+        (reserve_in - withdraw_in).into(),
+        SwapDirection::Coin2PC,
+    )
+    .as_u64();
+    let amount_out = withdraw_out
+        .checked_add(swap_out)
+        .ok_or(AmmError::CheckedAddOverflow)?;
+    Ok(SingleSidedWithdraw {
+        withdraw_in,
+        withdraw_out,
+        swap_fee,
+        protocol_fee,
+        swap_out,
+        amount_out,
+    })
+}
+
+/// Floor of the square root of `n`, computed with Newton's method.
+pub const fn integer_sqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // start from a power of two that is not below the root, the iteration then decreases
+    // monotonically until it reaches the floor
+    let bits = 128 - n.leading_zeros();
+    let mut x = 1u128 << ((bits + 1) / 2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// Decimals of the fixed-point price returned by `checked_spot_price_fixed`.
+pub const SPOT_PRICE_DECIMALS: u32 = 12;
+
+/// Spot price of one ui coin in ui pc, None if either reserve is empty.
+pub fn checked_spot_price(
+    reserve_coin: u64,
+    reserve_pc: u64,
+    coin_decimals: u8,
+    pc_decimals: u8,
+) -> Option<f64> {
+    if reserve_coin == 0 || reserve_pc == 0 {
+        return None;
+    }
+    let ui_coin = reserve_coin as f64 / 10f64.powi(coin_decimals.into());
+    let ui_pc = reserve_pc as f64 / 10f64.powi(pc_decimals.into());
+    Some(ui_pc / ui_coin)
+}
+
+/// Spot price of one ui coin in ui pc, 0 if either reserve is empty.
+pub fn spot_price(reserve_coin: u64, reserve_pc: u64, coin_decimals: u8, pc_decimals: u8) -> f64 {
+    checked_spot_price(reserve_coin, reserve_pc, coin_decimals, pc_decimals).unwrap_or(0.0)
+}
+
+/// Spot price of one ui coin in ui pc scaled by 10^SPOT_PRICE_DECIMALS and rounded down.
+/// None if either reserve is empty or the scaled price does not fit in u128.
+pub fn checked_spot_price_fixed(
+    reserve_coin: u64,
+    reserve_pc: u64,
+    coin_decimals: u8,
+    pc_decimals: u8,
+) -> Option<u128> {
+    if reserve_coin == 0 || reserve_pc == 0 {
+        return None;
+    }
+    // pc * 10^(coin_decimals + SPOT_PRICE_DECIMALS) / (coin * 10^pc_decimals)
+    let ten = U256::from(10u64);
+    let numerator = U256::from(reserve_pc).checked_mul(
+        ten.checked_pow(U256::from(u32::from(coin_decimals) + SPOT_PRICE_DECIMALS))?,
+    )?;
+    let denominator =
+        U256::from(reserve_coin).checked_mul(ten.checked_pow(U256::from(pc_decimals))?)?;
+    let price = numerator.checked_div(denominator)?;
+    if price > U256::from(u128::MAX) {
+        return None;
+    }
+    Some(price.as_u128())
+}
+
+/// Deviation of `price` from `expected_price` in basis points of `expected_price`, rounded
+/// up and capped at u64::MAX. None if `expected_price` is 0.
+pub fn price_deviation_bps(price: u128, expected_price: u128) -> Option<u64> {
+    if expected_price == 0 {
+        return None;
+    }
+    let deviation = U256::from(price.max(expected_price) - price.min(expected_price));
+    let bps = (deviation * U256::from(TEN_THOUSAND))
+        .checked_div_round(U256::from(expected_price), RoundDirection::Ceiling)?;
+    Some(if bps > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        bps.as_u64()
+    })
+}
+
+/// Fee numerator growing linearly from `base_fee_numerator` with a price `deviation_bps`
+/// away from its average, up to `max_fee_numerator` at `max_fee_deviation_bps` and beyond.
+/// Rounded up, the pool never charges less than the line between the two.
+pub fn dynamic_fee_numerator(
+    base_fee_numerator: u64,
+    max_fee_numerator: u64,
+    deviation_bps: u64,
+    max_fee_deviation_bps: u64,
+) -> u64 {
+    if max_fee_numerator <= base_fee_numerator || deviation_bps >= max_fee_deviation_bps {
+        return max_fee_numerator.max(base_fee_numerator);
+    }
+    // deviation_bps < max_fee_deviation_bps, so the elevation stays below the fee spread
+    let spread = (max_fee_numerator - base_fee_numerator) as u128;
+    let divisor = max_fee_deviation_bps as u128;
+    let elevation = (spread * deviation_bps as u128 + divisor - 1) / divisor;
+    base_fee_numerator + elevation as u64
+}
+
+/// Time weighted average of the Q64.64 price whose accumulator (see
+/// `AmmInfo::price_cumulative`) was snapshotted at two timestamps.
+/// The accumulator wraps, so the difference is taken modulo 2^128.
+/// None unless the end snapshot is strictly later than the start.
+pub fn twap(
+    cumulative_start: u128,
+    timestamp_start: u64,
+    cumulative_end: u128,
+    timestamp_end: u64,
+) -> Option<u128> {
+    let elapsed = timestamp_end
+        .checked_sub(timestamp_start)
+        .filter(|elapsed| *elapsed > 0)?;
+    Some(cumulative_end.wrapping_sub(cumulative_start) / u128::from(elapsed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// xorshift64, deterministic so that failures can be reproduced
+    pub(super) fn next_rand(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_swap_base_in_out_invariant_never_decreases() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let max_reserve = u64::MAX / 2;
+        for _ in 0..10000 {
+            let source_reserve = next_rand(&mut seed) % max_reserve + 1;
+            let dest_reserve = next_rand(&mut seed) % max_reserve + 1;
+            let source_amount = next_rand(&mut seed) % max_reserve;
+            let result = CurveCalculator::swap_base_in_out(
+                source_amount.into(),
+                source_reserve.into(),
+                dest_reserve.into(),
+                25,
+                10000,
+            )
+            .unwrap();
+            let old_k = CurveCalculator::invariant(source_reserve.into(), dest_reserve.into());
+            let new_k =
+                CurveCalculator::invariant(result.new_source_reserve, result.new_dest_reserve);
+            assert!(
+                new_k >= old_k,
+                "source_reserve:{}, dest_reserve:{}, source_amount:{}",
+                source_reserve,
+                dest_reserve,
+                source_amount
+            );
+            assert!(result.dest_amount < u128::from(dest_reserve));
+        }
+    }
+
+    #[test]
+    fn test_stable_swap_solvers_converge() {
+        // a balanced pool sits on the peg, where d = x + y
+        for &amp in &[MIN_AMP, 100, MAX_AMP] {
+            for &reserve in &[1u64, 1_000, 1_000_000_000_000, u64::MAX] {
+                let d = StableSwap::compute_d(amp, reserve.into(), reserve.into()).unwrap();
+                assert_eq!(d, U256::from(reserve) * U256::from(2u64));
+            }
+        }
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        for &amp in &[MIN_AMP, 10, 100, 2_000, MAX_AMP] {
+            for i in 0..2_000 {
+                let reserve_a = next_rand(&mut seed) % u64::MAX + 1;
+                let mut reserve_b = next_rand(&mut seed);
+                // every other pool tipped up to 2^62 times to one side
+                if i % 2 == 1 {
+                    reserve_b >>= next_rand(&mut seed) % 63;
+                }
+                let reserve_b = reserve_b.max(1);
+                let d = StableSwap::compute_d(amp, reserve_a.into(), reserve_b.into()).unwrap();
+                // solving back for the other reserve lands within the rounding of d
+                let reserve_y = StableSwap::compute_y(amp, reserve_a.into(), d).unwrap();
+                assert!(
+                    abs_diff(reserve_y, reserve_b.into())
+                        <= U256::from(reserve_b / 1_000_000_000 + 2),
+                    "amp:{}, reserve_a:{}, reserve_b:{}",
+                    amp,
+                    reserve_a,
+                    reserve_b
+                );
+            }
+        }
+        // nothing to solve against an empty side
+        assert_eq!(StableSwap::compute_d(100, 0, 0), Some(U256::zero()));
+        assert_eq!(StableSwap::compute_d(100, 1_000, 0), None);
+        assert_eq!(StableSwap::compute_y(100, 0, U256::from(1_000u64)), None);
+    }
+
+    #[test]
+    fn test_stable_swap_invariant_never_decreases() {
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let max_reserve = u64::MAX / 2;
+        for &amp in &[MIN_AMP, 100, MAX_AMP] {
+            for _ in 0..3000 {
+                let source_reserve = next_rand(&mut seed) % max_reserve + 1;
+                let dest_reserve = next_rand(&mut seed) % max_reserve + 1;
+                let source_amount = next_rand(&mut seed) % max_reserve;
+                let result = StableSwap::swap_base_in_out(
+                    amp,
+                    source_amount.into(),
+                    source_reserve.into(),
+                    dest_reserve.into(),
+                    25,
+                    10000,
+                )
+                .unwrap();
+                let old_d =
+                    StableSwap::compute_d(amp, source_reserve.into(), dest_reserve.into()).unwrap();
+                let new_d =
+                    StableSwap::compute_d(amp, result.new_source_reserve, result.new_dest_reserve)
+                        .unwrap();
+                assert!(
+                    new_d >= old_d,
+                    "amp:{}, source_reserve:{}, dest_reserve:{}, source_amount:{}",
+                    amp,
+                    source_reserve,
+                    dest_reserve,
+                    source_amount
+                );
+                assert!(result.dest_amount < u128::from(dest_reserve));
+
+                // the input solved for an output buys at least that output
+                let dest_amount = next_rand(&mut seed) % dest_reserve;
+                let source_amount = StableSwap::swap_base_out_in(
+                    amp,
+                    dest_amount.into(),
+                    source_reserve.into(),
+                    dest_reserve.into(),
+                )
+                .unwrap();
+                let result = StableSwap::swap_base_in_out(
+                    amp,
+                    source_amount,
+                    source_reserve.into(),
+                    dest_reserve.into(),
+                    0,
+                    10000,
+                )
+                .unwrap();
+                assert!(result.dest_amount >= dest_amount.into());
+            }
+        }
+    }
+
+    #[test]
+    fn test_stable_swap_flatter_than_constant_product() {
+        let reserve = 1_000_000_000_000u128;
+        let amount_in = 1_000_000_000u128;
+        let constant_product = CurveType::ConstantProduct
+            .swap_base_in_out(amount_in, reserve, reserve, 25, 10000)
+            .unwrap();
+        let stable = CurveType::Stable { amp: 100 }
+            .swap_base_in_out(amount_in, reserve, reserve, 25, 10000)
+            .unwrap();
+        assert_eq!(stable.fee, constant_product.fee);
+        assert!(stable.dest_amount > constant_product.dest_amount);
+        // within a thousandth of the input less the fee on a balanced pool
+        let amount_in_less_fee = amount_in - stable.fee;
+        assert!(stable.dest_amount * 1000 >= amount_in_less_fee * 999);
+        assert!(stable.dest_amount < amount_in_less_fee);
+    }
+
+    #[test]
+    fn test_reserves_product_near_u64_max() {
+        let reserve_a = u64::MAX - 1;
+        let reserve_b = u64::MAX - 2;
+        // reserve_a * reserve_b overflows u64 but fits in U128
+        assert!(reserve_a.checked_mul(reserve_b).is_none());
+        let product = U128::from(reserve_a)
+            .checked_mul(U128::from(reserve_b))
+            .unwrap();
+        assert_eq!(
+            product.as_u128(),
+            u128::from(reserve_a) * u128::from(reserve_b)
+        );
+        assert_eq!(product.to_u64_floor(reserve_a.into()), Some(reserve_b));
+        assert_eq!(product.to_u64_ceil(reserve_a.into()), Some(reserve_b));
+        // (MAX - 1) * (MAX - 2) / MAX = MAX - 3 + 2 / MAX
+        assert_eq!(product.to_u64_floor(u64::MAX.into()), Some(reserve_b - 1));
+        assert_eq!(product.to_u64_ceil(u64::MAX.into()), Some(reserve_b));
+        // truncation is reported instead of wrapping
+        assert_eq!(product.to_u64_floor(U128::one()), None);
+        assert_eq!(product.to_u64_floor(U128::zero()), None);
+    }
+
+    #[test]
+    fn test_checked_div_round() {
+        let (seven, two) = (U256::from(7), U256::from(2));
+        assert_eq!(
+            CurveCalculator::checked_div_round(seven, two, RoundDirection::Floor),
+            Some(U256::from(3))
+        );
+        assert_eq!(
+            CurveCalculator::checked_div_round(seven, two, RoundDirection::Ceiling),
+            Some(U256::from(4))
+        );
+        assert_eq!(
+            CurveCalculator::checked_div_round(seven, U256::zero(), RoundDirection::Floor),
+            None
+        );
+    }
+
+    #[test]
+    fn test_price_impact_bps() {
+        // small trades barely move the price
+        assert_eq!(
+            price_impact_bps(1_000_000, 1_000_000_000_000, 1_000_000_000_000),
+            1
+        );
+        assert_eq!(price_impact_bps(0, 1_000, 1_000), 0);
+        // consuming half of the output reserve halves the execution price
+        assert_eq!(price_impact_bps(1_000_000, 1_000_000, 4_000_000), 5_000);
+        assert_eq!(price_impact_bps(u64::MAX, u64::MAX, u64::MAX - 1), 5_000);
+        // the floored output dominates the impact of dust trades
+        assert_eq!(price_impact_bps(1, 50_000_000, 80_000_000), 3_750);
+        // otherwise larger trades have at least as much impact
+        let mut last = 0;
+        for amount_in in [
+            100_000u64,
+            1_000_000,
+            10_000_000,
+            100_000_000,
+            1_000_000_000,
+        ] {
+            let impact = price_impact_bps(amount_in, 50_000_000, 80_000_000);
+            assert!(impact >= last);
+            last = impact;
+        }
+        // empty reserves
+        assert_eq!(price_impact_bps(1_000, 0, 1_000), TEN_THOUSAND);
+        assert_eq!(price_impact_bps(1_000, 1_000, 0), TEN_THOUSAND);
+    }
+
+    #[test]
+    fn test_pool_reserves_include_open_orders() {
+        let mut open_orders: OpenOrders = bytemuck::Zeroable::zeroed();
+        open_orders.native_coin_total = 700;
+        open_orders.native_coin_free = 200;
+        open_orders.native_pc_total = 9_000;
+        open_orders.native_pc_free = 4_000;
+        let reserves = PoolReserves::new(1_000, 50_000, &open_orders);
+        assert_eq!(
+            reserves,
+            PoolReserves {
+                coin_vault: 1_000,
+                pc_vault: 50_000,
+                coin_in_orders: 700,
+                pc_in_orders: 9_000,
+                coin_free: 200,
+                pc_free: 4_000,
+            }
+        );
+
+        // the pnl owed to the pnl owner is not part of the pool
+        let mut amm = AmmInfo::default();
+        assert_eq!(reserves.total_without_take_pnl(&amm), Ok((59_000, 1_700)));
+        amm.state_data.need_take_pnl_coin = 100;
+        amm.state_data.need_take_pnl_pc = 1_000;
+        assert_eq!(reserves.total_without_take_pnl(&amm), Ok((58_000, 1_600)));
+        assert_eq!(
+            Calculator::calc_total_without_take_pnl_no_orderbook(1_000, 50_000, &open_orders, &amm),
+            Ok((58_000, 1_600))
+        );
+        amm.state_data.need_take_pnl_coin = 1_701;
+        assert_eq!(
+            reserves.total_without_take_pnl(&amm),
+            Err(AmmError::CheckedSubOverflow)
+        );
+
+        // a pool without open orders is its vaults
+        let reserves = PoolReserves::from_vaults(1_000, 50_000);
+        assert_eq!(
+            reserves,
+            PoolReserves::new(1_000, 50_000, &bytemuck::Zeroable::zeroed())
+        );
+        amm.state_data.need_take_pnl_coin = 100;
+        assert_eq!(reserves.total_without_take_pnl(&amm), Ok((49_000, 900)));
+    }
+
+    #[test]
+    fn test_price_deviation_bps() {
+        assert_eq!(price_deviation_bps(1_000, 1_000), Some(0));
+        assert_eq!(price_deviation_bps(1_100, 1_000), Some(1_000));
+        assert_eq!(price_deviation_bps(900, 1_000), Some(1_000));
+        // rounded up so a bound is never loosened
+        assert_eq!(price_deviation_bps(1_000_001, 1_000_000), Some(1));
+        assert_eq!(price_deviation_bps(0, 1_000), Some(TEN_THOUSAND));
+        assert_eq!(price_deviation_bps(u128::MAX, 1), Some(u64::MAX));
+        assert_eq!(price_deviation_bps(1_000, 0), None);
+    }
+
+    #[test]
+    fn test_dynamic_fee_numerator() {
+        // 25 bps at rest, 100 bps once the price moved 5%
+        let fee = |deviation_bps| dynamic_fee_numerator(25, 100, deviation_bps, 500);
+        assert_eq!(fee(0), 25);
+        assert_eq!(fee(100), 40);
+        assert_eq!(fee(250), 63);
+        assert_eq!(fee(499), 100);
+        assert_eq!(fee(500), 100);
+        assert_eq!(fee(u64::MAX), 100);
+        // rounded up
+        assert_eq!(fee(1), 26);
+        // without a spread there is nothing to scale
+        assert_eq!(dynamic_fee_numerator(25, 25, 250, 500), 25);
+        assert_eq!(dynamic_fee_numerator(25, 25, 0, 0), 25);
+        assert_eq!(
+            dynamic_fee_numerator(0, u64::MAX, u64::MAX - 1, u64::MAX),
+            u64::MAX - 1
+        );
+    }
+
+    #[test]
+    fn test_spot_price_with_differing_decimals() {
+        // 2 coin with 9 decimals against 300 pc with 6 decimals
+        assert_eq!(spot_price(2_000_000_000, 300_000_000, 9, 6), 150.0);
+        assert_eq!(
+            checked_spot_price_fixed(2_000_000_000, 300_000_000, 9, 6),
+            Some(150 * 10u128.pow(SPOT_PRICE_DECIMALS))
+        );
+        // 4 coin with 6 decimals against 2 pc with 9 decimals
+        assert_eq!(
+            checked_spot_price(4_000_000, 2_000_000_000, 6, 9),
+            Some(0.5)
+        );
+        assert_eq!(
+            checked_spot_price_fixed(4_000_000, 2_000_000_000, 6, 9),
+            Some(5 * 10u128.pow(SPOT_PRICE_DECIMALS - 1))
+        );
+        // 1/3 is truncated at the last fixed point decimal
+        assert_eq!(
+            checked_spot_price_fixed(3_000_000, 1_000_000, 6, 6),
+            Some(333_333_333_333)
+        );
+
+        assert_eq!(checked_spot_price(0, 1, 6, 6), None);
+        assert_eq!(checked_spot_price(1, 0, 6, 6), None);
+        assert_eq!(checked_spot_price_fixed(0, 1, 6, 6), None);
+        assert_eq!(spot_price(0, 1, 6, 6), 0.0);
+        // the scaled price overflows u128
+        assert_eq!(checked_spot_price_fixed(1, u64::MAX, 30, 0), None);
+    }
+
+    #[test]
+    fn test_deposit_matching_amounts_round_up() {
+        // exact ratios are not rounded
+        assert_eq!(deposit_pc_for_coin(500, 1_000, 2_000), Some(1_000));
+        assert_eq!(deposit_coin_for_pc(1_000, 1_000, 2_000), Some(500));
+        // 10 * 2_000_011 / 1_000_003 = 20.0000...6, the depositor pays the remainder
+        assert_eq!(deposit_pc_for_coin(10, 1_000_003, 2_000_011), Some(21));
+        // 7 * 3 / 2 = 10.5 and 7 * 2 / 3 = 4.67
+        assert_eq!(deposit_pc_for_coin(7, 2, 3), Some(11));
+        assert_eq!(deposit_coin_for_pc(7, 2, 3), Some(5));
+        // dust still pays at least one unit of the other side
+        assert_eq!(deposit_pc_for_coin(1, 1_000_000, 1), Some(1));
+        assert_eq!(deposit_coin_for_pc(1, 1, 1_000_000), Some(1));
+        assert_eq!(deposit_pc_for_coin(0, 1_000, 2_000), Some(0));
+
+        for (amount, reserve_coin, reserve_pc) in [
+            (333, 1_000_003, 2_000_011),
+            (1, 3, 7),
+            (999_999, 1_000_000_007, 999_999_937),
+        ] {
+            let pc = deposit_pc_for_coin(amount, reserve_coin, reserve_pc).unwrap();
+            // pc / coin is never below the pool ratio, and by less than one unit of pc
+            assert!(
+                u128::from(pc) * u128::from(reserve_coin)
+                    >= u128::from(amount) * u128::from(reserve_pc)
+            );
+            assert!(
+                u128::from(pc - 1) * u128::from(reserve_coin)
+                    < u128::from(amount) * u128::from(reserve_pc)
+            );
+        }
+
+        // the first deposit of an empty pool sets the ratio with both sides
+        assert_eq!(deposit_pc_for_coin(1, 0, 0), None);
+        assert_eq!(deposit_pc_for_coin(1, 0, 1), None);
+        assert_eq!(deposit_coin_for_pc(1, 1, 0), None);
+        // the matching side does not fit in u64
+        assert_eq!(deposit_pc_for_coin(u64::MAX, 1, 2), None);
+    }
+
+    fn assert_is_floor_sqrt(n: u128) {
+        let root = integer_sqrt(n);
+        assert!(root * root <= n, "n:{}", n);
+        if let Some(next) = (root + 1).checked_mul(root + 1) {
+            assert!(n < next, "n:{}", n);
+        } else {
+            // (root + 1)^2 does not fit in u128, so it is above n
+            assert_eq!(root, u128::from(u64::MAX));
+        }
+    }
+
+    #[test]
+    fn test_integer_sqrt() {
+        assert_eq!(integer_sqrt(0), 0);
+        assert_eq!(integer_sqrt(1), 1);
+        assert_eq!(integer_sqrt(2), 1);
+        assert_eq!(integer_sqrt(3), 1);
+        assert_eq!(integer_sqrt(4), 2);
+        for root in [2u128, 3, 10, 1_000, 1 << 32, u128::from(u64::MAX)] {
+            assert_eq!(integer_sqrt(root * root), root);
+            assert_eq!(integer_sqrt(root * root - 1), root - 1);
+            assert_eq!(integer_sqrt(root * root + 1), root);
+        }
+        assert_eq!(integer_sqrt(u128::MAX), u128::from(u64::MAX));
+        const ROOT: u128 = integer_sqrt(1 << 100);
+        assert_eq!(ROOT, 1 << 50);
+
+        for n in 0..10_000 {
+            assert_is_floor_sqrt(n);
+        }
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..10_000 {
+            let n = (u128::from(next_rand(&mut seed)) << 64) | u128::from(next_rand(&mut seed));
+            assert_is_floor_sqrt(n);
+            assert_is_floor_sqrt(n >> 64);
+        }
+        assert_is_floor_sqrt(u128::MAX);
+        assert_is_floor_sqrt(u128::MAX - 1);
+    }
+
+    #[test]
+    fn test_u128_conversions() {
+        assert_eq!(to_u128(u64::MAX), u64::MAX as u128);
+        assert_eq!(from_u128(0), Ok(0));
+        assert_eq!(from_u128(u64::MAX.into()), Ok(u64::MAX));
+        assert_eq!(
+            from_u128(u128::from(u64::MAX) + 1),
+            Err(AmmError::CalculationFailure)
+        );
+        assert_eq!(from_u128(u128::MAX), Err(AmmError::CalculationFailure));
+        assert_eq!(from_u256(U256::from(u64::MAX)), Ok(u64::MAX));
+        assert_eq!(
+            from_u256(U256::from(u64::MAX) + 1),
+            Err(AmmError::CalculationFailure)
+        );
+        assert_eq!(from_u256(U256::MAX), Err(AmmError::CalculationFailure));
+        assert_eq!(
+            Calculator::to_u64(u128::from(u64::MAX) + 1),
+            Err(AmmError::ConversionFailure)
+        );
+    }
+}
+
+/// Randomized swap sequences through the curve, checked against the on-chain swap math.
+#[cfg(test)]
+mod fuzz {
+    use super::test::next_rand;
+    use super::*;
+
+    const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+    const SEQUENCES: usize = 1000;
+    const SWAPS_PER_SEQUENCE: usize = 20;
+
+    #[derive(Debug)]
+    struct Case {
+        sequence: usize,
+        step: usize,
+        coin: u128,
+        pc: u128,
+        direction: SwapDirection,
+        amount_in: u128,
+        fee_numerator: u64,
+    }
+
+    /// The swap as `Calculator` does it, output in the destination token.
+    fn processor_amount_out(case: &Case, fee: u128) -> u128 {
+        Calculator::swap_token_amount_base_in(
+            U128::from(case.amount_in - fee),
+            U128::from(case.pc),
+            U128::from(case.coin),
+            case.direction,
+        )
+        .as_u128()
+    }
+
+    fn swap(case: &Case) -> SwapResult {
+        let (source, dest) = match case.direction {
+            SwapDirection::Coin2PC => (case.coin, case.pc),
+            SwapDirection::PC2Coin => (case.pc, case.coin),
+        };
+        CurveCalculator::swap_base_in_out(case.amount_in, source, dest, case.fee_numerator, 10000)
+            .unwrap_or_else(|| panic!("swap failed: {:?}", case))
+    }
+
+    #[test]
+    fn fuzz_swap_sequences() {
+        let mut seed = SEED;
+        for sequence in 0..SEQUENCES {
+            let mut coin = u128::from(next_rand(&mut seed) % (1 << 40) + 1);
+            let mut pc = u128::from(next_rand(&mut seed) % (1 << 40) + 1);
+            let fee_numerator = next_rand(&mut seed) % 101;
+            for step in 0..SWAPS_PER_SEQUENCE {
+                let direction = if next_rand(&mut seed) % 2 == 0 {
+                    SwapDirection::Coin2PC
+                } else {
+                    SwapDirection::PC2Coin
+                };
+                let (source, dest) = match direction {
+                    SwapDirection::Coin2PC => (coin, pc),
+                    SwapDirection::PC2Coin => (pc, coin),
+                };
+                // mix dust trades with trades up to the size of the source reserve
+                let amount_in = if next_rand(&mut seed) % 4 == 0 {
+                    u128::from(next_rand(&mut seed) % 100)
+                } else {
+                    u128::from(next_rand(&mut seed)) % source + 1
+                };
+                let case = Case {
+                    sequence,
+                    step,
+                    coin,
+                    pc,
+                    direction,
+                    amount_in,
+                    fee_numerator,
+                };
+
+                let result = swap(&case);
+                assert!(
+                    result.dest_amount < dest,
+                    "output drains the reserve: {:?}",
+                    case
+                );
+                assert!(
+                    CurveCalculator::invariant(result.new_source_reserve, result.new_dest_reserve)
+                        >= CurveCalculator::invariant(source, dest),
+                    "invariant decreased: {:?}",
+                    case
+                );
+                assert_eq!(
+                    result.dest_amount,
+                    processor_amount_out(&case, result.fee),
+                    "curve and calculator disagree: {:?}",
+                    case
+                );
+
+                // swapping the output straight back never returns more than was put in
+                let reverse = CurveCalculator::swap_base_in_out(
+                    result.dest_amount,
+                    result.new_dest_reserve,
+                    result.new_source_reserve,
+                    fee_numerator,
+                    10000,
+                )
+                .unwrap_or_else(|| panic!("reverse swap failed: {:?}", case));
+                assert!(
+                    reverse.dest_amount <= amount_in,
+                    "round trip gained {} from {:?}",
+                    reverse.dest_amount - amount_in,
+                    case
+                );
+
+                match direction {
+                    SwapDirection::Coin2PC => {
+                        coin = result.new_source_reserve;
+                        pc = result.new_dest_reserve;
+                    }
+                    SwapDirection::PC2Coin => {
+                        pc = result.new_source_reserve;
+                        coin = result.new_dest_reserve;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_sided_deposit_dust() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut amm = AmmInfo::default();
+        amm.fees.swap_fee_numerator = 25;
+        amm.fees.swap_fee_denominator = TEN_THOUSAND;
+        let lp_amount = 1_000_000_000_u64;
+        for i in 0..20000 {
+            (amm.protocol_fee_numerator, amm.protocol_fee_denominator) =
+                [(0, 0), (12, 100), (1, 1)][i % 3];
+            let reserve_in = next_rand(&mut seed) % 1_000_000_000_000_000_000 + 1_000_000;
+            let reserve_out = next_rand(&mut seed) % 1_000_000_000_000_000_000 + 1_000_000;
+            let amount = next_rand(&mut seed) % reserve_in + 1;
+            let deposit =
+                single_sided_deposit(&amm, amount, reserve_in, reserve_out, lp_amount).unwrap();
+            let dust = amount - deposit.swap_in - deposit.deposit_in;
+
+            // the dust is worth at most one unit of the swap output at the margin, plus the
+            // rounding of the fee and the protocol share the quadratic leaves out
+            let reserve_out_after = u128::from(reserve_out - deposit.swap_out);
+            let output_unit = (u128::from(reserve_in + deposit.swap_in) * u128::from(reserve_out)
+                + reserve_out_after * reserve_out_after
+                - 1)
+                / (reserve_out_after * reserve_out_after);
+            assert!(
+                u128::from(dust) <= 2 * u128::from(deposit.swap_fee) + output_unit + 2,
+                "dust {} of amount {} against reserves {}/{}: {:?}",
+                dust,
+                amount,
+                reserve_in,
+                reserve_out,
+                deposit
+            );
+
+            // the deposit never takes less input than the ratio after the swap requires
+            let reserve_in_after = u128::from(reserve_in + deposit.swap_in - deposit.protocol_fee);
+            assert!(
+                u128::from(deposit.deposit_in) * reserve_out_after
+                    >= u128::from(deposit.swap_out) * reserve_in_after
+                    || deposit.deposit_in == amount - deposit.swap_in
+            );
+            assert!(
+                u128::from(deposit.mint_lp) * reserve_out_after
+                    <= u128::from(deposit.swap_out) * u128::from(lp_amount)
+            );
+        }
+
+        // without a fee the swap leaves only rounding dust
+        amm.fees.swap_fee_numerator = 0;
+        let deposit =
+            single_sided_deposit(&amm, 1_000_000, 1_000_000_000, 2_000_000_000, lp_amount).unwrap();
+        assert!(1_000_000 - deposit.swap_in - deposit.deposit_in <= 2);
+        assert_eq!(
+            single_sided_deposit(&amm, 1_000_000, 0, 2_000_000_000, lp_amount),
+            Err(AmmError::CheckedEmptyFunds)
+        );
+    }
+
+    #[test]
+    fn test_lp_tokens_for_deposit() {
+        // a balanced deposit of 1% of the pool mints 1% of the supply
+        assert_eq!(
+            lp_tokens_for_deposit(10_000, 20_000, 1_000_000, 2_000_000, 500_000),
+            Some(5_000)
+        );
+        // the surplus of an imbalanced deposit credits nothing, either way round
+        assert_eq!(
+            lp_tokens_for_deposit(10_000, 1_000_000, 1_000_000, 2_000_000, 500_000),
+            Some(5_000)
+        );
+        assert_eq!(
+            lp_tokens_for_deposit(1_000_000, 20_000, 1_000_000, 2_000_000, 500_000),
+            Some(5_000)
+        );
+        assert_eq!(
+            lp_tokens_for_deposit(0, 20_000, 1_000_000, 2_000_000, 500_000),
+            Some(0)
+        );
+        // floored
+        assert_eq!(lp_tokens_for_deposit(3, 6, 1_000, 2_000, 1_000), Some(3));
+        assert_eq!(lp_tokens_for_deposit(3, 5, 1_000, 2_000, 1_000), Some(2));
+        // the first deposit is priced by the initial liquidity
+        assert_eq!(lp_tokens_for_deposit(10_000, 20_000, 0, 0, 0), None);
+        assert_eq!(
+            lp_tokens_for_deposit(10_000, 20_000, 1_000_000, 2_000_000, 0),
+            None
+        );
+        assert_eq!(
+            lp_tokens_for_deposit(10_000, 20_000, 0, 2_000_000, 500),
+            None
+        );
+        assert_eq!(lp_tokens_for_deposit(u64::MAX, u64::MAX, 1, 1, 2), None);
+
+        // the lp minted is never worth more than either side brought
+        let mut seed = 0xd1b5_4a32_d192_ed03_u64;
+        for _ in 0..1_000 {
+            let reserve_coin = next_rand(&mut seed) % 1_000_000_000_000 + 1;
+            let reserve_pc = next_rand(&mut seed) % 1_000_000_000_000 + 1;
+            let lp_supply = next_rand(&mut seed) % 1_000_000_000_000 + 1;
+            let coin_in = next_rand(&mut seed) % reserve_coin;
+            let pc_in = next_rand(&mut seed) % reserve_pc;
+            let lp =
+                lp_tokens_for_deposit(coin_in, pc_in, reserve_coin, reserve_pc, lp_supply).unwrap();
+            assert!(
+                u128::from(lp) * u128::from(reserve_coin)
+                    <= u128::from(coin_in) * u128::from(lp_supply)
+            );
+            assert!(
+                u128::from(lp) * u128::from(reserve_pc)
+                    <= u128::from(pc_in) * u128::from(lp_supply)
+            );
+        }
+    }
+
+    #[test]
+    fn test_single_sided_withdraw_matches_withdraw_then_swap() {
+        let mut seed = 0x94d0_49bb_1331_11eb_u64;
+        let mut amm = AmmInfo::default();
+        amm.fees.swap_fee_numerator = 25;
+        amm.fees.swap_fee_denominator = TEN_THOUSAND;
+        amm.protocol_fee_numerator = 12;
+        amm.protocol_fee_denominator = 100;
+        for _ in 0..1_000 {
+            let reserve_in = next_rand(&mut seed) % 1_000_000_000_000_000 + 1_000;
+            let reserve_out = next_rand(&mut seed) % 1_000_000_000_000_000 + 1_000;
+            let lp_amount = next_rand(&mut seed) % 1_000_000_000_000_000 + 2;
+            let lp = next_rand(&mut seed) % (lp_amount - 1) + 1;
+            let withdraw =
+                single_sided_withdraw(&amm, lp, reserve_in, reserve_out, lp_amount).unwrap();
+
+            // the same as a withdraw of both sides, then a swap of one of them at the pool fee
+            let withdraw_in =
+                (u128::from(reserve_in) * u128::from(lp) / u128::from(lp_amount)) as u64;
+            let withdraw_out =
+                (u128::from(reserve_out) * u128::from(lp) / u128::from(lp_amount)) as u64;
+            assert_eq!(withdraw.withdraw_in, withdraw_in);
+            assert_eq!(withdraw.withdraw_out, withdraw_out);
+            let swap_out = if withdraw_in == 0 {
+                0
+            } else {
+                get_amount_out(
+                    withdraw_in,
+                    reserve_in - withdraw_in,
+                    reserve_out - withdraw_out,
+                    amm.fees.swap_fee_numerator,
+                    amm.fees.swap_fee_denominator,
+                )
+                .unwrap()
+            };
+            assert_eq!(withdraw.swap_out, swap_out);
+            assert_eq!(withdraw.amount_out, withdraw_out + swap_out);
+            assert!(withdraw.protocol_fee <= withdraw.swap_fee);
+
+            // the conversion costs the user the fee and the price impact of the swap
+            let spot_value =
+                u128::from(withdraw_in) * u128::from(reserve_out) / u128::from(reserve_in);
+            assert!(u128::from(withdraw.swap_out) <= spot_value);
+        }
+
+        // the last lp can not be withdrawn
+        assert_eq!(
+            single_sided_withdraw(&amm, 10, 1_000_000, 1_000_000, 10),
+            Err(AmmError::NotAllowZeroLP)
+        );
+    }
+}
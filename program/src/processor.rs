@@ -3,19 +3,30 @@
 use crate::{
     error::AmmError,
     instruction::{
-        AdminCancelOrdersInstruction, AmmInstruction, ConfigArgs, DepositInstruction,
-        InitializeInstruction2, MonitorStepInstruction, SetParamsInstruction, SimulateInstruction,
-        SwapInstructionBaseIn, SwapInstructionBaseOut, WithdrawInstruction, WithdrawSrmInstruction,
+        AdminCancelOrdersInstruction, AmmInstruction, BatchQuoteInstruction,
+        CollectProtocolFeesInstruction, ConfigArgs, DepositInstruction,
+        DepositSingleSidedInstruction, InitPriceBound, InitializeInstruction2, LpMintExtension,
+        MonitorStepInstruction, ProposeNewAdminInstruction, SetDynamicFeeInstruction,
+        SetFeeRecipientInstruction, SetMinTradeAmountInstruction, SetParamsInstruction,
+        SetPoolAdminInstruction, SetPoolStatusInstruction, SetReserveCapsInstruction,
+        SetSwapDiscountInstruction, SetSwapGuardInstruction, SetSwapPermissionedInstruction,
+        SimulateInstruction, SwapInstructionBaseIn, SwapInstructionBaseOut, TradeDirection,
+        UpdateConfigFeesInstruction, UpdateSwapWhitelistInstruction, WithdrawInstruction,
+        WithdrawSingleSidedInstruction, WithdrawSrmInstruction,
     },
     invokers::Invokers,
     math::{
-        Calculator, CheckedCeilDiv, InvariantPool, InvariantToken, RoundDirection, SwapDirection,
+        checked_spot_price_fixed, deposit_coin_for_pc, deposit_pc_for_coin, from_u128, from_u256,
+        integer_sqrt, lp_tokens_for_deposit, price_deviation_bps, price_impact_bps,
+        single_sided_deposit, single_sided_withdraw, Calculator, CheckedCeilDiv, CurveCalculator,
+        CurveType, InvariantPool, PoolReserves, RoundDirection, SwapDirection, MAX_AMP, MIN_AMP,
         U128, U256,
     },
     state::{
-        AmmConfig, AmmInfo, AmmParams, AmmResetFlag, AmmState, AmmStatus, GetPoolData,
-        GetSwapBaseInData, GetSwapBaseOutData, Loadable, RunCrankData, SimulateParams,
-        TargetOrders, MAX_ORDER_LIMIT, TEN_THOUSAND,
+        validate_fraction, AmmConfig, AmmInfo, AmmInfoExtension, AmmParams, AmmResetFlag, AmmState,
+        AmmStatus, Fees, GetPoolData, GetSwapBaseInData, GetSwapBaseOutData, Loadable,
+        PoolStatusBit, RunCrankData, SimulateParams, SwapWhitelist, TargetOrders,
+        AMM_INFO_ACCOUNT_LEN, MAX_ORDER_LIMIT, TEN_THOUSAND,
     },
 };
 
@@ -29,7 +40,7 @@ use solana_program::{
     clock::Clock,
     entrypoint::ProgramResult,
     msg,
-    program::{invoke, invoke_signed},
+    program::{invoke, invoke_signed, set_return_data},
     // log::sol_log_compute_units,
     program_error::ProgramError,
     program_option::COption,
@@ -37,8 +48,9 @@ use solana_program::{
     pubkey::Pubkey,
     rent::Rent,
     system_instruction,
-    sysvar::{clock, Sysvar},
+    sysvar::{clock, instructions, Sysvar},
 };
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions};
 
 use super::log::*;
 use arrayref::{array_ref, array_refs};
@@ -144,6 +156,13 @@ pub const PC_VAULT_ASSOCIATED_SEED: &'static [u8] = b"pc_vault_associated_seed";
 pub const LP_MINT_ASSOCIATED_SEED: &'static [u8] = b"lp_mint_associated_seed";
 /// Amm config seed
 pub const AMM_CONFIG_SEED: &'static [u8] = b"amm_config_account_seed";
+/// Suffix for swap whitelist associated seed
+pub const SWAP_WHITELIST_SEED: &'static [u8] = b"swap_whitelist_seed";
+/// Least amount of lp locked forever by the first deposit, so that the share price of an
+/// empty pool can not be inflated by its first depositor
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+/// Most decimals a creator may request for the lp mint of a new pool
+pub const MAX_LP_DECIMALS: u8 = 9;
 
 pub fn get_associated_address_and_bump_seed(
     info_id: &Pubkey,
@@ -161,6 +180,74 @@ pub fn get_associated_address_and_bump_seed(
     )
 }
 
+/// The mints of a pair in the canonical order of the pool address seeds.
+fn sort_mints<'a>(coin_mint: &'a Pubkey, pc_mint: &'a Pubkey) -> (&'a Pubkey, &'a Pubkey) {
+    if coin_mint <= pc_mint {
+        (coin_mint, pc_mint)
+    } else {
+        (pc_mint, coin_mint)
+    }
+}
+
+/// Address of the pool of the `coin_mint`/`pc_mint` pair listed on `market`. The mints are
+/// sorted first, so both orders of a pair map to the same address. The market is part of the
+/// seeds, so a pool created against one market never blocks the pair on another.
+pub fn derive_pool_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+) -> (Pubkey, u8) {
+    let (mint_a, mint_b) = sort_mints(coin_mint, pc_mint);
+    Pubkey::find_program_address(
+        &[
+            &program_id.to_bytes(),
+            &market.to_bytes(),
+            &mint_a.to_bytes(),
+            &mint_b.to_bytes(),
+            AMM_ASSOCIATED_SEED,
+        ],
+        program_id,
+    )
+}
+
+/// Addresses of the accounts of the pool of the `coin_mint`/`pc_mint` pair listed on
+/// `market`, as derived by Initialize2.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmmPdas {
+    pub amm_pool: Pubkey,
+    pub amm_authority: Pubkey,
+    pub nonce: u8,
+    pub amm_open_orders: Pubkey,
+    pub amm_target_orders: Pubkey,
+    pub amm_coin_vault: Pubkey,
+    pub amm_pc_vault: Pubkey,
+    pub amm_lp_mint: Pubkey,
+    pub amm_config: Pubkey,
+}
+
+pub fn get_amm_pdas(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+) -> AmmPdas {
+    let associated_address =
+        |seed: &[u8]| get_associated_address_and_bump_seed(program_id, market, seed, program_id).0;
+    let (amm_authority, nonce) = Pubkey::find_program_address(&[AUTHORITY_AMM], program_id);
+    AmmPdas {
+        amm_pool: derive_pool_address(program_id, market, coin_mint, pc_mint).0,
+        amm_authority,
+        nonce,
+        amm_open_orders: associated_address(OPEN_ORDER_ASSOCIATED_SEED),
+        amm_target_orders: associated_address(TARGET_ASSOCIATED_SEED),
+        amm_coin_vault: associated_address(COIN_VAULT_ASSOCIATED_SEED),
+        amm_pc_vault: associated_address(PC_VAULT_ASSOCIATED_SEED),
+        amm_lp_mint: associated_address(LP_MINT_ASSOCIATED_SEED),
+        amm_config: Pubkey::find_program_address(&[AMM_CONFIG_SEED], program_id).0,
+    }
+}
+
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
@@ -172,7 +259,7 @@ impl Processor {
         return Ok(());
     }
 
-    /// Unpacks a spl_token `Account`.
+    /// Unpacks a spl_token `Account`, or the base of a Token-2022 account.
     #[inline]
     pub fn unpack_token_account(
         account_info: &AccountInfo,
@@ -180,13 +267,41 @@ impl Processor {
     ) -> Result<spl_token::state::Account, AmmError> {
         if account_info.owner != token_program_id {
             Err(AmmError::InvalidSplTokenProgram)
+        } else if *token_program_id == spl_token_2022::id() {
+            Self::unpack_token_2022_account(&account_info.data.borrow())
         } else {
             spl_token::state::Account::unpack(&account_info.data.borrow())
                 .map_err(|_| AmmError::ExpectedAccount)
         }
     }
 
-    /// Unpacks a spl_token `Mint`.
+    fn unpack_token_2022_account(data: &[u8]) -> Result<spl_token::state::Account, AmmError> {
+        let account = StateWithExtensions::<spl_token_2022::state::Account>::unpack(data)
+            .map_err(|_| AmmError::ExpectedAccount)?
+            .base;
+        Ok(spl_token::state::Account {
+            mint: account.mint,
+            owner: account.owner,
+            amount: account.amount,
+            delegate: account.delegate,
+            state: match account.state {
+                spl_token_2022::state::AccountState::Uninitialized => {
+                    spl_token::state::AccountState::Uninitialized
+                }
+                spl_token_2022::state::AccountState::Initialized => {
+                    spl_token::state::AccountState::Initialized
+                }
+                spl_token_2022::state::AccountState::Frozen => {
+                    spl_token::state::AccountState::Frozen
+                }
+            },
+            is_native: account.is_native,
+            delegated_amount: account.delegated_amount,
+            close_authority: account.close_authority,
+        })
+    }
+
+    /// Unpacks a spl_token `Mint`, or the base of a Token-2022 mint.
     #[inline]
     pub fn unpack_mint(
         account_info: &AccountInfo,
@@ -194,12 +309,38 @@ impl Processor {
     ) -> Result<spl_token::state::Mint, AmmError> {
         if account_info.owner != token_program_id {
             Err(AmmError::InvalidSplTokenProgram)
+        } else if *token_program_id == spl_token_2022::id() {
+            Self::unpack_token_2022_mint(&account_info.data.borrow())
         } else {
             spl_token::state::Mint::unpack(&account_info.data.borrow())
                 .map_err(|_| AmmError::ExpectedMint)
         }
     }
 
+    fn unpack_token_2022_mint(data: &[u8]) -> Result<spl_token::state::Mint, AmmError> {
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(data)
+            .map_err(|_| AmmError::ExpectedMint)?
+            .base;
+        Ok(spl_token::state::Mint {
+            mint_authority: mint.mint_authority,
+            supply: mint.supply,
+            decimals: mint.decimals,
+            is_initialized: mint.is_initialized,
+            freeze_authority: mint.freeze_authority,
+        })
+    }
+
+    /// Token program owning an lp mint or a discount token account. Lp mints are spl token
+    /// mints unless the pool was created with a Token-2022 lp mint extension, the discount mint
+    /// may belong to either program.
+    fn token_program_id<'a>(token_info: &'a AccountInfo) -> Result<&'a Pubkey, AmmError> {
+        if *token_info.owner == spl_token::id() || *token_info.owner == spl_token_2022::id() {
+            Ok(token_info.owner)
+        } else {
+            Err(AmmError::InvalidSplTokenProgram)
+        }
+    }
+
     fn load_orders<'a>(
         orders_account: &'a AccountInfo,
     ) -> Result<RefMut<'a, OpenOrders>, ProgramError> {
@@ -209,6 +350,15 @@ impl Processor {
         Ok(open_orders)
     }
 
+    /// The instructions working the order book need the open orders account that a pool
+    /// initialized without open orders lacks.
+    fn check_uses_open_orders(amm: &AmmInfo) -> Result<(), AmmError> {
+        if !amm.uses_open_orders() {
+            return Err(AmmError::NoOpenOrders);
+        }
+        Ok(())
+    }
+
     pub fn load_serum_market_order<'a>(
         market_acc: &AccountInfo<'a>,
         open_orders_acc: &AccountInfo<'a>,
@@ -413,29 +563,33 @@ impl Processor {
                 Calculator::restore_decimal(diff_x, amm.pc_decimals, amm.sys_decimal_value);
             let diff_coin_pnl_amount =
                 Calculator::restore_decimal(diff_y, amm.coin_decimals, amm.sys_decimal_value);
-            let pc_pnl_amount = diff_pc_pnl_amount
-                .checked_mul(amm.fees.pnl_numerator.into())
-                .unwrap()
-                .checked_div(amm.fees.pnl_denominator.into())
-                .unwrap()
-                .as_u64();
-            let coin_pnl_amount = diff_coin_pnl_amount
-                .checked_mul(amm.fees.pnl_numerator.into())
-                .unwrap()
-                .checked_div(amm.fees.pnl_denominator.into())
-                .unwrap()
-                .as_u64();
+            let pc_pnl_amount = from_u128(
+                diff_pc_pnl_amount
+                    .checked_mul(amm.fees.pnl_numerator.into())
+                    .unwrap()
+                    .checked_div(amm.fees.pnl_denominator.into())
+                    .unwrap()
+                    .as_u128(),
+            )?;
+            let coin_pnl_amount = from_u128(
+                diff_coin_pnl_amount
+                    .checked_mul(amm.fees.pnl_numerator.into())
+                    .unwrap()
+                    .checked_div(amm.fees.pnl_denominator.into())
+                    .unwrap()
+                    .as_u128(),
+            )?;
             if pc_pnl_amount != 0 && coin_pnl_amount != 0 {
                 // step2: save total_pnl_pc & total_pnl_coin
                 amm.state_data.total_pnl_pc = amm
                     .state_data
                     .total_pnl_pc
-                    .checked_add(diff_pc_pnl_amount.as_u64())
+                    .checked_add(from_u128(diff_pc_pnl_amount.as_u128())?)
                     .unwrap();
                 amm.state_data.total_pnl_coin = amm
                     .state_data
                     .total_pnl_coin
-                    .checked_add(diff_coin_pnl_amount.as_u64())
+                    .checked_add(from_u128(diff_coin_pnl_amount.as_u128())?)
                     .unwrap();
                 amm.state_data.need_take_pnl_pc = amm
                     .state_data
@@ -485,6 +639,20 @@ impl Processor {
             .map_err(|_| AmmError::InvalidProgramAddress.into())
     }
 
+    /// A new pool must store the canonical bump of $authority, every later instruction derives
+    /// $authority from the stored nonce with `create_program_address` alone.
+    fn check_authority_nonce(
+        program_id: &Pubkey,
+        amm_authority: &Pubkey,
+        nonce: u8,
+    ) -> Result<(), AmmError> {
+        let (authority, bump) = Pubkey::find_program_address(&[AUTHORITY_AMM], program_id);
+        if nonce != bump || *amm_authority != authority {
+            return Err(AmmError::InvalidProgramAddress);
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn check_accounts(
         program_id: &Pubkey,
@@ -701,6 +869,7 @@ impl Processor {
         associated_owner_account: &'a AccountInfo<'b>,
         associated_seed: &[u8],
         mint_decimals: u8,
+        extension: Option<&LpMintExtension>,
     ) -> ProgramResult {
         let (associated_token_address, bump_seed) = get_associated_address_and_bump_seed(
             program_id,
@@ -719,9 +888,10 @@ impl Processor {
                 associated_seed,
                 &[bump_seed],
             ];
+            let mint_len = Self::lp_mint_len(extension)?;
             let rent = &Rent::from_account_info(rent_sysvar_account)?;
             let required_lamports = rent
-                .minimum_balance(spl_token::state::Mint::LEN)
+                .minimum_balance(mint_len)
                 .max(1)
                 .saturating_sub(associated_token_account.lamports());
             if required_lamports > 0 {
@@ -739,10 +909,7 @@ impl Processor {
                 )?;
             }
             invoke_signed(
-                &system_instruction::allocate(
-                    associated_token_account.key,
-                    spl_token::state::Mint::LEN as u64,
-                ),
+                &system_instruction::allocate(associated_token_account.key, mint_len as u64),
                 &[
                     associated_token_account.clone(),
                     system_program_account.clone(),
@@ -758,8 +925,26 @@ impl Processor {
                 &[&associated_account_signer_seeds],
             )?;
 
+            match extension {
+                Some(LpMintExtension::MetadataPointer {
+                    authority,
+                    metadata_address,
+                }) => invoke(
+                    &spl_token_2022::extension::metadata_pointer::instruction::initialize(
+                        spl_token_program_id,
+                        associated_token_account.key,
+                        *authority,
+                        *metadata_address,
+                    )?,
+                    &[
+                        associated_token_account.clone(),
+                        spl_token_program_account.clone(),
+                    ],
+                )?,
+                None => {}
+            }
             invoke(
-                &spl_token::instruction::initialize_mint(
+                &spl_token_2022::instruction::initialize_mint(
                     spl_token_program_id,
                     associated_token_account.key,
                     associated_owner_account.key,
@@ -780,6 +965,451 @@ impl Processor {
         Ok(())
     }
 
+    /// Only the amm authority may mint the lp token, and freeze it if anyone can.
+    fn check_lp_mint_authority(
+        lp_mint: &spl_token::state::Mint,
+        amm_authority: &Pubkey,
+    ) -> Result<(), AmmError> {
+        if lp_mint.mint_authority != COption::Some(*amm_authority) {
+            return Err(AmmError::InvalidLpMintAuthority);
+        }
+        if lp_mint.freeze_authority.is_some()
+            && lp_mint.freeze_authority != COption::Some(*amm_authority)
+        {
+            return Err(AmmError::InvalidLpMintAuthority);
+        }
+        Ok(())
+    }
+
+    /// The instructions sysvar follows all other accounts of a swap that passes it, split it
+    /// off so that the remaining accounts keep the regular layout.
+    fn split_instructions_sysvar<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+    ) -> (&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>) {
+        match accounts.split_last() {
+            Some((instructions_info, rest)) if *instructions_info.key == instructions::id() => {
+                (rest, Some(instructions_info))
+            }
+            _ => (accounts, None),
+        }
+    }
+
+    /// A swap of a pool with the swap guard has to be the first swap of the pool in its
+    /// transaction, as far as the instructions sysvar shows, see `SetSwapGuard`.
+    fn check_swap_guard(
+        program_id: &Pubkey,
+        amm_key: &Pubkey,
+        instructions_info: Option<&AccountInfo>,
+    ) -> ProgramResult {
+        let instructions_info = instructions_info.ok_or(AmmError::InstructionsSysvarMissing)?;
+        let current_index = instructions::load_current_index_checked(instructions_info)?;
+        for index in 0..current_index {
+            let instruction =
+                instructions::load_instruction_at_checked(index as usize, instructions_info)?;
+            if instruction.program_id != *program_id
+                || instruction.accounts.get(1).map(|meta| meta.pubkey) != Some(*amm_key)
+            {
+                continue;
+            }
+            if let Ok(AmmInstruction::SwapBaseIn(_)) | Ok(AmmInstruction::SwapBaseOut(_)) =
+                AmmInstruction::unpack(&instruction.data)
+            {
+                return Err(AmmError::RepeatedSwap.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// A permissioned pool takes its swap whitelist as the last swap account, split it off
+    /// so that the remaining accounts keep the permissionless layout.
+    fn split_swap_whitelist<'a, 'b>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>), ProgramError> {
+        let amm_info = accounts.get(1).ok_or(AmmError::WrongAccountsNumber)?;
+        if !AmmInfo::load_checked(amm_info, program_id)?.swap_permissioned() {
+            return Ok((accounts, None));
+        }
+        match accounts.split_last() {
+            Some((whitelist_info, accounts)) => Ok((accounts, Some(whitelist_info))),
+            None => Err(AmmError::WrongAccountsNumber.into()),
+        }
+    }
+
+    /// The Token-2022 program follows all other accounts of a deposit or withdraw on a pool
+    /// with a Token-2022 lp mint, split it off so that the remaining accounts keep the
+    /// regular layout.
+    fn split_lp_token_program<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+    ) -> (&'a [AccountInfo<'b>], Option<&'a AccountInfo<'b>>) {
+        match accounts.split_last() {
+            Some((lp_token_program_info, rest))
+                if *lp_token_program_info.key == spl_token_2022::id() =>
+            {
+                (rest, Some(lp_token_program_info))
+            }
+            _ => (accounts, None),
+        }
+    }
+
+    /// Account size of an lp mint with `extension`.
+    fn lp_mint_len(extension: Option<&LpMintExtension>) -> Result<usize, ProgramError> {
+        match extension {
+            Some(LpMintExtension::MetadataPointer { .. }) => {
+                ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+                    ExtensionType::MetadataPointer,
+                ])
+            }
+            None => Ok(spl_token::state::Mint::LEN),
+        }
+    }
+
+    /// An lp mint extension must not hand control over the lp mint to anyone but $authority.
+    fn check_lp_mint_extension(
+        extension: &LpMintExtension,
+        amm_authority: &Pubkey,
+    ) -> Result<(), AmmError> {
+        match extension {
+            LpMintExtension::MetadataPointer { authority, .. } => {
+                if authority.is_some() && *authority != Some(*amm_authority) {
+                    return Err(AmmError::InvalidLpMintAuthority);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The price implied by the initial amounts must be within `price_tolerance_bps` of the
+    /// expected price, which catches amounts given in the wrong decimals.
+    fn check_init_price_bound(
+        bound: &InitPriceBound,
+        init_coin_amount: u64,
+        init_pc_amount: u64,
+        coin_decimals: u8,
+        pc_decimals: u8,
+    ) -> Result<(), AmmError> {
+        let price =
+            checked_spot_price_fixed(init_coin_amount, init_pc_amount, coin_decimals, pc_decimals)
+                .ok_or(AmmError::InvalidInput)?;
+        let deviation =
+            price_deviation_bps(price, bound.expected_price).ok_or(AmmError::InvalidInput)?;
+        if deviation > bound.price_tolerance_bps {
+            msg!(arrform!(
+                LOG_SIZE,
+                "init price:{}, expected:{}, deviation_bps:{}",
+                price,
+                bound.expected_price,
+                deviation
+            )
+            .as_str());
+            return Err(AmmError::PriceOutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Decimals of a new lp mint, the coin decimals unless the creator asks for others.
+    fn init_lp_decimals(requested: Option<u8>, coin_decimals: u8) -> Result<u8, AmmError> {
+        match requested {
+            Some(lp_decimals) if lp_decimals > MAX_LP_DECIMALS => {
+                msg!(
+                    "lp_decimals {} exceeds the maximum of {}",
+                    lp_decimals,
+                    MAX_LP_DECIMALS
+                );
+                Err(AmmError::InvalidInput)
+            }
+            Some(lp_decimals) => Ok(lp_decimals),
+            None => Ok(coin_decimals),
+        }
+    }
+
+    /// Curve of a new pool. A stable pool prices its pegged tokens one for one at the peg, so
+    /// both need the same decimals, and it places no orders on the market, whose order grid
+    /// follows the constant product curve.
+    fn init_curve_type(
+        stable_amp: Option<u64>,
+        without_open_orders: bool,
+        coin_decimals: u8,
+        pc_decimals: u8,
+    ) -> Result<CurveType, AmmError> {
+        let amp = match stable_amp {
+            Some(amp) => amp,
+            None => return Ok(CurveType::ConstantProduct),
+        };
+        if amp < MIN_AMP || amp > MAX_AMP {
+            msg!("stable_amp {} outside of {}..={}", amp, MIN_AMP, MAX_AMP);
+            return Err(AmmError::InvalidInput);
+        }
+        if !without_open_orders || coin_decimals != pc_decimals {
+            msg!(
+                "stable pool needs no open orders and equal decimals, coin:{}, pc:{}",
+                coin_decimals,
+                pc_decimals
+            );
+            return Err(AmmError::InvalidInput);
+        }
+        Ok(CurveType::Stable { amp })
+    }
+
+    /// Curve of a pool, pools without the extension are constant product.
+    fn curve_type(extension: Option<&AmmInfoExtension>) -> CurveType {
+        extension.map_or(CurveType::ConstantProduct, AmmInfoExtension::curve_type)
+    }
+
+    /// Single sided deposits and withdraws size their swap on the constant product curve, a
+    /// stable pool only takes balanced ones.
+    fn check_constant_product(extension: Option<&AmmInfoExtension>) -> ProgramResult {
+        match extension.map(AmmInfoExtension::curve_type) {
+            Some(CurveType::Stable { .. }) => {
+                msg!("single sided liquidity on a stable pool");
+                Err(AmmError::InvalidInput.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// A swapper holding the config discount mint passes the config and its discount token
+    /// account after its wallet, split them off so that the remaining accounts keep the
+    /// regular layout.
+    fn split_swap_discount<'a, 'b>(
+        accounts: &'a [AccountInfo<'b>],
+        account_len: usize,
+    ) -> (&'a [AccountInfo<'b>], Option<&'a [AccountInfo<'b>]>) {
+        if accounts.len() == account_len + 2 || accounts.len() == account_len + 3 {
+            let (accounts, discount_infos) = accounts.split_at(accounts.len() - 2);
+            (accounts, Some(discount_infos))
+        } else {
+            (accounts, None)
+        }
+    }
+
+    /// Fees of a swap that passed the discount accounts.
+    fn load_discount_fees(
+        program_id: &Pubkey,
+        fees: &Fees,
+        discount_infos: &[AccountInfo],
+        swapper: &Pubkey,
+    ) -> Result<Fees, ProgramError> {
+        let (amm_config_info, discount_account_info) = match discount_infos {
+            [amm_config_info, discount_account_info] => (amm_config_info, discount_account_info),
+            _ => return Err(AmmError::WrongAccountsNumber.into()),
+        };
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+        let amm_config = AmmConfig::load_checked(amm_config_info, program_id)?;
+        let discount_account = Self::unpack_token_account(
+            discount_account_info,
+            Self::token_program_id(discount_account_info)?,
+        )?;
+        Ok(Self::discounted_fees(
+            fees,
+            &amm_config,
+            &discount_account,
+            swapper,
+        )?)
+    }
+
+    /// Only a nonzero holding of the discount mint owned by the swapper earns the discount
+    /// fee, and never a fee above the one of the pool.
+    fn discounted_fees(
+        fees: &Fees,
+        amm_config: &AmmConfig,
+        discount_account: &spl_token::state::Account,
+        swapper: &Pubkey,
+    ) -> Result<Fees, AmmError> {
+        if amm_config.discount_mint == Pubkey::default()
+            || discount_account.mint != amm_config.discount_mint
+            || discount_account.owner != *swapper
+            || discount_account.amount == 0
+        {
+            return Err(AmmError::InvalidDiscountAccount);
+        }
+        let mut fees = *fees;
+        if u128::from(amm_config.discount_fee_numerator) * u128::from(fees.swap_fee_denominator)
+            < u128::from(fees.swap_fee_numerator) * u128::from(amm_config.discount_fee_denominator)
+        {
+            fees.swap_fee_numerator = amm_config.discount_fee_numerator;
+            fees.swap_fee_denominator = amm_config.discount_fee_denominator;
+        }
+        Ok(fees)
+    }
+
+    /// The mints of the user token accounts give the direction of a swap, a direction stated
+    /// in the instruction has to agree with it.
+    fn swap_direction(
+        user_source_mint: &Pubkey,
+        user_destination_mint: &Pubkey,
+        coin_mint: &Pubkey,
+        pc_mint: &Pubkey,
+        stated: Option<TradeDirection>,
+    ) -> Result<SwapDirection, AmmError> {
+        let swap_direction = if user_source_mint == coin_mint && user_destination_mint == pc_mint {
+            SwapDirection::Coin2PC
+        } else if user_source_mint == pc_mint && user_destination_mint == coin_mint {
+            SwapDirection::PC2Coin
+        } else {
+            return Err(AmmError::InvalidUserToken);
+        };
+        match (stated, swap_direction) {
+            (None, _)
+            | (Some(TradeDirection::CoinToPc), SwapDirection::Coin2PC)
+            | (Some(TradeDirection::PcToCoin), SwapDirection::PC2Coin) => Ok(swap_direction),
+            _ => Err(AmmError::TradeDirectionMismatch),
+        }
+    }
+
+    fn check_swap_whitelist(whitelist: &SwapWhitelist, swapper: &Pubkey) -> Result<(), AmmError> {
+        if !whitelist.contains(swapper) {
+            return Err(AmmError::NotWhitelisted);
+        }
+        Ok(())
+    }
+
+    /// The vaults must be the ones recorded in the pool and the user token accounts must
+    /// not be the vaults, otherwise a caller could substitute its own token accounts.
+    fn check_vault_accounts(
+        amm: &AmmInfo,
+        amm_coin_vault: &Pubkey,
+        amm_pc_vault: &Pubkey,
+        user_coin: &Pubkey,
+        user_pc: &Pubkey,
+    ) -> Result<(), AmmError> {
+        if *amm_coin_vault != amm.coin_vault || *user_coin == amm.coin_vault {
+            return Err(AmmError::InvalidCoinVault);
+        }
+        if *amm_pc_vault != amm.pc_vault || *user_pc == amm.pc_vault {
+            return Err(AmmError::InvalidPCVault);
+        }
+        Ok(())
+    }
+
+    /// A user token account receiving pool funds must be of the pool `mint` and held by the
+    /// `owner` signing the instruction.
+    fn check_user_token_account(
+        user_token: &spl_token::state::Account,
+        mint: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<(), AmmError> {
+        if user_token.mint != *mint || user_token.owner != *owner {
+            msg!(
+                "user token account of mint {} and owner {}, expected {} and {}",
+                user_token.mint,
+                user_token.owner,
+                mint,
+                owner
+            );
+            return Err(AmmError::InvalidUserAccount);
+        }
+        Ok(())
+    }
+
+    /// Lp minted to the first depositor, the locked part of `liquidity` is counted in the lp
+    /// supply but never minted, so nobody can withdraw it.
+    fn initial_user_lp_amount(liquidity: u64, lp_decimals: u8) -> Result<u64, AmmError> {
+        let locked = 10u64
+            .checked_pow(lp_decimals.into())
+            .ok_or(AmmError::InitLpAmountTooLess)?
+            .max(MINIMUM_LIQUIDITY);
+        match liquidity.checked_sub(locked) {
+            Some(user_lp_amount) if user_lp_amount > 0 => Ok(user_lp_amount),
+            _ => Err(AmmError::InitLpAmountTooLess),
+        }
+    }
+
+    /// A pool trading a mint against itself has a single vault balance on both sides of the
+    /// curve, its price is always one and every swap only pays fees.
+    fn check_distinct_mints(coin_mint: &Pubkey, pc_mint: &Pubkey) -> Result<(), AmmError> {
+        if coin_mint == pc_mint {
+            return Err(AmmError::IdenticalMints);
+        }
+        Ok(())
+    }
+
+    /// `lp_amount` counts every lp token the pool minted plus the initial liquidity locked
+    /// without being minted, so the mint supply can never exceed it unless lp tokens were
+    /// minted outside the pool. It may fall below it when holders burn their lp tokens.
+    fn check_lp_supply(amm: &AmmInfo, lp_supply: u64) -> Result<(), AmmError> {
+        if lp_supply > amm.lp_amount {
+            msg!(
+                "lp supply {} exceeds pool lp_amount {}",
+                lp_supply,
+                amm.lp_amount
+            );
+            return Err(AmmError::LpSupplyMismatch);
+        }
+        Ok(())
+    }
+
+    /// Last line of defense against curve math regressions, the pool invariant `k = coin * pc`
+    /// must not fall from `old_k` to `new_k`.
+    pub fn assert_invariant(old_k: U256, new_k: U256) -> Result<(), AmmError> {
+        if new_k < old_k {
+            msg!("invariant violated old_k:{}, new_k:{}", old_k, new_k);
+            return Err(AmmError::CalculationFailure);
+        }
+        Ok(())
+    }
+
+    /// Checks that a swap preserved the value of the pool on its curve, the protocol share of
+    /// the fee is owed to the pnl owner and does not count towards the reserves.
+    fn check_swap_invariant(
+        curve_type: CurveType,
+        swap_direction: SwapDirection,
+        total_coin_without_take_pnl: u64,
+        total_pc_without_take_pnl: u64,
+        amount_in: u64,
+        amount_out: u64,
+        protocol_fee: u64,
+    ) -> Result<(), AmmError> {
+        let (coin, pc) = (
+            u128::from(total_coin_without_take_pnl),
+            u128::from(total_pc_without_take_pnl),
+        );
+        let kept_in = u128::from(amount_in)
+            .checked_sub(protocol_fee.into())
+            .ok_or(AmmError::CalculationFailure)?;
+        let (new_coin, new_pc) = match swap_direction {
+            SwapDirection::Coin2PC => (
+                coin + kept_in,
+                pc.checked_sub(amount_out.into())
+                    .ok_or(AmmError::CalculationFailure)?,
+            ),
+            SwapDirection::PC2Coin => (
+                coin.checked_sub(amount_out.into())
+                    .ok_or(AmmError::CalculationFailure)?,
+                pc + kept_in,
+            ),
+        };
+        Self::assert_invariant(
+            curve_type
+                .invariant(coin, pc)
+                .ok_or(AmmError::CalculationFailure)?,
+            curve_type
+                .invariant(new_coin, new_pc)
+                .ok_or(AmmError::CalculationFailure)?,
+        )
+    }
+
+    /// Checks that a deposit or withdraw did not dilute the lp, `k` moves with the lp supply
+    /// there, so the invariant per lp share `k / lp^2` is what may not fall.
+    fn check_liquidity_invariant(
+        coin: u128,
+        pc: u128,
+        lp: u64,
+        new_coin: u128,
+        new_pc: u128,
+        new_lp: u64,
+    ) -> Result<(), AmmError> {
+        // cross multiplied, k < 2^128 and lp^2 < 2^128 so neither side overflows
+        let lp_squared = |lp: u64| U256::from(lp) * U256::from(lp);
+        Self::assert_invariant(
+            CurveCalculator::invariant(coin, pc) * lp_squared(new_lp),
+            CurveCalculator::invariant(new_coin, new_pc) * lp_squared(lp),
+        )
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn generate_amm_associated_account<'a, 'b: 'a>(
         program_id: &Pubkey,
@@ -802,13 +1432,34 @@ impl Processor {
             msg!("Error: Associated token address does not match seed derivation");
             return Err(AmmError::ExpectedAccount.into());
         }
-        if associated_token_account.owner == system_program_account.key {
-            let associated_account_signer_seeds: &[&[_]] = &[
+        Self::create_associated_account(
+            assign_to,
+            associated_token_account,
+            user_wallet_account,
+            system_program_account,
+            rent_sysvar_account,
+            &[
                 &program_id.to_bytes(),
                 &market_account.key.to_bytes(),
                 associated_seed,
                 &[bump_seed],
-            ];
+            ],
+            data_size,
+        )
+    }
+
+    /// Creates the account of program address `associated_token_account` signed for with
+    /// `associated_account_signer_seeds`, funded by the user wallet and assigned to `assign_to`.
+    fn create_associated_account<'a, 'b: 'a>(
+        assign_to: &Pubkey,
+        associated_token_account: &'a AccountInfo<'b>,
+        user_wallet_account: &'a AccountInfo<'b>,
+        system_program_account: &'a AccountInfo<'b>,
+        rent_sysvar_account: &'a AccountInfo<'b>,
+        associated_account_signer_seeds: &[&[u8]],
+        data_size: usize,
+    ) -> ProgramResult {
+        if associated_token_account.owner == system_program_account.key {
             let rent = &Rent::from_account_info(rent_sysvar_account)?;
             let required_lamports = rent
                 .minimum_balance(data_size)
@@ -845,7 +1496,7 @@ impl Processor {
                 &[&associated_account_signer_seeds],
             )?;
         } else {
-            associated_token_address.log();
+            associated_token_account.key.log();
             return Err(AmmError::RepeatCreateAmm.into());
         }
         Ok(())
@@ -881,16 +1532,32 @@ impl Processor {
         let user_token_coin_info = next_account_info(account_info_iter)?;
         let user_token_pc_info = next_account_info(account_info_iter)?;
         let user_token_lp_info = next_account_info(account_info_iter)?;
+        let lp_token_program_info = match init.lp_mint_extension {
+            Some(_) => next_account_info(account_info_iter)?,
+            None => token_program_info,
+        };
 
         let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
         if pda != *amm_config_info.key || amm_config_info.owner != program_id {
             return Err(AmmError::InvalidConfigAccount.into());
         }
 
-        msg!(arrform!(LOG_SIZE, "initialize2: {:?}", init).as_str());
+        msg!(arrform!(
+            LOG_SIZE,
+            "initialize2: nonce:{}, open_time:{}, init_pc_amount:{}, init_coin_amount:{}",
+            init.nonce,
+            init.open_time,
+            init.init_pc_amount,
+            init.init_coin_amount
+        )
+        .as_str());
+        if let Some(extension) = init.lp_mint_extension {
+            msg!(arrform!(LOG_SIZE, "lp_mint_extension: {:?}", extension).as_str());
+        }
         if !user_wallet_info.is_signer {
             return Err(AmmError::InvalidSignAccount.into());
         }
+        Self::check_distinct_mints(amm_coin_mint_info.key, amm_pc_mint_info.key)?;
         check_assert_eq!(
             *token_program_info.key,
             spl_token::id(),
@@ -916,8 +1583,15 @@ impl Processor {
             "sys_program",
             AmmError::InvalidSysProgramAddress
         );
-        if *amm_authority_info.key != Self::authority_id(program_id, AUTHORITY_AMM, init.nonce)? {
-            return Err(AmmError::InvalidProgramAddress.into());
+        Self::check_authority_nonce(program_id, amm_authority_info.key, init.nonce)?;
+        if let Some(extension) = init.lp_mint_extension.as_ref() {
+            check_assert_eq!(
+                *lp_token_program_info.key,
+                spl_token_2022::id(),
+                "lp_token_program",
+                AmmError::InvalidSplTokenProgram
+            );
+            Self::check_lp_mint_extension(extension, amm_authority_info.key)?;
         }
         if *create_fee_destination_info.key != config_feature::create_pool_fee_address::id() {
             return Err(AmmError::InvalidFee.into());
@@ -953,6 +1627,21 @@ impl Processor {
         let coin_mint = Self::unpack_mint(&amm_coin_mint_info, spl_token_program_id)?;
         // unpack and check pc_mint
         let pc_mint = Self::unpack_mint(&amm_pc_mint_info, spl_token_program_id)?;
+        if let Some(bound) = init.price_bound.as_ref() {
+            Self::check_init_price_bound(
+                bound,
+                init.init_coin_amount,
+                init.init_pc_amount,
+                coin_mint.decimals,
+                pc_mint.decimals,
+            )?;
+        }
+        let curve_type = Self::init_curve_type(
+            init.stable_amp,
+            init.without_open_orders,
+            coin_mint.decimals,
+            pc_mint.decimals,
+        )?;
 
         // create target_order account
         Self::generate_amm_associated_account(
@@ -968,19 +1657,20 @@ impl Processor {
         )?;
 
         // create lp mint account
-        let lp_decimals = coin_mint.decimals;
+        let lp_decimals = Self::init_lp_decimals(init.lp_decimals, coin_mint.decimals)?;
         Self::generate_amm_associated_spl_mint(
             program_id,
-            spl_token_program_id,
+            lp_token_program_info.key,
             market_info,
             amm_lp_mint_info,
             user_wallet_info,
             system_program_info,
             rent_sysvar_info,
-            token_program_info,
+            lp_token_program_info,
             amm_authority_info,
             LP_MINT_ASSOCIATED_SEED,
             lp_decimals,
+            init.lp_mint_extension.as_ref(),
         )?;
         // create coin vault account
         Self::generate_amm_associated_spl_token(
@@ -1010,49 +1700,79 @@ impl Processor {
             amm_authority_info,
             PC_VAULT_ASSOCIATED_SEED,
         )?;
-        // create amm account
-        Self::generate_amm_associated_account(
+        // create amm account, one per mint pair and market
+        let (amm_address, amm_bump) = derive_pool_address(
             program_id,
+            market_info.key,
+            amm_coin_mint_info.key,
+            amm_pc_mint_info.key,
+        );
+        if amm_address != *amm_info.key {
+            msg!("Error: Amm address does not match the market and mint pair derivation");
+            return Err(AmmError::ExpectedAccount.into());
+        }
+        let (mint_a, mint_b) = sort_mints(amm_coin_mint_info.key, amm_pc_mint_info.key);
+        Self::create_associated_account(
             program_id,
-            market_info,
             amm_info,
             user_wallet_info,
             system_program_info,
             rent_sysvar_info,
-            AMM_ASSOCIATED_SEED,
-            size_of::<AmmInfo>(),
+            &[
+                &program_id.to_bytes(),
+                &market_info.key.to_bytes(),
+                &mint_a.to_bytes(),
+                &mint_b.to_bytes(),
+                AMM_ASSOCIATED_SEED,
+                &[amm_bump],
+            ],
+            AMM_INFO_ACCOUNT_LEN,
         )?;
 
-        // create amm open order account
-        Self::generate_amm_associated_account(
-            program_id,
-            market_program_info.key,
-            market_info,
-            amm_open_orders_info,
-            user_wallet_info,
-            system_program_info,
-            rent_sysvar_info,
-            OPEN_ORDER_ASSOCIATED_SEED,
-            size_of::<serum_dex::state::OpenOrders>() + 12,
-        )?;
-        // init open orders account
-        Invokers::invoke_dex_init_open_orders(
-            market_program_info.clone(),
-            amm_open_orders_info.clone(),
-            amm_authority_info.clone(),
-            market_info.clone(),
-            rent_sysvar_info.clone(),
-            AUTHORITY_AMM,
-            init.nonce as u8,
-        )?;
+        if init.without_open_orders {
+            // the address is kept, the account is never created
+            let (open_orders_address, _) = get_associated_address_and_bump_seed(
+                program_id,
+                market_info.key,
+                OPEN_ORDER_ASSOCIATED_SEED,
+                program_id,
+            );
+            if open_orders_address != *amm_open_orders_info.key {
+                msg!("Error: Associated token address does not match seed derivation");
+                return Err(AmmError::ExpectedAccount.into());
+            }
+        } else {
+            // create amm open order account
+            Self::generate_amm_associated_account(
+                program_id,
+                market_program_info.key,
+                market_info,
+                amm_open_orders_info,
+                user_wallet_info,
+                system_program_info,
+                rent_sysvar_info,
+                OPEN_ORDER_ASSOCIATED_SEED,
+                size_of::<serum_dex::state::OpenOrders>() + 12,
+            )?;
+            // init open orders account
+            Invokers::invoke_dex_init_open_orders(
+                market_program_info.clone(),
+                amm_open_orders_info.clone(),
+                amm_authority_info.clone(),
+                market_info.clone(),
+                rent_sysvar_info.clone(),
+                AUTHORITY_AMM,
+                init.nonce as u8,
+            )?;
+        }
 
-        // create user ata lp token
-        Invokers::create_ata_spl_token(
+        // create user ata lp token, keeps an existing one
+        Invokers::create_ata_spl_token_idempotent(
             user_token_lp_info.clone(),
             user_wallet_info.clone(),
             user_wallet_info.clone(),
             amm_lp_mint_info.clone(),
-            token_program_info.clone(),
+            lp_token_program_info.clone(),
             ata_token_program_info.clone(),
             system_program_info.clone(),
         )?;
@@ -1074,7 +1794,7 @@ impl Processor {
         )?;
 
         // load AmmInfo
-        let mut amm = AmmInfo::load_mut(&amm_info)?;
+        let (mut amm, mut extension) = AmmInfo::load_mut_uninitialized(&amm_info)?;
         if amm.status != AmmStatus::Uninitialized.into_u64() {
             return Err(AmmError::AlreadyInUse.into());
         }
@@ -1149,32 +1869,23 @@ impl Processor {
             return Err(AmmError::InvalidMarket.into());
         }
 
-        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, spl_token_program_id)?;
+        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, lp_token_program_info.key)?;
         if lp_mint.supply != 0 {
             return Err(AmmError::InvalidSupply.into());
         }
-        if COption::Some(*amm_authority_info.key) != lp_mint.mint_authority {
-            return Err(AmmError::InvalidOwner.into());
-        }
-        if lp_mint.freeze_authority.is_some() {
-            return Err(AmmError::InvalidFreezeAuthority.into());
-        }
+        Self::check_lp_mint_authority(&lp_mint, amm_authority_info.key)?;
 
-        let liquidity = Calculator::to_u64(
-            U128::from(amm_pc_vault.amount)
+        let liquidity = Calculator::to_u64(integer_sqrt(
+            u128::from(amm_pc_vault.amount)
                 .checked_mul(amm_coin_vault.amount.into())
-                .unwrap()
-                .integer_sqrt()
-                .as_u128(),
-        )?;
-        let user_lp_amount = liquidity
-            .checked_sub((10u64).checked_pow(lp_mint.decimals.into()).unwrap())
-            .ok_or(AmmError::InitLpAmountTooLess)?;
+                .unwrap(),
+        ))?;
+        let user_lp_amount = Self::initial_user_lp_amount(liquidity, lp_mint.decimals)?;
 
         // liquidity is measured in terms of token_a's value since both sides of
         // the pool are equal
         Invokers::token_mint_to(
-            token_program_info.clone(),
+            lp_token_program_info.clone(),
             amm_lp_mint_info.clone(),
             user_token_lp_info.clone(),
             amm_authority_info.clone(),
@@ -1191,6 +1902,10 @@ impl Processor {
             market_state.coin_lot_size,
             market_state.pc_lot_size,
         )?;
+        if init.without_open_orders {
+            amm.pool_status |= PoolStatusBit::OpenOrders.mask();
+        }
+        extension.set_curve_type(curve_type);
         encode_ray_log(InitLog {
             log_type: LogType::Init.into_u8(),
             time: init.open_time,
@@ -1228,8 +1943,8 @@ impl Processor {
         amm.market = *market_info.key;
         amm.market_program = *market_program_info.key;
         amm.target_orders = *amm_target_orders_info.key;
-        amm.amm_owner = config_feature::amm_owner::ID;
         amm.amm_subscriber = config_feature::amm_subscriber::ID;
+        amm.apply_config_fees(&amm_config);
         amm.lp_amount = liquidity;
         amm.status = if init.open_time > (Clock::get()?.unix_timestamp as u64) {
             AmmStatus::WaitingTrade.into_u64()
@@ -1248,6 +1963,7 @@ impl Processor {
         deposit: DepositInstruction,
     ) -> ProgramResult {
         const ACCOUNT_LEN: usize = 14;
+        let (accounts, lp_token_program_info) = Self::split_lp_token_program(accounts);
         let input_account_len = accounts.len();
         if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
             return Err(AmmError::WrongAccountsNumber.into());
@@ -1292,6 +2008,9 @@ impl Processor {
             return Err(AmmError::InvalidSignAccount.into());
         }
 
+        if amm.status == AmmStatus::Disabled.into_u64() || amm.is_disabled(PoolStatusBit::Deposit) {
+            return Err(AmmError::PoolPaused.into());
+        }
         if !AmmStatus::from_u64(amm.status).deposit_permission() {
             return Err(AmmError::InvalidStatus.into());
         }
@@ -1307,22 +2026,20 @@ impl Processor {
             AmmError::InvalidSplTokenProgram
         );
         let spl_token_program_id = token_program_info.key;
+        let lp_token_program_info = lp_token_program_info.unwrap_or(token_program_info);
         check_assert_eq!(
             *market_info.key,
             amm.market,
             "market",
             AmmError::InvalidMarket
         );
-        // token_coin must be amm.coin_vault or token_source_coin must not be amm.coin_vault
-        if *amm_coin_vault_info.key != amm.coin_vault
-            || *user_source_coin_info.key == amm.coin_vault
-        {
-            return Err(AmmError::InvalidCoinVault.into());
-        }
-        // token_pc must be amm.pc_vault or token_source_pc must not be amm.pc_vault
-        if *amm_pc_vault_info.key != amm.pc_vault || *user_source_pc_info.key == amm.pc_vault {
-            return Err(AmmError::InvalidPCVault.into());
-        }
+        Self::check_vault_accounts(
+            &amm,
+            amm_coin_vault_info.key,
+            amm_pc_vault_info.key,
+            user_source_coin_info.key,
+            user_source_pc_info.key,
+        )?;
         check_assert_eq!(
             *amm_lp_mint_info.key,
             amm.lp_mint,
@@ -1344,34 +2061,49 @@ impl Processor {
             Self::unpack_token_account(&user_source_pc_info, spl_token_program_id)?;
         let mut target_orders =
             TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-        let (market_state, open_orders) = Self::load_serum_market_order(
-            market_info,
-            amm_open_orders_info,
-            amm_authority_info,
-            &amm,
-            false,
-        )?;
-        if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes()
-            || identity(market_state.coin_mint) != user_source_coin.mint.to_aligned_bytes()
-        {
-            return Err(AmmError::InvalidCoinMint.into());
-        }
-        if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes()
-            || identity(market_state.pc_mint) != user_source_pc.mint.to_aligned_bytes()
-        {
-            return Err(AmmError::InvalidPCMint.into());
-        }
         // calc the remaining total_pc & total_coin
-        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
-            Calculator::calc_total_without_take_pnl(
-                amm_pc_vault.amount,
+        let reserves = if amm.uses_open_orders() {
+            let (market_state, open_orders) = Self::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?;
+            if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes()
+                || identity(market_state.coin_mint) != user_source_coin.mint.to_aligned_bytes()
+            {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes()
+                || identity(market_state.pc_mint) != user_source_pc.mint.to_aligned_bytes()
+            {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+            PoolReserves::with_market(
                 amm_coin_vault.amount,
+                amm_pc_vault.amount,
                 &open_orders,
-                &amm,
                 &market_state,
                 &market_event_queue_info,
                 &amm_open_orders_info,
-            )?;
+            )?
+        } else {
+            if user_source_coin.mint != amm_coin_vault.mint {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if user_source_pc.mint != amm_pc_vault.mint {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+            PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount)
+        };
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+        amm.update_price_cumulative(
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            Clock::get()?.unix_timestamp as u64,
+        );
         let x1 = Calculator::normalize_decimal_v2(
             total_pc_without_take_pnl,
             amm.pc_decimals,
@@ -1391,12 +2123,8 @@ impl Processor {
             x1.as_u128().into(),
             y1.as_u128().into(),
         )?;
-        let invariant = InvariantToken {
-            token_coin: total_coin_without_take_pnl,
-            token_pc: total_pc_without_take_pnl,
-        };
-
-        // let lp_mint  = Self::unpack_mint(&lp_mint_info, spl_token_program_id)?;
+        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, lp_token_program_info.key)?;
+        Self::check_lp_supply(&amm, lp_mint.supply)?;
         if amm.lp_amount == 0 {
             encode_ray_log(DepositLog {
                 log_type: LogType::Deposit.into_u8(),
@@ -1414,14 +2142,20 @@ impl Processor {
             });
             return Err(AmmError::NotAllowZeroLP.into());
         }
+        // the ratio is undefined once either side has been drained
+        if total_coin_without_take_pnl == 0 || total_pc_without_take_pnl == 0 {
+            return Err(AmmError::CheckedEmptyFunds.into());
+        }
         let deduct_pc_amount;
         let deduct_coin_amount;
-        let mint_lp_amount;
         if deposit.base_side == 0 {
             // base coin
-            deduct_pc_amount = invariant
-                .exchange_coin_to_pc(deposit.max_coin_amount, RoundDirection::Ceiling)
-                .ok_or(AmmError::CalculationExRateFailure)?;
+            deduct_pc_amount = deposit_pc_for_coin(
+                deposit.max_coin_amount,
+                total_coin_without_take_pnl,
+                total_pc_without_take_pnl,
+            )
+            .ok_or(AmmError::CalculationExRateFailure)?;
             deduct_coin_amount = deposit.max_coin_amount;
             if deduct_pc_amount > deposit.max_pc_amount {
                 encode_ray_log(DepositLog {
@@ -1440,19 +2174,14 @@ impl Processor {
                 });
                 return Err(AmmError::ExceededSlippage.into());
             }
-            // coin_amount/ (total_coin_amount + coin_amount)  = output / (lp_mint.supply + output) =>  output = coin_amount / total_coin_amount * lp_mint.supply
-            let invariant_coin = InvariantPool {
-                token_input: deduct_coin_amount,
-                token_total: total_coin_without_take_pnl,
-            };
-            mint_lp_amount = invariant_coin
-                .exchange_token_to_pool(amm.lp_amount, RoundDirection::Floor)
-                .ok_or(AmmError::CalculationExRateFailure)?;
         } else {
             // base pc
-            deduct_coin_amount = invariant
-                .exchange_pc_to_coin(deposit.max_pc_amount, RoundDirection::Ceiling)
-                .ok_or(AmmError::CalculationExRateFailure)?;
+            deduct_coin_amount = deposit_coin_for_pc(
+                deposit.max_pc_amount,
+                total_coin_without_take_pnl,
+                total_pc_without_take_pnl,
+            )
+            .ok_or(AmmError::CalculationExRateFailure)?;
             deduct_pc_amount = deposit.max_pc_amount;
             if deduct_coin_amount > deposit.max_coin_amount {
                 encode_ray_log(DepositLog {
@@ -1471,15 +2200,16 @@ impl Processor {
                 });
                 return Err(AmmError::ExceededSlippage.into());
             }
-            let invariant_pc = InvariantPool {
-                token_input: deduct_pc_amount,
-                token_total: total_pc_without_take_pnl,
-            };
-            // pc_amount/ (total_pc_amount + pc_amount)  = output / (lp_mint.supply + output) =>  output = pc_amount / total_pc_amount * lp_mint.supply
-            mint_lp_amount = invariant_pc
-                .exchange_token_to_pool(amm.lp_amount, RoundDirection::Floor)
-                .ok_or(AmmError::CalculationExRateFailure)?;
         }
+        // the matching side is rounded up, so the base side sets the lp minted
+        let mint_lp_amount = lp_tokens_for_deposit(
+            deduct_coin_amount,
+            deduct_pc_amount,
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            amm.lp_amount,
+        )
+        .ok_or(AmmError::CalculationExRateFailure)?;
         encode_ray_log(DepositLog {
             log_type: LogType::Deposit.into_u8(),
             max_coin: deposit.max_coin_amount,
@@ -1500,7 +2230,16 @@ impl Processor {
             return Err(AmmError::InsufficientFunds.into());
         }
         if mint_lp_amount == 0 || deduct_coin_amount == 0 || deduct_pc_amount == 0 {
-            return Err(AmmError::InvalidInput.into());
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        if mint_lp_amount < deposit.minimum_lp_amount {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+        if amm.exceeds_reserve_cap(
+            total_coin_without_take_pnl.saturating_add(deduct_coin_amount),
+            total_pc_without_take_pnl.saturating_add(deduct_pc_amount),
+        ) {
+            return Err(AmmError::ReserveCapExceeded.into());
         }
 
         Invokers::token_transfer(
@@ -1518,7 +2257,7 @@ impl Processor {
             deduct_pc_amount,
         )?;
         Invokers::token_mint_to(
-            token_program_info.clone(),
+            lp_token_program_info.clone(),
             amm_lp_mint_info.clone(),
             user_dest_lp_info.clone(),
             amm_authority_info.clone(),
@@ -1526,7 +2265,18 @@ impl Processor {
             amm.nonce as u8,
             mint_lp_amount,
         )?;
-        amm.lp_amount = amm.lp_amount.checked_add(mint_lp_amount).unwrap();
+        amm.lp_amount = amm
+            .lp_amount
+            .checked_add(mint_lp_amount)
+            .ok_or(AmmError::CalculationFailure)?;
+        Self::check_liquidity_invariant(
+            total_coin_without_take_pnl.into(),
+            total_pc_without_take_pnl.into(),
+            amm.lp_amount - mint_lp_amount,
+            u128::from(total_coin_without_take_pnl) + u128::from(deduct_coin_amount),
+            u128::from(total_pc_without_take_pnl) + u128::from(deduct_pc_amount),
+            amm.lp_amount,
+        )?;
 
         target_orders.calc_pnl_x = x1
             .checked_add(Calculator::normalize_decimal_v2(
@@ -1551,73 +2301,70 @@ impl Processor {
         Ok(())
     }
 
-    pub fn process_owner_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        const ACCOUNT_LEN: usize = 15;
-        let input_account_len = accounts.len();
-        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+    /// Processes a [DepositSingleSided](enum.Instruction.html).
+    pub fn process_deposit_single_sided(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        deposit: DepositSingleSidedInstruction,
+    ) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 13;
+        let (accounts, lp_token_program_info) = Self::split_lp_token_program(accounts);
+        let (accounts, swap_whitelist_info) = Self::split_swap_whitelist(program_id, accounts)?;
+        if accounts.len() != ACCOUNT_LEN {
             return Err(AmmError::WrongAccountsNumber.into());
         }
+        if deposit.amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
-        let ata_program_info = next_account_info(account_info_iter)?;
-        let system_program_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
-        let amm_coin_mint_info = next_account_info(account_info_iter)?;
-        let amm_pc_mint_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
-        let user_coin_account_info = next_account_info(account_info_iter)?;
-        let user_pc_account_info = next_account_info(account_info_iter)?;
-        let withdrawer_info = next_account_info(account_info_iter)?;
-        let amm_target_orders_info = next_account_info(account_info_iter)?;
 
-        let payer_info = next_account_info(account_info_iter)?;
-        let mut referrer_pc_wallet = None;
-        if input_account_len == ACCOUNT_LEN + 1 {
-            referrer_pc_wallet = Some(next_account_info(account_info_iter)?);
-            let referrer_pc_token =
-                Self::unpack_token_account(&referrer_pc_wallet.unwrap(), token_program_info.key)?;
-            check_assert_eq!(
-                referrer_pc_token.owner,
-                config_feature::referrer_pc_wallet::id(),
-                "referrer_pc_owner",
-                AmmError::InvalidOwner
-            );
-        }
+        let market_info = next_account_info(account_info_iter)?;
 
-        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
-        if *amm_authority_info.key
-            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
-        {
-            return Err(AmmError::InvalidProgramAddress.into());
+        let user_source_info = next_account_info(account_info_iter)?;
+        let user_dest_lp_info = next_account_info(account_info_iter)?;
+        let source_owner_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        if !source_owner_info.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
         }
-        if amm_info.owner != program_id {
-            return Err(AmmError::InvalidOwner.into());
+        if let Some(swap_whitelist_info) = swap_whitelist_info {
+            let whitelist =
+                SwapWhitelist::load_checked(swap_whitelist_info, program_id, amm_info.key)?;
+            Self::check_swap_whitelist(&whitelist, source_owner_info.key)?;
         }
 
-        if *withdrawer_info.key != config_feature::amm_subscriber::ID {
-            return Err(AmmError::InvalidSignAccount.into());
+        let (mut amm, extension) = AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        // the deposit swaps within the pool, so it needs both permissions
+        if amm.status == AmmStatus::Disabled.into_u64()
+            || amm.is_disabled(PoolStatusBit::Deposit)
+            || amm.is_disabled(PoolStatusBit::Swap)
+        {
+            return Err(AmmError::PoolPaused.into());
         }
-        // withdrawpnl in all status except Uninitialized
-        if amm.status == AmmStatus::Uninitialized.into_u64() {
-            msg!(&format!("withdrawpnl: status {}", amm.status));
+        Self::check_constant_product(extension.as_deref())?;
+        let status = AmmStatus::from_u64(amm.status);
+        if !status.deposit_permission() || !status.swap_permission() {
             return Err(AmmError::InvalidStatus.into());
         }
-        check_assert_eq!(
-            *amm_coin_vault_info.key,
-            amm.coin_vault,
-            "coin_vault",
-            AmmError::InvalidCoinVault
-        );
-        check_assert_eq!(
-            *amm_pc_vault_info.key,
-            amm.pc_vault,
-            "pc_vault",
-            AmmError::InvalidPCVault
-        );
+        if amm.status == AmmStatus::WaitingTrade.into_u64()
+            && (Clock::get()?.unix_timestamp as u64) < amm.state_data.pool_open_time
+        {
+            return Err(AmmError::InvalidStatus.into());
+        }
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
         check_assert_eq!(
             *token_program_info.key,
             spl_token::id(),
@@ -1625,11 +2372,25 @@ impl Processor {
             AmmError::InvalidSplTokenProgram
         );
         let spl_token_program_id = token_program_info.key;
+        let lp_token_program_info = lp_token_program_info.unwrap_or(token_program_info);
         check_assert_eq!(
-            *amm_open_orders_info.key,
-            amm.open_orders,
-            "open_orders",
-            AmmError::InvalidOpenOrders
+            *market_info.key,
+            amm.market,
+            "market",
+            AmmError::InvalidMarket
+        );
+        Self::check_vault_accounts(
+            &amm,
+            amm_coin_vault_info.key,
+            amm_pc_vault_info.key,
+            user_source_info.key,
+            user_source_info.key,
+        )?;
+        check_assert_eq!(
+            *amm_lp_mint_info.key,
+            amm.lp_mint,
+            "lp_mint",
+            AmmError::InvalidPoolMint
         );
         check_assert_eq!(
             *amm_target_orders_info.key,
@@ -1637,105 +2398,258 @@ impl Processor {
             "target_orders",
             AmmError::InvalidTargetOrders
         );
-
-        if user_coin_account_info.owner == system_program_info.key {
-            Invokers::create_ata_spl_token(
-                user_coin_account_info.clone(),
-                payer_info.clone(),
-                withdrawer_info.clone(),
-                amm_coin_mint_info.clone(),
-                token_program_info.clone(),
-                ata_program_info.clone(),
-                system_program_info.clone(),
-            )?;
-        }
-
-        if user_pc_account_info.owner == system_program_info.key {
-            Invokers::create_ata_spl_token(
-                user_pc_account_info.clone(),
-                payer_info.clone(),
-                withdrawer_info.clone(),
-                amm_pc_mint_info.clone(),
-                token_program_info.clone(),
-                ata_program_info.clone(),
-                system_program_info.clone(),
-            )?;
-        }
-
         let amm_coin_vault =
             Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
         let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-        let user_pnl_coin =
-            Self::unpack_token_account(&user_coin_account_info, spl_token_program_id)?;
-        let user_pnl_pc = Self::unpack_token_account(&user_pc_account_info, spl_token_program_id)?;
+        let user_source = Self::unpack_token_account(&user_source_info, spl_token_program_id)?;
         let mut target_orders =
             TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-        if amm_coin_vault.mint != amm.coin_vault_mint || user_pnl_coin.mint != amm.coin_vault_mint {
-            return Err(AmmError::InvalidCoinMint.into());
-        }
-        if amm_pc_vault.mint != amm.pc_vault_mint || user_pnl_pc.mint != amm.pc_vault_mint {
-            return Err(AmmError::InvalidPCMint.into());
-        }
-
-        let withdraw_coin = amm_coin_vault.amount;
-        let withdraw_pc = amm_pc_vault.amount;
-        
-        if  withdraw_coin > 0
-        {
-            // coin & pc is enough, transfer directly
-            Invokers::token_transfer_with_authority(
-                token_program_info.clone(),
-                amm_coin_vault_info.clone(),
-                user_coin_account_info.clone(),
-                amm_authority_info.clone(),
-                AUTHORITY_AMM,
-                amm.nonce as u8,
-                withdraw_coin,
+        let market_order = if amm.uses_open_orders() {
+            let (market_state, open_orders) = Self::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
             )?;
+            if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+            Some((market_state, open_orders))
+        } else {
+            None
+        };
+        let (swap_direction, amm_source_vault_info) = match deposit.side {
+            TradeDirection::CoinToPc => (SwapDirection::Coin2PC, amm_coin_vault_info),
+            TradeDirection::PcToCoin => (SwapDirection::PC2Coin, amm_pc_vault_info),
+        };
+        let source_mint = match swap_direction {
+            SwapDirection::Coin2PC => amm_coin_vault.mint,
+            SwapDirection::PC2Coin => amm_pc_vault.mint,
+        };
+        if user_source.mint != source_mint {
+            return Err(AmmError::TradeDirectionMismatch.into());
+        }
+        // calc the remaining total_pc & total_coin
+        let reserves = match market_order {
+            Some((market_state, open_orders)) => PoolReserves::with_market(
+                amm_coin_vault.amount,
+                amm_pc_vault.amount,
+                &open_orders,
+                &market_state,
+                &market_event_queue_info,
+                &amm_open_orders_info,
+            )?,
+            None => PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount),
+        };
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+        amm.update_price_cumulative(
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            Clock::get()?.unix_timestamp as u64,
+        );
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+        // calc and update pnl
+        let (delta_x, delta_y) = Self::calc_take_pnl(
+            &target_orders,
+            &mut amm,
+            &mut total_pc_without_take_pnl,
+            &mut total_coin_without_take_pnl,
+            x1.as_u128().into(),
+            y1.as_u128().into(),
+        )?;
+        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, lp_token_program_info.key)?;
+        Self::check_lp_supply(&amm, lp_mint.supply)?;
+        if amm.lp_amount == 0 {
+            return Err(AmmError::NotAllowZeroLP.into());
+        }
+        let (reserve_in, reserve_out) = match swap_direction {
+            SwapDirection::Coin2PC => (total_coin_without_take_pnl, total_pc_without_take_pnl),
+            SwapDirection::PC2Coin => (total_pc_without_take_pnl, total_coin_without_take_pnl),
+        };
+        let single_sided =
+            single_sided_deposit(&amm, deposit.amount, reserve_in, reserve_out, amm.lp_amount)?;
+        let deduct_amount = single_sided
+            .swap_in
+            .checked_add(single_sided.deposit_in)
+            .ok_or(AmmError::CheckedAddOverflow)?;
+        // the deposit part of the instruction, the swap output never leaves the pool
+        let (deduct_coin_amount, deduct_pc_amount) = match swap_direction {
+            SwapDirection::Coin2PC => (single_sided.deposit_in, single_sided.swap_out),
+            SwapDirection::PC2Coin => (single_sided.swap_out, single_sided.deposit_in),
+        };
+        let (max_coin, max_pc) = match swap_direction {
+            SwapDirection::Coin2PC => (deposit.amount, 0),
+            SwapDirection::PC2Coin => (0, deposit.amount),
+        };
+        encode_ray_log(DepositLog {
+            log_type: LogType::Deposit.into_u8(),
+            max_coin,
+            max_pc,
+            base: deposit.side as u64,
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+            pool_lp: amm.lp_amount,
+            calc_pnl_x: target_orders.calc_pnl_x,
+            calc_pnl_y: target_orders.calc_pnl_y,
+            deduct_coin: deduct_coin_amount,
+            deduct_pc: deduct_pc_amount,
+            mint_lp: single_sided.mint_lp,
+        });
+
+        if deposit.amount > user_source.amount {
+            return Err(AmmError::InsufficientFunds.into());
+        }
+        if single_sided.mint_lp == 0 || single_sided.swap_out == 0 || single_sided.deposit_in == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        if single_sided.mint_lp < deposit.minimum_lp_amount {
+            return Err(AmmError::ExceededSlippage.into());
+        }
+        let kept_in = deduct_amount - single_sided.protocol_fee;
+        let (new_coin, new_pc) = match swap_direction {
+            SwapDirection::Coin2PC => (
+                u128::from(total_coin_without_take_pnl) + u128::from(kept_in),
+                u128::from(total_pc_without_take_pnl),
+            ),
+            SwapDirection::PC2Coin => (
+                u128::from(total_coin_without_take_pnl),
+                u128::from(total_pc_without_take_pnl) + u128::from(kept_in),
+            ),
+        };
+        if amm.exceeds_reserve_cap(
+            u64::try_from(new_coin).unwrap_or(u64::MAX),
+            u64::try_from(new_pc).unwrap_or(u64::MAX),
+        ) {
+            return Err(AmmError::ReserveCapExceeded.into());
+        }
+
+        Invokers::token_transfer(
+            token_program_info.clone(),
+            user_source_info.clone(),
+            amm_source_vault_info.clone(),
+            source_owner_info.clone(),
+            deduct_amount,
+        )?;
+        Invokers::token_mint_to(
+            lp_token_program_info.clone(),
+            amm_lp_mint_info.clone(),
+            user_dest_lp_info.clone(),
+            amm_authority_info.clone(),
+            AUTHORITY_AMM,
+            amm.nonce as u8,
+            single_sided.mint_lp,
+        )?;
+        amm.lp_amount = amm
+            .lp_amount
+            .checked_add(single_sided.mint_lp)
+            .ok_or(AmmError::CalculationFailure)?;
+        match swap_direction {
+            SwapDirection::Coin2PC => {
+                amm.state_data.record_swap_coin_in(
+                    single_sided.swap_in,
+                    single_sided.swap_out,
+                    single_sided.swap_fee,
+                );
+                amm.state_data.need_take_pnl_coin = amm
+                    .state_data
+                    .need_take_pnl_coin
+                    .checked_add(single_sided.protocol_fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+            SwapDirection::PC2Coin => {
+                amm.state_data.record_swap_pc_in(
+                    single_sided.swap_in,
+                    single_sided.swap_out,
+                    single_sided.swap_fee,
+                );
+                amm.state_data.need_take_pnl_pc = amm
+                    .state_data
+                    .need_take_pnl_pc
+                    .checked_add(single_sided.protocol_fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
         }
+        Self::check_liquidity_invariant(
+            total_coin_without_take_pnl.into(),
+            total_pc_without_take_pnl.into(),
+            amm.lp_amount - single_sided.mint_lp,
+            new_coin,
+            new_pc,
+            amm.lp_amount,
+        )?;
+        encode_ray_log(SwapEvent {
+            log_type: LogType::SwapEvent.into_u8(),
+            pool: *amm_info.key,
+            direction: swap_direction as u64,
+            amount_in: single_sided.swap_in,
+            amount_out: single_sided.swap_out,
+            fee: single_sided.swap_fee,
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+        });
 
-        if  withdraw_pc > 0 {
-            Invokers::token_transfer_with_authority(
-                token_program_info.clone(),
-                amm_pc_vault_info.clone(),
-                user_pc_account_info.clone(),
-                amm_authority_info.clone(),
-                AUTHORITY_AMM,
-                amm.nonce as u8,
-                withdraw_pc,
-            )?;
-            // clear need take pnl
-        }
-
+        // like a swap followed by a deposit, only the deposited amounts move the pnl baseline
+        target_orders.calc_pnl_x = x1
+            .checked_add(Calculator::normalize_decimal_v2(
+                deduct_pc_amount,
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            ))
+            .unwrap()
+            .checked_sub(U128::from(delta_x))
+            .unwrap()
+            .as_u128();
+        target_orders.calc_pnl_y = y1
+            .checked_add(Calculator::normalize_decimal_v2(
+                deduct_coin_amount,
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            ))
+            .unwrap()
+            .checked_sub(U128::from(delta_y))
+            .unwrap()
+            .as_u128();
         Ok(())
     }
 
-    pub fn process_withdrawpnl(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
-        const ACCOUNT_LEN: usize = 17;
+    pub fn process_owner_withdraw(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 15;
         let input_account_len = accounts.len();
         if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
             return Err(AmmError::WrongAccountsNumber.into());
         }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
+        let ata_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
-        let amm_config_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_mint_info = next_account_info(account_info_iter)?;
+        let amm_pc_mint_info = next_account_info(account_info_iter)?;
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
-        let user_pnl_coin_info = next_account_info(account_info_iter)?;
-        let user_pnl_pc_info = next_account_info(account_info_iter)?;
-        let pnl_owner_info = next_account_info(account_info_iter)?;
+        let user_coin_account_info = next_account_info(account_info_iter)?;
+        let user_pc_account_info = next_account_info(account_info_iter)?;
+        let withdrawer_info = next_account_info(account_info_iter)?;
         let amm_target_orders_info = next_account_info(account_info_iter)?;
 
-        let market_program_info = next_account_info(account_info_iter)?;
-        let market_info = next_account_info(account_info_iter)?;
-        let market_event_queue_info = next_account_info(account_info_iter)?;
-        let market_coin_vault_info = next_account_info(account_info_iter)?;
-        let market_pc_vault_info = next_account_info(account_info_iter)?;
-        let market_vault_signer = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
         let mut referrer_pc_wallet = None;
         if input_account_len == ACCOUNT_LEN + 1 {
             referrer_pc_wallet = Some(next_account_info(account_info_iter)?);
@@ -1759,17 +2673,7 @@ impl Processor {
             return Err(AmmError::InvalidOwner.into());
         }
 
-        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
-        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
-            return Err(AmmError::InvalidConfigAccount.into());
-        }
-        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
-
-        if !pnl_owner_info.is_signer
-            || (*pnl_owner_info.key != config_feature::amm_owner::ID
-                && *pnl_owner_info.key != amm_config.pnl_owner
-                && *pnl_owner_info.key != config_feature::amm_subscriber::ID)
-        {
+        if *withdrawer_info.key != config_feature::amm_subscriber::ID {
             return Err(AmmError::InvalidSignAccount.into());
         }
         // withdrawpnl in all status except Uninitialized
@@ -1777,12 +2681,6 @@ impl Processor {
             msg!(&format!("withdrawpnl: status {}", amm.status));
             return Err(AmmError::InvalidStatus.into());
         }
-        check_assert_eq!(
-            *market_info.key,
-            amm.market,
-            "market",
-            AmmError::InvalidMarket
-        );
         check_assert_eq!(
             *amm_coin_vault_info.key,
             amm.coin_vault,
@@ -1802,12 +2700,6 @@ impl Processor {
             AmmError::InvalidSplTokenProgram
         );
         let spl_token_program_id = token_program_info.key;
-        check_assert_eq!(
-            *market_program_info.key,
-            amm.market_program,
-            "market_program",
-            AmmError::InvalidMarketProgram
-        );
         check_assert_eq!(
             *amm_open_orders_info.key,
             amm.open_orders,
@@ -1820,11 +2712,37 @@ impl Processor {
             "target_orders",
             AmmError::InvalidTargetOrders
         );
+
+        if user_coin_account_info.owner == system_program_info.key {
+            Invokers::create_ata_spl_token(
+                user_coin_account_info.clone(),
+                payer_info.clone(),
+                withdrawer_info.clone(),
+                amm_coin_mint_info.clone(),
+                token_program_info.clone(),
+                ata_program_info.clone(),
+                system_program_info.clone(),
+            )?;
+        }
+
+        if user_pc_account_info.owner == system_program_info.key {
+            Invokers::create_ata_spl_token(
+                user_pc_account_info.clone(),
+                payer_info.clone(),
+                withdrawer_info.clone(),
+                amm_pc_mint_info.clone(),
+                token_program_info.clone(),
+                ata_program_info.clone(),
+                system_program_info.clone(),
+            )?;
+        }
+
         let amm_coin_vault =
             Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
         let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-        let user_pnl_coin = Self::unpack_token_account(&user_pnl_coin_info, spl_token_program_id)?;
-        let user_pnl_pc = Self::unpack_token_account(&user_pnl_pc_info, spl_token_program_id)?;
+        let user_pnl_coin =
+            Self::unpack_token_account(&user_coin_account_info, spl_token_program_id)?;
+        let user_pnl_pc = Self::unpack_token_account(&user_pc_account_info, spl_token_program_id)?;
         let mut target_orders =
             TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
         if amm_coin_vault.mint != amm.coin_vault_mint || user_pnl_coin.mint != amm.coin_vault_mint {
@@ -1834,39 +2752,209 @@ impl Processor {
             return Err(AmmError::InvalidPCMint.into());
         }
 
-        let (market_state, open_orders) = Self::load_serum_market_order(
-            market_info,
-            amm_open_orders_info,
-            amm_authority_info,
-            &amm,
-            false,
-        )?;
-
-        msg!(arrform!(
-            LOG_SIZE,
-            "withdrawpnl need_take_coin:{}, need_take_pc:{}",
-            amm.state_data.need_take_pnl_coin,
-            amm.state_data.need_take_pnl_pc
-        )
-        .as_str());
+        let withdraw_coin = amm_coin_vault.amount;
+        let withdraw_pc = amm_pc_vault.amount;
+        
+        if  withdraw_coin > 0
+        {
+            // coin & pc is enough, transfer directly
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_coin_vault_info.clone(),
+                user_coin_account_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                amm.nonce as u8,
+                withdraw_coin,
+            )?;
+        }
 
-        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
-            Calculator::calc_total_without_take_pnl(
-                amm_pc_vault.amount,
-                amm_coin_vault.amount,
-                &open_orders,
-                &amm,
-                &market_state,
-                &market_event_queue_info,
-                &amm_open_orders_info,
+        if  withdraw_pc > 0 {
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_pc_vault_info.clone(),
+                user_pc_account_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                amm.nonce as u8,
+                withdraw_pc,
             )?;
-        let x1 = Calculator::normalize_decimal_v2(
-            total_pc_without_take_pnl,
-            amm.pc_decimals,
-            amm.sys_decimal_value,
-        );
-        let y1 = Calculator::normalize_decimal_v2(
-            total_coin_without_take_pnl,
+            // clear need take pnl
+        }
+
+        Ok(())
+    }
+
+    pub fn process_withdrawpnl(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 17;
+        let input_account_len = accounts.len();
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let user_pnl_coin_info = next_account_info(account_info_iter)?;
+        let user_pnl_pc_info = next_account_info(account_info_iter)?;
+        let pnl_owner_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let market_coin_vault_info = next_account_info(account_info_iter)?;
+        let market_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_vault_signer = next_account_info(account_info_iter)?;
+        let mut referrer_pc_wallet = None;
+        if input_account_len == ACCOUNT_LEN + 1 {
+            referrer_pc_wallet = Some(next_account_info(account_info_iter)?);
+            let referrer_pc_token =
+                Self::unpack_token_account(&referrer_pc_wallet.unwrap(), token_program_info.key)?;
+            check_assert_eq!(
+                referrer_pc_token.owner,
+                config_feature::referrer_pc_wallet::id(),
+                "referrer_pc_owner",
+                AmmError::InvalidOwner
+            );
+        }
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        if amm_info.owner != program_id {
+            return Err(AmmError::InvalidOwner.into());
+        }
+
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
+
+        if !pnl_owner_info.is_signer
+            || (*pnl_owner_info.key != config_feature::amm_owner::ID
+                && *pnl_owner_info.key != amm_config.pnl_owner
+                && *pnl_owner_info.key != config_feature::amm_subscriber::ID)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        // withdrawpnl in all status except Uninitialized
+        if amm.status == AmmStatus::Uninitialized.into_u64() {
+            msg!(&format!("withdrawpnl: status {}", amm.status));
+            return Err(AmmError::InvalidStatus.into());
+        }
+        check_assert_eq!(
+            *market_info.key,
+            amm.market,
+            "market",
+            AmmError::InvalidMarket
+        );
+        check_assert_eq!(
+            *amm_coin_vault_info.key,
+            amm.coin_vault,
+            "coin_vault",
+            AmmError::InvalidCoinVault
+        );
+        check_assert_eq!(
+            *amm_pc_vault_info.key,
+            amm.pc_vault,
+            "pc_vault",
+            AmmError::InvalidPCVault
+        );
+        check_assert_eq!(
+            *token_program_info.key,
+            spl_token::id(),
+            "spl_token_program",
+            AmmError::InvalidSplTokenProgram
+        );
+        let spl_token_program_id = token_program_info.key;
+        check_assert_eq!(
+            *market_program_info.key,
+            amm.market_program,
+            "market_program",
+            AmmError::InvalidMarketProgram
+        );
+        check_assert_eq!(
+            *amm_open_orders_info.key,
+            amm.open_orders,
+            "open_orders",
+            AmmError::InvalidOpenOrders
+        );
+        check_assert_eq!(
+            *amm_target_orders_info.key,
+            amm.target_orders,
+            "target_orders",
+            AmmError::InvalidTargetOrders
+        );
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let user_pnl_coin = Self::unpack_token_account(&user_pnl_coin_info, spl_token_program_id)?;
+        let user_pnl_pc = Self::unpack_token_account(&user_pnl_pc_info, spl_token_program_id)?;
+        let mut target_orders =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+        if amm_coin_vault.mint != amm.coin_vault_mint || user_pnl_coin.mint != amm.coin_vault_mint {
+            return Err(AmmError::InvalidCoinMint.into());
+        }
+        if amm_pc_vault.mint != amm.pc_vault_mint || user_pnl_pc.mint != amm.pc_vault_mint {
+            return Err(AmmError::InvalidPCMint.into());
+        }
+
+        let market_order = if amm.uses_open_orders() {
+            Some(Self::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?)
+        } else {
+            None
+        };
+
+        msg!(arrform!(
+            LOG_SIZE,
+            "withdrawpnl need_take_coin:{}, need_take_pc:{}",
+            amm.state_data.need_take_pnl_coin,
+            amm.state_data.need_take_pnl_pc
+        )
+        .as_str());
+
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            match market_order.as_ref() {
+                Some((market_state, open_orders)) => Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    open_orders,
+                    &amm,
+                    market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?,
+                None => PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount)
+                    .total_without_take_pnl(&amm)?,
+            };
+        // a pool without open orders has nothing on the market, its pnl is in the vaults
+        let open_orders: Box<OpenOrders> = match market_order {
+            Some((_, open_orders)) => open_orders,
+            None => Box::new(bytemuck::Zeroable::zeroed()),
+        };
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
             amm.coin_decimals,
             amm.sys_decimal_value,
         );
@@ -1996,6 +3084,7 @@ impl Processor {
         withdraw: WithdrawInstruction,
     ) -> ProgramResult {
         const ACCOUNT_LEN: usize = 20;
+        let (accounts, lp_token_program_info) = Self::split_lp_token_program(accounts);
         let input_account_len = accounts.len();
         if input_account_len != ACCOUNT_LEN
             && input_account_len != ACCOUNT_LEN + 1
@@ -2014,7 +3103,8 @@ impl Processor {
         let amm_lp_mint_info = next_account_info(account_info_iter)?;
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
-        if input_account_len != ACCOUNT_LEN + 2 || input_account_len != ACCOUNT_LEN + 3 {
+        // the two padding accounts are only present in the 22 and 23 accounts layout
+        if input_account_len == ACCOUNT_LEN + 2 || input_account_len == ACCOUNT_LEN + 3 {
             let _padding_account_info1 = next_account_info(account_info_iter)?;
             let _padding_account_info2 = next_account_info(account_info_iter)?;
         }
@@ -2061,6 +3151,10 @@ impl Processor {
         let mut target_orders =
             TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
 
+        if amm.status == AmmStatus::Disabled.into_u64() || amm.is_disabled(PoolStatusBit::Withdraw)
+        {
+            return Err(AmmError::PoolPaused.into());
+        }
         if !AmmStatus::from_u64(amm.status).withdraw_permission() {
             return Err(AmmError::InvalidStatus.into());
         }
@@ -2076,21 +3170,20 @@ impl Processor {
             AmmError::InvalidSplTokenProgram
         );
         let spl_token_program_id = token_program_info.key;
+        let lp_token_program_info = lp_token_program_info.unwrap_or(token_program_info);
         check_assert_eq!(
             *market_info.key,
             amm.market,
             "market",
             AmmError::InvalidMarket
         );
-        // token_coin must be amm.coin_vault or token_dest_coin must not be amm.coin_vault
-        if *amm_coin_vault_info.key != amm.coin_vault || *user_dest_coin_info.key == amm.coin_vault
-        {
-            return Err(AmmError::InvalidCoinVault.into());
-        }
-        // token_pc must be amm.pc_vault or token_dest_pc must not be amm.pc_vault
-        if *amm_pc_vault_info.key != amm.pc_vault || *user_dest_pc_info.key == amm.pc_vault {
-            return Err(AmmError::InvalidPCVault.into());
-        }
+        Self::check_vault_accounts(
+            &amm,
+            amm_coin_vault_info.key,
+            amm_pc_vault_info.key,
+            user_dest_coin_info.key,
+            user_dest_pc_info.key,
+        )?;
         check_assert_eq!(
             *market_program_info.key,
             amm.market_program,
@@ -2116,87 +3209,120 @@ impl Processor {
         let user_dest_coin =
             Self::unpack_token_account(&user_dest_coin_info, spl_token_program_id)?;
         let user_dest_pc = Self::unpack_token_account(&user_dest_pc_info, spl_token_program_id)?;
-        let (market_state, open_orders) = Self::load_serum_market_order(
-            market_info,
-            amm_open_orders_info,
-            amm_authority_info,
-            &amm,
-            false,
+        Self::check_user_token_account(
+            &user_dest_coin,
+            &amm_coin_vault.mint,
+            source_lp_owner_info.key,
         )?;
-        let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
-        let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-        let (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-        // let amm_orders = Self::get_amm_order(&market_state, &open_orders, serum_bids_info, serum_asks_info)?;
-        // cancel all orders
-        let mut amm_order_ids_vec = Vec::new();
-        let mut order_ids = [0u64; 8];
-        let mut count = 0;
-        for i in 0..std::cmp::max(bids.len(), asks.len()) {
-            if i < bids.len() {
-                order_ids[count] = bids[i].client_order_id();
-                count += 1;
-            }
-            if i < asks.len() {
-                order_ids[count] = asks[i].client_order_id();
-                count += 1;
+        Self::check_user_token_account(
+            &user_dest_pc,
+            &amm_pc_vault.mint,
+            source_lp_owner_info.key,
+        )?;
+        // a pool without open orders has no orders to cancel nor funds to settle
+        let market_order = if amm.uses_open_orders() {
+            let (market_state, open_orders) = Self::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?;
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            let (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+            // let amm_orders = Self::get_amm_order(&market_state, &open_orders, serum_bids_info, serum_asks_info)?;
+            // cancel all orders
+            let mut amm_order_ids_vec = Vec::new();
+            let mut order_ids = [0u64; 8];
+            let mut count = 0;
+            for i in 0..std::cmp::max(bids.len(), asks.len()) {
+                if i < bids.len() {
+                    order_ids[count] = bids[i].client_order_id();
+                    count += 1;
+                }
+                if i < asks.len() {
+                    order_ids[count] = asks[i].client_order_id();
+                    count += 1;
+                }
+                if count == 8 {
+                    amm_order_ids_vec.push(order_ids);
+                    order_ids = [0u64; 8];
+                    count = 0;
+                }
             }
-            if count == 8 {
+            if count != 0 {
                 amm_order_ids_vec.push(order_ids);
-                order_ids = [0u64; 8];
-                count = 0;
             }
+            for ids in amm_order_ids_vec.iter() {
+                Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                    market_program_info.clone(),
+                    market_info.clone(),
+                    market_bids_info.clone(),
+                    market_asks_info.clone(),
+                    amm_open_orders_info.clone(),
+                    amm_authority_info.clone(),
+                    market_event_q_info.clone(),
+                    AUTHORITY_AMM,
+                    amm.nonce as u8,
+                    *ids,
+                )?;
+            }
+
+            if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes()
+                || identity(market_state.coin_mint) != user_dest_coin.mint.to_aligned_bytes()
+            {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes()
+                || identity(market_state.pc_mint) != user_dest_pc.mint.to_aligned_bytes()
+            {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+            Some((market_state, open_orders))
+        } else {
+            None
+        };
+
+        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, lp_token_program_info.key)?;
+        let user_source_lp =
+            Self::unpack_token_account(&user_source_lp_info, lp_token_program_info.key)?;
+        if user_source_lp.mint != *amm_lp_mint_info.key {
+            return Err(AmmError::InvalidTokenLP.into());
         }
-        if count != 0 {
-            amm_order_ids_vec.push(order_ids);
-        }
-        for ids in amm_order_ids_vec.iter() {
-            Invokers::invoke_dex_cancel_orders_by_client_order_ids(
-                market_program_info.clone(),
-                market_info.clone(),
-                market_bids_info.clone(),
-                market_asks_info.clone(),
-                amm_open_orders_info.clone(),
-                amm_authority_info.clone(),
-                market_event_q_info.clone(),
-                AUTHORITY_AMM,
-                amm.nonce as u8,
-                *ids,
-            )?;
-        }
-
-        if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes()
-            || identity(market_state.coin_mint) != user_dest_coin.mint.to_aligned_bytes()
-        {
-            return Err(AmmError::InvalidCoinMint.into());
-        }
-        if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes()
-            || identity(market_state.pc_mint) != user_dest_pc.mint.to_aligned_bytes()
-        {
-            return Err(AmmError::InvalidPCMint.into());
-        }
-
-        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, spl_token_program_id)?;
-        let user_source_lp =
-            Self::unpack_token_account(&user_source_lp_info, spl_token_program_id)?;
-        if user_source_lp.mint != *amm_lp_mint_info.key {
-            return Err(AmmError::InvalidTokenLP.into());
-        }
-        if withdraw.amount > user_source_lp.amount {
-            return Err(AmmError::InsufficientFunds.into());
+        if withdraw.amount > user_source_lp.amount {
+            return Err(AmmError::InsufficientFunds.into());
         }
+        Self::check_lp_supply(&amm, lp_mint.supply)?;
         if withdraw.amount > lp_mint.supply || withdraw.amount >= amm.lp_amount {
             return Err(AmmError::NotAllowZeroLP.into());
         }
+        let (reserves, coin_in_open_orders, pc_in_open_orders) = match market_order {
+            Some((market_state, open_orders)) => (
+                PoolReserves::with_market(
+                    amm_coin_vault.amount,
+                    amm_pc_vault.amount,
+                    &open_orders,
+                    &market_state,
+                    &market_event_q_info,
+                    &amm_open_orders_info,
+                )?,
+                open_orders.native_coin_total,
+                open_orders.native_pc_total,
+            ),
+            None => (
+                PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount),
+                0,
+                0,
+            ),
+        };
         let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
-            Calculator::calc_total_without_take_pnl(
-                amm_pc_vault.amount,
-                amm_coin_vault.amount,
-                &open_orders,
-                &amm,
-                &market_state,
-                &market_event_q_info,
-                &amm_open_orders_info,
-            )?;
+            reserves.total_without_take_pnl(&amm)?;
+        amm.update_price_cumulative(
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            Clock::get()?.unix_timestamp as u64,
+        );
         let x1 = Calculator::normalize_decimal_v2(
             total_pc_without_take_pnl,
             amm.pc_decimals,
@@ -2247,10 +3373,10 @@ impl Processor {
             out_pc: pc_amount,
         });
         if withdraw.amount == 0 || coin_amount == 0 || pc_amount == 0 {
-            return Err(AmmError::InvalidInput.into());
+            return Err(AmmError::ZeroTradingTokens.into());
         }
 
-        if coin_amount <= amm_coin_vault.amount && pc_amount <= amm_pc_vault.amount {
+        if coin_amount <= reserves.coin_vault && pc_amount <= reserves.pc_vault {
             Invokers::token_transfer_with_authority(
                 token_program_info.clone(),
                 amm_coin_vault_info.clone(),
@@ -2270,23 +3396,15 @@ impl Processor {
                 pc_amount,
             )?;
             Invokers::token_burn(
-                token_program_info.clone(),
+                lp_token_program_info.clone(),
                 user_source_lp_info.clone(),
                 amm_lp_mint_info.clone(),
                 source_lp_owner_info.clone(),
                 withdraw.amount,
             )?;
-            amm.lp_amount = amm.lp_amount.checked_sub(withdraw.amount).unwrap();
-        } else if coin_amount
-            <= amm_coin_vault
-                .amount
-                .checked_add(open_orders.native_coin_free)
-                .unwrap()
-            && pc_amount
-                <= amm_pc_vault
-                    .amount
-                    .checked_add(open_orders.native_pc_free)
-                    .unwrap()
+            amm.lp_amount = checked!(amm.lp_amount.checked_sub(withdraw.amount));
+        } else if coin_amount <= checked!(reserves.coin_vault.checked_add(reserves.coin_free))
+            && pc_amount <= checked!(reserves.pc_vault.checked_add(reserves.pc_free))
         {
             Invokers::invoke_dex_settle_funds(
                 market_program_info.clone(),
@@ -2323,23 +3441,15 @@ impl Processor {
                 pc_amount,
             )?;
             Invokers::token_burn(
-                token_program_info.clone(),
+                lp_token_program_info.clone(),
                 user_source_lp_info.clone(),
                 amm_lp_mint_info.clone(),
                 source_lp_owner_info.clone(),
                 withdraw.amount,
             )?;
-            amm.lp_amount = amm.lp_amount.checked_sub(withdraw.amount).unwrap();
-        } else if coin_amount
-            <= amm_coin_vault
-                .amount
-                .checked_add(open_orders.native_coin_total)
-                .unwrap()
-            && pc_amount
-                <= amm_pc_vault
-                    .amount
-                    .checked_add(open_orders.native_pc_total)
-                    .unwrap()
+            amm.lp_amount = checked!(amm.lp_amount.checked_sub(withdraw.amount));
+        } else if coin_amount <= checked!(reserves.coin_vault.checked_add(coin_in_open_orders))
+            && pc_amount <= checked!(reserves.pc_vault.checked_add(pc_in_open_orders))
         {
             Invokers::invoke_dex_settle_funds(
                 market_program_info.clone(),
@@ -2376,485 +3486,412 @@ impl Processor {
                 pc_amount,
             )?;
             Invokers::token_burn(
-                token_program_info.clone(),
+                lp_token_program_info.clone(),
                 user_source_lp_info.clone(),
                 amm_lp_mint_info.clone(),
                 source_lp_owner_info.clone(),
                 withdraw.amount,
             )?;
-            amm.lp_amount = amm.lp_amount.checked_sub(withdraw.amount).unwrap();
+            amm.lp_amount = checked!(amm.lp_amount.checked_sub(withdraw.amount));
         } else {
             // calc error
             return Err(AmmError::TakePnlError.into());
         }
+        Self::check_liquidity_invariant(
+            total_coin_without_take_pnl.into(),
+            total_pc_without_take_pnl.into(),
+            checked!(amm.lp_amount.checked_add(withdraw.amount)),
+            checked!(u128::from(total_coin_without_take_pnl).checked_sub(coin_amount.into())),
+            checked!(u128::from(total_pc_without_take_pnl).checked_sub(pc_amount.into())),
+            amm.lp_amount,
+        )?;
 
         // step4: update target_orders.calc_pnl_x & target_orders.calc_pnl_y
-        target_orders.calc_pnl_x = x1
-            .checked_sub(Calculator::normalize_decimal_v2(
-                pc_amount,
-                amm.pc_decimals,
-                amm.sys_decimal_value,
-            ))
-            .unwrap()
-            .checked_sub(U128::from(delta_x))
-            .unwrap()
-            .as_u128();
-        target_orders.calc_pnl_y = y1
-            .checked_sub(Calculator::normalize_decimal_v2(
-                coin_amount,
-                amm.coin_decimals,
-                amm.sys_decimal_value,
-            ))
-            .unwrap()
-            .checked_sub(U128::from(delta_y))
-            .unwrap()
-            .as_u128();
+        let pc_out =
+            Calculator::normalize_decimal_v2(pc_amount, amm.pc_decimals, amm.sys_decimal_value);
+        let coin_out =
+            Calculator::normalize_decimal_v2(coin_amount, amm.coin_decimals, amm.sys_decimal_value);
+        target_orders.calc_pnl_x =
+            checked!(checked!(x1.checked_sub(pc_out)).checked_sub(U128::from(delta_x))).as_u128();
+        target_orders.calc_pnl_y =
+            checked!(checked!(y1.checked_sub(coin_out)).checked_sub(U128::from(delta_y))).as_u128();
         Ok(())
     }
 
-    pub fn process_swap_base_in(
+    /// Processes a [WithdrawSingleSided](enum.Instruction.html).
+    pub fn process_withdraw_single_sided(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        swap: SwapInstructionBaseIn,
+        withdraw: WithdrawSingleSidedInstruction,
     ) -> ProgramResult {
-        const ACCOUNT_LEN: usize = 17;
-        let input_account_len = accounts.len();
-        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
+        const ACCOUNT_LEN: usize = 13;
+        let (accounts, lp_token_program_info) = Self::split_lp_token_program(accounts);
+        let (accounts, swap_whitelist_info) = Self::split_swap_whitelist(program_id, accounts)?;
+        if accounts.len() != ACCOUNT_LEN {
             return Err(AmmError::WrongAccountsNumber.into());
         }
+        if withdraw.lp_amount == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
-        if input_account_len == ACCOUNT_LEN + 1 {
-            let _amm_target_orders_info = next_account_info(account_info_iter)?;
-        }
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
 
-        let market_porgram_info = next_account_info(account_info_iter)?;
-
-        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
-        let enable_orderbook;
-        if AmmStatus::from_u64(amm.status).orderbook_permission() {
-            enable_orderbook = true;
-        } else {
-            enable_orderbook = false;
-        }
         let market_info = next_account_info(account_info_iter)?;
-        let market_bids_info = next_account_info(account_info_iter)?;
-        let market_asks_info = next_account_info(account_info_iter)?;
-        let market_event_queue_info = next_account_info(account_info_iter)?;
-        let market_coin_vault_info = next_account_info(account_info_iter)?;
-        let market_pc_vault_info = next_account_info(account_info_iter)?;
-        let market_vault_signer = next_account_info(account_info_iter)?;
 
-        let user_source_info = next_account_info(account_info_iter)?;
-        let user_destination_info = next_account_info(account_info_iter)?;
-        let user_source_owner = next_account_info(account_info_iter)?;
-        if !user_source_owner.is_signer {
+        let user_source_lp_info = next_account_info(account_info_iter)?;
+        let user_dest_info = next_account_info(account_info_iter)?;
+        let source_lp_owner_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        if !source_lp_owner_info.is_signer {
             return Err(AmmError::InvalidSignAccount.into());
         }
-        check_assert_eq!(
-            *token_program_info.key,
-            spl_token::id(),
-            "spl_token_program",
-            AmmError::InvalidSplTokenProgram
-        );
-        let spl_token_program_id = token_program_info.key;
+        if let Some(swap_whitelist_info) = swap_whitelist_info {
+            let whitelist =
+                SwapWhitelist::load_checked(swap_whitelist_info, program_id, amm_info.key)?;
+            Self::check_swap_whitelist(&whitelist, source_lp_owner_info.key)?;
+        }
+
+        let (mut amm, extension) = AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        // the withdraw swaps within the pool, so it needs both permissions
+        if amm.status == AmmStatus::Disabled.into_u64()
+            || amm.is_disabled(PoolStatusBit::Withdraw)
+            || amm.is_disabled(PoolStatusBit::Swap)
+        {
+            return Err(AmmError::PoolPaused.into());
+        }
+        Self::check_constant_product(extension.as_deref())?;
+        let status = AmmStatus::from_u64(amm.status);
+        if !status.withdraw_permission() || !status.swap_permission() {
+            return Err(AmmError::InvalidStatus.into());
+        }
+        if amm.status == AmmStatus::WaitingTrade.into_u64()
+            && (Clock::get()?.unix_timestamp as u64) < amm.state_data.pool_open_time
+        {
+            return Err(AmmError::InvalidStatus.into());
+        }
         if *amm_authority_info.key
             != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
         {
             return Err(AmmError::InvalidProgramAddress.into());
         }
         check_assert_eq!(
-            *amm_coin_vault_info.key,
-            amm.coin_vault,
-            "coin_vault",
-            AmmError::InvalidCoinVault
+            *token_program_info.key,
+            spl_token::id(),
+            "spl_token_program",
+            AmmError::InvalidSplTokenProgram
         );
+        let spl_token_program_id = token_program_info.key;
+        let lp_token_program_info = lp_token_program_info.unwrap_or(token_program_info);
         check_assert_eq!(
-            *amm_pc_vault_info.key,
-            amm.pc_vault,
-            "pc_vault",
-            AmmError::InvalidPCVault
+            *market_info.key,
+            amm.market,
+            "market",
+            AmmError::InvalidMarket
         );
+        Self::check_vault_accounts(
+            &amm,
+            amm_coin_vault_info.key,
+            amm_pc_vault_info.key,
+            user_dest_info.key,
+            user_dest_info.key,
+        )?;
         check_assert_eq!(
-            *amm_open_orders_info.key,
-            amm.open_orders,
-            "open_orders",
-            AmmError::InvalidOpenOrders
+            *amm_lp_mint_info.key,
+            amm.lp_mint,
+            "lp_mint",
+            AmmError::InvalidPoolMint
         );
         check_assert_eq!(
-            *market_porgram_info.key,
-            amm.market_program,
-            "market_program",
-            AmmError::InvalidMarketProgram
+            *amm_target_orders_info.key,
+            amm.target_orders,
+            "target_orders",
+            AmmError::InvalidTargetOrders
         );
-
-        if *user_source_info.key == amm.pc_vault || *user_source_info.key == amm.coin_vault {
-            return Err(AmmError::InvalidUserToken.into());
-        }
-        if *user_destination_info.key == amm.pc_vault
-            || *user_destination_info.key == amm.coin_vault
-        {
-            return Err(AmmError::InvalidUserToken.into());
-        }
-
         let amm_coin_vault =
             Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
         let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-
-        let user_source = Self::unpack_token_account(&user_source_info, spl_token_program_id)?;
-        let user_destination =
-            Self::unpack_token_account(&user_destination_info, spl_token_program_id)?;
-
-        if !AmmStatus::from_u64(amm.status).swap_permission() {
-            msg!(&format!("swap_base_in: status {}", amm.status));
-            let clock = Clock::get()?;
-            if amm.status == AmmStatus::OrderBookOnly.into_u64()
-                && (clock.unix_timestamp as u64) >= amm.state_data.orderbook_to_init_time
-            {
-                amm.status = AmmStatus::Initialized.into_u64();
-                msg!("swap_base_in: OrderBook to Initialized");
-            } else {
-                return Err(AmmError::InvalidStatus.into());
-            }
-        } else if amm.status == AmmStatus::WaitingTrade.into_u64() {
-            let clock = Clock::get()?;
-            if (clock.unix_timestamp as u64) < amm.state_data.pool_open_time {
-                return Err(AmmError::InvalidStatus.into());
-            } else {
-                amm.status = AmmStatus::SwapOnly.into_u64();
-                msg!("swap_base_in: WaitingTrade to SwapOnly");
-            }
-        }
-
-        let total_pc_without_take_pnl;
-        let total_coin_without_take_pnl;
-        let mut bids: Vec<LeafNode> = Vec::new();
-        let mut asks: Vec<LeafNode> = Vec::new();
-        if enable_orderbook {
-            check_assert_eq!(
-                *market_info.key,
-                amm.market,
-                "market",
-                AmmError::InvalidMarket
-            );
-            let (market_state, open_orders) = Processor::load_serum_market_order(
+        let user_dest = Self::unpack_token_account(&user_dest_info, spl_token_program_id)?;
+        let mut target_orders =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+        let market_order = if amm.uses_open_orders() {
+            let (market_state, open_orders) = Self::load_serum_market_order(
                 market_info,
                 amm_open_orders_info,
                 amm_authority_info,
                 &amm,
                 false,
             )?;
-            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
-            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-            (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
+            if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+            Some((market_state, open_orders))
         } else {
-            let open_orders = Self::load_orders(amm_open_orders_info)?;
-            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl_no_orderbook(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                )?;
+            None
+        };
+        let (swap_direction, amm_dest_vault_info, dest_mint) = match withdraw.out_side {
+            TradeDirection::CoinToPc => {
+                (SwapDirection::Coin2PC, amm_pc_vault_info, amm_pc_vault.mint)
+            }
+            TradeDirection::PcToCoin => (
+                SwapDirection::PC2Coin,
+                amm_coin_vault_info,
+                amm_coin_vault.mint,
+            ),
+        };
+        if user_dest.mint != dest_mint {
+            return Err(AmmError::TradeDirectionMismatch.into());
         }
+        Self::check_user_token_account(&user_dest, &dest_mint, source_lp_owner_info.key)?;
 
-        let swap_direction;
-        if user_source.mint == amm_coin_vault.mint && user_destination.mint == amm_pc_vault.mint {
-            swap_direction = SwapDirection::Coin2PC
-        } else if user_source.mint == amm_pc_vault.mint
-            && user_destination.mint == amm_coin_vault.mint
-        {
-            swap_direction = SwapDirection::PC2Coin
-        } else {
-            return Err(AmmError::InvalidUserToken.into());
+        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, lp_token_program_info.key)?;
+        let user_source_lp =
+            Self::unpack_token_account(&user_source_lp_info, lp_token_program_info.key)?;
+        if user_source_lp.mint != *amm_lp_mint_info.key {
+            return Err(AmmError::InvalidTokenLP.into());
         }
-        if user_source.amount < swap.amount_in {
-            encode_ray_log(SwapBaseInLog {
-                log_type: LogType::SwapBaseIn.into_u8(),
-                amount_in: swap.amount_in,
-                minimum_out: swap.minimum_amount_out,
-                direction: swap_direction as u64,
-                user_source: user_source.amount,
-                pool_coin: total_coin_without_take_pnl,
-                pool_pc: total_pc_without_take_pnl,
-                out_amount: 0,
-            });
+        if withdraw.lp_amount > user_source_lp.amount {
             return Err(AmmError::InsufficientFunds.into());
         }
-        let swap_fee = U128::from(swap.amount_in)
-            .checked_mul(amm.fees.swap_fee_numerator.into())
-            .unwrap()
-            .checked_ceil_div(amm.fees.swap_fee_denominator.into())
-            .unwrap()
-            .0;
-        let swap_in_after_deduct_fee = U128::from(swap.amount_in).checked_sub(swap_fee).unwrap();
-        let swap_amount_out = Calculator::swap_token_amount_base_in(
-            swap_in_after_deduct_fee,
-            total_pc_without_take_pnl.into(),
-            total_coin_without_take_pnl.into(),
-            swap_direction,
-        )
-        .as_u64();
-        encode_ray_log(SwapBaseInLog {
-            log_type: LogType::SwapBaseIn.into_u8(),
-            amount_in: swap.amount_in,
-            minimum_out: swap.minimum_amount_out,
-            direction: swap_direction as u64,
-            user_source: user_source.amount,
+        Self::check_lp_supply(&amm, lp_mint.supply)?;
+        // calc the remaining total_pc & total_coin
+        let reserves = match market_order {
+            Some((market_state, open_orders)) => PoolReserves::with_market(
+                amm_coin_vault.amount,
+                amm_pc_vault.amount,
+                &open_orders,
+                &market_state,
+                &market_event_queue_info,
+                &amm_open_orders_info,
+            )?,
+            None => PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount),
+        };
+        let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+        amm.update_price_cumulative(
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            Clock::get()?.unix_timestamp as u64,
+        );
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+        // calc and update pnl
+        let (delta_x, delta_y) = Self::calc_take_pnl(
+            &target_orders,
+            &mut amm,
+            &mut total_pc_without_take_pnl,
+            &mut total_coin_without_take_pnl,
+            x1.as_u128().into(),
+            y1.as_u128().into(),
+        )?;
+        let (reserve_in, reserve_out) = match swap_direction {
+            SwapDirection::Coin2PC => (total_coin_without_take_pnl, total_pc_without_take_pnl),
+            SwapDirection::PC2Coin => (total_pc_without_take_pnl, total_coin_without_take_pnl),
+        };
+        let single_sided = single_sided_withdraw(
+            &amm,
+            withdraw.lp_amount,
+            reserve_in,
+            reserve_out,
+            amm.lp_amount,
+        )?;
+        // the withdraw part of the instruction, the converted side never leaves the pool
+        let (coin_amount, pc_amount) = match swap_direction {
+            SwapDirection::Coin2PC => (single_sided.withdraw_in, single_sided.withdraw_out),
+            SwapDirection::PC2Coin => (single_sided.withdraw_out, single_sided.withdraw_in),
+        };
+        encode_ray_log(WithdrawLog {
+            log_type: LogType::Withdraw.into_u8(),
+            withdraw_lp: withdraw.lp_amount,
+            user_lp: user_source_lp.amount,
             pool_coin: total_coin_without_take_pnl,
             pool_pc: total_pc_without_take_pnl,
-            out_amount: swap_amount_out,
+            pool_lp: amm.lp_amount,
+            calc_pnl_x: target_orders.calc_pnl_x,
+            calc_pnl_y: target_orders.calc_pnl_y,
+            out_coin: coin_amount,
+            out_pc: pc_amount,
         });
-        if swap_amount_out < swap.minimum_amount_out {
+        if single_sided.withdraw_in == 0 || single_sided.withdraw_out == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        if single_sided.amount_out < withdraw.min_out {
             return Err(AmmError::ExceededSlippage.into());
         }
-        if swap_amount_out == 0 || swap.amount_in == 0 {
-            return Err(AmmError::InvalidInput.into());
+        let dest_vault_amount = match swap_direction {
+            SwapDirection::Coin2PC => reserves.pc_vault,
+            SwapDirection::PC2Coin => reserves.coin_vault,
+        };
+        if single_sided.amount_out > dest_vault_amount {
+            msg!(
+                "withdraw_single_sided: vault holds {}, the rest is on the order book",
+                dest_vault_amount
+            );
+            return Err(AmmError::InsufficientFunds.into());
         }
 
+        Invokers::token_transfer_with_authority(
+            token_program_info.clone(),
+            amm_dest_vault_info.clone(),
+            user_dest_info.clone(),
+            amm_authority_info.clone(),
+            AUTHORITY_AMM,
+            amm.nonce as u8,
+            single_sided.amount_out,
+        )?;
+        Invokers::token_burn(
+            lp_token_program_info.clone(),
+            user_source_lp_info.clone(),
+            amm_lp_mint_info.clone(),
+            source_lp_owner_info.clone(),
+            withdraw.lp_amount,
+        )?;
+        amm.lp_amount = amm
+            .lp_amount
+            .checked_sub(withdraw.lp_amount)
+            .ok_or(AmmError::CalculationFailure)?;
         match swap_direction {
             SwapDirection::Coin2PC => {
-                if swap_amount_out >= total_pc_without_take_pnl {
-                    return Err(AmmError::InsufficientFunds.into());
-                }
-
-                if enable_orderbook {
-                    // coin -> pc, need cancel buy order
-                    if !bids.is_empty() {
-                        let mut amm_order_ids_vec = Vec::new();
-                        let mut order_ids = [0u64; 8];
-                        let mut count = 0;
-                        // fetch cancel order ids{
-                        for order in bids.into_iter() {
-                            order_ids[count] = order.client_order_id();
-                            count += 1;
-                            if count == 8 {
-                                amm_order_ids_vec.push(order_ids);
-                                order_ids = [0u64; 8];
-                                count = 0;
-                            }
-                        }
-                        if count != 0 {
-                            amm_order_ids_vec.push(order_ids);
-                        }
-                        for ids in amm_order_ids_vec.iter() {
-                            Invokers::invoke_dex_cancel_orders_by_client_order_ids(
-                                market_porgram_info.clone(),
-                                market_info.clone(),
-                                market_bids_info.clone(),
-                                market_asks_info.clone(),
-                                amm_open_orders_info.clone(),
-                                amm_authority_info.clone(),
-                                market_event_queue_info.clone(),
-                                AUTHORITY_AMM,
-                                amm.nonce as u8,
-                                *ids,
-                            )?;
-                        }
-                    }
-
-                    if swap_amount_out > amm_pc_vault.amount {
-                        // need settle funds
-                        Invokers::invoke_dex_settle_funds(
-                            market_porgram_info.clone(),
-                            market_info.clone(),
-                            amm_open_orders_info.clone(),
-                            amm_authority_info.clone(),
-                            market_coin_vault_info.clone(),
-                            market_pc_vault_info.clone(),
-                            amm_coin_vault_info.clone(),
-                            amm_pc_vault_info.clone(),
-                            market_vault_signer.clone(),
-                            token_program_info.clone(),
-                            Some(&amm_pc_vault_info.clone()),
-                            AUTHORITY_AMM,
-                            amm.nonce as u8,
-                        )?;
-                    }
-                }
-                // deposit source coin to amm_coin_vault
-                Invokers::token_transfer(
-                    token_program_info.clone(),
-                    user_source_info.clone(),
-                    amm_coin_vault_info.clone(),
-                    user_source_owner.clone(),
-                    swap.amount_in,
-                )?;
-                // withdraw amm_pc_vault to destination pc
-                Invokers::token_transfer_with_authority(
-                    token_program_info.clone(),
-                    amm_pc_vault_info.clone(),
-                    user_destination_info.clone(),
-                    amm_authority_info.clone(),
-                    AUTHORITY_AMM,
-                    amm.nonce as u8,
-                    swap_amount_out,
-                )?;
-                // update state_data data
-                amm.state_data.swap_coin_in_amount = amm
-                    .state_data
-                    .swap_coin_in_amount
-                    .checked_add(swap.amount_in.into())
-                    .unwrap();
-                amm.state_data.swap_pc_out_amount = amm
-                    .state_data
-                    .swap_pc_out_amount
-                    .checked_add(swap_amount_out.into())
-                    .unwrap();
-                // charge coin as swap fee
-                amm.state_data.swap_acc_coin_fee = amm
+                amm.state_data.record_swap_coin_in(
+                    single_sided.withdraw_in,
+                    single_sided.swap_out,
+                    single_sided.swap_fee,
+                );
+                amm.state_data.need_take_pnl_coin = amm
                     .state_data
-                    .swap_acc_coin_fee
-                    .checked_add(swap_fee.as_u64())
-                    .unwrap();
+                    .need_take_pnl_coin
+                    .checked_add(single_sided.protocol_fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
             SwapDirection::PC2Coin => {
-                if swap_amount_out >= total_coin_without_take_pnl {
-                    return Err(AmmError::InsufficientFunds.into());
-                }
-
-                if enable_orderbook {
-                    // pc -> coin, need cancel sell order
-                    if !asks.is_empty() {
-                        let mut amm_order_ids_vec = Vec::new();
-                        let mut order_ids = [0u64; 8];
-                        let mut count = 0;
-                        // fetch cancel order ids{
-                        for order in asks.into_iter() {
-                            order_ids[count] = order.client_order_id();
-                            count += 1;
-                            if count == 8 {
-                                amm_order_ids_vec.push(order_ids);
-                                order_ids = [0u64; 8];
-                                count = 0;
-                            }
-                        }
-                        if count != 0 {
-                            amm_order_ids_vec.push(order_ids);
-                        }
-                        for ids in amm_order_ids_vec.iter() {
-                            Invokers::invoke_dex_cancel_orders_by_client_order_ids(
-                                market_porgram_info.clone(),
-                                market_info.clone(),
-                                market_bids_info.clone(),
-                                market_asks_info.clone(),
-                                amm_open_orders_info.clone(),
-                                amm_authority_info.clone(),
-                                market_event_queue_info.clone(),
-                                AUTHORITY_AMM,
-                                amm.nonce as u8,
-                                *ids,
-                            )?;
-                        }
-                    }
-
-                    if swap_amount_out > amm_coin_vault.amount {
-                        Invokers::invoke_dex_settle_funds(
-                            market_porgram_info.clone(),
-                            market_info.clone(),
-                            amm_open_orders_info.clone(),
-                            amm_authority_info.clone(),
-                            market_coin_vault_info.clone(),
-                            market_pc_vault_info.clone(),
-                            amm_coin_vault_info.clone(),
-                            amm_pc_vault_info.clone(),
-                            market_vault_signer.clone(),
-                            token_program_info.clone(),
-                            Some(&amm_pc_vault_info.clone()),
-                            AUTHORITY_AMM,
-                            amm.nonce as u8,
-                        )?;
-                    }
-                }
-                // deposit source pc to amm_pc_vault
-                Invokers::token_transfer(
-                    token_program_info.clone(),
-                    user_source_info.clone(),
-                    amm_pc_vault_info.clone(),
-                    user_source_owner.clone(),
-                    swap.amount_in,
-                )?;
-                // withdraw amm_coin_vault to destination coin
-                Invokers::token_transfer_with_authority(
-                    token_program_info.clone(),
-                    amm_coin_vault_info.clone(),
-                    user_destination_info.clone(),
-                    amm_authority_info.clone(),
-                    AUTHORITY_AMM,
-                    amm.nonce as u8,
-                    swap_amount_out,
-                )?;
-                // update state_data data
-                amm.state_data.swap_pc_in_amount = amm
-                    .state_data
-                    .swap_pc_in_amount
-                    .checked_add(swap.amount_in.into())
-                    .unwrap();
-                amm.state_data.swap_coin_out_amount = amm
-                    .state_data
-                    .swap_coin_out_amount
-                    .checked_add(swap_amount_out.into())
-                    .unwrap();
-                // charge pc as swap fee
-                amm.state_data.swap_acc_pc_fee = amm
+                amm.state_data.record_swap_pc_in(
+                    single_sided.withdraw_in,
+                    single_sided.swap_out,
+                    single_sided.swap_fee,
+                );
+                amm.state_data.need_take_pnl_pc = amm
                     .state_data
-                    .swap_acc_pc_fee
-                    .checked_add(swap_fee.as_u64())
-                    .unwrap();
+                    .need_take_pnl_pc
+                    .checked_add(single_sided.protocol_fee)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
-        };
-
-        Ok(())
+        }
+        // the swap part may not shrink the pool the withdraw part left
+        let after_withdraw_in = u128::from(reserve_in - single_sided.withdraw_in);
+        let after_withdraw_out = u128::from(reserve_out - single_sided.withdraw_out);
+        Self::assert_invariant(
+            CurveCalculator::invariant(after_withdraw_in, after_withdraw_out),
+            CurveCalculator::invariant(
+                u128::from(reserve_in - single_sided.protocol_fee),
+                u128::from(reserve_out - single_sided.amount_out),
+            ),
+        )?;
+        encode_ray_log(SwapEvent {
+            log_type: LogType::SwapEvent.into_u8(),
+            pool: *amm_info.key,
+            direction: swap_direction as u64,
+            amount_in: single_sided.withdraw_in,
+            amount_out: single_sided.swap_out,
+            fee: single_sided.swap_fee,
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+        });
+
+        // like a withdraw followed by a swap, only the withdrawn amounts move the pnl baseline
+        target_orders.calc_pnl_x = x1
+            .checked_sub(Calculator::normalize_decimal_v2(
+                pc_amount,
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            ))
+            .unwrap()
+            .checked_sub(U128::from(delta_x))
+            .unwrap()
+            .as_u128();
+        target_orders.calc_pnl_y = y1
+            .checked_sub(Calculator::normalize_decimal_v2(
+                coin_amount,
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            ))
+            .unwrap()
+            .checked_sub(U128::from(delta_y))
+            .unwrap()
+            .as_u128();
+        Ok(())
     }
 
-    pub fn process_swap_base_out(
+    /// The protocol share of a swap fee, see `AmmInfo::owner_trading_fee`.
+    fn calc_protocol_fee(amm: &AmmInfo, swap_fee: u64) -> Result<u64, AmmError> {
+        Calculator::to_u64(
+            amm.owner_trading_fee(swap_fee.into())
+                .ok_or(AmmError::CalculationFailure)?,
+        )
+    }
+
+    pub fn process_swap_base_in(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        swap: SwapInstructionBaseOut,
+        swap: SwapInstructionBaseIn,
     ) -> ProgramResult {
-        const SWAP_ACCOUNT_NUM: usize = 17;
+        const ACCOUNT_LEN: usize = 17;
+        let (accounts, instructions_info) = Self::split_instructions_sysvar(accounts);
+        let (accounts, swap_whitelist_info) = Self::split_swap_whitelist(program_id, accounts)?;
+        let (accounts, swap_discount_infos) = Self::split_swap_discount(accounts, ACCOUNT_LEN);
         let input_account_len = accounts.len();
-        if input_account_len != SWAP_ACCOUNT_NUM && input_account_len != SWAP_ACCOUNT_NUM + 1 {
+        if input_account_len != ACCOUNT_LEN && input_account_len != ACCOUNT_LEN + 1 {
             return Err(AmmError::WrongAccountsNumber.into());
         }
+        // a zero input can never produce output, reject it before touching any account
+        if swap.amount_in == 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
-        if input_account_len == SWAP_ACCOUNT_NUM + 1 {
+        if input_account_len == ACCOUNT_LEN + 1 {
             let _amm_target_orders_info = next_account_info(account_info_iter)?;
         }
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
 
-        let market_program_info = next_account_info(account_info_iter)?;
+        let market_porgram_info = next_account_info(account_info_iter)?;
 
-        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        let (mut amm, mut extension) =
+            AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        if amm.is_disabled(PoolStatusBit::Swap) {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if amm.swap_guarded() {
+            Self::check_swap_guard(program_id, amm_info.key, instructions_info)?;
+        }
         let enable_orderbook;
         if AmmStatus::from_u64(amm.status).orderbook_permission() {
             enable_orderbook = true;
         } else {
             enable_orderbook = false;
         }
-
         let market_info = next_account_info(account_info_iter)?;
         let market_bids_info = next_account_info(account_info_iter)?;
         let market_asks_info = next_account_info(account_info_iter)?;
@@ -2869,7 +3906,11 @@ impl Processor {
         if !user_source_owner.is_signer {
             return Err(AmmError::InvalidSignAccount.into());
         }
-
+        if let Some(swap_whitelist_info) = swap_whitelist_info {
+            let whitelist =
+                SwapWhitelist::load_checked(swap_whitelist_info, program_id, amm_info.key)?;
+            Self::check_swap_whitelist(&whitelist, user_source_owner.key)?;
+        }
         check_assert_eq!(
             *token_program_info.key,
             spl_token::id(),
@@ -2877,13 +3918,11 @@ impl Processor {
             AmmError::InvalidSplTokenProgram
         );
         let spl_token_program_id = token_program_info.key;
-        let authority = Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?;
-        check_assert_eq!(
-            *amm_authority_info.key,
-            authority,
-            "authority",
-            AmmError::InvalidProgramAddress
-        );
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
         check_assert_eq!(
             *amm_coin_vault_info.key,
             amm.coin_vault,
@@ -2903,7 +3942,7 @@ impl Processor {
             AmmError::InvalidOpenOrders
         );
         check_assert_eq!(
-            *market_program_info.key,
+            *market_porgram_info.key,
             amm.market_program,
             "market_program",
             AmmError::InvalidMarketProgram
@@ -2927,13 +3966,15 @@ impl Processor {
             Self::unpack_token_account(&user_destination_info, spl_token_program_id)?;
 
         if !AmmStatus::from_u64(amm.status).swap_permission() {
-            msg!(&format!("swap_base_out: status {}", amm.status));
+            msg!(&format!("swap_base_in: status {}", amm.status));
             let clock = Clock::get()?;
             if amm.status == AmmStatus::OrderBookOnly.into_u64()
                 && (clock.unix_timestamp as u64) >= amm.state_data.orderbook_to_init_time
             {
                 amm.status = AmmStatus::Initialized.into_u64();
-                msg!("swap_base_out: OrderBook to Initialized");
+                msg!("swap_base_in: OrderBook to Initialized");
+            } else if amm.status == AmmStatus::Disabled.into_u64() {
+                return Err(AmmError::PoolPaused.into());
             } else {
                 return Err(AmmError::InvalidStatus.into());
             }
@@ -2943,12 +3984,11 @@ impl Processor {
                 return Err(AmmError::InvalidStatus.into());
             } else {
                 amm.status = AmmStatus::SwapOnly.into_u64();
-                msg!("swap_base_out: WaitingTrade to SwapOnly");
+                msg!("swap_base_in: WaitingTrade to SwapOnly");
             }
         }
 
-        let total_pc_without_take_pnl;
-        let total_coin_without_take_pnl;
+        let reserves;
         let mut bids: Vec<LeafNode> = Vec::new();
         let mut asks: Vec<LeafNode> = Vec::new();
         if enable_orderbook {
@@ -2968,85 +4008,116 @@ impl Processor {
             let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
             let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
             (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
-        } else {
+            reserves = PoolReserves::with_market(
+                amm_coin_vault.amount,
+                amm_pc_vault.amount,
+                &open_orders,
+                &market_state,
+                &market_event_queue_info,
+                &amm_open_orders_info,
+            )?;
+        } else if amm.uses_open_orders() {
             let open_orders = Self::load_orders(amm_open_orders_info)?;
-            (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl_no_orderbook(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                )?;
-        }
-
-        let swap_direction;
-        if user_source.mint == amm_coin_vault.mint && user_destination.mint == amm_pc_vault.mint {
-            swap_direction = SwapDirection::Coin2PC
-        } else if user_source.mint == amm_pc_vault.mint
-            && user_destination.mint == amm_coin_vault.mint
-        {
-            swap_direction = SwapDirection::PC2Coin
+            reserves = PoolReserves::new(amm_coin_vault.amount, amm_pc_vault.amount, &open_orders);
         } else {
-            return Err(AmmError::InvalidUserToken.into());
+            reserves = PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount);
+        }
+        let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+        let now = Clock::get()?.unix_timestamp as u64;
+        amm.update_price_cumulative(total_coin_without_take_pnl, total_pc_without_take_pnl, now);
+        // the curve is undefined once either side of the pool has been drained
+        if total_pc_without_take_pnl == 0 || total_coin_without_take_pnl == 0 {
+            return Err(AmmError::CheckedEmptyFunds.into());
         }
+        // the dynamic fee measures the reserves before the swap against their recent average
+        let pool_fees = match extension.as_deref_mut() {
+            Some(extension) => extension.swap_fees(
+                &amm,
+                total_coin_without_take_pnl,
+                total_pc_without_take_pnl,
+                now,
+            ),
+            None => amm.fees,
+        };
+        let fees = match swap_discount_infos {
+            Some(discount_infos) => Self::load_discount_fees(
+                program_id,
+                &pool_fees,
+                discount_infos,
+                user_source_owner.key,
+            )?,
+            None => pool_fees,
+        };
 
-        let swap_in_before_add_fee = Calculator::swap_token_amount_base_out(
-            swap.amount_out.into(),
-            total_pc_without_take_pnl.into(),
-            total_coin_without_take_pnl.into(),
-            swap_direction,
-        );
-        // swap_in_after_add_fee * (1 - 0.0025) = swap_in_before_add_fee
-        // swap_in_after_add_fee = swap_in_before_add_fee / (1 - 0.0025)
-        let swap_in_after_add_fee = swap_in_before_add_fee
-            .checked_mul(amm.fees.swap_fee_denominator.into())
-            .unwrap()
-            .checked_ceil_div(
-                (amm.fees
-                    .swap_fee_denominator
-                    .checked_sub(amm.fees.swap_fee_numerator)
-                    .unwrap())
-                .into(),
+        let swap_direction = Self::swap_direction(
+            &user_source.mint,
+            &user_destination.mint,
+            &amm_coin_vault.mint,
+            &amm_pc_vault.mint,
+            swap.direction,
+        )?;
+        if let Some(extension) = extension.as_deref() {
+            if swap.amount_in < extension.min_trade_amount(swap_direction) {
+                return Err(AmmError::TradeTooSmall.into());
+            }
+        }
+        if user_source.amount < swap.amount_in {
+            encode_ray_log(SwapBaseInLog {
+                log_type: LogType::SwapBaseIn.into_u8(),
+                amount_in: swap.amount_in,
+                minimum_out: swap.minimum_amount_out,
+                direction: swap_direction as u64,
+                user_source: user_source.amount,
+                pool_coin: total_coin_without_take_pnl,
+                pool_pc: total_pc_without_take_pnl,
+                out_amount: 0,
+            });
+            return Err(AmmError::InsufficientFunds.into());
+        }
+        let (reserve_in, reserve_out) = match swap_direction {
+            SwapDirection::Coin2PC => (total_coin_without_take_pnl, total_pc_without_take_pnl),
+            SwapDirection::PC2Coin => (total_pc_without_take_pnl, total_coin_without_take_pnl),
+        };
+        let swap_result = Self::curve_type(extension.as_deref())
+            .swap_base_in_out(
+                swap.amount_in.into(),
+                reserve_in.into(),
+                reserve_out.into(),
+                fees.swap_fee_numerator,
+                fees.swap_fee_denominator,
             )
-            .unwrap()
-            .0
-            .as_u64();
-        let swap_fee = swap_in_after_add_fee
-            .checked_sub(swap_in_before_add_fee.as_u64())
-            .unwrap();
-        encode_ray_log(SwapBaseOutLog {
-            log_type: LogType::SwapBaseOut.into_u8(),
-            max_in: swap.max_amount_in,
-            amount_out: swap.amount_out,
+            .ok_or(AmmError::CalculationFailure)?;
+        let swap_fee = U128::from(swap_result.fee);
+        let swap_amount_out = from_u128(swap_result.dest_amount)?;
+        encode_ray_log(SwapBaseInLog {
+            log_type: LogType::SwapBaseIn.into_u8(),
+            amount_in: swap.amount_in,
+            minimum_out: swap.minimum_amount_out,
             direction: swap_direction as u64,
             user_source: user_source.amount,
             pool_coin: total_coin_without_take_pnl,
             pool_pc: total_pc_without_take_pnl,
-            deduct_in: swap_in_after_add_fee,
+            out_amount: swap_amount_out,
         });
-        if user_source.amount < swap_in_after_add_fee {
-            return Err(AmmError::InsufficientFunds.into());
-        }
-        if swap.max_amount_in < swap_in_after_add_fee {
+        if swap_amount_out < swap.minimum_amount_out {
             return Err(AmmError::ExceededSlippage.into());
         }
-        if swap_in_after_add_fee == 0 || swap.amount_out == 0 {
-            return Err(AmmError::InvalidInput.into());
+        if swap.max_impact_bps != u64::MAX {
+            let impact_bps =
+                price_impact_bps(swap.amount_in - swap_fee.as_u64(), reserve_in, reserve_out);
+            if impact_bps > swap.max_impact_bps {
+                msg!("swap_base_in: price impact {} bps", impact_bps);
+                return Err(AmmError::ExceededSlippage.into());
+            }
+        }
+        if swap_amount_out == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
         }
 
         match swap_direction {
             SwapDirection::Coin2PC => {
-                if swap.amount_out >= total_pc_without_take_pnl {
+                if swap_amount_out >= total_pc_without_take_pnl {
                     return Err(AmmError::InsufficientFunds.into());
                 }
 
@@ -3056,7 +4127,7 @@ impl Processor {
                         let mut amm_order_ids_vec = Vec::new();
                         let mut order_ids = [0u64; 8];
                         let mut count = 0;
-                        // fetch cancel order ids
+                        // fetch cancel order ids{
                         for order in bids.into_iter() {
                             order_ids[count] = order.client_order_id();
                             count += 1;
@@ -3071,7 +4142,7 @@ impl Processor {
                         }
                         for ids in amm_order_ids_vec.iter() {
                             Invokers::invoke_dex_cancel_orders_by_client_order_ids(
-                                market_program_info.clone(),
+                                market_porgram_info.clone(),
                                 market_info.clone(),
                                 market_bids_info.clone(),
                                 market_asks_info.clone(),
@@ -3084,10 +4155,11 @@ impl Processor {
                             )?;
                         }
                     }
-                    if swap.amount_out > amm_pc_vault.amount {
+
+                    if swap_amount_out > reserves.pc_vault {
                         // need settle funds
                         Invokers::invoke_dex_settle_funds(
-                            market_program_info.clone(),
+                            market_porgram_info.clone(),
                             market_info.clone(),
                             amm_open_orders_info.clone(),
                             amm_authority_info.clone(),
@@ -3109,7 +4181,7 @@ impl Processor {
                     user_source_info.clone(),
                     amm_coin_vault_info.clone(),
                     user_source_owner.clone(),
-                    swap_in_after_add_fee,
+                    swap.amount_in,
                 )?;
                 // withdraw amm_pc_vault to destination pc
                 Invokers::token_transfer_with_authority(
@@ -3119,28 +4191,23 @@ impl Processor {
                     amm_authority_info.clone(),
                     AUTHORITY_AMM,
                     amm.nonce as u8,
-                    swap.amount_out,
+                    swap_amount_out,
                 )?;
                 // update state_data data
-                amm.state_data.swap_coin_in_amount = amm
-                    .state_data
-                    .swap_coin_in_amount
-                    .checked_add(swap_in_after_add_fee.into())
-                    .unwrap();
-                amm.state_data.swap_pc_out_amount = amm
-                    .state_data
-                    .swap_pc_out_amount
-                    .checked_add(Calculator::to_u128(swap.amount_out)?)
-                    .unwrap();
-                // charge coin as swap fee
-                amm.state_data.swap_acc_coin_fee = amm
+                amm.state_data.record_swap_coin_in(
+                    swap.amount_in,
+                    swap_amount_out,
+                    swap_fee.as_u64(),
+                );
+                // the protocol share of the fee is owed to the pnl owner
+                amm.state_data.need_take_pnl_coin = amm
                     .state_data
-                    .swap_acc_coin_fee
-                    .checked_add(swap_fee)
-                    .unwrap();
+                    .need_take_pnl_coin
+                    .checked_add(Self::calc_protocol_fee(&amm, swap_fee.as_u64())?)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
             SwapDirection::PC2Coin => {
-                if swap.amount_out >= total_coin_without_take_pnl {
+                if swap_amount_out >= total_coin_without_take_pnl {
                     return Err(AmmError::InsufficientFunds.into());
                 }
 
@@ -3150,7 +4217,7 @@ impl Processor {
                         let mut amm_order_ids_vec = Vec::new();
                         let mut order_ids = [0u64; 8];
                         let mut count = 0;
-                        // fetch cancel order ids
+                        // fetch cancel order ids{
                         for order in asks.into_iter() {
                             order_ids[count] = order.client_order_id();
                             count += 1;
@@ -3165,7 +4232,7 @@ impl Processor {
                         }
                         for ids in amm_order_ids_vec.iter() {
                             Invokers::invoke_dex_cancel_orders_by_client_order_ids(
-                                market_program_info.clone(),
+                                market_porgram_info.clone(),
                                 market_info.clone(),
                                 market_bids_info.clone(),
                                 market_asks_info.clone(),
@@ -3178,13 +4245,14 @@ impl Processor {
                             )?;
                         }
                     }
-                    if swap.amount_out > amm_coin_vault.amount {
+
+                    if swap_amount_out > reserves.coin_vault {
                         Invokers::invoke_dex_settle_funds(
-                            market_program_info.clone(),
+                            market_porgram_info.clone(),
                             market_info.clone(),
                             amm_open_orders_info.clone(),
                             amm_authority_info.clone(),
-                            market_asks_info.clone(),
+                            market_coin_vault_info.clone(),
                             market_pc_vault_info.clone(),
                             amm_coin_vault_info.clone(),
                             amm_pc_vault_info.clone(),
@@ -3196,14 +4264,13 @@ impl Processor {
                         )?;
                     }
                 }
-
                 // deposit source pc to amm_pc_vault
                 Invokers::token_transfer(
                     token_program_info.clone(),
                     user_source_info.clone(),
                     amm_pc_vault_info.clone(),
                     user_source_owner.clone(),
-                    swap_in_after_add_fee,
+                    swap.amount_in,
                 )?;
                 // withdraw amm_coin_vault to destination coin
                 Invokers::token_transfer_with_authority(
@@ -3213,48 +4280,89 @@ impl Processor {
                     amm_authority_info.clone(),
                     AUTHORITY_AMM,
                     amm.nonce as u8,
-                    swap.amount_out,
+                    swap_amount_out,
                 )?;
                 // update state_data data
-                amm.state_data.swap_pc_in_amount = amm
-                    .state_data
-                    .swap_pc_in_amount
-                    .checked_add(swap_in_after_add_fee.into())
-                    .unwrap();
-                amm.state_data.swap_coin_out_amount = amm
-                    .state_data
-                    .swap_coin_out_amount
-                    .checked_add(swap.amount_out.into())
-                    .unwrap();
-                // charge pc as swap fee
-                amm.state_data.swap_acc_pc_fee = amm
+                amm.state_data.record_swap_pc_in(
+                    swap.amount_in,
+                    swap_amount_out,
+                    swap_fee.as_u64(),
+                );
+                // the protocol share of the fee is owed to the pnl owner
+                amm.state_data.need_take_pnl_pc = amm
                     .state_data
-                    .swap_acc_pc_fee
-                    .checked_add(swap_fee)
-                    .unwrap();
+                    .need_take_pnl_pc
+                    .checked_add(Self::calc_protocol_fee(&amm, swap_fee.as_u64())?)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
             }
         };
 
+        Self::check_swap_invariant(
+            Self::curve_type(extension.as_deref()),
+            swap_direction,
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            swap.amount_in,
+            swap_amount_out,
+            Self::calc_protocol_fee(&amm, swap_fee.as_u64())?,
+        )?;
+
+        encode_ray_log(SwapEvent {
+            log_type: LogType::SwapEvent.into_u8(),
+            pool: *amm_info.key,
+            direction: swap_direction as u64,
+            amount_in: swap.amount_in,
+            amount_out: swap_amount_out,
+            fee: swap_fee.as_u64(),
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+        });
+
         Ok(())
     }
 
-    pub fn process_migrate_to_openbook(
+    pub fn process_swap_base_out(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        swap: SwapInstructionBaseOut,
     ) -> ProgramResult {
+        const SWAP_ACCOUNT_NUM: usize = 17;
+        let (accounts, instructions_info) = Self::split_instructions_sysvar(accounts);
+        let (accounts, swap_whitelist_info) = Self::split_swap_whitelist(program_id, accounts)?;
+        let (accounts, swap_discount_infos) = Self::split_swap_discount(accounts, SWAP_ACCOUNT_NUM);
+        let input_account_len = accounts.len();
+        if input_account_len != SWAP_ACCOUNT_NUM && input_account_len != SWAP_ACCOUNT_NUM + 1 {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
-        let system_program_info = next_account_info(account_info_iter)?;
-        let rent_sysvar_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
         let amm_open_orders_info = next_account_info(account_info_iter)?;
+        if input_account_len == SWAP_ACCOUNT_NUM + 1 {
+            let _amm_target_orders_info = next_account_info(account_info_iter)?;
+        }
         let amm_coin_vault_info = next_account_info(account_info_iter)?;
         let amm_pc_vault_info = next_account_info(account_info_iter)?;
-        let amm_target_orders_info = next_account_info(account_info_iter)?;
 
         let market_program_info = next_account_info(account_info_iter)?;
+
+        let (mut amm, mut extension) =
+            AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        if amm.is_disabled(PoolStatusBit::Swap) {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if amm.swap_guarded() {
+            Self::check_swap_guard(program_id, amm_info.key, instructions_info)?;
+        }
+        let enable_orderbook;
+        if AmmStatus::from_u64(amm.status).orderbook_permission() {
+            enable_orderbook = true;
+        } else {
+            enable_orderbook = false;
+        }
+
         let market_info = next_account_info(account_info_iter)?;
         let market_bids_info = next_account_info(account_info_iter)?;
         let market_asks_info = next_account_info(account_info_iter)?;
@@ -3263,38 +4371,32 @@ impl Processor {
         let market_pc_vault_info = next_account_info(account_info_iter)?;
         let market_vault_signer = next_account_info(account_info_iter)?;
 
-        let new_amm_open_orders_info = next_account_info(account_info_iter)?;
-        let new_market_program_info = next_account_info(account_info_iter)?;
-        let new_market_info = next_account_info(account_info_iter)?;
-        let admin_info = next_account_info(account_info_iter)?;
-        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
-        if !admin_info.is_signer
-            || (*admin_info.key != config_feature::amm_owner::ID
-                && *admin_info.key != config_feature::amm_subscriber::ID)
-        {
+        let user_source_info = next_account_info(account_info_iter)?;
+        let user_destination_info = next_account_info(account_info_iter)?;
+        let user_source_owner = next_account_info(account_info_iter)?;
+        if !user_source_owner.is_signer {
             return Err(AmmError::InvalidSignAccount.into());
         }
-        let authority = Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?;
+        if let Some(swap_whitelist_info) = swap_whitelist_info {
+            let whitelist =
+                SwapWhitelist::load_checked(swap_whitelist_info, program_id, amm_info.key)?;
+            Self::check_swap_whitelist(&whitelist, user_source_owner.key)?;
+        }
+
         check_assert_eq!(
-            *amm_authority_info.key,
-            authority,
-            "authority",
-            AmmError::InvalidProgramAddress
-        );
-        let spl_token_program_id = token_program_info.key;
-        check_assert_eq!(
-            *spl_token_program_id,
+            *token_program_info.key,
             spl_token::id(),
             "spl_token_program",
             AmmError::InvalidSplTokenProgram
         );
+        let spl_token_program_id = token_program_info.key;
+        let authority = Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?;
         check_assert_eq!(
-            *system_program_info.key,
-            solana_program::system_program::id(),
-            "sys_program",
-            AmmError::InvalidSysProgramAddress
+            *amm_authority_info.key,
+            authority,
+            "authority",
+            AmmError::InvalidProgramAddress
         );
-        // old account check
         check_assert_eq!(
             *amm_coin_vault_info.key,
             amm.coin_vault,
@@ -3308,16 +4410,10 @@ impl Processor {
             AmmError::InvalidPCVault
         );
         check_assert_eq!(
-            *amm_target_orders_info.key,
-            amm.target_orders,
-            "target_orders",
-            AmmError::InvalidPCVault
-        );
-        check_assert_eq!(
-            *market_info.key,
-            amm.market,
-            "market",
-            AmmError::InvalidMarket
+            *amm_open_orders_info.key,
+            amm.open_orders,
+            "open_orders",
+            AmmError::InvalidOpenOrders
         );
         check_assert_eq!(
             *market_program_info.key,
@@ -3325,212 +4421,678 @@ impl Processor {
             "market_program",
             AmmError::InvalidMarketProgram
         );
-        check_assert_eq!(
-            *amm_open_orders_info.key,
-            amm.open_orders,
-            "open_orders",
-            AmmError::InvalidOpenOrders
-        );
-        let mut target =
-            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-        let pnl_pc_amount = Calculator::restore_decimal(
-            target.calc_pnl_x.into(),
-            amm.pc_decimals,
-            amm.sys_decimal_value,
-        )
-        .as_u64();
-        let pnl_coin_amount = Calculator::restore_decimal(
-            target.calc_pnl_y.into(),
-            amm.coin_decimals,
-            amm.sys_decimal_value,
-        )
-        .as_u64();
-        // cancel amm orders in old market
-        Self::do_cancel_amm_orders(
-            &amm,
-            amm_authority_info,
-            amm_open_orders_info,
-            market_program_info,
-            market_info,
-            market_bids_info,
-            market_asks_info,
-            market_event_queue_info,
-            AUTHORITY_AMM,
-        )?;
-        // settle assets
-        Invokers::invoke_dex_settle_funds(
-            market_program_info.clone(),
-            market_info.clone(),
-            amm_open_orders_info.clone(),
-            amm_authority_info.clone(),
-            market_coin_vault_info.clone(),
-            market_pc_vault_info.clone(),
-            amm_coin_vault_info.clone(),
-            amm_pc_vault_info.clone(),
-            market_vault_signer.clone(),
-            token_program_info.clone(),
-            None,
-            AUTHORITY_AMM,
-            amm.nonce as u8,
-        )?;
-        let (market_state, open_orders) = Self::load_serum_market_order(
-            market_info,
-            amm_open_orders_info,
-            amm_authority_info,
-            &amm,
-            false,
-        )?;
-        if identity(market_state.coin_mint) != amm.coin_vault_mint.to_aligned_bytes() {
-            return Err(AmmError::InvalidCoinMint.into());
+
+        if *user_source_info.key == amm.pc_vault || *user_source_info.key == amm.coin_vault {
+            return Err(AmmError::InvalidUserToken.into());
         }
-        if identity(market_state.pc_mint) != amm.pc_vault_mint.to_aligned_bytes() {
-            return Err(AmmError::InvalidPCMint.into());
+        if *user_destination_info.key == amm.pc_vault
+            || *user_destination_info.key == amm.coin_vault
+        {
+            return Err(AmmError::InvalidUserToken.into());
         }
-        // Partial filled without consumed assets exclude native total in open_orders will not be settled back to amm.
-        // It is necessary to check that all assets have been indeed settled back to amm
-        let (pc_total_in_serum, coin_total_in_serum) = Calculator::calc_exact_vault_in_serum(
-            &open_orders,
-            &market_state,
-            market_event_queue_info,
-            amm_open_orders_info,
-        )?;
-        if pc_total_in_serum != 0 || coin_total_in_serum != 0 {
-            // Invokers::invoke_dex_close_open_orders(
-            //     serum_dex_info.clone(),
-            //     amm_open_orders_info.clone(),
-            //     authority_info.clone(),
-            //     admin_info.clone(),
-            //     market_info.clone(),
-            //     AUTHORITY_AMM,
-            //     amm.nonce as u8,
-            // )?;
-            msg!(
-                "{}, {}, {}, {}, {:?}",
-                pc_total_in_serum,
-                coin_total_in_serum,
-                identity(open_orders.native_pc_total),
-                identity(open_orders.native_coin_total),
-                identity(open_orders.free_slot_bits)
-            );
-            return Err(AmmError::UnknownAmmError.into());
+
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+
+        let user_source = Self::unpack_token_account(&user_source_info, spl_token_program_id)?;
+        let user_destination =
+            Self::unpack_token_account(&user_destination_info, spl_token_program_id)?;
+
+        if !AmmStatus::from_u64(amm.status).swap_permission() {
+            msg!(&format!("swap_base_out: status {}", amm.status));
+            let clock = Clock::get()?;
+            if amm.status == AmmStatus::OrderBookOnly.into_u64()
+                && (clock.unix_timestamp as u64) >= amm.state_data.orderbook_to_init_time
+            {
+                amm.status = AmmStatus::Initialized.into_u64();
+                msg!("swap_base_out: OrderBook to Initialized");
+            } else if amm.status == AmmStatus::Disabled.into_u64() {
+                return Err(AmmError::PoolPaused.into());
+            } else {
+                return Err(AmmError::InvalidStatus.into());
+            }
+        } else if amm.status == AmmStatus::WaitingTrade.into_u64() {
+            let clock = Clock::get()?;
+            if (clock.unix_timestamp as u64) < amm.state_data.pool_open_time {
+                return Err(AmmError::InvalidStatus.into());
+            } else {
+                amm.status = AmmStatus::SwapOnly.into_u64();
+                msg!("swap_base_out: WaitingTrade to SwapOnly");
+            }
         }
-        // check new dex
-        check_assert_eq!(
-            *new_market_program_info.key,
-            config_feature::openbook_program::id(),
-            "new_market_program",
-            AmmError::InvalidMarketProgram
-        );
-        let new_market_pc_lot_size;
-        let new_market_coin_lot_size;
-        {
-            let new_market_state = Market::load(
-                new_market_info,
-                &config_feature::openbook_program::id(),
+
+        let reserves;
+        let mut bids: Vec<LeafNode> = Vec::new();
+        let mut asks: Vec<LeafNode> = Vec::new();
+        if enable_orderbook {
+            check_assert_eq!(
+                *market_info.key,
+                amm.market,
+                "market",
+                AmmError::InvalidMarket
+            );
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
                 false,
             )?;
-            new_market_pc_lot_size = new_market_state.pc_lot_size;
-            new_market_coin_lot_size = new_market_state.coin_lot_size;
-            if identity(new_market_state.coin_mint) != amm.coin_vault_mint.to_aligned_bytes() {
-                return Err(AmmError::InvalidCoinMint.into());
-            }
-            if identity(new_market_state.pc_mint) != amm.pc_vault_mint.to_aligned_bytes() {
-                return Err(AmmError::InvalidPCMint.into());
-            }
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            (bids, asks) = Self::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+            reserves = PoolReserves::with_market(
+                amm_coin_vault.amount,
+                amm_pc_vault.amount,
+                &open_orders,
+                &market_state,
+                &market_event_queue_info,
+                &amm_open_orders_info,
+            )?;
+        } else if amm.uses_open_orders() {
+            let open_orders = Self::load_orders(amm_open_orders_info)?;
+            reserves = PoolReserves::new(amm_coin_vault.amount, amm_pc_vault.amount, &open_orders);
+        } else {
+            reserves = PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount);
         }
-        // create amm open order account
-        Self::generate_amm_associated_account(
-            program_id,
-            new_market_program_info.key,
-            new_market_info,
-            new_amm_open_orders_info,
-            admin_info,
-            system_program_info,
-            rent_sysvar_info,
-            OPEN_ORDER_ASSOCIATED_SEED,
-            size_of::<serum_dex::state::OpenOrders>() + 12,
-        )?;
-        // init open orders account
-        Invokers::invoke_dex_init_open_orders(
-            new_market_program_info.clone(),
-            new_amm_open_orders_info.clone(),
-            amm_authority_info.clone(),
-            new_market_info.clone(),
-            rent_sysvar_info.clone(),
-            AUTHORITY_AMM,
-            amm.nonce as u8,
-        )?;
-        // update pool
-        amm.market_program = *new_market_program_info.key;
-        amm.market = *new_market_info.key;
-        amm.open_orders = *new_amm_open_orders_info.key;
-        let nonce = amm.nonce as u8;
-        let pool_open_time = amm.state_data.pool_open_time;
-        let coin_decimals = amm.coin_decimals as u8;
-        let pc_decimals = amm.pc_decimals as u8;
-        amm.initialize(
-            nonce,
-            pool_open_time,
-            coin_decimals,
-            pc_decimals,
-            new_market_coin_lot_size,
-            new_market_pc_lot_size,
+        let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+        let now = Clock::get()?.unix_timestamp as u64;
+        amm.update_price_cumulative(total_coin_without_take_pnl, total_pc_without_take_pnl, now);
+        // the dynamic fee measures the reserves before the swap against their recent average
+        let pool_fees = match extension.as_deref_mut() {
+            Some(extension) => extension.swap_fees(
+                &amm,
+                total_coin_without_take_pnl,
+                total_pc_without_take_pnl,
+                now,
+            ),
+            None => amm.fees,
+        };
+        let fees = match swap_discount_infos {
+            Some(discount_infos) => Self::load_discount_fees(
+                program_id,
+                &pool_fees,
+                discount_infos,
+                user_source_owner.key,
+            )?,
+            None => pool_fees,
+        };
+
+        let swap_direction = Self::swap_direction(
+            &user_source.mint,
+            &user_destination.mint,
+            &amm_coin_vault.mint,
+            &amm_pc_vault.mint,
+            swap.direction,
         )?;
-        amm.status = AmmStatus::WaitingTrade.into_u64();
-        amm.reset_flag = AmmResetFlag::ResetYes.into_u64();
+        // the curve can never pay out the whole reserve, check before solving for the input
+        let reserve_out = match swap_direction {
+            SwapDirection::Coin2PC => total_pc_without_take_pnl,
+            SwapDirection::PC2Coin => total_coin_without_take_pnl,
+        };
+        if swap.amount_out >= reserve_out {
+            return Err(AmmError::InsufficientLiquidity.into());
+        }
 
-        if new_market_coin_lot_size != market_state.coin_lot_size
-            || new_market_pc_lot_size != market_state.pc_lot_size
-        {
-            // market lot size is different, calc_pnl_x & calc_pnl_x must update
-            target.calc_pnl_x = Calculator::normalize_decimal_v2(
-                pnl_pc_amount,
-                amm.pc_decimals,
-                amm.sys_decimal_value,
+        let swap_in_before_add_fee = Self::curve_type(extension.as_deref())
+            .swap_base_out_in(
+                swap.amount_out,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                swap_direction,
             )
-            .as_u128();
-            target.calc_pnl_y = Calculator::normalize_decimal_v2(
-                pnl_coin_amount,
-                amm.coin_decimals,
-                amm.sys_decimal_value,
-            )
-            .as_u128();
+            .ok_or(AmmError::CalculationFailure)?;
+        // swap_in_after_add_fee * (1 - 0.0025) = swap_in_before_add_fee
+        // swap_in_after_add_fee = swap_in_before_add_fee / (1 - 0.0025)
+        let swap_in_after_add_fee = from_u128(
+            swap_in_before_add_fee
+                .checked_mul(fees.swap_fee_denominator.into())
+                .ok_or(AmmError::CalculationFailure)?
+                .checked_ceil_div(
+                    (fees
+                        .swap_fee_denominator
+                        .checked_sub(fees.swap_fee_numerator)
+                        .ok_or(AmmError::CalculationFailure)?)
+                    .into(),
+                )
+                .ok_or(AmmError::CalculationFailure)?
+                .0
+                .as_u128(),
+        )?;
+        let swap_fee = swap_in_after_add_fee
+            .checked_sub(from_u128(swap_in_before_add_fee.as_u128())?)
+            .ok_or(AmmError::CalculationFailure)?;
+        encode_ray_log(SwapBaseOutLog {
+            log_type: LogType::SwapBaseOut.into_u8(),
+            max_in: swap.max_amount_in,
+            amount_out: swap.amount_out,
+            direction: swap_direction as u64,
+            user_source: user_source.amount,
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+            deduct_in: swap_in_after_add_fee,
+        });
+        if user_source.amount < swap_in_after_add_fee {
+            return Err(AmmError::InsufficientFunds.into());
+        }
+        if swap.max_amount_in < swap_in_after_add_fee {
+            return Err(AmmError::ExceededSlippage.into());
         }
+        if swap_in_after_add_fee == 0 || swap.amount_out == 0 {
+            return Err(AmmError::ZeroTradingTokens.into());
+        }
+        if let Some(extension) = extension.as_deref() {
+            if swap_in_after_add_fee < extension.min_trade_amount(swap_direction) {
+                return Err(AmmError::TradeTooSmall.into());
+            }
+        }
+
+        match swap_direction {
+            SwapDirection::Coin2PC => {
+                if swap.amount_out >= total_pc_without_take_pnl {
+                    return Err(AmmError::InsufficientFunds.into());
+                }
+
+                if enable_orderbook {
+                    // coin -> pc, need cancel buy order
+                    if !bids.is_empty() {
+                        let mut amm_order_ids_vec = Vec::new();
+                        let mut order_ids = [0u64; 8];
+                        let mut count = 0;
+                        // fetch cancel order ids
+                        for order in bids.into_iter() {
+                            order_ids[count] = order.client_order_id();
+                            count += 1;
+                            if count == 8 {
+                                amm_order_ids_vec.push(order_ids);
+                                order_ids = [0u64; 8];
+                                count = 0;
+                            }
+                        }
+                        if count != 0 {
+                            amm_order_ids_vec.push(order_ids);
+                        }
+                        for ids in amm_order_ids_vec.iter() {
+                            Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                                market_program_info.clone(),
+                                market_info.clone(),
+                                market_bids_info.clone(),
+                                market_asks_info.clone(),
+                                amm_open_orders_info.clone(),
+                                amm_authority_info.clone(),
+                                market_event_queue_info.clone(),
+                                AUTHORITY_AMM,
+                                amm.nonce as u8,
+                                *ids,
+                            )?;
+                        }
+                    }
+                    if swap.amount_out > reserves.pc_vault {
+                        // need settle funds
+                        Invokers::invoke_dex_settle_funds(
+                            market_program_info.clone(),
+                            market_info.clone(),
+                            amm_open_orders_info.clone(),
+                            amm_authority_info.clone(),
+                            market_coin_vault_info.clone(),
+                            market_pc_vault_info.clone(),
+                            amm_coin_vault_info.clone(),
+                            amm_pc_vault_info.clone(),
+                            market_vault_signer.clone(),
+                            token_program_info.clone(),
+                            Some(&amm_pc_vault_info.clone()),
+                            AUTHORITY_AMM,
+                            amm.nonce as u8,
+                        )?;
+                    }
+                }
+                // deposit source coin to amm_coin_vault
+                Invokers::token_transfer(
+                    token_program_info.clone(),
+                    user_source_info.clone(),
+                    amm_coin_vault_info.clone(),
+                    user_source_owner.clone(),
+                    swap_in_after_add_fee,
+                )?;
+                // withdraw amm_pc_vault to destination pc
+                Invokers::token_transfer_with_authority(
+                    token_program_info.clone(),
+                    amm_pc_vault_info.clone(),
+                    user_destination_info.clone(),
+                    amm_authority_info.clone(),
+                    AUTHORITY_AMM,
+                    amm.nonce as u8,
+                    swap.amount_out,
+                )?;
+                // update state_data data
+                amm.state_data.record_swap_coin_in(
+                    swap_in_after_add_fee,
+                    swap.amount_out,
+                    swap_fee,
+                );
+                // the protocol share of the fee is owed to the pnl owner
+                amm.state_data.need_take_pnl_coin = amm
+                    .state_data
+                    .need_take_pnl_coin
+                    .checked_add(Self::calc_protocol_fee(&amm, swap_fee)?)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+            SwapDirection::PC2Coin => {
+                if swap.amount_out >= total_coin_without_take_pnl {
+                    return Err(AmmError::InsufficientFunds.into());
+                }
+
+                if enable_orderbook {
+                    // pc -> coin, need cancel sell order
+                    if !asks.is_empty() {
+                        let mut amm_order_ids_vec = Vec::new();
+                        let mut order_ids = [0u64; 8];
+                        let mut count = 0;
+                        // fetch cancel order ids
+                        for order in asks.into_iter() {
+                            order_ids[count] = order.client_order_id();
+                            count += 1;
+                            if count == 8 {
+                                amm_order_ids_vec.push(order_ids);
+                                order_ids = [0u64; 8];
+                                count = 0;
+                            }
+                        }
+                        if count != 0 {
+                            amm_order_ids_vec.push(order_ids);
+                        }
+                        for ids in amm_order_ids_vec.iter() {
+                            Invokers::invoke_dex_cancel_orders_by_client_order_ids(
+                                market_program_info.clone(),
+                                market_info.clone(),
+                                market_bids_info.clone(),
+                                market_asks_info.clone(),
+                                amm_open_orders_info.clone(),
+                                amm_authority_info.clone(),
+                                market_event_queue_info.clone(),
+                                AUTHORITY_AMM,
+                                amm.nonce as u8,
+                                *ids,
+                            )?;
+                        }
+                    }
+                    if swap.amount_out > reserves.coin_vault {
+                        Invokers::invoke_dex_settle_funds(
+                            market_program_info.clone(),
+                            market_info.clone(),
+                            amm_open_orders_info.clone(),
+                            amm_authority_info.clone(),
+                            market_asks_info.clone(),
+                            market_pc_vault_info.clone(),
+                            amm_coin_vault_info.clone(),
+                            amm_pc_vault_info.clone(),
+                            market_vault_signer.clone(),
+                            token_program_info.clone(),
+                            Some(&amm_pc_vault_info.clone()),
+                            AUTHORITY_AMM,
+                            amm.nonce as u8,
+                        )?;
+                    }
+                }
+
+                // deposit source pc to amm_pc_vault
+                Invokers::token_transfer(
+                    token_program_info.clone(),
+                    user_source_info.clone(),
+                    amm_pc_vault_info.clone(),
+                    user_source_owner.clone(),
+                    swap_in_after_add_fee,
+                )?;
+                // withdraw amm_coin_vault to destination coin
+                Invokers::token_transfer_with_authority(
+                    token_program_info.clone(),
+                    amm_coin_vault_info.clone(),
+                    user_destination_info.clone(),
+                    amm_authority_info.clone(),
+                    AUTHORITY_AMM,
+                    amm.nonce as u8,
+                    swap.amount_out,
+                )?;
+                // update state_data data
+                amm.state_data
+                    .record_swap_pc_in(swap_in_after_add_fee, swap.amount_out, swap_fee);
+                // the protocol share of the fee is owed to the pnl owner
+                amm.state_data.need_take_pnl_pc = amm
+                    .state_data
+                    .need_take_pnl_pc
+                    .checked_add(Self::calc_protocol_fee(&amm, swap_fee)?)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
+            }
+        };
+
+        Self::check_swap_invariant(
+            Self::curve_type(extension.as_deref()),
+            swap_direction,
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            swap_in_after_add_fee,
+            swap.amount_out,
+            Self::calc_protocol_fee(&amm, swap_fee)?,
+        )?;
+
+        encode_ray_log(SwapEvent {
+            log_type: LogType::SwapEvent.into_u8(),
+            pool: *amm_info.key,
+            direction: swap_direction as u64,
+            amount_in: swap_in_after_add_fee,
+            amount_out: swap.amount_out,
+            fee: swap_fee,
+            pool_coin: total_coin_without_take_pnl,
+            pool_pc: total_pc_without_take_pnl,
+        });
 
         Ok(())
     }
 
-    /// withdraw_srm
-    pub fn process_withdraw_srm(
+    pub fn process_migrate_to_openbook(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
-        withdrawsrm: WithdrawSrmInstruction,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let token_program_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_sysvar_info = next_account_info(account_info_iter)?;
 
         let amm_info = next_account_info(account_info_iter)?;
-        let amm_owner_info = next_account_info(account_info_iter)?;
         let amm_authority_info = next_account_info(account_info_iter)?;
-        let srm_token_info = next_account_info(account_info_iter)?;
-        let dest_srm_token_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
 
-        msg!("withdraw_srm: {}", withdrawsrm.amount);
-        let amm = AmmInfo::load_checked(&amm_info, program_id)?;
-        if amm.status == AmmStatus::Uninitialized.into_u64() {
-            msg!(&format!("withdraw_srm: status {}", amm.status));
-            return Err(AmmError::InvalidStatus.into());
-        }
-        if !amm_owner_info.is_signer
-            || (*amm_owner_info.key != config_feature::amm_owner::ID
-                && *amm_owner_info.key != config_feature::amm_subscriber::ID)
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_bids_info = next_account_info(account_info_iter)?;
+        let market_asks_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+        let market_coin_vault_info = next_account_info(account_info_iter)?;
+        let market_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_vault_signer = next_account_info(account_info_iter)?;
+
+        let new_amm_open_orders_info = next_account_info(account_info_iter)?;
+        let new_market_program_info = next_account_info(account_info_iter)?;
+        let new_market_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        Self::check_uses_open_orders(&amm)?;
+        if !admin_info.is_signer
+            || (*admin_info.key != config_feature::amm_owner::ID
+                && *admin_info.key != config_feature::amm_subscriber::ID)
         {
             return Err(AmmError::InvalidSignAccount.into());
         }
-        // check_assert_eq!(
-        //     *amm_owner_info.key,
-        //     amm.amm_owner,
-        //     "amm_owner",
+        let authority = Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?;
+        check_assert_eq!(
+            *amm_authority_info.key,
+            authority,
+            "authority",
+            AmmError::InvalidProgramAddress
+        );
+        let spl_token_program_id = token_program_info.key;
+        check_assert_eq!(
+            *spl_token_program_id,
+            spl_token::id(),
+            "spl_token_program",
+            AmmError::InvalidSplTokenProgram
+        );
+        check_assert_eq!(
+            *system_program_info.key,
+            solana_program::system_program::id(),
+            "sys_program",
+            AmmError::InvalidSysProgramAddress
+        );
+        // old account check
+        check_assert_eq!(
+            *amm_coin_vault_info.key,
+            amm.coin_vault,
+            "coin_vault",
+            AmmError::InvalidCoinVault
+        );
+        check_assert_eq!(
+            *amm_pc_vault_info.key,
+            amm.pc_vault,
+            "pc_vault",
+            AmmError::InvalidPCVault
+        );
+        check_assert_eq!(
+            *amm_target_orders_info.key,
+            amm.target_orders,
+            "target_orders",
+            AmmError::InvalidPCVault
+        );
+        check_assert_eq!(
+            *market_info.key,
+            amm.market,
+            "market",
+            AmmError::InvalidMarket
+        );
+        check_assert_eq!(
+            *market_program_info.key,
+            amm.market_program,
+            "market_program",
+            AmmError::InvalidMarketProgram
+        );
+        check_assert_eq!(
+            *amm_open_orders_info.key,
+            amm.open_orders,
+            "open_orders",
+            AmmError::InvalidOpenOrders
+        );
+        let mut target =
+            TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+        let pnl_pc_amount = from_u128(
+            Calculator::restore_decimal(
+                target.calc_pnl_x.into(),
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            )
+            .as_u128(),
+        )?;
+        let pnl_coin_amount = from_u128(
+            Calculator::restore_decimal(
+                target.calc_pnl_y.into(),
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            )
+            .as_u128(),
+        )?;
+        // cancel amm orders in old market
+        Self::do_cancel_amm_orders(
+            &amm,
+            amm_authority_info,
+            amm_open_orders_info,
+            market_program_info,
+            market_info,
+            market_bids_info,
+            market_asks_info,
+            market_event_queue_info,
+            AUTHORITY_AMM,
+        )?;
+        // settle assets
+        Invokers::invoke_dex_settle_funds(
+            market_program_info.clone(),
+            market_info.clone(),
+            amm_open_orders_info.clone(),
+            amm_authority_info.clone(),
+            market_coin_vault_info.clone(),
+            market_pc_vault_info.clone(),
+            amm_coin_vault_info.clone(),
+            amm_pc_vault_info.clone(),
+            market_vault_signer.clone(),
+            token_program_info.clone(),
+            None,
+            AUTHORITY_AMM,
+            amm.nonce as u8,
+        )?;
+        let (market_state, open_orders) = Self::load_serum_market_order(
+            market_info,
+            amm_open_orders_info,
+            amm_authority_info,
+            &amm,
+            false,
+        )?;
+        if identity(market_state.coin_mint) != amm.coin_vault_mint.to_aligned_bytes() {
+            return Err(AmmError::InvalidCoinMint.into());
+        }
+        if identity(market_state.pc_mint) != amm.pc_vault_mint.to_aligned_bytes() {
+            return Err(AmmError::InvalidPCMint.into());
+        }
+        // Partial filled without consumed assets exclude native total in open_orders will not be settled back to amm.
+        // It is necessary to check that all assets have been indeed settled back to amm
+        let (pc_total_in_serum, coin_total_in_serum) = Calculator::calc_exact_vault_in_serum(
+            &open_orders,
+            &market_state,
+            market_event_queue_info,
+            amm_open_orders_info,
+        )?;
+        if pc_total_in_serum != 0 || coin_total_in_serum != 0 {
+            // Invokers::invoke_dex_close_open_orders(
+            //     serum_dex_info.clone(),
+            //     amm_open_orders_info.clone(),
+            //     authority_info.clone(),
+            //     admin_info.clone(),
+            //     market_info.clone(),
+            //     AUTHORITY_AMM,
+            //     amm.nonce as u8,
+            // )?;
+            msg!(
+                "{}, {}, {}, {}, {:?}",
+                pc_total_in_serum,
+                coin_total_in_serum,
+                identity(open_orders.native_pc_total),
+                identity(open_orders.native_coin_total),
+                identity(open_orders.free_slot_bits)
+            );
+            return Err(AmmError::UnknownAmmError.into());
+        }
+        // check new dex
+        check_assert_eq!(
+            *new_market_program_info.key,
+            config_feature::openbook_program::id(),
+            "new_market_program",
+            AmmError::InvalidMarketProgram
+        );
+        let new_market_pc_lot_size;
+        let new_market_coin_lot_size;
+        {
+            let new_market_state = Market::load(
+                new_market_info,
+                &config_feature::openbook_program::id(),
+                false,
+            )?;
+            new_market_pc_lot_size = new_market_state.pc_lot_size;
+            new_market_coin_lot_size = new_market_state.coin_lot_size;
+            if identity(new_market_state.coin_mint) != amm.coin_vault_mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(new_market_state.pc_mint) != amm.pc_vault_mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidPCMint.into());
+            }
+        }
+        // create amm open order account
+        Self::generate_amm_associated_account(
+            program_id,
+            new_market_program_info.key,
+            new_market_info,
+            new_amm_open_orders_info,
+            admin_info,
+            system_program_info,
+            rent_sysvar_info,
+            OPEN_ORDER_ASSOCIATED_SEED,
+            size_of::<serum_dex::state::OpenOrders>() + 12,
+        )?;
+        // init open orders account
+        Invokers::invoke_dex_init_open_orders(
+            new_market_program_info.clone(),
+            new_amm_open_orders_info.clone(),
+            amm_authority_info.clone(),
+            new_market_info.clone(),
+            rent_sysvar_info.clone(),
+            AUTHORITY_AMM,
+            amm.nonce as u8,
+        )?;
+        // update pool
+        amm.market_program = *new_market_program_info.key;
+        amm.market = *new_market_info.key;
+        amm.open_orders = *new_amm_open_orders_info.key;
+        let nonce = amm.nonce as u8;
+        let pool_open_time = amm.state_data.pool_open_time;
+        let coin_decimals = amm.coin_decimals as u8;
+        let pc_decimals = amm.pc_decimals as u8;
+        // the account keeps its size, and so the layout version it was migrated to
+        let version = amm.version;
+        amm.initialize(
+            nonce,
+            pool_open_time,
+            coin_decimals,
+            pc_decimals,
+            new_market_coin_lot_size,
+            new_market_pc_lot_size,
+        )?;
+        amm.version = version;
+        amm.status = AmmStatus::WaitingTrade.into_u64();
+        amm.reset_flag = AmmResetFlag::ResetYes.into_u64();
+
+        if new_market_coin_lot_size != market_state.coin_lot_size
+            || new_market_pc_lot_size != market_state.pc_lot_size
+        {
+            // market lot size is different, calc_pnl_x & calc_pnl_x must update
+            target.calc_pnl_x = Calculator::normalize_decimal_v2(
+                pnl_pc_amount,
+                amm.pc_decimals,
+                amm.sys_decimal_value,
+            )
+            .as_u128();
+            target.calc_pnl_y = Calculator::normalize_decimal_v2(
+                pnl_coin_amount,
+                amm.coin_decimals,
+                amm.sys_decimal_value,
+            )
+            .as_u128();
+        }
+
+        Ok(())
+    }
+
+    /// withdraw_srm
+    pub fn process_withdraw_srm(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        withdrawsrm: WithdrawSrmInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let srm_token_info = next_account_info(account_info_iter)?;
+        let dest_srm_token_info = next_account_info(account_info_iter)?;
+
+        msg!("withdraw_srm: {}", withdrawsrm.amount);
+        let amm = AmmInfo::load_checked(&amm_info, program_id)?;
+        Self::check_uses_open_orders(&amm)?;
+        if amm.status == AmmStatus::Uninitialized.into_u64() {
+            msg!(&format!("withdraw_srm: status {}", amm.status));
+            return Err(AmmError::InvalidStatus.into());
+        }
+        if !amm_owner_info.is_signer
+            || (*amm_owner_info.key != config_feature::amm_owner::ID
+                && *amm_owner_info.key != config_feature::amm_subscriber::ID)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        // check_assert_eq!(
+        //     *amm_owner_info.key,
+        //     amm.amm_owner,
+        //     "amm_owner",
         //     AmmError::InvalidOwner
         // );
         check_assert_eq!(
@@ -3654,18 +5216,22 @@ impl Processor {
                 AmmError::InvalidTargetOrders
             );
             let target = TargetOrders::load_checked(&target_orders_info, program_id, amm_info.key)?;
-            pnl_pc_amount = Calculator::restore_decimal(
-                target.calc_pnl_x.into(),
-                amm.pc_decimals,
-                amm.sys_decimal_value,
-            )
-            .as_u64();
-            pnl_coin_amount = Calculator::restore_decimal(
-                target.calc_pnl_y.into(),
-                amm.coin_decimals,
-                amm.sys_decimal_value,
-            )
-            .as_u64();
+            pnl_pc_amount = from_u128(
+                Calculator::restore_decimal(
+                    target.calc_pnl_x.into(),
+                    amm.pc_decimals,
+                    amm.sys_decimal_value,
+                )
+                .as_u128(),
+            )?;
+            pnl_coin_amount = from_u128(
+                Calculator::restore_decimal(
+                    target.calc_pnl_y.into(),
+                    amm.coin_decimals,
+                    amm.sys_decimal_value,
+                )
+                .as_u128(),
+            )?;
         } else {
             pnl_pc_amount = 0;
             pnl_coin_amount = 0;
@@ -3673,21 +5239,24 @@ impl Processor {
 
         let amm_coin_vault = Self::unpack_token_account(&amm_coin_vault_info, &spl_token::id())?;
         let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, &spl_token::id())?;
-        let lp_mint = Self::unpack_mint(&amm_lp_mint_info, &spl_token::id())?;
-        let (market_state, open_orders) = Self::load_serum_market_order(
-            market_info,
-            amm_open_orders_info,
-            amm_authority_info,
-            &amm,
-            false,
+        let lp_mint = Self::unpack_mint(
+            &amm_lp_mint_info,
+            Self::token_program_id(&amm_lp_mint_info)?,
         )?;
-        if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes() {
-            return Err(AmmError::InvalidCoinMint.into());
-        }
-        if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes() {
-            return Err(AmmError::InvalidPCMint.into());
-        }
-        let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+        let (total_pc_without_take_pnl, total_coin_without_take_pnl) = if amm.uses_open_orders() {
+            let (market_state, open_orders) = Self::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?;
+            if identity(market_state.coin_mint) != amm_coin_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidCoinMint.into());
+            }
+            if identity(market_state.pc_mint) != amm_pc_vault.mint.to_aligned_bytes() {
+                return Err(AmmError::InvalidPCMint.into());
+            }
             Calculator::calc_total_without_take_pnl(
                 amm_pc_vault.amount,
                 amm_coin_vault.amount,
@@ -3696,7 +5265,11 @@ impl Processor {
                 &market_state,
                 &market_event_queue_info,
                 &amm_open_orders_info,
-            )?;
+            )?
+        } else {
+            PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount)
+                .total_without_take_pnl(&amm)?
+        };
         let pool_info_data = GetPoolData {
             status: amm.status,
             coin_decimals: amm.coin_decimals,
@@ -3713,6 +5286,232 @@ impl Processor {
         return Ok(pool_info_data);
     }
 
+    /// A copy of `amm` with the fees a swap against the pool totals would pay now, see
+    /// `AmmInfoExtension::swap_fees`, and the curve of the pool. The price references of the
+    /// pool are left as they are.
+    fn quote_state(
+        amm: &AmmInfo,
+        amm_info: &AccountInfo,
+        total_pc_without_take_pnl: u64,
+        total_coin_without_take_pnl: u64,
+    ) -> Result<(AmmInfo, CurveType), ProgramError> {
+        let mut quoted = *amm;
+        let now = Clock::get()?.unix_timestamp as u64;
+        quoted.update_price_cumulative(total_coin_without_take_pnl, total_pc_without_take_pnl, now);
+        let mut extension = AmmInfoExtension::from_account_data(&amm_info.data.borrow())?;
+        quoted.fees = extension.swap_fees(
+            &quoted,
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+            now,
+        );
+        Ok((quoted, extension.curve_type()))
+    }
+
+    /// Quote a swap base in against the pool totals without moving any tokens, returns the
+    /// amount out and the price impact in millionths of the price before the swap.
+    pub fn get_swap_quote(
+        amm: &AmmInfo,
+        curve_type: CurveType,
+        total_pc_without_take_pnl: u64,
+        total_coin_without_take_pnl: u64,
+        amount_in: u64,
+        swap_direction: SwapDirection,
+    ) -> Result<(u64, u64), AmmError> {
+        if total_pc_without_take_pnl == 0 || total_coin_without_take_pnl == 0 {
+            return Err(AmmError::CheckedEmptyFunds);
+        }
+        let (reserve_in, reserve_out) = match swap_direction {
+            SwapDirection::Coin2PC => (total_coin_without_take_pnl, total_pc_without_take_pnl),
+            SwapDirection::PC2Coin => (total_pc_without_take_pnl, total_coin_without_take_pnl),
+        };
+        let swap_amount_out = from_u128(
+            curve_type
+                .swap_base_in_out(
+                    amount_in.into(),
+                    reserve_in.into(),
+                    reserve_out.into(),
+                    amm.fees.swap_fee_numerator,
+                    amm.fees.swap_fee_denominator,
+                )
+                .ok_or(AmmError::CalculationFailure)?
+                .dest_amount,
+        )?;
+
+        // price = pc / coin, compare the prices cross multiplied to keep the precision
+        let (pc_after_swap, coin_after_swap) = match swap_direction {
+            SwapDirection::Coin2PC => (
+                total_pc_without_take_pnl
+                    .checked_sub(swap_amount_out)
+                    .ok_or(AmmError::CheckedSubOverflow)?,
+                total_coin_without_take_pnl
+                    .checked_add(amount_in)
+                    .ok_or(AmmError::CheckedAddOverflow)?,
+            ),
+            SwapDirection::PC2Coin => (
+                total_pc_without_take_pnl
+                    .checked_add(amount_in)
+                    .ok_or(AmmError::CheckedAddOverflow)?,
+                total_coin_without_take_pnl
+                    .checked_sub(swap_amount_out)
+                    .ok_or(AmmError::CheckedSubOverflow)?,
+            ),
+        };
+        let price_before = U256::from(total_pc_without_take_pnl) * U256::from(coin_after_swap);
+        let price_after = U256::from(pc_after_swap) * U256::from(total_coin_without_take_pnl);
+        let price_delta = if price_before > price_after {
+            price_before - price_after
+        } else {
+            price_after - price_before
+        };
+        // a large trade against a thin side can move the price by more than u64::MAX millionths
+        let price_impact = from_u256(price_delta * U256::from(1000000u64) / price_before)?;
+        Ok((swap_amount_out, price_impact))
+    }
+
+    /// Amount out of a swap base in of each of `amounts_in` against the same pool totals,
+    /// see `get_swap_quote`.
+    fn batch_swap_quote(
+        amm: &AmmInfo,
+        curve_type: CurveType,
+        total_pc_without_take_pnl: u64,
+        total_coin_without_take_pnl: u64,
+        amounts_in: &[u64],
+        swap_direction: SwapDirection,
+    ) -> Result<Vec<u64>, AmmError> {
+        amounts_in
+            .iter()
+            .map(|amount_in| {
+                Self::get_swap_quote(
+                    amm,
+                    curve_type,
+                    total_pc_without_take_pnl,
+                    total_coin_without_take_pnl,
+                    *amount_in,
+                    swap_direction,
+                )
+                .map(|(amount_out, _price_impact)| amount_out)
+            })
+            .collect()
+    }
+
+    /// Processes `process_batch_quote` instruction.
+    pub fn process_batch_quote(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        batch: BatchQuoteInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let market_event_queue_info = next_account_info(account_info_iter)?;
+
+        let amm = AmmInfo::load_checked(&amm_info, program_id)?;
+        if amm.is_disabled(PoolStatusBit::Swap) {
+            return Err(AmmError::PoolPaused.into());
+        }
+        if !AmmStatus::from_u64(amm.status).swap_permission() {
+            msg!("batch_quote: status {}", amm.status);
+            return Err(AmmError::InvalidStatus.into());
+        }
+        check_assert_eq!(
+            *amm_authority_info.key,
+            Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?,
+            "authority",
+            AmmError::InvalidProgramAddress
+        );
+        check_assert_eq!(
+            *amm_open_orders_info.key,
+            amm.open_orders,
+            "open_orders",
+            AmmError::InvalidOpenOrders
+        );
+        check_assert_eq!(
+            *market_program_info.key,
+            amm.market_program,
+            "market_program",
+            AmmError::InvalidMarketProgram
+        );
+        check_assert_eq!(
+            *amm_coin_vault_info.key,
+            amm.coin_vault,
+            "coin_vault",
+            AmmError::InvalidCoinVault
+        );
+        check_assert_eq!(
+            *amm_pc_vault_info.key,
+            amm.pc_vault,
+            "pc_vault",
+            AmmError::InvalidPCVault
+        );
+        let amm_coin_vault = Self::unpack_token_account(&amm_coin_vault_info, &spl_token::id())?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, &spl_token::id())?;
+
+        // the same reserves a swap would trade against
+        let reserves;
+        if AmmStatus::from_u64(amm.status).orderbook_permission() {
+            check_assert_eq!(
+                *market_info.key,
+                amm.market,
+                "market",
+                AmmError::InvalidMarket
+            );
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?;
+            reserves = PoolReserves::with_market(
+                amm_coin_vault.amount,
+                amm_pc_vault.amount,
+                &open_orders,
+                &market_state,
+                &market_event_queue_info,
+                &amm_open_orders_info,
+            )?;
+        } else if amm.uses_open_orders() {
+            let open_orders = Self::load_orders(amm_open_orders_info)?;
+            reserves = PoolReserves::new(amm_coin_vault.amount, amm_pc_vault.amount, &open_orders);
+        } else {
+            reserves = PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount);
+        }
+        let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+            reserves.total_without_take_pnl(&amm)?;
+
+        let swap_direction = match batch.direction {
+            TradeDirection::CoinToPc => SwapDirection::Coin2PC,
+            TradeDirection::PcToCoin => SwapDirection::PC2Coin,
+        };
+        let (amm, curve_type) = Self::quote_state(
+            &amm,
+            amm_info,
+            total_pc_without_take_pnl,
+            total_coin_without_take_pnl,
+        )?;
+        let amounts_out = Self::batch_swap_quote(
+            &amm,
+            curve_type,
+            total_pc_without_take_pnl,
+            total_coin_without_take_pnl,
+            &batch.amounts_in,
+            swap_direction,
+        )?;
+        let data: Vec<u8> = amounts_out
+            .iter()
+            .flat_map(|amount_out| amount_out.to_le_bytes())
+            .collect();
+        set_return_data(&data);
+
+        Ok(())
+    }
+
     fn simulate_swap_base_in(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
@@ -3820,18 +5619,25 @@ impl Processor {
                 return Err(AmmError::InvalidInput.into());
             }
 
-            let (market_state, open_orders) = Self::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                false,
-            )?;
+            let market_order = if amm.uses_open_orders() {
+                Some(Self::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?)
+            } else {
+                None
+            };
 
             let amm_coin_vault =
                 Self::unpack_token_account(&amm_coin_vault_info, &spl_token::id())?;
             let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, &spl_token::id())?;
-            let lp_mint = Self::unpack_mint(&amm_lp_mint_info, &spl_token::id())?;
+            let lp_mint = Self::unpack_mint(
+                &amm_lp_mint_info,
+                Self::token_program_id(&amm_lp_mint_info)?,
+            )?;
 
             let user_source = Self::unpack_token_account(&user_source_info, &spl_token::id())?;
             let user_destination =
@@ -3851,15 +5657,19 @@ impl Processor {
                 return Err(AmmError::InvalidInput.into());
             }
             let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
+                match market_order.as_ref() {
+                    Some((market_state, open_orders)) => Calculator::calc_total_without_take_pnl(
+                        amm_pc_vault.amount,
+                        amm_coin_vault.amount,
+                        open_orders,
+                        &amm,
+                        market_state,
+                        &market_event_queue_info,
+                        &amm_open_orders_info,
+                    )?,
+                    None => PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount)
+                        .total_without_take_pnl(&amm)?,
+                };
             swap_base_in.pool_data.status = amm.status;
             swap_base_in.pool_data.coin_decimals = amm.coin_decimals;
             swap_base_in.pool_data.pc_decimals = amm.pc_decimals;
@@ -3869,69 +5679,23 @@ impl Processor {
             swap_base_in.pool_data.pool_pc_amount = total_pc_without_take_pnl;
             swap_base_in.pool_data.pool_coin_amount = total_coin_without_take_pnl;
             swap_base_in.pool_data.amm_id = amm_info.key.to_string();
+            let (amm, curve_type) = Self::quote_state(
+                &amm,
+                amm_info,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+            )?;
 
-            let swap_fee = U128::from(swap.amount_in)
-                .checked_mul(amm.fees.swap_fee_numerator.into())
-                .unwrap()
-                .checked_ceil_div(amm.fees.swap_fee_denominator.into())
-                .unwrap()
-                .0;
-            let swap_in_after_deduct_fee =
-                U128::from(swap.amount_in).checked_sub(swap_fee).unwrap();
-            let swap_amount_out = Calculator::swap_token_amount_base_in(
-                swap_in_after_deduct_fee,
-                total_pc_without_take_pnl.into(),
-                total_coin_without_take_pnl.into(),
+            let (swap_amount_out, price_impact) = Self::get_swap_quote(
+                &amm,
+                curve_type,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                swap.amount_in,
                 swap_direction,
-            )
-            .as_u64();
+            )?;
             swap_base_in.minimum_amount_out = swap_amount_out;
-            match swap_direction {
-                SwapDirection::Coin2PC => {
-                    // coin -> pc, need cancel buy order
-                    let token_pc_after_swap = total_pc_without_take_pnl
-                        .checked_sub(swap_amount_out)
-                        .unwrap();
-                    let token_coin_after_swap = total_coin_without_take_pnl
-                        .checked_add(swap.amount_in)
-                        .unwrap();
-
-                    let swap_price_before = total_pc_without_take_pnl
-                        .checked_div(total_coin_without_take_pnl)
-                        .unwrap();
-                    let swap_price_after = token_pc_after_swap
-                        .checked_div(token_coin_after_swap)
-                        .unwrap();
-                    swap_base_in.price_impact =
-                        (swap_price_before.checked_sub(swap_price_after).unwrap())
-                            .checked_mul(1000000)
-                            .unwrap()
-                            .checked_div(swap_price_before)
-                            .unwrap();
-                }
-                SwapDirection::PC2Coin => {
-                    // pc -> coin, need cancel sell order
-                    let token_pc_after_swap = total_pc_without_take_pnl
-                        .checked_add(swap.amount_in)
-                        .unwrap();
-                    let token_coin_after_swap = total_coin_without_take_pnl
-                        .checked_sub(swap_amount_out)
-                        .unwrap();
-
-                    let swap_price_before = total_pc_without_take_pnl
-                        .checked_div(total_coin_without_take_pnl)
-                        .unwrap();
-                    let swap_price_after = token_pc_after_swap
-                        .checked_div(token_coin_after_swap)
-                        .unwrap();
-                    swap_base_in.price_impact =
-                        (swap_price_after.checked_sub(swap_price_before).unwrap())
-                            .checked_mul(1000000)
-                            .unwrap()
-                            .checked_div(swap_price_before)
-                            .unwrap();
-                }
-            }
+            swap_base_in.price_impact = price_impact;
         }
         return Ok(swap_base_in);
     }
@@ -4042,18 +5806,25 @@ impl Processor {
                 return Err(AmmError::InvalidInput.into());
             }
 
-            let (market_state, open_orders) = Self::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                false,
-            )?;
+            let market_order = if amm.uses_open_orders() {
+                Some(Self::load_serum_market_order(
+                    market_info,
+                    amm_open_orders_info,
+                    amm_authority_info,
+                    &amm,
+                    false,
+                )?)
+            } else {
+                None
+            };
 
             let amm_coin_vault =
                 Self::unpack_token_account(&amm_coin_vault_info, &spl_token::id())?;
             let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, &spl_token::id())?;
-            let lp_mint = Self::unpack_mint(&amm_lp_mint_info, &spl_token::id())?;
+            let lp_mint = Self::unpack_mint(
+                &amm_lp_mint_info,
+                Self::token_program_id(&amm_lp_mint_info)?,
+            )?;
 
             let user_swap_source = Self::unpack_token_account(&user_source_info, &spl_token::id())?;
             let user_swap_destination =
@@ -4074,15 +5845,19 @@ impl Processor {
                 return Err(AmmError::InvalidInput.into());
             }
             let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
+                match market_order.as_ref() {
+                    Some((market_state, open_orders)) => Calculator::calc_total_without_take_pnl(
+                        amm_pc_vault.amount,
+                        amm_coin_vault.amount,
+                        open_orders,
+                        &amm,
+                        market_state,
+                        &market_event_queue_info,
+                        &amm_open_orders_info,
+                    )?,
+                    None => PoolReserves::from_vaults(amm_coin_vault.amount, amm_pc_vault.amount)
+                        .total_without_take_pnl(&amm)?,
+                };
             swap_base_out.pool_data.status = amm.status;
             swap_base_out.pool_data.coin_decimals = amm.coin_decimals;
             swap_base_out.pool_data.pc_decimals = amm.pc_decimals;
@@ -4092,29 +5867,39 @@ impl Processor {
             swap_base_out.pool_data.pool_pc_amount = total_pc_without_take_pnl;
             swap_base_out.pool_data.pool_coin_amount = total_coin_without_take_pnl;
             swap_base_out.pool_data.amm_id = amm_info.key.to_string();
+            let (amm, curve_type) = Self::quote_state(
+                &amm,
+                amm_info,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+            )?;
 
-            let swap_in_before_add_fee = Calculator::swap_token_amount_base_out(
-                swap.amount_out.into(),
-                total_pc_without_take_pnl.into(),
-                total_coin_without_take_pnl.into(),
-                swap_direction,
-            );
+            let swap_in_before_add_fee = curve_type
+                .swap_base_out_in(
+                    swap.amount_out,
+                    total_pc_without_take_pnl,
+                    total_coin_without_take_pnl,
+                    swap_direction,
+                )
+                .ok_or(AmmError::CalculationFailure)?;
 
             // swap_in_after_add_fee * (1 - 0.0025) = swap_in_before_add_fee
             // swap_in_after_add_fee = swap_in_before_add_fee / (1 - 0.0025)
-            let swap_in_after_add_fee = swap_in_before_add_fee
-                .checked_mul(amm.fees.swap_fee_denominator.into())
-                .unwrap()
-                .checked_ceil_div(
-                    (amm.fees
-                        .swap_fee_denominator
-                        .checked_sub(amm.fees.swap_fee_numerator)
-                        .unwrap())
-                    .into(),
-                )
-                .unwrap()
-                .0
-                .as_u64();
+            let swap_in_after_add_fee = from_u128(
+                swap_in_before_add_fee
+                    .checked_mul(amm.fees.swap_fee_denominator.into())
+                    .unwrap()
+                    .checked_ceil_div(
+                        (amm.fees
+                            .swap_fee_denominator
+                            .checked_sub(amm.fees.swap_fee_numerator)
+                            .unwrap())
+                        .into(),
+                    )
+                    .unwrap()
+                    .0
+                    .as_u128(),
+            )?;
             swap_base_out.max_amount_in = swap_in_after_add_fee;
 
             match swap_direction {
@@ -4199,6 +5984,7 @@ impl Processor {
         Self::check_account_readonly(market_event_queue_info)?;
 
         let amm = AmmInfo::load_checked(amm_info, program_id)?;
+        Self::check_uses_open_orders(&amm)?;
         Self::check_accounts(
             program_id,
             &amm,
@@ -4636,28 +6422,32 @@ impl Processor {
                 amm.state = AmmState::IdleState.into_u64();
             } else {
                 //let max_bid: u64 = cur_price * (amm.sys_decimal_value - (amm.min_separate + amm.fee)) / amm.sys_decimal_value;
-                let max_bid: u64 = U128::from(cur_price)
-                    .checked_mul(
-                        (amm.fees.trade_fee_denominator
-                            - (amm.fees.min_separate_numerator + amm.fees.trade_fee_numerator))
-                            .into(),
-                    )
-                    .unwrap()
-                    .checked_div(amm.fees.trade_fee_denominator.into())
-                    .unwrap()
-                    .as_u64();
+                let max_bid: u64 = from_u128(
+                    U128::from(cur_price)
+                        .checked_mul(
+                            (amm.fees.trade_fee_denominator
+                                - (amm.fees.min_separate_numerator + amm.fees.trade_fee_numerator))
+                                .into(),
+                        )
+                        .unwrap()
+                        .checked_div(amm.fees.trade_fee_denominator.into())
+                        .unwrap()
+                        .as_u128(),
+                )?;
                 //let min_ask: u64 = cur_price * (amm.sys_decimal_value + amm.min_separate + amm.fee) / amm.sys_decimal_value;
-                let min_ask: u64 = U128::from(cur_price)
-                    .checked_mul(
-                        (amm.fees.trade_fee_denominator
-                            + (amm.fees.min_separate_numerator + amm.fees.trade_fee_numerator))
-                            .into(),
-                    )
-                    .unwrap()
-                    .checked_ceil_div(amm.fees.trade_fee_denominator.into())
-                    .unwrap()
-                    .0
-                    .as_u64();
+                let min_ask: u64 = from_u128(
+                    U128::from(cur_price)
+                        .checked_mul(
+                            (amm.fees.trade_fee_denominator
+                                + (amm.fees.min_separate_numerator + amm.fees.trade_fee_numerator))
+                                .into(),
+                        )
+                        .unwrap()
+                        .checked_ceil_div(amm.fees.trade_fee_denominator.into())
+                        .unwrap()
+                        .0
+                        .as_u128(),
+                )?;
 
                 //let grid: u64 = cur_price * (amm.depth as u64) / (100) / (amm.order_num as u64); // percent: e.g., 5*10**6 is 5%
                 let mut grid: u64 = cur_price
@@ -5381,7 +7171,10 @@ impl Processor {
         if amm_info.owner != program_id {
             return Err(AmmError::InvalidOwner.into());
         }
-        if !amm_owner_info.is_signer
+        // the pool admin may tweak the fees, every other param stays with the amm owners
+        if setparams.param as u64 == AmmParams::Fees.into_u64() {
+            Self::check_pool_admin(&amm, amm_owner_info)?;
+        } else if !amm_owner_info.is_signer
             || (*amm_owner_info.key != config_feature::amm_owner::ID
                 && *amm_owner_info.key != config_feature::amm_subscriber::ID)
         {
@@ -5405,39 +7198,41 @@ impl Processor {
         if amm.target_orders != *amm_target_orders_info.key {
             return Err(AmmError::InvalidTargetOrders.into());
         }
-        // cancel amm orders in openbook
-        Self::do_cancel_amm_orders(
-            &amm,
-            amm_authority_info,
-            amm_open_orders_info,
-            market_program_info,
-            market_info,
-            market_bids_info,
-            market_asks_info,
-            market_event_q_info,
-            AUTHORITY_AMM,
-        )?;
-        Invokers::invoke_dex_settle_funds(
-            market_program_info.clone(),
-            market_info.clone(),
-            amm_open_orders_info.clone(),
-            amm_authority_info.clone(),
-            market_coin_vault_info.clone(),
-            market_pc_vault_info.clone(),
-            amm_coin_vault_info.clone(),
-            amm_pc_vault_info.clone(),
-            market_vault_signer.clone(),
-            token_program_info.clone(),
-            None,
-            AUTHORITY_AMM,
-            amm.nonce as u8,
-        )?;
+        if amm.uses_open_orders() {
+            // cancel amm orders in openbook
+            Self::do_cancel_amm_orders(
+                &amm,
+                amm_authority_info,
+                amm_open_orders_info,
+                market_program_info,
+                market_info,
+                market_bids_info,
+                market_asks_info,
+                market_event_q_info,
+                AUTHORITY_AMM,
+            )?;
+            Invokers::invoke_dex_settle_funds(
+                market_program_info.clone(),
+                market_info.clone(),
+                amm_open_orders_info.clone(),
+                amm_authority_info.clone(),
+                market_coin_vault_info.clone(),
+                market_pc_vault_info.clone(),
+                amm_coin_vault_info.clone(),
+                amm_pc_vault_info.clone(),
+                market_vault_signer.clone(),
+                token_program_info.clone(),
+                None,
+                AUTHORITY_AMM,
+                amm.nonce as u8,
+            )?;
+        }
 
         let param = setparams.param;
         let mut set_valid = false;
         match AmmParams::from_u64(param as u64) {
             AmmParams::Status => {
-                {
+                if amm.uses_open_orders() {
                     let (market_state, open_orders) = Processor::load_serum_market_order(
                         market_info,
                         amm_open_orders_info,
@@ -5462,6 +7257,10 @@ impl Processor {
                     None => return Err(AmmError::InvalidInput.into()),
                 };
                 if AmmStatus::valid_status(value) {
+                    if !amm.uses_open_orders() && AmmStatus::from_u64(value).orderbook_permission()
+                    {
+                        return Err(AmmError::NoOpenOrders.into());
+                    }
                     amm.status = value as u64;
                     set_valid = true;
                 }
@@ -5559,7 +7358,9 @@ impl Processor {
                     Some(a) => a,
                     None => return Err(AmmError::InvalidInput.into()),
                 };
-                amm.amm_owner = new_pubkey;
+                // older layouts ignore pool_admin
+                amm.migrate(false)?;
+                amm.pool_admin = new_pubkey;
                 set_valid = true;
             }
             AmmParams::SetOpenTime => {
@@ -5603,6 +7404,7 @@ impl Processor {
                 set_valid = true;
             }
             AmmParams::UpdateOpenOrder => {
+                Self::check_uses_open_orders(&amm)?;
                 let new_open_orders_info = next_account_info(account_info_iter)?;
                 amm.open_orders = *new_open_orders_info.key;
                 let (_market_state, _open_orders) = Processor::load_serum_market_order(
@@ -5657,6 +7459,7 @@ impl Processor {
         }
 
         let mut amm = AmmInfo::load_mut_checked(amm_info, program_id)?;
+        Self::check_uses_open_orders(&amm)?;
         Self::check_accounts(
             program_id,
             &amm,
@@ -6013,6 +7816,7 @@ impl Processor {
             return Err(AmmError::InvalidSplTokenProgram.into());
         }
         let amm = AmmInfo::load_checked(&amm_info, program_id)?;
+        Self::check_uses_open_orders(&amm)?;
         if *amm_authority_info.key
             != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
         {
@@ -6116,6 +7920,18 @@ impl Processor {
         Ok(())
     }
 
+    /// The config pda must still be a plain system account, once created it is owned by the
+    /// program and has to be changed with the update instructions instead.
+    fn check_config_not_created(amm_config_info: &AccountInfo) -> Result<(), AmmError> {
+        if *amm_config_info.owner != solana_program::system_program::id()
+            || !amm_config_info.data_is_empty()
+        {
+            msg!("amm config {} already exists", amm_config_info.key);
+            return Err(AmmError::RepeatCreateConfigAccount);
+        }
+        Ok(())
+    }
+
     /// Processes `process_create_config` instruction.
     pub fn process_create_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -6144,9 +7960,7 @@ impl Processor {
         if pda != *amm_config_info.key {
             return Err(AmmError::InvalidConfigAccount.into());
         }
-        if amm_config_info.owner != system_program_info.key {
-            return Err(AmmError::RepeatCreateConfigAccount.into());
-        }
+        Self::check_config_not_created(amm_config_info)?;
         let pda_signer_seeds: &[&[_]] = &[&AMM_CONFIG_SEED, &[bump_seed]];
         let rent = &Rent::from_account_info(rent_sysvar_info)?;
         let data_size = size_of::<AmmConfig>();
@@ -6181,6 +7995,7 @@ impl Processor {
 
         let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
         amm_config.pnl_owner = *pnl_owner_info.key;
+        amm_config.fee_recipient = *pnl_owner_info.key;
         amm_config.create_pool_fee = 0;
 
         Ok(())
@@ -6195,24 +8010,24 @@ impl Processor {
         let account_info_iter = &mut accounts.iter();
         let admin_info = next_account_info(account_info_iter)?;
         let amm_config_info = next_account_info(account_info_iter)?;
-        if !admin_info.is_signer
-            || (config_feature::amm_owner::id() != *admin_info.key
-                && config_feature::amm_subscriber::id() != *admin_info.key)
-        {
-            return Err(AmmError::InvalidSignAccount.into());
-        }
         let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
         if pda != *amm_config_info.key || amm_config_info.owner != program_id {
             return Err(AmmError::InvalidConfigAccount.into());
         }
 
         let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
         match config_args.param {
             0 => {
                 let pnl_owner = config_args.owner.unwrap();
                 if pnl_owner == Pubkey::default() {
                     return Err(AmmError::InvalidInput.into());
                 }
+                msg!(
+                    "update_config: pnl_owner {} to {}",
+                    amm_config.pnl_owner,
+                    pnl_owner
+                );
                 amm_config.pnl_owner = pnl_owner;
             }
             1 => {
@@ -6220,6 +8035,11 @@ impl Processor {
                 if cancel_owner == Pubkey::default() {
                     return Err(AmmError::InvalidInput.into());
                 }
+                msg!(
+                    "update_config: cancel_owner {} to {}",
+                    amm_config.cancel_owner,
+                    cancel_owner
+                );
                 amm_config.cancel_owner = cancel_owner;
             }
             2 => {
@@ -6231,88 +8051,1196 @@ impl Processor {
             }
         }
 
-        return Ok(());
-    }
+        return Ok(());
+    }
+
+    /// The built-in amm owners sign for the config account until an admin has been set,
+    /// after that only the config admin is accepted.
+    fn check_config_admin(amm_config: &AmmConfig, admin_info: &AccountInfo) -> ProgramResult {
+        if !admin_info.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if amm_config.admin != Pubkey::default() {
+            if amm_config.admin != *admin_info.key {
+                return Err(AmmError::InvalidSignAccount.into());
+            }
+        } else if config_feature::amm_owner::id() != *admin_info.key
+            && config_feature::amm_subscriber::id() != *admin_info.key
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        Ok(())
+    }
+
+    /// The pool scoped admin instructions are signed by one of the amm owners or by the pool
+    /// admin they delegated the pool to, which never reaches the config account.
+    fn check_pool_admin(amm: &AmmInfo, admin_info: &AccountInfo) -> ProgramResult {
+        if !admin_info.is_signer {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if *admin_info.key != config_feature::amm_owner::ID
+            && *admin_info.key != config_feature::amm_subscriber::ID
+            && amm.pool_admin() != Some(*admin_info.key)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        Ok(())
+    }
+
+    /// Processes `process_propose_new_admin` instruction.
+    pub fn process_propose_new_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        propose: ProposeNewAdminInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
+        if propose.new_admin == Pubkey::default() {
+            return Err(AmmError::InvalidInput.into());
+        }
+        amm_config.pending_admin = propose.new_admin;
+
+        Ok(())
+    }
+
+    /// Processes `process_accept_admin` instruction.
+    pub fn process_accept_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let pending_admin_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        if !pending_admin_info.is_signer
+            || amm_config.pending_admin == Pubkey::default()
+            || amm_config.pending_admin != *pending_admin_info.key
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        amm_config.admin = amm_config.pending_admin;
+        amm_config.pending_admin = Pubkey::default();
+
+        Ok(())
+    }
+
+    /// Processes `process_update_config_fees` instruction.
+    pub fn process_update_config_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        fees: UpdateConfigFeesInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
+        validate_fraction(fees.trade_fee_numerator, fees.trade_fee_denominator)?;
+        validate_fraction(fees.protocol_fee_numerator, fees.protocol_fee_denominator)?;
+        amm_config.trade_fee_numerator = fees.trade_fee_numerator;
+        amm_config.trade_fee_denominator = fees.trade_fee_denominator;
+        amm_config.protocol_fee_numerator = fees.protocol_fee_numerator;
+        amm_config.protocol_fee_denominator = fees.protocol_fee_denominator;
+
+        Ok(())
+    }
+
+    /// Processes `process_set_fee_recipient` instruction.
+    pub fn process_set_fee_recipient(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_recipient: SetFeeRecipientInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
+        if set_recipient.fee_recipient == Pubkey::default() {
+            return Err(AmmError::InvalidInput.into());
+        }
+        amm_config.fee_recipient = set_recipient.fee_recipient;
+        msg!("set_fee_recipient: {}", amm_config.fee_recipient);
+
+        Ok(())
+    }
+
+    /// Processes `process_set_swap_discount` instruction.
+    pub fn process_set_swap_discount(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_discount: SetSwapDiscountInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let mut amm_config = AmmConfig::load_mut_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
+        if set_discount.discount_mint != Pubkey::default() {
+            validate_fraction(set_discount.fee_numerator, set_discount.fee_denominator)?;
+        }
+        amm_config.discount_mint = set_discount.discount_mint;
+        amm_config.discount_fee_numerator = set_discount.fee_numerator;
+        amm_config.discount_fee_denominator = set_discount.fee_denominator;
+        msg!(
+            "set_swap_discount: {} {}/{}",
+            amm_config.discount_mint,
+            amm_config.discount_fee_numerator,
+            amm_config.discount_fee_denominator
+        );
+
+        Ok(())
+    }
+
+    /// Protocol fees may only be collected to token accounts of the config fee recipient.
+    fn check_fee_recipient(
+        amm_config: &AmmConfig,
+        coin_owner: &Pubkey,
+        pc_owner: &Pubkey,
+    ) -> Result<(), AmmError> {
+        let fee_recipient = amm_config.fee_recipient();
+        if *coin_owner != fee_recipient || *pc_owner != fee_recipient {
+            return Err(AmmError::InvalidOwner);
+        }
+        Ok(())
+    }
+
+    /// Processes `process_set_pool_status` instruction.
+    pub fn process_set_pool_status(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_status: SetPoolStatusInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        if set_status.status & !PoolStatusBit::PAUSE_MASK != 0 {
+            return Err(AmmError::InvalidInput.into());
+        }
+        // SwapPermissioned, OpenOrders and SwapGuard are left to their own instructions
+        amm.pool_status = (amm.pool_status & !PoolStatusBit::PAUSE_MASK) | set_status.status;
+        msg!("set_pool_status: {:#x}", amm.pool_status);
+
+        Ok(())
+    }
+
+    /// Processes `process_set_reserve_caps` instruction.
+    pub fn process_set_reserve_caps(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        caps: SetReserveCapsInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        amm.max_coin_reserve = caps.max_coin_reserve;
+        amm.max_pc_reserve = caps.max_pc_reserve;
+        msg!(
+            "set_reserve_caps: max_coin_reserve:{}, max_pc_reserve:{}",
+            amm.max_coin_reserve,
+            amm.max_pc_reserve
+        );
+
+        Ok(())
+    }
+
+    /// Processes `process_set_swap_permissioned` instruction.
+    pub fn process_set_swap_permissioned(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_permissioned: SetSwapPermissionedInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        // older layouts ignore swap_permissioned
+        amm.migrate(false)?;
+        amm.set_swap_permissioned(set_permissioned.permissioned != 0);
+        msg!("set_swap_permissioned: {}", amm.swap_permissioned());
+
+        Ok(())
+    }
+
+    /// Processes `process_update_swap_whitelist` instruction.
+    pub fn process_update_swap_whitelist(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        update: UpdateSwapWhitelistInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let swap_whitelist_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        let amm = AmmInfo::load_checked(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        if *system_program_info.key != solana_program::system_program::id() {
+            return Err(AmmError::InvalidSysProgramAddress.into());
+        }
+        let (pda, bump_seed) =
+            Pubkey::find_program_address(&[amm_info.key.as_ref(), SWAP_WHITELIST_SEED], program_id);
+        if pda != *swap_whitelist_info.key {
+            return Err(AmmError::InvalidInput.into());
+        }
+
+        if swap_whitelist_info.owner == system_program_info.key {
+            let pda_signer_seeds: &[&[_]] =
+                &[amm_info.key.as_ref(), SWAP_WHITELIST_SEED, &[bump_seed]];
+            let data_size = size_of::<SwapWhitelist>();
+            let required_lamports = Rent::get()?
+                .minimum_balance(data_size)
+                .max(1)
+                .saturating_sub(swap_whitelist_info.lamports());
+            if required_lamports > 0 {
+                invoke(
+                    &system_instruction::transfer(
+                        amm_owner_info.key,
+                        swap_whitelist_info.key,
+                        required_lamports,
+                    ),
+                    &[
+                        amm_owner_info.clone(),
+                        swap_whitelist_info.clone(),
+                        system_program_info.clone(),
+                    ],
+                )?;
+            }
+            invoke_signed(
+                &system_instruction::allocate(swap_whitelist_info.key, data_size as u64),
+                &[swap_whitelist_info.clone(), system_program_info.clone()],
+                &[&pda_signer_seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::assign(swap_whitelist_info.key, &program_id),
+                &[swap_whitelist_info.clone(), system_program_info.clone()],
+                &[&pda_signer_seeds],
+            )?;
+            SwapWhitelist::load_mut(swap_whitelist_info)?.amm = *amm_info.key;
+        }
+
+        let mut whitelist =
+            SwapWhitelist::load_mut_checked(swap_whitelist_info, program_id, amm_info.key)?;
+        if update.add != 0 {
+            whitelist.add(&update.wallet)?;
+        } else {
+            whitelist.remove(&update.wallet);
+        }
+        msg!(
+            "update_swap_whitelist: {} {}, len:{}",
+            if update.add != 0 { "add" } else { "remove" },
+            update.wallet,
+            whitelist.len
+        );
+
+        Ok(())
+    }
+
+    /// Processes `process_set_pool_admin` instruction.
+    pub fn process_set_pool_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_admin: SetPoolAdminInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        if !amm_owner_info.is_signer
+            || (*amm_owner_info.key != config_feature::amm_owner::ID
+                && *amm_owner_info.key != config_feature::amm_subscriber::ID)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        // older layouts ignore pool_admin
+        amm.migrate(false)?;
+        amm.pool_admin = set_admin.pool_admin;
+        msg!("set_pool_admin: {}", amm.pool_admin);
+
+        Ok(())
+    }
+
+    /// Processes `process_sync_pool_fees` instruction.
+    pub fn process_sync_pool_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+
+        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, admin_info)?;
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        // older layouts have no protocol fee
+        amm.migrate(false)?;
+        amm.apply_config_fees(&amm_config);
+        msg!(
+            "sync_pool_fees: swap {}/{}, protocol {}/{}",
+            amm.fees.swap_fee_numerator,
+            amm.fees.swap_fee_denominator,
+            amm.protocol_fee_numerator,
+            amm.protocol_fee_denominator
+        );
+
+        Ok(())
+    }
+
+    /// Processes `process_set_dynamic_fee` instruction.
+    pub fn process_set_dynamic_fee(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        dynamic_fee: SetDynamicFeeInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let (amm, extension) = AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        let mut extension = extension.ok_or(AmmError::PoolNotMigrated)?;
+        if dynamic_fee.enabled != 0 {
+            validate_fraction(dynamic_fee.base_fee_numerator, dynamic_fee.fee_denominator)?;
+            validate_fraction(dynamic_fee.max_fee_numerator, dynamic_fee.fee_denominator)?;
+            if dynamic_fee.base_fee_numerator > dynamic_fee.max_fee_numerator
+                || dynamic_fee.max_fee_deviation_bps == 0
+            {
+                return Err(AmmError::InvalidFee.into());
+            }
+        }
+        // the price averages restart with the next swap, earlier ones may be long stale
+        if extension.dynamic_fee_enabled == 0 {
+            extension.reference_timestamp = 0;
+            extension.next_reference_timestamp = 0;
+        }
+        extension.dynamic_fee_enabled = (dynamic_fee.enabled != 0) as u64;
+        extension.base_fee_numerator = dynamic_fee.base_fee_numerator;
+        extension.max_fee_numerator = dynamic_fee.max_fee_numerator;
+        extension.dynamic_fee_denominator = dynamic_fee.fee_denominator;
+        extension.max_fee_deviation_bps = dynamic_fee.max_fee_deviation_bps;
+        msg!(
+            "set_dynamic_fee: enabled:{}, {}..{}/{} at {} bps",
+            extension.dynamic_fee_enabled,
+            extension.base_fee_numerator,
+            extension.max_fee_numerator,
+            extension.dynamic_fee_denominator,
+            extension.max_fee_deviation_bps
+        );
+
+        Ok(())
+    }
+
+    /// Processes `process_set_min_trade_amount` instruction.
+    pub fn process_set_min_trade_amount(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        min_trade: SetMinTradeAmountInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let (amm, extension) = AmmInfo::load_mut_checked_with_extension(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        let mut extension = extension.ok_or(AmmError::PoolNotMigrated)?;
+        extension.min_coin_trade_amount = min_trade.min_coin_amount;
+        extension.min_pc_trade_amount = min_trade.min_pc_amount;
+        msg!(
+            "set_min_trade_amount: coin:{}, pc:{}",
+            extension.min_coin_trade_amount,
+            extension.min_pc_trade_amount
+        );
+
+        Ok(())
+    }
+
+    /// Processes `process_set_swap_guard` instruction.
+    pub fn process_set_swap_guard(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        set_guard: SetSwapGuardInstruction,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        Self::check_pool_admin(&amm, amm_owner_info)?;
+        if set_guard.guarded != 0 {
+            amm.pool_status |= PoolStatusBit::SwapGuard.mask();
+        } else {
+            amm.pool_status &= !PoolStatusBit::SwapGuard.mask();
+        }
+        msg!("set_swap_guard: {}", amm.swap_guarded());
+
+        Ok(())
+    }
+
+    /// Processes `process_migrate_state` instruction.
+    pub fn process_migrate_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if !amm_owner_info.is_signer
+            || (*amm_owner_info.key != config_feature::amm_owner::ID
+                && *amm_owner_info.key != config_feature::amm_subscriber::ID)
+        {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        if *system_program_info.key != solana_program::system_program::id() {
+            return Err(AmmError::InvalidSysProgramAddress.into());
+        }
+        let old_version = AmmInfo::load_checked(&amm_info, program_id)?.version;
+        // pools of older versions end with AmmInfo, grow them to hold the extension, the admin
+        // pays the rent of the new bytes
+        if amm_info.data_len() != AMM_INFO_ACCOUNT_LEN {
+            let required_lamports = Rent::get()?
+                .minimum_balance(AMM_INFO_ACCOUNT_LEN)
+                .saturating_sub(amm_info.lamports());
+            if required_lamports > 0 {
+                invoke(
+                    &system_instruction::transfer(
+                        amm_owner_info.key,
+                        amm_info.key,
+                        required_lamports,
+                    ),
+                    &[
+                        amm_owner_info.clone(),
+                        amm_info.clone(),
+                        system_program_info.clone(),
+                    ],
+                )?;
+            }
+            amm_info.realloc(AMM_INFO_ACCOUNT_LEN, true)?;
+        }
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        amm.migrate(true)?;
+        msg!("migrate_state: version {} to {}", old_version, amm.version);
+
+        Ok(())
+    }
+
+    /// Processes `process_collect_protocol_fees` instruction.
+    pub fn process_collect_protocol_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        collect: CollectProtocolFeesInstruction,
+    ) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 9;
+        if accounts.len() != ACCOUNT_LEN {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let owner_coin_info = next_account_info(account_info_iter)?;
+        let owner_pc_info = next_account_info(account_info_iter)?;
+        let pnl_owner_info = next_account_info(account_info_iter)?;
+
+        let mut amm = AmmInfo::load_mut_checked(&amm_info, program_id)?;
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
+        if !pnl_owner_info.is_signer || *pnl_owner_info.key != amm_config.pnl_owner {
+            return Err(AmmError::InvalidSignAccount.into());
+        }
+        check_assert_eq!(
+            *token_program_info.key,
+            spl_token::id(),
+            "spl_token_program",
+            AmmError::InvalidSplTokenProgram
+        );
+        check_assert_eq!(
+            *amm_coin_vault_info.key,
+            amm.coin_vault,
+            "coin_vault",
+            AmmError::InvalidCoinVault
+        );
+        check_assert_eq!(
+            *amm_pc_vault_info.key,
+            amm.pc_vault,
+            "pc_vault",
+            AmmError::InvalidPCVault
+        );
+        let spl_token_program_id = token_program_info.key;
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let owner_coin = Self::unpack_token_account(&owner_coin_info, spl_token_program_id)?;
+        let owner_pc = Self::unpack_token_account(&owner_pc_info, spl_token_program_id)?;
+        if owner_coin.mint != amm.coin_vault_mint {
+            return Err(AmmError::InvalidCoinMint.into());
+        }
+        if owner_pc.mint != amm.pc_vault_mint {
+            return Err(AmmError::InvalidPCMint.into());
+        }
+        Self::check_fee_recipient(&amm_config, &owner_coin.owner, &owner_pc.owner)?;
+
+        let coin_amount = collect
+            .coin_amount
+            .min(amm.state_data.need_take_pnl_coin)
+            .min(amm_coin_vault.amount);
+        let pc_amount = collect
+            .pc_amount
+            .min(amm.state_data.need_take_pnl_pc)
+            .min(amm_pc_vault.amount);
+        if coin_amount != 0 {
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_coin_vault_info.clone(),
+                owner_coin_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                amm.nonce as u8,
+                coin_amount,
+            )?;
+        }
+        if pc_amount != 0 {
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_pc_vault_info.clone(),
+                owner_pc_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                amm.nonce as u8,
+                pc_amount,
+            )?;
+        }
+        amm.state_data.need_take_pnl_coin -= coin_amount;
+        amm.state_data.need_take_pnl_pc -= pc_amount;
+        msg!(arrform!(
+            LOG_SIZE,
+            "collect_protocol_fees coin:{}, pc:{}, need_take_coin:{}, need_take_pc:{}",
+            coin_amount,
+            pc_amount,
+            amm.state_data.need_take_pnl_coin,
+            amm.state_data.need_take_pnl_pc
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    /// A pool can only be closed once every minted lp token is burned and the vaults hold
+    /// nothing beyond the pnl still owed to the protocol, which is swept to the fee recipient.
+    fn check_pool_empty(
+        amm: &AmmInfo,
+        coin_vault_amount: u64,
+        pc_vault_amount: u64,
+        lp_supply: u64,
+    ) -> Result<(), AmmError> {
+        if lp_supply != 0
+            || coin_vault_amount > amm.state_data.need_take_pnl_coin
+            || pc_vault_amount > amm.state_data.need_take_pnl_pc
+        {
+            return Err(AmmError::PoolNotEmpty);
+        }
+        Ok(())
+    }
+
+    /// Move all lamports of an account owned by this program to `destination` and wipe it.
+    fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+        let lamports = destination
+            .lamports()
+            .checked_add(account.lamports())
+            .ok_or(AmmError::CheckedAddOverflow)?;
+        **destination.lamports.borrow_mut() = lamports;
+        **account.lamports.borrow_mut() = 0;
+        account.data.borrow_mut().fill(0);
+        Ok(())
+    }
+
+    /// Processes `process_close_pool` instruction.
+    pub fn process_close_pool(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        const ACCOUNT_LEN: usize = 15;
+        if accounts.len() != ACCOUNT_LEN {
+            return Err(AmmError::WrongAccountsNumber.into());
+        }
+        let account_info_iter = &mut accounts.iter();
+        let token_program_info = next_account_info(account_info_iter)?;
+        let amm_info = next_account_info(account_info_iter)?;
+        let amm_authority_info = next_account_info(account_info_iter)?;
+        let amm_open_orders_info = next_account_info(account_info_iter)?;
+        let amm_coin_vault_info = next_account_info(account_info_iter)?;
+        let amm_pc_vault_info = next_account_info(account_info_iter)?;
+        let amm_lp_mint_info = next_account_info(account_info_iter)?;
+        let amm_target_orders_info = next_account_info(account_info_iter)?;
+        let market_program_info = next_account_info(account_info_iter)?;
+        let market_info = next_account_info(account_info_iter)?;
+        let amm_owner_info = next_account_info(account_info_iter)?;
+        let recipient_info = next_account_info(account_info_iter)?;
+        let amm_config_info = next_account_info(account_info_iter)?;
+        let recipient_coin_info = next_account_info(account_info_iter)?;
+        let recipient_pc_info = next_account_info(account_info_iter)?;
+
+        let (pda, _) = Pubkey::find_program_address(&[&AMM_CONFIG_SEED], program_id);
+        if pda != *amm_config_info.key || amm_config_info.owner != program_id {
+            return Err(AmmError::InvalidConfigAccount.into());
+        }
+        let amm_config = AmmConfig::load_checked(&amm_config_info, program_id)?;
+        Self::check_config_admin(&amm_config, amm_owner_info)?;
+        let amm = AmmInfo::load_checked(&amm_info, program_id)?;
+        if *amm_authority_info.key
+            != Self::authority_id(program_id, AUTHORITY_AMM, amm.nonce as u8)?
+        {
+            return Err(AmmError::InvalidProgramAddress.into());
+        }
+        check_assert_eq!(
+            *token_program_info.key,
+            spl_token::id(),
+            "spl_token_program",
+            AmmError::InvalidSplTokenProgram
+        );
+        check_assert_eq!(
+            *amm_open_orders_info.key,
+            amm.open_orders,
+            "open_orders",
+            AmmError::InvalidOpenOrders
+        );
+        check_assert_eq!(
+            *amm_coin_vault_info.key,
+            amm.coin_vault,
+            "coin_vault",
+            AmmError::InvalidCoinVault
+        );
+        check_assert_eq!(
+            *amm_pc_vault_info.key,
+            amm.pc_vault,
+            "pc_vault",
+            AmmError::InvalidPCVault
+        );
+        check_assert_eq!(
+            *amm_lp_mint_info.key,
+            amm.lp_mint,
+            "lp_mint",
+            AmmError::InvalidPoolMint
+        );
+        check_assert_eq!(
+            *amm_target_orders_info.key,
+            amm.target_orders,
+            "target_orders",
+            AmmError::InvalidTargetOrders
+        );
+        check_assert_eq!(
+            *market_program_info.key,
+            amm.market_program,
+            "market_program",
+            AmmError::InvalidMarketProgram
+        );
+        check_assert_eq!(
+            *market_info.key,
+            amm.market,
+            "market",
+            AmmError::InvalidMarket
+        );
+        if amm_target_orders_info.owner != program_id {
+            return Err(AmmError::InvalidTargetOrders.into());
+        }
+
+        let spl_token_program_id = token_program_info.key;
+        let amm_coin_vault =
+            Self::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+        let amm_pc_vault = Self::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+        let recipient_coin =
+            Self::unpack_token_account(&recipient_coin_info, spl_token_program_id)?;
+        let recipient_pc = Self::unpack_token_account(&recipient_pc_info, spl_token_program_id)?;
+        if recipient_coin.mint != amm.coin_vault_mint {
+            return Err(AmmError::InvalidCoinMint.into());
+        }
+        if recipient_pc.mint != amm.pc_vault_mint {
+            return Err(AmmError::InvalidPCMint.into());
+        }
+        Self::check_fee_recipient(&amm_config, &recipient_coin.owner, &recipient_pc.owner)?;
+        let lp_mint = Self::unpack_mint(
+            &amm_lp_mint_info,
+            Self::token_program_id(&amm_lp_mint_info)?,
+        )?;
+        Self::check_pool_empty(
+            &amm,
+            amm_coin_vault.amount,
+            amm_pc_vault.amount,
+            lp_mint.supply,
+        )?;
+
+        let nonce = amm.nonce as u8;
+        drop(amm);
+        // the vaults only close once empty, sweep the uncollected pnl first
+        if amm_coin_vault.amount != 0 {
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_coin_vault_info.clone(),
+                recipient_coin_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                nonce,
+                amm_coin_vault.amount,
+            )?;
+        }
+        if amm_pc_vault.amount != 0 {
+            Invokers::token_transfer_with_authority(
+                token_program_info.clone(),
+                amm_pc_vault_info.clone(),
+                recipient_pc_info.clone(),
+                amm_authority_info.clone(),
+                AUTHORITY_AMM,
+                nonce,
+                amm_pc_vault.amount,
+            )?;
+        }
+        Invokers::token_close_with_authority(
+            token_program_info.clone(),
+            amm_coin_vault_info.clone(),
+            recipient_info.clone(),
+            amm_authority_info.clone(),
+            AUTHORITY_AMM,
+            nonce,
+        )?;
+        Invokers::token_close_with_authority(
+            token_program_info.clone(),
+            amm_pc_vault_info.clone(),
+            recipient_info.clone(),
+            amm_authority_info.clone(),
+            AUTHORITY_AMM,
+            nonce,
+        )?;
+        // the dex refuses to close open orders that still hold funds or orders
+        Invokers::invoke_dex_close_open_orders(
+            market_program_info.clone(),
+            amm_open_orders_info.clone(),
+            amm_authority_info.clone(),
+            recipient_info.clone(),
+            market_info.clone(),
+            AUTHORITY_AMM,
+            nonce,
+        )?;
+        Self::close_program_account(amm_target_orders_info, recipient_info)?;
+        Self::close_program_account(amm_info, recipient_info)?;
+        msg!(arrform!(
+            LOG_SIZE,
+            "close_pool: {}, swept coin:{}, pc:{}",
+            amm_info.key,
+            amm_coin_vault.amount,
+            amm_pc_vault.amount
+        )
+        .as_str());
+
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let instruction = AmmInstruction::unpack(input)?;
+        match instruction {
+            AmmInstruction::PreInitialize(_init_arg) => {
+                unimplemented!("This instruction is not supported, please use Initialize2")
+            }
+            AmmInstruction::Initialize(_init1) => {
+                unimplemented!("This instruction is not supported, please use Initialize2")
+            }
+            AmmInstruction::Initialize2(init2) => {
+                Self::process_initialize2(program_id, accounts, init2)
+            }
+            AmmInstruction::MonitorStep(monitor) => {
+                Self::process_monitor_step(program_id, accounts, monitor)
+            }
+            AmmInstruction::Deposit(deposit) => {
+                Self::process_deposit(program_id, accounts, deposit)
+            }
+            AmmInstruction::Withdraw(withdraw) => {
+                Self::process_withdraw(program_id, accounts, withdraw)
+            }
+            AmmInstruction::MigrateToOpenBook => {
+                Self::process_migrate_to_openbook(program_id, accounts)
+            }
+            AmmInstruction::SetParams(setparams) => {
+                Self::process_set_params(program_id, accounts, setparams)
+            }
+            AmmInstruction::WithdrawPnl => Self::process_withdrawpnl(program_id, accounts),
+            AmmInstruction::OwnerWithdraw => Self::process_owner_withdraw(program_id, accounts),
+            AmmInstruction::WithdrawSrm(withdrawsrm) => {
+                Self::process_withdraw_srm(program_id, accounts, withdrawsrm)
+            }
+            AmmInstruction::SwapBaseIn(swap) => {
+                Self::process_swap_base_in(program_id, accounts, swap)
+            }
+            AmmInstruction::SwapBaseOut(swap) => {
+                Self::process_swap_base_out(program_id, accounts, swap)
+            }
+            AmmInstruction::SimulateInfo(simulate) => {
+                Self::process_simulate_info(program_id, accounts, simulate)
+            }
+            AmmInstruction::AdminCancelOrders(cancel) => {
+                Self::process_admin_cancel_orders(program_id, accounts, cancel)
+            }
+            AmmInstruction::CreateConfigAccount => {
+                Self::process_create_config(program_id, accounts)
+            }
+            AmmInstruction::UpdateConfigAccount(config_args) => {
+                Self::process_update_config(program_id, accounts, config_args)
+            }
+            AmmInstruction::ProposeNewAdmin(propose) => {
+                Self::process_propose_new_admin(program_id, accounts, propose)
+            }
+            AmmInstruction::AcceptAdmin => Self::process_accept_admin(program_id, accounts),
+            AmmInstruction::UpdateConfigFees(fees) => {
+                Self::process_update_config_fees(program_id, accounts, fees)
+            }
+            AmmInstruction::SetPoolStatus(set_status) => {
+                Self::process_set_pool_status(program_id, accounts, set_status)
+            }
+            AmmInstruction::CollectProtocolFees(collect) => {
+                Self::process_collect_protocol_fees(program_id, accounts, collect)
+            }
+            AmmInstruction::MigrateState => Self::process_migrate_state(program_id, accounts),
+            AmmInstruction::ClosePool => Self::process_close_pool(program_id, accounts),
+            AmmInstruction::SetReserveCaps(caps) => {
+                Self::process_set_reserve_caps(program_id, accounts, caps)
+            }
+            AmmInstruction::SetSwapPermissioned(set_permissioned) => {
+                Self::process_set_swap_permissioned(program_id, accounts, set_permissioned)
+            }
+            AmmInstruction::UpdateSwapWhitelist(update) => {
+                Self::process_update_swap_whitelist(program_id, accounts, update)
+            }
+            AmmInstruction::SetFeeRecipient(set_recipient) => {
+                Self::process_set_fee_recipient(program_id, accounts, set_recipient)
+            }
+            AmmInstruction::SetSwapDiscount(set_discount) => {
+                Self::process_set_swap_discount(program_id, accounts, set_discount)
+            }
+            AmmInstruction::DepositSingleSided(deposit) => {
+                Self::process_deposit_single_sided(program_id, accounts, deposit)
+            }
+            AmmInstruction::WithdrawSingleSided(withdraw) => {
+                Self::process_withdraw_single_sided(program_id, accounts, withdraw)
+            }
+            AmmInstruction::SetPoolAdmin(set_admin) => {
+                Self::process_set_pool_admin(program_id, accounts, set_admin)
+            }
+            AmmInstruction::BatchQuote(batch) => {
+                Self::process_batch_quote(program_id, accounts, batch)
+            }
+            AmmInstruction::SyncPoolFees => Self::process_sync_pool_fees(program_id, accounts),
+            AmmInstruction::SetDynamicFee(dynamic_fee) => {
+                Self::process_set_dynamic_fee(program_id, accounts, dynamic_fee)
+            }
+            AmmInstruction::SetMinTradeAmount(min_trade) => {
+                Self::process_set_min_trade_amount(program_id, accounts, min_trade)
+            }
+            AmmInstruction::SetSwapGuard(set_guard) => {
+                Self::process_set_swap_guard(program_id, accounts, set_guard)
+            }
+        }
+    }
+}
+
+pub mod account_parser {
+    use super::*;
+    pub struct IdleArgs<'a> {
+        pub program_id: &'a Pubkey,
+        pub total_coin_without_take_pnl: &'a mut u64,
+        pub total_pc_without_take_pnl: &'a mut u64,
+        pub amm: &'a mut AmmInfo,
+        pub bids: &'a Vec<LeafNode>,
+        pub asks: &'a Vec<LeafNode>,
+        pub target: &'a mut TargetOrders,
+    }
+    impl<'a> IdleArgs<'a> {
+        pub fn with_parsed_args(
+            program_id: &'a Pubkey,
+            spl_token_program_id: &'a Pubkey,
+            amm: &mut AmmInfo,
+            accounts: &'a [AccountInfo],
+            f: impl FnOnce(IdleArgs) -> ProgramResult,
+        ) -> ProgramResult {
+            if accounts.len() != 11 {
+                return Err(AmmError::WrongAccountsNumber.into());
+            }
+            let &[ref amm_info, ref _market_program_info, ref market_info, ref market_bids_info, ref market_asks_info, ref market_event_queue_info, ref market_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 11];
+
+            let amm_coin_vault =
+                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+            let amm_pc_vault =
+                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+            let mut target =
+                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                market_authority_info,
+                &amm,
+                false,
+            )?;
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+
+            let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?;
+
+            let args = IdleArgs {
+                program_id,
+                total_coin_without_take_pnl: &mut total_coin_without_take_pnl,
+                total_pc_without_take_pnl: &mut total_pc_without_take_pnl,
+                amm,
+                bids: &bids,
+                asks: &asks,
+                target: &mut target,
+            };
+            f(args)
+        }
+    }
+
+    pub struct CancelAllOrdersArgs<'a, 'b: 'a> {
+        pub program_id: &'a Pubkey,
+        pub limit: u16,
+        pub market_program_info: &'a AccountInfo<'b>,
+        pub market_info: &'a AccountInfo<'b>,
+        pub amm_open_orders_info: &'a AccountInfo<'b>,
+        pub amm_authority_info: &'a AccountInfo<'b>,
+        pub market_event_queue_info: &'a AccountInfo<'b>,
+        pub market_coin_vault_info: &'a AccountInfo<'b>,
+        pub market_pc_vault_info: &'a AccountInfo<'b>,
+        pub market_bids_info: &'a AccountInfo<'b>,
+        pub market_asks_info: &'a AccountInfo<'b>,
+        pub market_vault_signer: &'a AccountInfo<'b>,
+        pub token_program_info: &'a AccountInfo<'b>,
+        pub amm_coin_vault_info: &'a AccountInfo<'b>,
+        pub amm_pc_vault_info: &'a AccountInfo<'b>,
+
+        pub referrer_pc_account: Option<&'a AccountInfo<'b>>,
+
+        pub amm: &'a mut AmmInfo,
+        pub open_orders: &'a OpenOrders,
+        pub target: &'a mut TargetOrders,
+        pub bids: &'a Vec<LeafNode>,
+        pub asks: &'a Vec<LeafNode>,
+    }
+    impl<'a, 'b: 'a> CancelAllOrdersArgs<'a, 'b> {
+        pub fn with_parsed_args(
+            program_id: &'a Pubkey,
+            limit: u16,
+            amm: &mut AmmInfo,
+            accounts: &'a [AccountInfo<'b>],
+            referrer_pc_wallet: Option<&'a AccountInfo<'b>>,
+            f: impl FnOnce(CancelAllOrdersArgs) -> ProgramResult,
+        ) -> ProgramResult {
+            if accounts.len() != 15 {
+                return Err(AmmError::WrongAccountsNumber.into());
+            }
+            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_coin_vault_info, ref market_pc_vault_info, ref market_bids_info, ref market_asks_info, ref market_vault_signer, ref token_program_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 15];
+
+            let mut target =
+                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                true,
+            )?;
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+
+            let args = CancelAllOrdersArgs {
+                program_id,
+                limit,
+                market_program_info,
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                market_event_queue_info,
+                market_coin_vault_info,
+                market_pc_vault_info,
+                market_bids_info,
+                market_asks_info,
+                market_vault_signer,
+                token_program_info,
+                amm_coin_vault_info,
+                amm_pc_vault_info,
+                referrer_pc_account: referrer_pc_wallet,
+                amm,
+                open_orders: &open_orders,
+                target: &mut target,
+                bids: &bids,
+                asks: &asks,
+            };
+            f(args)
+        }
+    }
+
+    pub struct PlanOrderBookArgs<'a> {
+        pub program_id: &'a Pubkey,
+        pub limit: u16,
+        pub total_coin_without_take_pnl: u64,
+        pub total_pc_without_take_pnl: u64,
+        pub amm: &'a mut AmmInfo,
+        pub target: &'a mut TargetOrders,
+    }
+    impl<'a> PlanOrderBookArgs<'a> {
+        pub fn with_parsed_args(
+            program_id: &'a Pubkey,
+            spl_token_program_id: &'a Pubkey,
+            limit: u16,
+            amm: &mut AmmInfo,
+            accounts: &'a [AccountInfo],
+            f: impl FnOnce(PlanOrderBookArgs) -> ProgramResult,
+        ) -> ProgramResult {
+            if accounts.len() != 8 {
+                return Err(AmmError::WrongAccountsNumber.into());
+            }
+            let &[ref amm_info, ref market_info, ref market_event_queue_info, ref amm_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 8];
+
+            let amm_coin_vault =
+                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+            let amm_pc_vault =
+                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
+            let mut target =
+                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                false,
+            )?;
+            let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
+                Calculator::calc_total_without_take_pnl(
+                    amm_pc_vault.amount,
+                    amm_coin_vault.amount,
+                    &open_orders,
+                    &amm,
+                    &market_state,
+                    &market_event_queue_info,
+                    &amm_open_orders_info,
+                )?;
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        let instruction = AmmInstruction::unpack(input)?;
-        match instruction {
-            AmmInstruction::PreInitialize(_init_arg) => {
-                unimplemented!("This instruction is not supported, please use Initialize2")
-            }
-            AmmInstruction::Initialize(_init1) => {
-                unimplemented!("This instruction is not supported, please use Initialize2")
-            }
-            AmmInstruction::Initialize2(init2) => {
-                Self::process_initialize2(program_id, accounts, init2)
-            }
-            AmmInstruction::MonitorStep(monitor) => {
-                Self::process_monitor_step(program_id, accounts, monitor)
-            }
-            AmmInstruction::Deposit(deposit) => {
-                Self::process_deposit(program_id, accounts, deposit)
-            }
-            AmmInstruction::Withdraw(withdraw) => {
-                Self::process_withdraw(program_id, accounts, withdraw)
-            }
-            AmmInstruction::MigrateToOpenBook => {
-                Self::process_migrate_to_openbook(program_id, accounts)
-            }
-            AmmInstruction::SetParams(setparams) => {
-                Self::process_set_params(program_id, accounts, setparams)
-            }
-            AmmInstruction::WithdrawPnl => Self::process_withdrawpnl(program_id, accounts),
-            AmmInstruction::OwnerWithdraw => Self::process_owner_withdraw(program_id, accounts),
-            AmmInstruction::WithdrawSrm(withdrawsrm) => {
-                Self::process_withdraw_srm(program_id, accounts, withdrawsrm)
-            }
-            AmmInstruction::SwapBaseIn(swap) => {
-                Self::process_swap_base_in(program_id, accounts, swap)
-            }
-            AmmInstruction::SwapBaseOut(swap) => {
-                Self::process_swap_base_out(program_id, accounts, swap)
-            }
-            AmmInstruction::SimulateInfo(simulate) => {
-                Self::process_simulate_info(program_id, accounts, simulate)
-            }
-            AmmInstruction::AdminCancelOrders(cancel) => {
-                Self::process_admin_cancel_orders(program_id, accounts, cancel)
-            }
-            AmmInstruction::CreateConfigAccount => {
-                Self::process_create_config(program_id, accounts)
-            }
-            AmmInstruction::UpdateConfigAccount(config_args) => {
-                Self::process_update_config(program_id, accounts, config_args)
-            }
+            let args = PlanOrderBookArgs {
+                program_id,
+                limit,
+                total_coin_without_take_pnl,
+                total_pc_without_take_pnl,
+                amm,
+                target: &mut target,
+            };
+            f(args)
         }
     }
-}
 
-pub mod account_parser {
-    use super::*;
-    pub struct IdleArgs<'a> {
+    pub struct PlaceOrdersArgs<'a, 'b: 'a> {
         pub program_id: &'a Pubkey,
-        pub total_coin_without_take_pnl: &'a mut u64,
-        pub total_pc_without_take_pnl: &'a mut u64,
+        pub limit: u16,
+
+        pub amm_authority_info: &'a AccountInfo<'b>,
+        pub amm_open_orders_info: &'a AccountInfo<'b>,
+        pub market_program_info: &'a AccountInfo<'b>,
+        pub market_info: &'a AccountInfo<'b>,
+        pub market_request_queue_info: &'a AccountInfo<'b>,
+        pub amm_coin_vault_info: &'a AccountInfo<'b>,
+        pub amm_pc_vault_info: &'a AccountInfo<'b>,
+        pub market_coin_vault_info: &'a AccountInfo<'b>,
+        pub market_pc_vault_info: &'a AccountInfo<'b>,
+        pub token_program_info: &'a AccountInfo<'b>,
+        pub rent_info: &'a AccountInfo<'b>,
+
+        pub market_event_queue_info: &'a AccountInfo<'b>,
+        pub market_bids_info: &'a AccountInfo<'b>,
+        pub market_asks_info: &'a AccountInfo<'b>,
+
+        pub srm_token_account: Option<&'a AccountInfo<'b>>,
+
         pub amm: &'a mut AmmInfo,
+        pub open_orders: &'a OpenOrders,
         pub bids: &'a Vec<LeafNode>,
         pub asks: &'a Vec<LeafNode>,
         pub target: &'a mut TargetOrders,
+
+        pub total_coin_without_take_pnl: u64,
+        pub total_pc_without_take_pnl: u64,
+        pub coin_vault_amount: u64,
+        pub pc_vault_amount: u64,
     }
-    impl<'a> IdleArgs<'a> {
+    impl<'a, 'b: 'a> PlaceOrdersArgs<'a, 'b> {
         pub fn with_parsed_args(
             program_id: &'a Pubkey,
             spl_token_program_id: &'a Pubkey,
+            limit: u16,
             amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo],
-            f: impl FnOnce(IdleArgs) -> ProgramResult,
+            accounts: &'a [AccountInfo<'b>],
+            srm_token_account: Option<&'a AccountInfo<'b>>,
+            f: impl FnOnce(PlaceOrdersArgs) -> ProgramResult,
         ) -> ProgramResult {
-            if accounts.len() != 11 {
+            if accounts.len() != 17 {
                 return Err(AmmError::WrongAccountsNumber.into());
             }
-            let &[ref amm_info, ref _market_program_info, ref market_info, ref market_bids_info, ref market_asks_info, ref market_event_queue_info, ref market_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 11];
+            let accounts = array_ref![accounts, 0, 17];
+            let (new_orders_accounts, data_accounts) = array_refs![accounts, 15, 2];
+            let &[ref amm_info, ref amm_authority_info, ref amm_open_orders_info, ref market_program_info, ref market_info, ref market_request_queue_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref market_coin_vault_info, ref market_pc_vault_info, ref token_program_info, ref rent_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info] =
+                array_ref![new_orders_accounts, 0, 15];
+            let &[ref amm_target_orders_info, ref _clock_info] = array_ref![data_accounts, 0, 2];
 
             let amm_coin_vault =
                 Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
@@ -6320,19 +9248,17 @@ pub mod account_parser {
                 Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
             let mut target =
                 TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-
             let (market_state, open_orders) = Processor::load_serum_market_order(
                 market_info,
                 amm_open_orders_info,
-                market_authority_info,
+                amm_authority_info,
                 &amm,
                 false,
             )?;
             let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
             let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
             let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-
-            let (mut total_pc_without_take_pnl, mut total_coin_without_take_pnl) =
+            let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
                 Calculator::calc_total_without_take_pnl(
                     amm_pc_vault.amount,
                     amm_coin_vault.amount,
@@ -6343,20 +9269,44 @@ pub mod account_parser {
                     &amm_open_orders_info,
                 )?;
 
-            let args = IdleArgs {
+            let args = PlaceOrdersArgs {
                 program_id,
-                total_coin_without_take_pnl: &mut total_coin_without_take_pnl,
-                total_pc_without_take_pnl: &mut total_pc_without_take_pnl,
+                limit,
+
+                amm_authority_info,
+                amm_open_orders_info,
+                market_program_info,
+                market_info,
+                market_request_queue_info,
+                amm_coin_vault_info,
+                amm_pc_vault_info,
+                market_coin_vault_info,
+                market_pc_vault_info,
+                token_program_info,
+                rent_info,
+
+                market_event_queue_info,
+                market_bids_info,
+                market_asks_info,
+                srm_token_account,
+
                 amm,
+                open_orders: &open_orders,
                 bids: &bids,
                 asks: &asks,
                 target: &mut target,
+
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+
+                coin_vault_amount: amm_coin_vault.amount,
+                pc_vault_amount: amm_pc_vault.amount,
             };
             f(args)
         }
     }
 
-    pub struct CancelAllOrdersArgs<'a, 'b: 'a> {
+    pub struct PurgeOrderArgs<'a, 'b: 'a> {
         pub program_id: &'a Pubkey,
         pub limit: u16,
         pub market_program_info: &'a AccountInfo<'b>,
@@ -6364,38 +9314,98 @@ pub mod account_parser {
         pub amm_open_orders_info: &'a AccountInfo<'b>,
         pub amm_authority_info: &'a AccountInfo<'b>,
         pub market_event_queue_info: &'a AccountInfo<'b>,
-        pub market_coin_vault_info: &'a AccountInfo<'b>,
-        pub market_pc_vault_info: &'a AccountInfo<'b>,
         pub market_bids_info: &'a AccountInfo<'b>,
         pub market_asks_info: &'a AccountInfo<'b>,
-        pub market_vault_signer: &'a AccountInfo<'b>,
-        pub token_program_info: &'a AccountInfo<'b>,
-        pub amm_coin_vault_info: &'a AccountInfo<'b>,
-        pub amm_pc_vault_info: &'a AccountInfo<'b>,
+        pub amm: &'a mut AmmInfo,
+        pub target: &'a TargetOrders,
+        pub bids: &'a Vec<LeafNode>,
+        pub asks: &'a Vec<LeafNode>,
+    }
+    impl<'a, 'b: 'a> PurgeOrderArgs<'a, 'b> {
+        pub fn with_parsed_args(
+            program_id: &'a Pubkey,
+            _spl_token_program_id: &'a Pubkey,
+            limit: u16,
+            amm: &mut AmmInfo,
+            accounts: &'a [AccountInfo<'b>],
+            f: impl FnOnce(PurgeOrderArgs) -> ProgramResult,
+        ) -> ProgramResult {
+            if accounts.len() != 9 {
+                return Err(AmmError::WrongAccountsNumber.into());
+            }
+            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 9];
 
-        pub referrer_pc_account: Option<&'a AccountInfo<'b>>,
+            let target =
+                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
+            let (market_state, open_orders) = Processor::load_serum_market_order(
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                &amm,
+                true,
+            )?;
+            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
+            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
+            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+
+            let args = PurgeOrderArgs {
+                program_id,
+                limit,
+                market_program_info,
+                market_info,
+                amm_open_orders_info,
+                amm_authority_info,
+                market_event_queue_info,
+                market_bids_info,
+                market_asks_info,
+                amm,
+                target: &target,
+                bids: &bids,
+                asks: &asks,
+            };
+            f(args)
+        }
+    }
 
+    pub struct CancelOrderArgs<'a, 'b: 'a> {
+        pub program_id: &'a Pubkey,
+        pub limit: u16,
+        pub market_program_info: &'a AccountInfo<'b>,
+        pub market_info: &'a AccountInfo<'b>,
+        pub amm_open_orders_info: &'a AccountInfo<'b>,
+        pub amm_authority_info: &'a AccountInfo<'b>,
+        pub market_event_queue_info: &'a AccountInfo<'b>,
+        pub market_bids_info: &'a AccountInfo<'b>,
+        pub market_asks_info: &'a AccountInfo<'b>,
         pub amm: &'a mut AmmInfo,
         pub open_orders: &'a OpenOrders,
         pub target: &'a mut TargetOrders,
         pub bids: &'a Vec<LeafNode>,
         pub asks: &'a Vec<LeafNode>,
+
+        pub coin_amount: u64,
+        pub pc_amount: u64,
     }
-    impl<'a, 'b: 'a> CancelAllOrdersArgs<'a, 'b> {
+    impl<'a, 'b: 'a> CancelOrderArgs<'a, 'b> {
         pub fn with_parsed_args(
             program_id: &'a Pubkey,
+            spl_token_program_id: &'a Pubkey,
             limit: u16,
             amm: &mut AmmInfo,
             accounts: &'a [AccountInfo<'b>],
-            referrer_pc_wallet: Option<&'a AccountInfo<'b>>,
-            f: impl FnOnce(CancelAllOrdersArgs) -> ProgramResult,
+            f: impl FnOnce(CancelOrderArgs) -> ProgramResult,
         ) -> ProgramResult {
-            if accounts.len() != 15 {
+            if accounts.len() != 11 {
                 return Err(AmmError::WrongAccountsNumber.into());
             }
-            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_coin_vault_info, ref market_pc_vault_info, ref market_bids_info, ref market_asks_info, ref market_vault_signer, ref token_program_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 15];
+            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
+                array_ref![accounts, 0, 11];
 
+            let amm_coin_vault =
+                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
+            let amm_pc_vault =
+                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
             let mut target =
                 TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
             let (market_state, open_orders) = Processor::load_serum_market_order(
@@ -6405,11 +9415,12 @@ pub mod account_parser {
                 &amm,
                 true,
             )?;
+            // let (myorders, _max_bid, _min_ask) = Processor::get_amm_orders_and_dex_best_price(&market_state, &open_orders, bids_acc, asks_acc)?;
             let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
             let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
             let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
 
-            let args = CancelAllOrdersArgs {
+            let args = CancelOrderArgs {
                 program_id,
                 limit,
                 market_program_info,
@@ -6417,400 +9428,1620 @@ pub mod account_parser {
                 amm_open_orders_info,
                 amm_authority_info,
                 market_event_queue_info,
-                market_coin_vault_info,
-                market_pc_vault_info,
                 market_bids_info,
                 market_asks_info,
-                market_vault_signer,
-                token_program_info,
-                amm_coin_vault_info,
-                amm_pc_vault_info,
-                referrer_pc_account: referrer_pc_wallet,
                 amm,
                 open_orders: &open_orders,
                 target: &mut target,
                 bids: &bids,
                 asks: &asks,
+                coin_amount: amm_coin_vault.amount,
+                pc_amount: amm_pc_vault.amount,
             };
             f(args)
         }
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_calc_tack_pnl() {
+        let mut amm = AmmInfo::default();
+        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+        let mut target = TargetOrders::default();
+        target.calc_pnl_x = 900000000000000;
+        target.calc_pnl_y = 150000000000000000000000000;
+
+        let mut total_pc_without_take_pnl = 1343675125663;
+        let mut total_coin_without_take_pnl = 117837534493793;
+        let x1 = Calculator::normalize_decimal_v2(
+            total_pc_without_take_pnl,
+            amm.pc_decimals,
+            amm.sys_decimal_value,
+        );
+        let y1 = Calculator::normalize_decimal_v2(
+            total_coin_without_take_pnl,
+            amm.coin_decimals,
+            amm.sys_decimal_value,
+        );
+
+        let (delta_x, delta_y) = Processor::calc_take_pnl(
+            &target,
+            &mut amm,
+            &mut total_pc_without_take_pnl,
+            &mut total_coin_without_take_pnl,
+            x1.as_u128().into(),
+            y1.as_u128().into(),
+        )
+        .unwrap();
+        println!("delta_x:{}, delta_y:{}", delta_x, delta_y);
+    }
+
+    #[test]
+    fn test_swap_base_in() {
+        let amount_in = 212854295571_u64;
+        let total_coin_without_take_pnl = 77043918330755_u64;
+        let total_pc_without_take_pnl = 1511361338135_u64;
+
+        let swap_direction = SwapDirection::Coin2PC;
+        let mut amm = AmmInfo::default();
+        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+
+        let swap_fee = U128::from(amount_in)
+            .checked_mul(amm.fees.swap_fee_numerator.into())
+            .unwrap()
+            .checked_ceil_div(amm.fees.swap_fee_denominator.into())
+            .unwrap()
+            .0;
+
+        let swap_in_after_deduct_fee = U128::from(amount_in).checked_sub(swap_fee).unwrap();
+        let swap_amount_out = Calculator::swap_token_amount_base_in(
+            swap_in_after_deduct_fee,
+            total_pc_without_take_pnl.into(),
+            total_coin_without_take_pnl.into(),
+            swap_direction,
+        )
+        .as_u64();
+
+        println!("swap_amount_out:{}", swap_amount_out);
+    }
+
+    #[test]
+    fn test_get_swap_quote() {
+        let mut amm = AmmInfo::default();
+        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+        let total_coin_without_take_pnl = 77043918330755_u64;
+        let total_pc_without_take_pnl = 1511361338135_u64;
+
+        for swap_direction in [SwapDirection::Coin2PC, SwapDirection::PC2Coin] {
+            let (small_out, small_impact) = Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                1000000,
+                swap_direction,
+            )
+            .unwrap();
+            let (large_out, large_impact) = Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                100000000000000,
+                swap_direction,
+            )
+            .unwrap();
+            assert!(small_out > 0 && large_out > small_out);
+            assert!(small_impact < large_impact);
+        }
+        assert_eq!(
+            Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                0,
+                total_coin_without_take_pnl,
+                1,
+                SwapDirection::Coin2PC
+            ),
+            Err(AmmError::CheckedEmptyFunds)
+        );
+        // draining a thin coin side moves the price by far more than u64::MAX millionths
+        assert_eq!(
+            Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                1,
+                1_000_000,
+                1_000_000_000_000_000_000,
+                SwapDirection::PC2Coin
+            ),
+            Err(AmmError::CalculationFailure)
+        );
+        // around the peg a stable pool pays out more than the constant product one
+        let (product_out, _) = Processor::get_swap_quote(
+            &amm,
+            CurveType::ConstantProduct,
+            1_000_000_000,
+            1_000_000_000,
+            100_000_000,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        let (stable_out, _) = Processor::get_swap_quote(
+            &amm,
+            CurveType::Stable { amp: 100 },
+            1_000_000_000,
+            1_000_000_000,
+            100_000_000,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        assert!(stable_out > product_out && stable_out < 100_000_000);
+    }
+
+    #[test]
+    fn test_deposit_rounding_favors_pool() {
+        let total_coin_without_take_pnl = 1_000_003_u64;
+        let total_pc_without_take_pnl = 2_000_011_u64;
+        let lp_amount = 1_414_219_u64;
+        let max_coin_amount = 333_u64;
+
+        let deduct_pc_amount = deposit_pc_for_coin(
+            max_coin_amount,
+            total_coin_without_take_pnl,
+            total_pc_without_take_pnl,
+        )
+        .unwrap();
+        let invariant_coin = InvariantPool {
+            token_input: max_coin_amount,
+            token_total: total_coin_without_take_pnl,
+        };
+        let mint_lp_amount = invariant_coin
+            .exchange_token_to_pool(lp_amount, RoundDirection::Floor)
+            .unwrap();
+
+        // the user never pays less pc than the exact ratio requires
+        assert!(
+            u128::from(deduct_pc_amount) * u128::from(total_coin_without_take_pnl)
+                >= u128::from(max_coin_amount) * u128::from(total_pc_without_take_pnl)
+        );
+        // and never receives more lp than the exact share of the deposit
+        assert!(
+            u128::from(mint_lp_amount) * u128::from(total_coin_without_take_pnl)
+                <= u128::from(max_coin_amount) * u128::from(lp_amount)
+        );
+    }
+
+    #[test]
+    fn test_deposit_minimum_lp_after_ratio_shift() {
+        let total_coin_without_take_pnl = 1_000_003_u64;
+        let lp_amount = 1_414_219_u64;
+        let max_coin_amount = 333_333_u64;
+        let quote = InvariantPool {
+            token_input: max_coin_amount,
+            token_total: total_coin_without_take_pnl,
+        }
+        .exchange_token_to_pool(lp_amount, RoundDirection::Floor)
+        .unwrap();
+
+        // a deposit landing first keeps lp per coin, the quote is still honoured
+        let front_deposit = 500_000_u64;
+        let front_lp = InvariantPool {
+            token_input: front_deposit,
+            token_total: total_coin_without_take_pnl,
+        }
+        .exchange_token_to_pool(lp_amount, RoundDirection::Floor)
+        .unwrap();
+        let after_deposit = InvariantPool {
+            token_input: max_coin_amount,
+            token_total: total_coin_without_take_pnl + front_deposit,
+        }
+        .exchange_token_to_pool(lp_amount + front_lp, RoundDirection::Floor)
+        .unwrap();
+        assert!(after_deposit >= quote);
+
+        // a swap selling coin into the pool first dilutes lp per coin below the quote,
+        // so a deposit with minimum_lp_amount = quote reverts with ExceededSlippage
+        let front_swap_in = 100_000_u64;
+        let after_swap = InvariantPool {
+            token_input: max_coin_amount,
+            token_total: total_coin_without_take_pnl + front_swap_in,
+        }
+        .exchange_token_to_pool(lp_amount, RoundDirection::Floor)
+        .unwrap();
+        assert!(after_swap < quote);
+
+        // clients built before minimum_lp_amount existed keep working without a bound
+        let mut legacy = vec![3u8];
+        legacy.extend_from_slice(&max_coin_amount.to_le_bytes());
+        legacy.extend_from_slice(&u64::MAX.to_le_bytes());
+        legacy.extend_from_slice(&0u64.to_le_bytes());
+        match AmmInstruction::unpack(&legacy).unwrap() {
+            AmmInstruction::Deposit(deposit) => assert_eq!(deposit.minimum_lp_amount, 0),
+            _ => panic!("expected deposit"),
+        }
+    }
+
+    #[test]
+    fn test_check_lp_mint_authority() {
+        let amm_authority = Pubkey::new_unique();
+        let attacker = Pubkey::new_unique();
+        let mut lp_mint = spl_token::state::Mint {
+            mint_authority: COption::Some(amm_authority),
+            supply: 0,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Ok(())
+        );
+        lp_mint.freeze_authority = COption::Some(amm_authority);
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Ok(())
+        );
+
+        lp_mint.freeze_authority = COption::Some(attacker);
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Err(AmmError::InvalidLpMintAuthority)
+        );
+        lp_mint.freeze_authority = COption::None;
+        lp_mint.mint_authority = COption::Some(attacker);
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Err(AmmError::InvalidLpMintAuthority)
+        );
+        lp_mint.mint_authority = COption::None;
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Err(AmmError::InvalidLpMintAuthority)
+        );
+    }
+
+    #[test]
+    fn test_swap_invariant_trips_on_broken_math() {
+        let mut amm = AmmInfo::default();
+        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+        let (total_coin, total_pc) = (77043918330755_u64, 1511361338135_u64);
+        let amount_in = 212854295571_u64;
+        let swap_fee = amm.fees.trading_fee(amount_in.into()).unwrap() as u64;
+        let protocol_fee = Processor::calc_protocol_fee(&amm, swap_fee).unwrap();
+        let amount_out = Calculator::swap_token_amount_base_in(
+            U128::from(amount_in - swap_fee),
+            total_pc.into(),
+            total_coin.into(),
+            SwapDirection::Coin2PC,
+        )
+        .as_u64();
+        assert_eq!(
+            Processor::check_swap_invariant(
+                CurveType::ConstantProduct,
+                SwapDirection::Coin2PC,
+                total_coin,
+                total_pc,
+                amount_in,
+                amount_out,
+                protocol_fee,
+            ),
+            Ok(())
+        );
+
+        // dropping the fee and rounding the output up instead of down leaks value
+        let broken_amount_out = Calculator::swap_token_amount_base_in(
+            U128::from(amount_in),
+            total_pc.into(),
+            total_coin.into(),
+            SwapDirection::Coin2PC,
+        )
+        .as_u64()
+            + 1;
+        assert_eq!(
+            Processor::check_swap_invariant(
+                CurveType::ConstantProduct,
+                SwapDirection::Coin2PC,
+                total_coin,
+                total_pc,
+                amount_in,
+                broken_amount_out,
+                0,
+            ),
+            Err(AmmError::CalculationFailure)
+        );
+        // a stable swap pays out more than x * y = k allows, it is checked against d instead
+        let stable = CurveType::Stable { amp: 100 };
+        let stable_out = stable
+            .swap_base_in_out(
+                amount_in.into(),
+                total_coin.into(),
+                total_pc.into(),
+                amm.fees.swap_fee_numerator,
+                amm.fees.swap_fee_denominator,
+            )
+            .unwrap()
+            .dest_amount as u64;
+        assert_eq!(
+            Processor::check_swap_invariant(
+                stable,
+                SwapDirection::Coin2PC,
+                total_coin,
+                total_pc,
+                amount_in,
+                stable_out,
+                protocol_fee,
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_swap_invariant(
+                CurveType::ConstantProduct,
+                SwapDirection::Coin2PC,
+                total_coin,
+                total_pc,
+                amount_in,
+                stable_out,
+                protocol_fee,
+            ),
+            Err(AmmError::CalculationFailure)
+        );
+        assert_eq!(
+            Processor::assert_invariant(U256::from(10), U256::from(9)),
+            Err(AmmError::CalculationFailure)
+        );
+        assert_eq!(
+            Processor::assert_invariant(U256::from(9), U256::from(9)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_liquidity_invariant_trips_on_broken_math() {
+        let (coin, pc, lp) = (4_000_000_u64, 2_000_000_u64, 2_828_427_u64);
+        let deposit_coin = 1_000_000_u64;
+        let deposit_pc = InvariantPool {
+            token_input: deposit_coin,
+            token_total: coin,
+        }
+        .exchange_token_to_pool(pc, RoundDirection::Ceiling)
+        .unwrap();
+        let mint_lp = InvariantPool {
+            token_input: deposit_coin,
+            token_total: coin,
+        }
+        .exchange_token_to_pool(lp, RoundDirection::Floor)
+        .unwrap();
+        let deposit = |mint_lp: u64| {
+            Processor::check_liquidity_invariant(
+                coin.into(),
+                pc.into(),
+                lp,
+                u128::from(coin + deposit_coin),
+                u128::from(pc + deposit_pc),
+                lp + mint_lp,
+            )
+        };
+        assert_eq!(deposit(mint_lp), Ok(()));
+        // k grows with any deposit, only the per lp share catches an over mint
+        assert_eq!(deposit(mint_lp + 1), Err(AmmError::CalculationFailure));
+
+        let withdraw_lp = 1_000_000_u64;
+        let withdraw = |round_direction: RoundDirection| {
+            let share = InvariantPool {
+                token_input: withdraw_lp,
+                token_total: lp,
+            };
+            Processor::check_liquidity_invariant(
+                coin.into(),
+                pc.into(),
+                lp,
+                u128::from(coin - share.exchange_pool_to_token(coin, round_direction).unwrap()),
+                u128::from(pc - share.exchange_pool_to_token(pc, round_direction).unwrap()),
+                lp - withdraw_lp,
+            )
+        };
+        assert_eq!(withdraw(RoundDirection::Floor), Ok(()));
+        // rounding the payouts up instead of down leaks value to the withdrawer
+        assert_eq!(
+            withdraw(RoundDirection::Ceiling),
+            Err(AmmError::CalculationFailure)
+        );
+    }
+
+    #[test]
+    fn test_initial_lp_locks_minimum_liquidity() {
+        // a tiny first deposit can not cover the lock
+        let tiny_liquidity = integer_sqrt(30 * 30) as u64;
+        assert_eq!(
+            Processor::initial_user_lp_amount(tiny_liquidity, 0),
+            Err(AmmError::InitLpAmountTooLess)
+        );
+        assert_eq!(
+            Processor::initial_user_lp_amount(MINIMUM_LIQUIDITY, 0),
+            Err(AmmError::InitLpAmountTooLess)
+        );
+        assert_eq!(
+            Processor::initial_user_lp_amount(MINIMUM_LIQUIDITY + 1, 0),
+            Ok(1)
+        );
+        // mints with more decimals lock 10^decimals
+        assert_eq!(
+            Processor::initial_user_lp_amount(1_000_000, 6),
+            Err(AmmError::InitLpAmountTooLess)
+        );
+        assert_eq!(Processor::initial_user_lp_amount(1_000_001, 6), Ok(1));
+
+        // a second depositor matching the first one gets a share of the whole supply,
+        // locked part included
+        let (coin, pc) = (4_000_000u64, 9_000_000u64);
+        let liquidity = integer_sqrt(u128::from(coin) * u128::from(pc)) as u64;
+        let first_lp = Processor::initial_user_lp_amount(liquidity, 0).unwrap();
+        assert_eq!(first_lp, liquidity - MINIMUM_LIQUIDITY);
+        let second_lp = InvariantPool {
+            token_input: coin,
+            token_total: coin,
+        }
+        .exchange_token_to_pool(liquidity, RoundDirection::Floor)
+        .unwrap();
+        assert_eq!(second_lp, liquidity);
+        // so withdrawing first_lp of the doubled pool returns less than the first deposit
+        let first_out = InvariantPool {
+            token_input: first_lp,
+            token_total: liquidity + second_lp,
+        }
+        .exchange_pool_to_token(2 * coin, RoundDirection::Floor)
+        .unwrap();
+        assert!(first_out < coin);
+    }
+
+    #[test]
+    fn test_close_pool_requires_empty_pool() {
+        let mut amm = AmmInfo::default();
+        assert_eq!(Processor::check_pool_empty(&amm, 0, 0, 0), Ok(()));
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 0, 0, 1),
+            Err(AmmError::PoolNotEmpty)
+        );
+        // every lp burned but the reserves of the locked liquidity are still in the vaults
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 1_000, 10_000, 0),
+            Err(AmmError::PoolNotEmpty)
+        );
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 1, 0, 0),
+            Err(AmmError::PoolNotEmpty)
+        );
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 0, 1, 0),
+            Err(AmmError::PoolNotEmpty)
+        );
+
+        // the uncollected pnl is swept on close, anything above it is not
+        amm.state_data.need_take_pnl_coin = 5;
+        amm.state_data.need_take_pnl_pc = 7;
+        assert_eq!(Processor::check_pool_empty(&amm, 5, 7, 0), Ok(()));
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 6, 7, 0),
+            Err(AmmError::PoolNotEmpty)
+        );
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 5, 8, 0),
+            Err(AmmError::PoolNotEmpty)
+        );
+        assert_eq!(
+            Processor::check_pool_empty(&amm, 5, 7, 1),
+            Err(AmmError::PoolNotEmpty)
+        );
+    }
+
+    #[test]
+    fn test_batch_swap_quote() {
+        let mut amm = AmmInfo::default();
+        amm.fees.initialize().unwrap();
+        let amounts_in = [0, 1_000, 10_000, 100_000, 1_000_000];
+        let amounts_out = Processor::batch_swap_quote(
+            &amm,
+            CurveType::ConstantProduct,
+            4_000_000,
+            2_000_000,
+            &amounts_in,
+            SwapDirection::Coin2PC,
+        )
+        .unwrap();
+        assert_eq!(amounts_out.len(), amounts_in.len());
+        // each quote is the single quote of its amount, not a sequence of swaps
+        for (amount_in, amount_out) in amounts_in.iter().zip(&amounts_out) {
+            let (single, _) = Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                4_000_000,
+                2_000_000,
+                *amount_in,
+                SwapDirection::Coin2PC,
+            )
+            .unwrap();
+            assert_eq!(*amount_out, single);
+        }
+        assert_eq!(amounts_out[0], 0);
+        assert!(amounts_out.windows(2).all(|pair| pair[0] < pair[1]));
+        // larger sizes get a worse price
+        assert!(amounts_out[4] < amounts_out[1] * 1_000);
+        assert!(Processor::batch_swap_quote(
+            &amm,
+            CurveType::ConstantProduct,
+            0,
+            2_000_000,
+            &[1],
+            SwapDirection::PC2Coin
+        )
+        .is_err());
+        // one oversized amount fails the whole batch instead of panicking
+        assert_eq!(
+            Processor::batch_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                4_000_000,
+                2_000_000,
+                &[1_000, u64::MAX],
+                SwapDirection::PC2Coin,
+            ),
+            Err(AmmError::CheckedAddOverflow)
+        );
+
+        let batch = AmmInstruction::BatchQuote(BatchQuoteInstruction {
+            direction: TradeDirection::CoinToPc,
+            amounts_in: amounts_in.to_vec(),
+        });
+        let data = batch.pack().unwrap();
+        assert_eq!(data.len(), 1 + 1 + 1 + 8 * amounts_in.len());
+        assert_eq!(AmmInstruction::unpack(&data).unwrap(), batch);
+        assert!(AmmInstruction::unpack(&data[..data.len() - 1]).is_err());
+        let too_many = AmmInstruction::BatchQuote(BatchQuoteInstruction {
+            direction: TradeDirection::CoinToPc,
+            amounts_in: vec![1; crate::instruction::MAX_BATCH_QUOTE_AMOUNTS + 1],
+        });
+        assert!(too_many.pack().is_err());
+        assert!(AmmInstruction::unpack(&[32, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_get_amount_out_matches_processor() {
+        use crate::math::get_amount_out;
+
+        let mut amm = AmmInfo::default();
+        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+        let total_coin_without_take_pnl = 77043918330755_u64;
+        let total_pc_without_take_pnl = 1511361338135_u64;
+        for amount_in in [0u64, 1, 399, 212854295571, u64::MAX / 4] {
+            let (coin_to_pc, _) = Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                amount_in,
+                SwapDirection::Coin2PC,
+            )
+            .unwrap();
+            assert_eq!(
+                get_amount_out(
+                    amount_in,
+                    total_coin_without_take_pnl,
+                    total_pc_without_take_pnl,
+                    amm.fees.swap_fee_numerator,
+                    amm.fees.swap_fee_denominator,
+                ),
+                Ok(coin_to_pc)
+            );
+            let (pc_to_coin, _) = Processor::get_swap_quote(
+                &amm,
+                CurveType::ConstantProduct,
+                total_pc_without_take_pnl,
+                total_coin_without_take_pnl,
+                amount_in,
+                SwapDirection::PC2Coin,
+            )
+            .unwrap();
+            assert_eq!(
+                get_amount_out(
+                    amount_in,
+                    total_pc_without_take_pnl,
+                    total_coin_without_take_pnl,
+                    amm.fees.swap_fee_numerator,
+                    amm.fees.swap_fee_denominator,
+                ),
+                Ok(pc_to_coin)
+            );
+        }
+        assert_eq!(
+            get_amount_out(1, 0, 1, 25, 10000),
+            Err(AmmError::CheckedEmptyFunds)
+        );
+        assert_eq!(get_amount_out(1, 1, 1, 25, 0), Err(AmmError::InvalidFee));
+    }
+
+    #[test]
+    fn test_check_vault_accounts_rejects_substituted_vaults() {
+        let mut amm = AmmInfo::default();
+        amm.coin_vault = Pubkey::new_unique();
+        amm.pc_vault = Pubkey::new_unique();
+        let (user_coin, user_pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let attacker_vault = Pubkey::new_unique();
+        assert_eq!(
+            Processor::check_vault_accounts(
+                &amm,
+                &amm.coin_vault,
+                &amm.pc_vault,
+                &user_coin,
+                &user_pc,
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_vault_accounts(
+                &amm,
+                &attacker_vault,
+                &amm.pc_vault,
+                &user_coin,
+                &user_pc,
+            ),
+            Err(AmmError::InvalidCoinVault)
+        );
+        assert_eq!(
+            Processor::check_vault_accounts(
+                &amm,
+                &amm.coin_vault,
+                &attacker_vault,
+                &user_coin,
+                &user_pc,
+            ),
+            Err(AmmError::InvalidPCVault)
+        );
+        // paying the vault back to itself is rejected too
+        assert_eq!(
+            Processor::check_vault_accounts(
+                &amm,
+                &amm.coin_vault,
+                &amm.pc_vault,
+                &user_coin,
+                &amm.pc_vault,
+            ),
+            Err(AmmError::InvalidPCVault)
+        );
+    }
 
-    pub struct PlanOrderBookArgs<'a> {
-        pub program_id: &'a Pubkey,
-        pub limit: u16,
-        pub total_coin_without_take_pnl: u64,
-        pub total_pc_without_take_pnl: u64,
-        pub amm: &'a mut AmmInfo,
-        pub target: &'a mut TargetOrders,
+    #[test]
+    fn test_check_swap_whitelist() {
+        let mut whitelist: SwapWhitelist = bytemuck::Zeroable::zeroed();
+        let (listed, unlisted) = (Pubkey::new_unique(), Pubkey::new_unique());
+        whitelist.add(&listed).unwrap();
+        assert_eq!(Processor::check_swap_whitelist(&whitelist, &listed), Ok(()));
+        assert_eq!(
+            Processor::check_swap_whitelist(&whitelist, &unlisted),
+            Err(AmmError::NotWhitelisted)
+        );
+        whitelist.remove(&listed);
+        assert_eq!(
+            Processor::check_swap_whitelist(&whitelist, &listed),
+            Err(AmmError::NotWhitelisted)
+        );
     }
-    impl<'a> PlanOrderBookArgs<'a> {
-        pub fn with_parsed_args(
-            program_id: &'a Pubkey,
-            spl_token_program_id: &'a Pubkey,
-            limit: u16,
-            amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo],
-            f: impl FnOnce(PlanOrderBookArgs) -> ProgramResult,
-        ) -> ProgramResult {
-            if accounts.len() != 8 {
-                return Err(AmmError::WrongAccountsNumber.into());
-            }
-            let &[ref amm_info, ref market_info, ref market_event_queue_info, ref amm_authority_info, ref amm_open_orders_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 8];
 
-            let amm_coin_vault =
-                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
-            let amm_pc_vault =
-                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-            let mut target =
-                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-            let (market_state, open_orders) = Processor::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                false,
-            )?;
-            let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
+    #[test]
+    fn test_protocol_fees_flow_to_fee_recipient() {
+        let mut amm_config = AmmConfig::default();
+        amm_config.pnl_owner = Pubkey::new_unique();
+        let pnl_owner = amm_config.pnl_owner;
+        // configs created before fee_recipient keep paying the pnl owner
+        assert_eq!(amm_config.fee_recipient(), pnl_owner);
+        assert_eq!(
+            Processor::check_fee_recipient(&amm_config, &pnl_owner, &pnl_owner),
+            Ok(())
+        );
 
-            let args = PlanOrderBookArgs {
-                program_id,
-                limit,
-                total_coin_without_take_pnl,
-                total_pc_without_take_pnl,
-                amm,
-                target: &mut target,
-            };
-            f(args)
+        let treasury = Pubkey::new_unique();
+        amm_config.fee_recipient = treasury;
+        assert_eq!(
+            Processor::check_fee_recipient(&amm_config, &treasury, &treasury),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_fee_recipient(&amm_config, &pnl_owner, &pnl_owner),
+            Err(AmmError::InvalidOwner)
+        );
+        assert_eq!(
+            Processor::check_fee_recipient(&amm_config, &treasury, &pnl_owner),
+            Err(AmmError::InvalidOwner)
+        );
+    }
+
+    #[test]
+    fn test_check_lp_supply_trips_on_external_mint() {
+        let mut amm = AmmInfo::default();
+        let liquidity = 1_414_213_u64;
+        amm.lp_amount = liquidity;
+        let supply = Processor::initial_user_lp_amount(liquidity, 6).unwrap();
+        assert_eq!(Processor::check_lp_supply(&amm, supply), Ok(()));
+        // holders burning their lp only donate to the pool
+        assert_eq!(Processor::check_lp_supply(&amm, supply - 10), Ok(()));
+        assert_eq!(Processor::check_lp_supply(&amm, liquidity), Ok(()));
+        // lp minted outside the pool would claim the locked liquidity and more
+        assert_eq!(
+            Processor::check_lp_supply(&amm, liquidity + 1),
+            Err(AmmError::LpSupplyMismatch)
+        );
+        assert_eq!(
+            Processor::check_lp_supply(&amm, u64::MAX),
+            Err(AmmError::LpSupplyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_amm_pdas_match_program_checks() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let coin_mint = Pubkey::new_unique();
+        let pc_mint = Pubkey::new_unique();
+        let pdas = get_amm_pdas(&program_id, &market, &coin_mint, &pc_mint);
+        assert_eq!(
+            Processor::authority_id(&program_id, AUTHORITY_AMM, pdas.nonce),
+            Ok(pdas.amm_authority)
+        );
+        assert_eq!(
+            pdas.amm_pool,
+            derive_pool_address(&program_id, &market, &coin_mint, &pc_mint).0
+        );
+        // every account of a pool is distinct, the pool is tied to its pair and market and the
+        // rest to its market
+        let other = get_amm_pdas(
+            &program_id,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &pc_mint,
+        );
+        assert_ne!(pdas.amm_pool, other.amm_pool);
+        assert_ne!(pdas.amm_lp_mint, other.amm_lp_mint);
+        assert_eq!(pdas.amm_authority, other.amm_authority);
+        assert_eq!(pdas.amm_config, other.amm_config);
+        let keys = [
+            pdas.amm_pool,
+            pdas.amm_open_orders,
+            pdas.amm_target_orders,
+            pdas.amm_coin_vault,
+            pdas.amm_pc_vault,
+            pdas.amm_lp_mint,
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key));
         }
     }
 
-    pub struct PlaceOrdersArgs<'a, 'b: 'a> {
-        pub program_id: &'a Pubkey,
-        pub limit: u16,
+    #[test]
+    fn test_pool_address_ignores_mint_order() {
+        let program_id = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let coin_mint = Pubkey::new_unique();
+        let pc_mint = Pubkey::new_unique();
+        let pool = derive_pool_address(&program_id, &market, &coin_mint, &pc_mint);
+        assert_eq!(
+            derive_pool_address(&program_id, &market, &pc_mint, &coin_mint),
+            pool
+        );
+        assert_eq!(
+            Pubkey::create_program_address(
+                &[
+                    &program_id.to_bytes(),
+                    &market.to_bytes(),
+                    &coin_mint.min(pc_mint).to_bytes(),
+                    &coin_mint.max(pc_mint).to_bytes(),
+                    AMM_ASSOCIATED_SEED,
+                    &[pool.1],
+                ],
+                &program_id
+            ),
+            Ok(pool.0)
+        );
+        assert_eq!(
+            get_amm_pdas(&program_id, &market, &pc_mint, &coin_mint).amm_pool,
+            pool.0
+        );
+        // a pool squatted on another market of the pair does not take this address
+        assert_ne!(
+            derive_pool_address(&program_id, &Pubkey::new_unique(), &coin_mint, &pc_mint),
+            pool
+        );
+        assert_ne!(
+            derive_pool_address(&program_id, &market, &coin_mint, &Pubkey::new_unique()),
+            pool
+        );
+        assert_ne!(
+            derive_pool_address(&Pubkey::new_unique(), &market, &coin_mint, &pc_mint),
+            pool
+        );
+    }
 
-        pub amm_authority_info: &'a AccountInfo<'b>,
-        pub amm_open_orders_info: &'a AccountInfo<'b>,
-        pub market_program_info: &'a AccountInfo<'b>,
-        pub market_info: &'a AccountInfo<'b>,
-        pub market_request_queue_info: &'a AccountInfo<'b>,
-        pub amm_coin_vault_info: &'a AccountInfo<'b>,
-        pub amm_pc_vault_info: &'a AccountInfo<'b>,
-        pub market_coin_vault_info: &'a AccountInfo<'b>,
-        pub market_pc_vault_info: &'a AccountInfo<'b>,
-        pub token_program_info: &'a AccountInfo<'b>,
-        pub rent_info: &'a AccountInfo<'b>,
+    #[test]
+    fn test_initialize_rejects_identical_mints() {
+        let (coin_mint, pc_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(
+            Processor::check_distinct_mints(&coin_mint, &pc_mint),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_distinct_mints(&pc_mint, &coin_mint),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_distinct_mints(&coin_mint, &coin_mint),
+            Err(AmmError::IdenticalMints)
+        );
+    }
 
-        pub market_event_queue_info: &'a AccountInfo<'b>,
-        pub market_bids_info: &'a AccountInfo<'b>,
-        pub market_asks_info: &'a AccountInfo<'b>,
+    #[test]
+    fn test_swap_discount_fees() {
+        let mut fees = Fees::default();
+        fees.initialize().unwrap();
+        let swapper = Pubkey::new_unique();
+        let mut amm_config = AmmConfig::default();
+        amm_config.discount_mint = Pubkey::new_unique();
+        amm_config.discount_fee_numerator = 10;
+        amm_config.discount_fee_denominator = TEN_THOUSAND;
+        let mut discount_account = spl_token::state::Account {
+            mint: amm_config.discount_mint,
+            owner: swapper,
+            amount: 1,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
 
-        pub srm_token_account: Option<&'a AccountInfo<'b>>,
+        // full fee without the discount accounts, discounted fee for a holder
+        assert_eq!(fees.trading_fee(1_000_000), Some(2_500));
+        let discounted =
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &swapper).unwrap();
+        assert_eq!(discounted.trading_fee(1_000_000), Some(1_000));
+        assert_eq!(
+            discounted.min_separate_numerator,
+            fees.min_separate_numerator
+        );
 
-        pub amm: &'a mut AmmInfo,
-        pub open_orders: &'a OpenOrders,
-        pub bids: &'a Vec<LeafNode>,
-        pub asks: &'a Vec<LeafNode>,
-        pub target: &'a mut TargetOrders,
+        // a discount above the pool fee keeps the pool fee
+        amm_config.discount_fee_numerator = 30;
+        assert_eq!(
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &swapper),
+            Ok(fees)
+        );
+        amm_config.discount_fee_numerator = 10;
 
-        pub total_coin_without_take_pnl: u64,
-        pub total_pc_without_take_pnl: u64,
-        pub coin_vault_amount: u64,
-        pub pc_vault_amount: u64,
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &other),
+            Err(AmmError::InvalidDiscountAccount)
+        );
+        discount_account.amount = 0;
+        assert_eq!(
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &swapper),
+            Err(AmmError::InvalidDiscountAccount)
+        );
+        discount_account.amount = 1;
+        discount_account.mint = other;
+        assert_eq!(
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &swapper),
+            Err(AmmError::InvalidDiscountAccount)
+        );
+        // an unset discount mint matches no holding
+        amm_config.discount_mint = Pubkey::default();
+        discount_account.mint = Pubkey::default();
+        assert_eq!(
+            Processor::discounted_fees(&fees, &amm_config, &discount_account, &swapper),
+            Err(AmmError::InvalidDiscountAccount)
+        );
     }
-    impl<'a, 'b: 'a> PlaceOrdersArgs<'a, 'b> {
-        pub fn with_parsed_args(
-            program_id: &'a Pubkey,
-            spl_token_program_id: &'a Pubkey,
-            limit: u16,
-            amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo<'b>],
-            srm_token_account: Option<&'a AccountInfo<'b>>,
-            f: impl FnOnce(PlaceOrdersArgs) -> ProgramResult,
-        ) -> ProgramResult {
-            if accounts.len() != 17 {
-                return Err(AmmError::WrongAccountsNumber.into());
-            }
-            let accounts = array_ref![accounts, 0, 17];
-            let (new_orders_accounts, data_accounts) = array_refs![accounts, 15, 2];
-            let &[ref amm_info, ref amm_authority_info, ref amm_open_orders_info, ref market_program_info, ref market_info, ref market_request_queue_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref market_coin_vault_info, ref market_pc_vault_info, ref token_program_info, ref rent_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info] =
-                array_ref![new_orders_accounts, 0, 15];
-            let &[ref amm_target_orders_info, ref _clock_info] = array_ref![data_accounts, 0, 2];
 
-            let amm_coin_vault =
-                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
-            let amm_pc_vault =
-                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-            let mut target =
-                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-            let (market_state, open_orders) = Processor::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                false,
-            )?;
-            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
-            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
-            let (total_pc_without_take_pnl, total_coin_without_take_pnl) =
-                Calculator::calc_total_without_take_pnl(
-                    amm_pc_vault.amount,
-                    amm_coin_vault.amount,
-                    &open_orders,
-                    &amm,
-                    &market_state,
-                    &market_event_queue_info,
-                    &amm_open_orders_info,
-                )?;
+    #[test]
+    fn test_stated_swap_direction_must_match_accounts() {
+        let (coin_mint, pc_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        for stated in [None, Some(TradeDirection::CoinToPc)] {
+            assert_eq!(
+                Processor::swap_direction(&coin_mint, &pc_mint, &coin_mint, &pc_mint, stated),
+                Ok(SwapDirection::Coin2PC)
+            );
+        }
+        for stated in [None, Some(TradeDirection::PcToCoin)] {
+            assert_eq!(
+                Processor::swap_direction(&pc_mint, &coin_mint, &coin_mint, &pc_mint, stated),
+                Ok(SwapDirection::PC2Coin)
+            );
+        }
+        // user accounts passed the other way round than the stated direction
+        assert_eq!(
+            Processor::swap_direction(
+                &pc_mint,
+                &coin_mint,
+                &coin_mint,
+                &pc_mint,
+                Some(TradeDirection::CoinToPc)
+            ),
+            Err(AmmError::TradeDirectionMismatch)
+        );
+        assert_eq!(
+            Processor::swap_direction(
+                &coin_mint,
+                &pc_mint,
+                &coin_mint,
+                &pc_mint,
+                Some(TradeDirection::PcToCoin)
+            ),
+            Err(AmmError::TradeDirectionMismatch)
+        );
+        assert_eq!(
+            Processor::swap_direction(&coin_mint, &coin_mint, &coin_mint, &pc_mint, None),
+            Err(AmmError::InvalidUserToken)
+        );
 
-            let args = PlaceOrdersArgs {
-                program_id,
-                limit,
+        // the direction is a single trailing byte, older encodings still unpack
+        let swap = SwapInstructionBaseOut {
+            max_amount_in: 10,
+            amount_out: 5,
+            direction: Some(TradeDirection::PcToCoin),
+        };
+        let data = AmmInstruction::SwapBaseOut(swap).pack().unwrap();
+        assert_eq!(data.len(), 1 + 8 + 8 + 1);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::SwapBaseOut(swap)
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&data[..data.len() - 1]).unwrap(),
+            AmmInstruction::SwapBaseOut(SwapInstructionBaseOut {
+                direction: None,
+                ..swap
+            })
+        );
+        let mut data = data;
+        *data.last_mut().unwrap() = 2;
+        assert!(AmmInstruction::unpack(&data).is_err());
+    }
 
-                amm_authority_info,
-                amm_open_orders_info,
-                market_program_info,
-                market_info,
-                market_request_queue_info,
-                amm_coin_vault_info,
-                amm_pc_vault_info,
-                market_coin_vault_info,
-                market_pc_vault_info,
-                token_program_info,
-                rent_info,
+    #[test]
+    fn test_unpack_rejects_truncated_data() {
+        let swap = SwapInstructionBaseIn {
+            amount_in: 10,
+            minimum_amount_out: 5,
+            max_impact_bps: 100,
+            direction: Some(TradeDirection::CoinToPc),
+        };
+        let data = AmmInstruction::SwapBaseIn(swap).pack().unwrap();
+        assert_eq!(data.len(), 1 + 8 + 8 + 8 + 1);
+        // cut inside amount_in, minimum_amount_out or max_impact_bps, only the optional
+        // fields may be left out whole
+        for len in (0..1 + 8 + 8).chain(1 + 8 + 8 + 1..1 + 8 + 8 + 8) {
+            assert_eq!(
+                AmmInstruction::unpack(&data[..len]),
+                Err(ProgramError::InvalidInstructionData),
+                "{} bytes",
+                len
+            );
+        }
+        assert!(AmmInstruction::unpack(&data[..1 + 8 + 8]).is_ok());
 
-                market_event_queue_info,
-                market_bids_info,
-                market_asks_info,
-                srm_token_account,
+        // a param no instruction has is an error, not a panic
+        let mut set_params = vec![6, 200];
+        set_params.extend_from_slice(&1u64.to_le_bytes());
+        assert_eq!(
+            AmmInstruction::unpack(&set_params),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&[12, 9]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        // and so is a short pubkey
+        assert_eq!(
+            AmmInstruction::unpack(&[15, 0, 1, 2, 3]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
 
-                amm,
-                open_orders: &open_orders,
-                bids: &bids,
-                asks: &asks,
-                target: &mut target,
+    #[test]
+    fn test_lp_mint_with_metadata_pointer() {
+        use spl_token_2022::extension::{
+            metadata_pointer::MetadataPointer, BaseStateWithExtensions, StateWithExtensionsMut,
+        };
+
+        let amm_authority = Pubkey::new_unique();
+        let metadata = Pubkey::new_unique();
+        let extension = LpMintExtension::MetadataPointer {
+            authority: Some(amm_authority),
+            metadata_address: Some(metadata),
+        };
+        assert_eq!(
+            Processor::check_lp_mint_extension(&extension, &amm_authority),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_lp_mint_extension(
+                &LpMintExtension::MetadataPointer {
+                    authority: None,
+                    metadata_address: Some(metadata),
+                },
+                &amm_authority
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_lp_mint_extension(
+                &LpMintExtension::MetadataPointer {
+                    authority: Some(Pubkey::new_unique()),
+                    metadata_address: Some(metadata),
+                },
+                &amm_authority
+            ),
+            Err(AmmError::InvalidLpMintAuthority)
+        );
+
+        // lay out the lp mint the way initialize2 sizes and initializes it
+        assert_eq!(
+            Processor::lp_mint_len(None).unwrap(),
+            spl_token::state::Mint::LEN
+        );
+        let mut data = vec![0u8; Processor::lp_mint_len(Some(&extension)).unwrap()];
+        let mut state =
+            StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data)
+                .unwrap();
+        state.init_extension::<MetadataPointer>(true).unwrap();
+        state.base = spl_token_2022::state::Mint {
+            mint_authority: COption::Some(amm_authority),
+            supply: 0,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+
+        assert!(spl_token::state::Mint::unpack(&data).is_err());
+        let lp_mint = Processor::unpack_token_2022_mint(&data).unwrap();
+        assert_eq!(lp_mint.decimals, 6);
+        assert_eq!(
+            Processor::check_lp_mint_authority(&lp_mint, &amm_authority),
+            Ok(())
+        );
+        let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data).unwrap();
+        assert!(state.get_extension::<MetadataPointer>().is_ok());
+
+        // the extension trails the initialize2 data, plain pools keep the old encoding
+        let init = InitializeInstruction2 {
+            nonce: 254,
+            open_time: 0,
+            init_pc_amount: 10,
+            init_coin_amount: 20,
+            lp_mint_extension: Some(extension),
+            price_bound: None,
+            lp_decimals: None,
+            without_open_orders: false,
+            stable_amp: None,
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 64);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&data[..1 + 1 + 8 * 3]).unwrap(),
+            AmmInstruction::Initialize2(InitializeInstruction2 {
+                lp_mint_extension: None,
+                ..init
+            })
+        );
+    }
+
+    #[test]
+    fn test_authority_nonce_must_be_canonical() {
+        let program_id = Pubkey::new_unique();
+        let (authority, nonce) = Pubkey::find_program_address(&[AUTHORITY_AMM], &program_id);
+        assert_eq!(
+            Processor::check_authority_nonce(&program_id, &authority, nonce),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::authority_id(&program_id, AUTHORITY_AMM, nonce),
+            Ok(authority)
+        );
+
+        // any other bump that is off the curve derives a different authority
+        let other = (0..nonce)
+            .rev()
+            .find_map(|bump| {
+                Processor::authority_id(&program_id, AUTHORITY_AMM, bump)
+                    .ok()
+                    .map(|authority| (bump, authority))
+            })
+            .unwrap();
+        assert_ne!(other.1, authority);
+        assert_eq!(
+            Processor::check_authority_nonce(&program_id, &other.1, other.0),
+            Err(AmmError::InvalidProgramAddress)
+        );
+        assert_eq!(
+            Processor::check_authority_nonce(&program_id, &authority, other.0),
+            Err(AmmError::InvalidProgramAddress)
+        );
+        assert_eq!(
+            Processor::check_authority_nonce(&program_id, &Pubkey::new_unique(), nonce),
+            Err(AmmError::InvalidProgramAddress)
+        );
+    }
+
+    #[test]
+    fn test_init_price_bound() {
+        // 1 ui coin at 25.5 ui pc, coin with 9 decimals and pc with 6 decimals
+        let bound = InitPriceBound {
+            expected_price: 25_500_000_000_000,
+            price_tolerance_bps: 100,
+        };
+        assert_eq!(
+            Processor::check_init_price_bound(&bound, 2_000_000_000, 51_000_000, 9, 6),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_init_price_bound(&bound, 2_000_000_000, 51_500_000, 9, 6),
+            Ok(())
+        );
+        assert_eq!(
+            Processor::check_init_price_bound(&bound, 2_000_000_000, 51_600_000, 9, 6),
+            Err(AmmError::PriceOutOfBounds)
+        );
+        // amounts given with the decimals of the other mint
+        assert_eq!(
+            Processor::check_init_price_bound(&bound, 2_000_000, 51_000_000_000, 9, 6),
+            Err(AmmError::PriceOutOfBounds)
+        );
+        assert_eq!(
+            Processor::check_init_price_bound(&bound, 0, 51_000_000, 9, 6),
+            Err(AmmError::InvalidInput)
+        );
+        assert_eq!(
+            Processor::check_init_price_bound(
+                &InitPriceBound {
+                    expected_price: 0,
+                    price_tolerance_bps: 100,
+                },
+                2_000_000_000,
+                51_000_000,
+                9,
+                6
+            ),
+            Err(AmmError::InvalidInput)
+        );
+
+        // the bound trails the lp mint extension, either one may be left out
+        let init = InitializeInstruction2 {
+            nonce: 254,
+            open_time: 0,
+            init_pc_amount: 51_000_000,
+            init_coin_amount: 2_000_000_000,
+            lp_mint_extension: Some(LpMintExtension::MetadataPointer {
+                authority: None,
+                metadata_address: Some(Pubkey::new_unique()),
+            }),
+            price_bound: Some(bound),
+            lp_decimals: None,
+            without_open_orders: false,
+            stable_amp: None,
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 64 + 1 + 16 + 8);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        let init = InitializeInstruction2 {
+            lp_mint_extension: None,
+            ..init
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 16 + 8);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        // an option may only be given once
+        let mut twice = data.clone();
+        twice.extend_from_slice(&data[1 + 1 + 8 * 3..]);
+        assert!(AmmInstruction::unpack(&twice).is_err());
+        assert!(AmmInstruction::unpack(&data[..data.len() - 1]).is_err());
+    }
 
-                total_pc_without_take_pnl,
-                total_coin_without_take_pnl,
+    #[test]
+    fn test_init_lp_decimals() {
+        assert_eq!(Processor::init_lp_decimals(None, 9), Ok(9));
+        assert_eq!(Processor::init_lp_decimals(Some(6), 9), Ok(6));
+        assert_eq!(Processor::init_lp_decimals(Some(0), 9), Ok(0));
+        assert_eq!(
+            Processor::init_lp_decimals(Some(MAX_LP_DECIMALS), 6),
+            Ok(MAX_LP_DECIMALS)
+        );
+        assert_eq!(
+            Processor::init_lp_decimals(Some(MAX_LP_DECIMALS + 1), 6),
+            Err(AmmError::InvalidInput)
+        );
 
-                coin_vault_amount: amm_coin_vault.amount,
-                pc_vault_amount: amm_pc_vault.amount,
-            };
-            f(args)
-        }
+        // the decimals trail the other options
+        let init = InitializeInstruction2 {
+            nonce: 254,
+            open_time: 0,
+            init_pc_amount: 10,
+            init_coin_amount: 20,
+            lp_mint_extension: None,
+            price_bound: None,
+            lp_decimals: Some(6),
+            without_open_orders: false,
+            stable_amp: None,
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 1);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&data[..1 + 1 + 8 * 3]).unwrap(),
+            AmmInstruction::Initialize2(InitializeInstruction2 {
+                lp_decimals: None,
+                ..init
+            })
+        );
     }
 
-    pub struct PurgeOrderArgs<'a, 'b: 'a> {
-        pub program_id: &'a Pubkey,
-        pub limit: u16,
-        pub market_program_info: &'a AccountInfo<'b>,
-        pub market_info: &'a AccountInfo<'b>,
-        pub amm_open_orders_info: &'a AccountInfo<'b>,
-        pub amm_authority_info: &'a AccountInfo<'b>,
-        pub market_event_queue_info: &'a AccountInfo<'b>,
-        pub market_bids_info: &'a AccountInfo<'b>,
-        pub market_asks_info: &'a AccountInfo<'b>,
-        pub amm: &'a mut AmmInfo,
-        pub target: &'a TargetOrders,
-        pub bids: &'a Vec<LeafNode>,
-        pub asks: &'a Vec<LeafNode>,
+    #[test]
+    fn test_init_without_open_orders() {
+        let init = InitializeInstruction2 {
+            nonce: 254,
+            open_time: 0,
+            init_pc_amount: 10,
+            init_coin_amount: 20,
+            lp_mint_extension: None,
+            price_bound: None,
+            lp_decimals: Some(6),
+            without_open_orders: true,
+            stable_amp: None,
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        // the option is a bare tag
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 1 + 1);
+        assert_eq!(data[data.len() - 1], 3);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        // it is off unless requested
+        assert_eq!(
+            AmmInstruction::unpack(&data[..data.len() - 1]).unwrap(),
+            AmmInstruction::Initialize2(InitializeInstruction2 {
+                without_open_orders: false,
+                ..init
+            })
+        );
+        // and requested at most once
+        let mut twice = data.clone();
+        twice.push(3);
+        assert_eq!(
+            AmmInstruction::unpack(&twice),
+            Err(ProgramError::InvalidInstructionData)
+        );
     }
-    impl<'a, 'b: 'a> PurgeOrderArgs<'a, 'b> {
-        pub fn with_parsed_args(
-            program_id: &'a Pubkey,
-            _spl_token_program_id: &'a Pubkey,
-            limit: u16,
-            amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo<'b>],
-            f: impl FnOnce(PurgeOrderArgs) -> ProgramResult,
-        ) -> ProgramResult {
-            if accounts.len() != 9 {
-                return Err(AmmError::WrongAccountsNumber.into());
-            }
-            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 9];
 
-            let target =
-                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-            let (market_state, open_orders) = Processor::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                true,
-            )?;
-            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
-            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+    #[test]
+    fn test_init_stable_amp() {
+        let init = InitializeInstruction2 {
+            nonce: 254,
+            open_time: 0,
+            init_pc_amount: 10,
+            init_coin_amount: 20,
+            lp_mint_extension: None,
+            price_bound: None,
+            lp_decimals: None,
+            without_open_orders: true,
+            stable_amp: Some(100),
+        };
+        let data = AmmInstruction::Initialize2(init).pack().unwrap();
+        // tag 3 then tag 4 and the amp
+        assert_eq!(data.len(), 1 + 1 + 8 * 3 + 1 + 1 + 8);
+        assert_eq!(data[data.len() - 9], 4);
+        assert_eq!(
+            AmmInstruction::unpack(&data).unwrap(),
+            AmmInstruction::Initialize2(init)
+        );
+        assert_eq!(
+            AmmInstruction::unpack(&data[..data.len() - 1]),
+            Err(ProgramError::InvalidInstructionData)
+        );
 
-            let args = PurgeOrderArgs {
-                program_id,
-                limit,
-                market_program_info,
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                market_event_queue_info,
-                market_bids_info,
-                market_asks_info,
-                amm,
-                target: &target,
-                bids: &bids,
-                asks: &asks,
-            };
-            f(args)
+        assert_eq!(
+            Processor::init_curve_type(None, false, 9, 6),
+            Ok(CurveType::ConstantProduct)
+        );
+        assert_eq!(
+            Processor::init_curve_type(Some(100), true, 6, 6),
+            Ok(CurveType::Stable { amp: 100 })
+        );
+        for (amp, without_open_orders, pc_decimals) in [
+            (MIN_AMP - 1, true, 6),
+            (MAX_AMP + 1, true, 6),
+            (100, false, 6),
+            (100, true, 9),
+        ] {
+            assert_eq!(
+                Processor::init_curve_type(Some(amp), without_open_orders, 6, pc_decimals),
+                Err(AmmError::InvalidInput)
+            );
         }
     }
 
-    pub struct CancelOrderArgs<'a, 'b: 'a> {
-        pub program_id: &'a Pubkey,
-        pub limit: u16,
-        pub market_program_info: &'a AccountInfo<'b>,
-        pub market_info: &'a AccountInfo<'b>,
-        pub amm_open_orders_info: &'a AccountInfo<'b>,
-        pub amm_authority_info: &'a AccountInfo<'b>,
-        pub market_event_queue_info: &'a AccountInfo<'b>,
-        pub market_bids_info: &'a AccountInfo<'b>,
-        pub market_asks_info: &'a AccountInfo<'b>,
-        pub amm: &'a mut AmmInfo,
-        pub open_orders: &'a OpenOrders,
-        pub target: &'a mut TargetOrders,
-        pub bids: &'a Vec<LeafNode>,
-        pub asks: &'a Vec<LeafNode>,
-
-        pub coin_amount: u64,
-        pub pc_amount: u64,
+    #[test]
+    fn test_checked_macro() {
+        fn withdraw_amount(lp_amount: u64, amount: u64) -> Result<u64, AmmError> {
+            Ok(checked!(lp_amount.checked_sub(amount)))
+        }
+        fn vault_total(vault: u64, free: u64) -> Result<u64, ProgramError> {
+            Ok(checked!(vault.checked_add(free)))
+        }
+        assert_eq!(withdraw_amount(10, 3), Ok(7));
+        assert_eq!(withdraw_amount(3, 10), Err(AmmError::CalculationFailure));
+        assert_eq!(vault_total(u64::MAX - 1, 1), Ok(u64::MAX));
+        assert_eq!(
+            vault_total(u64::MAX, 1),
+            Err(AmmError::CalculationFailure.into())
+        );
     }
-    impl<'a, 'b: 'a> CancelOrderArgs<'a, 'b> {
-        pub fn with_parsed_args(
-            program_id: &'a Pubkey,
-            spl_token_program_id: &'a Pubkey,
-            limit: u16,
-            amm: &mut AmmInfo,
-            accounts: &'a [AccountInfo<'b>],
-            f: impl FnOnce(CancelOrderArgs) -> ProgramResult,
-        ) -> ProgramResult {
-            if accounts.len() != 11 {
-                return Err(AmmError::WrongAccountsNumber.into());
-            }
-            let &[ref amm_info, ref market_program_info, ref market_info, ref amm_open_orders_info, ref amm_authority_info, ref market_event_queue_info, ref market_bids_info, ref market_asks_info, ref amm_coin_vault_info, ref amm_pc_vault_info, ref amm_target_orders_info] =
-                array_ref![accounts, 0, 11];
 
-            let amm_coin_vault =
-                Processor::unpack_token_account(&amm_coin_vault_info, spl_token_program_id)?;
-            let amm_pc_vault =
-                Processor::unpack_token_account(&amm_pc_vault_info, spl_token_program_id)?;
-            let mut target =
-                TargetOrders::load_mut_checked(&amm_target_orders_info, program_id, amm_info.key)?;
-            let (market_state, open_orders) = Processor::load_serum_market_order(
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                &amm,
-                true,
-            )?;
-            // let (myorders, _max_bid, _min_ask) = Processor::get_amm_orders_and_dex_best_price(&market_state, &open_orders, bids_acc, asks_acc)?;
-            let bids_orders = market_state.load_bids_mut(&market_bids_info)?;
-            let asks_orders = market_state.load_asks_mut(&market_asks_info)?;
-            let (bids, asks) = Processor::get_amm_orders(&open_orders, bids_orders, asks_orders)?;
+    #[test]
+    fn test_withdraw_then_deposit_never_mints_more_lp() {
+        // the withdraw math: each side is the floored share of the burned lp
+        let withdraw = |reserve: u64, lp_supply: u64, lp: u64| {
+            InvariantPool {
+                token_input: lp,
+                token_total: lp_supply,
+            }
+            .exchange_pool_to_token(reserve, RoundDirection::Floor)
+            .unwrap()
+        };
+        // the deposit math: lp for the base side, the other side is what the pool asks for
+        let deposit = |amount: u64, reserve: u64, lp_supply: u64| {
+            InvariantPool {
+                token_input: amount,
+                token_total: reserve,
+            }
+            .exchange_token_to_pool(lp_supply, RoundDirection::Floor)
+            .unwrap()
+        };
+        let check = |reserve_coin: u64, reserve_pc: u64, lp_supply: u64, lp: u64| {
+            let coin_out = withdraw(reserve_coin, lp_supply, lp);
+            let pc_out = withdraw(reserve_pc, lp_supply, lp);
+            if coin_out == 0 || pc_out == 0 || coin_out == reserve_coin || pc_out == reserve_pc {
+                // withdraw rejects zero outputs, deposit an emptied side
+                return;
+            }
+            let (reserve_coin, reserve_pc) = (reserve_coin - coin_out, reserve_pc - pc_out);
+            let lp_supply = lp_supply - lp;
+            // base coin, when the returned pc covers what the pool asks for
+            let pc_needed = deposit_pc_for_coin(coin_out, reserve_coin, reserve_pc);
+            if pc_needed.map_or(false, |pc_needed| pc_needed <= pc_out) {
+                let minted = deposit(coin_out, reserve_coin, lp_supply);
+                assert!(
+                    minted <= lp,
+                    "base coin minted {} > burned {}: coin {} pc {} supply {} lp {}",
+                    minted,
+                    lp,
+                    reserve_coin + coin_out,
+                    reserve_pc + pc_out,
+                    lp_supply + lp,
+                    lp
+                );
+            }
+            // base pc
+            let coin_needed = deposit_coin_for_pc(pc_out, reserve_coin, reserve_pc);
+            if coin_needed.map_or(false, |coin_needed| coin_needed <= coin_out) {
+                let minted = deposit(pc_out, reserve_pc, lp_supply);
+                assert!(
+                    minted <= lp,
+                    "base pc minted {} > burned {}: coin {} pc {} supply {} lp {}",
+                    minted,
+                    lp,
+                    reserve_coin + coin_out,
+                    reserve_pc + pc_out,
+                    lp_supply + lp,
+                    lp
+                );
+            }
+        };
 
-            let args = CancelOrderArgs {
-                program_id,
-                limit,
-                market_program_info,
-                market_info,
-                amm_open_orders_info,
-                amm_authority_info,
-                market_event_queue_info,
-                market_bids_info,
-                market_asks_info,
-                amm,
-                open_orders: &open_orders,
-                target: &mut target,
-                bids: &bids,
-                asks: &asks,
-                coin_amount: amm_coin_vault.amount,
-                pc_amount: amm_pc_vault.amount,
-            };
-            f(args)
+        let edges = [
+            1u64,
+            2,
+            3,
+            7,
+            10,
+            999,
+            1_000_003,
+            2_000_011,
+            1 << 32,
+            u64::MAX / 3,
+        ];
+        for &reserve_coin in edges.iter() {
+            for &reserve_pc in edges.iter() {
+                for &lp_supply in edges.iter() {
+                    for &lp in edges.iter().filter(|lp| **lp < lp_supply) {
+                        check(reserve_coin, reserve_pc, lp_supply, lp);
+                    }
+                }
+            }
+        }
+        // and a fixed pseudo random sweep, the same on every run
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |bound: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            1 + (seed >> 11) % bound
+        };
+        for _ in 0..20_000 {
+            let reserve_coin = next(1 << 40);
+            let reserve_pc = next(1 << 40);
+            let lp_supply = next(1 << 40) + 1;
+            let lp = next(lp_supply - 1);
+            check(reserve_coin, reserve_pc, lp_supply, lp);
         }
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
     #[test]
-    fn test_calc_tack_pnl() {
-        let mut amm = AmmInfo::default();
-        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
-        let mut target = TargetOrders::default();
-        target.calc_pnl_x = 900000000000000;
-        target.calc_pnl_y = 150000000000000000000000000;
+    fn test_withdraw_user_token_account_checks() {
+        let coin_mint = Pubkey::new_unique();
+        let withdrawer = Pubkey::new_unique();
+        let user_token = spl_token::state::Account {
+            mint: coin_mint,
+            owner: withdrawer,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        assert_eq!(
+            Processor::check_user_token_account(&user_token, &coin_mint, &withdrawer),
+            Ok(())
+        );
 
-        let mut total_pc_without_take_pnl = 1343675125663;
-        let mut total_coin_without_take_pnl = 117837534493793;
-        let x1 = Calculator::normalize_decimal_v2(
-            total_pc_without_take_pnl,
-            amm.pc_decimals,
-            amm.sys_decimal_value,
+        // an account of the other side of the pool is rejected
+        let pc_mint = Pubkey::new_unique();
+        assert_eq!(
+            Processor::check_user_token_account(&user_token, &pc_mint, &withdrawer),
+            Err(AmmError::InvalidUserAccount)
         );
-        let y1 = Calculator::normalize_decimal_v2(
-            total_coin_without_take_pnl,
-            amm.coin_decimals,
-            amm.sys_decimal_value,
+        // and so is one held by another wallet than the withdrawer
+        assert_eq!(
+            Processor::check_user_token_account(&user_token, &coin_mint, &Pubkey::new_unique()),
+            Err(AmmError::InvalidUserAccount)
         );
-
-        let (delta_x, delta_y) = Processor::calc_take_pnl(
-            &target,
-            &mut amm,
-            &mut total_pc_without_take_pnl,
-            &mut total_coin_without_take_pnl,
-            x1.as_u128().into(),
-            y1.as_u128().into(),
-        )
-        .unwrap();
-        println!("delta_x:{}, delta_y:{}", delta_x, delta_y);
     }
 
     #[test]
-    fn test_swap_base_in() {
-        let amount_in = 212854295571_u64;
-        let total_coin_without_take_pnl = 77043918330755_u64;
-        let total_pc_without_take_pnl = 1511361338135_u64;
-
-        let swap_direction = SwapDirection::Coin2PC;
+    fn test_pool_admin_cannot_touch_config() {
+        let program_id = Pubkey::new_unique();
+        let pool_admin = Pubkey::new_unique();
         let mut amm = AmmInfo::default();
-        amm.initialize(0, 0, 2, 9, 1000000, 1).unwrap();
+        amm.version = crate::state::AMM_INFO_VERSION;
+        amm.pool_admin = pool_admin;
+
+        let mut lamports = 0;
+        let mut data = vec![];
+        let signer = AccountInfo::new(
+            &pool_admin,
+            true,
+            false,
+            &mut lamports,
+            &mut data,
+            &program_id,
+            false,
+            0,
+        );
+        // the pool admin may pause its pool
+        assert_eq!(Processor::check_pool_admin(&amm, &signer), Ok(()));
+        // but not a pool it was not delegated
+        let mut other_amm = amm;
+        other_amm.pool_admin = Pubkey::new_unique();
+        assert_eq!(
+            Processor::check_pool_admin(&other_amm, &signer),
+            Err(AmmError::InvalidSignAccount.into())
+        );
+        // nor a pool whose layout predates pool_admin
+        let mut old_amm = amm;
+        old_amm.version = 4;
+        assert_eq!(
+            Processor::check_pool_admin(&old_amm, &signer),
+            Err(AmmError::InvalidSignAccount.into())
+        );
+        // nor the global config, whether it has an admin or is left to the amm owners
+        let mut amm_config = AmmConfig::default();
+        assert_eq!(
+            Processor::check_config_admin(&amm_config, &signer),
+            Err(AmmError::InvalidSignAccount.into())
+        );
+        amm_config.admin = Pubkey::new_unique();
+        assert_eq!(
+            Processor::check_config_admin(&amm_config, &signer),
+            Err(AmmError::InvalidSignAccount.into())
+        );
 
-        let swap_fee = U128::from(amount_in)
-            .checked_mul(amm.fees.swap_fee_numerator.into())
-            .unwrap()
-            .checked_ceil_div(amm.fees.swap_fee_denominator.into())
-            .unwrap()
-            .0;
+        let mut lamports = 0;
+        let mut data = vec![];
+        let unsigned = AccountInfo::new(
+            &pool_admin,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &program_id,
+            false,
+            0,
+        );
+        assert_eq!(
+            Processor::check_pool_admin(&amm, &unsigned),
+            Err(AmmError::InvalidSignAccount.into())
+        );
+    }
 
-        let swap_in_after_deduct_fee = U128::from(amount_in).checked_sub(swap_fee).unwrap();
-        let swap_amount_out = Calculator::swap_token_amount_base_in(
-            swap_in_after_deduct_fee,
-            total_pc_without_take_pnl.into(),
-            total_coin_without_take_pnl.into(),
-            swap_direction,
-        )
-        .as_u64();
+    #[test]
+    fn test_create_config_twice_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let system_program = solana_program::system_program::id();
+        let mut lamports = 0;
+        let mut data = vec![];
+        let uncreated = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &system_program,
+            false,
+            0,
+        );
+        assert_eq!(Processor::check_config_not_created(&uncreated), Ok(()));
 
-        println!("swap_amount_out:{}", swap_amount_out);
+        // a prefunded pda is still created
+        let mut lamports = 1_000_000;
+        let mut data = vec![];
+        let prefunded = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &system_program,
+            false,
+            0,
+        );
+        assert_eq!(Processor::check_config_not_created(&prefunded), Ok(()));
+
+        let mut lamports = 1_000_000;
+        let mut data = vec![0u8; size_of::<AmmConfig>()];
+        let created = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &program_id,
+            false,
+            0,
+        );
+        assert_eq!(
+            Processor::check_config_not_created(&created),
+            Err(AmmError::RepeatCreateConfigAccount)
+        );
     }
 }
@@ -0,0 +1,71 @@
+//! Base58 string (de)serialization of `Pubkey` fields, for `#[serde(with = "...")]` on the
+//! state and instruction types under the `serde` feature.
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use solana_program::pubkey::Pubkey;
+use std::str::FromStr;
+
+fn parse<E: Error>(s: &str) -> Result<Pubkey, E> {
+    Pubkey::from_str(s).map_err(|e| E::custom(format!("invalid pubkey {}: {}", s, e)))
+}
+
+pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pubkey)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+    parse(&String::deserialize(deserializer)?)
+}
+
+/// `Option<Pubkey>` as null or a base58 string.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        pubkey: &Option<Pubkey>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match pubkey {
+            Some(pubkey) => serializer.serialize_some(&pubkey.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Pubkey>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| parse(&s))
+            .transpose()
+    }
+}
+
+/// `[Pubkey; N]` as a sequence of N base58 strings.
+pub mod array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        pubkeys: &[Pubkey; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(pubkeys.iter().map(|pubkey| pubkey.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[Pubkey; N], D::Error> {
+        let strings = Vec::<String>::deserialize(deserializer)?;
+        if strings.len() != N {
+            return Err(D::Error::custom(format!(
+                "expected {} pubkeys, found {}",
+                N,
+                strings.len()
+            )));
+        }
+        let mut pubkeys = [Pubkey::default(); N];
+        for (pubkey, s) in pubkeys.iter_mut().zip(&strings) {
+            *pubkey = parse(s)?;
+        }
+        Ok(pubkeys)
+    }
+}
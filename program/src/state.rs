@@ -1,6 +1,11 @@
 //! State transition types
 
-use crate::{error::AmmError, math::Calculator};
+use crate::{
+    error::AmmError,
+    math::{
+        dynamic_fee_numerator, price_deviation_bps, twap, Calculator, CurveType, SwapDirection,
+    },
+};
 use serum_dex::state::ToAlignedBytes;
 use solana_program::{
     account_info::AccountInfo,
@@ -10,7 +15,7 @@ use solana_program::{
 };
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
-use bytemuck::{from_bytes, from_bytes_mut, Pod, Zeroable};
+use bytemuck::{bytes_of, bytes_of_mut, from_bytes, from_bytes_mut, Pod, Zeroable};
 use safe_transmute::{self, trivial::TriviallyTransmutable};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -21,7 +26,24 @@ use std::{
 };
 
 pub const TEN_THOUSAND: u64 = 10000;
+/// Current AmmInfo layout version.
+/// 0: pools created before the version field, protocol fee and pool_status were zero padding
+/// 1: protocol_fee_numerator/denominator, pool_status and version
+/// 2: max_coin_reserve and max_pc_reserve
+/// 3: swap_permissioned
+/// 4: price_cumulative_coin, price_cumulative_pc and last_update_timestamp
+/// 5: pool_admin, the former amm_owner which no instruction checked
+/// 6: AmmInfoExtension appended to the account, MigrateState grows older pools, and
+///    swap_permissioned moved into PoolStatusBit::SwapPermissioned
+pub const AMM_INFO_VERSION: u64 = 6;
+/// Last version of pools whose account ends with AmmInfo, they have no AmmInfoExtension.
+pub const AMM_INFO_UNEXTENDED_VERSION: u64 = 5;
+/// Size of the account of a pool of the current version, AmmInfo followed by its extension.
+pub const AMM_INFO_ACCOUNT_LEN: usize = size_of::<AmmInfo>() + size_of::<AmmInfoExtension>();
 pub const MAX_ORDER_LIMIT: usize = 10;
+/// Seconds a dynamic fee reference is kept before it is replaced, the average price a
+/// dynamic fee measures the spot price against spans between one and two of them.
+pub const DYNAMIC_FEE_WINDOW: u64 = 600;
 
 pub trait Loadable: Pod {
     fn load_mut<'a>(account: &'a AccountInfo) -> Result<RefMut<'a, Self>, ProgramError> {
@@ -36,8 +58,20 @@ pub trait Loadable: Pod {
         }))
     }
 
-    fn load_from_bytes(data: &[u8]) -> Result<&Self, ProgramError> {
-        Ok(from_bytes(data))
+    /// Copy of the state decoded from raw account data, e.g. fetched over RPC.
+    /// The data does not need to be aligned but must have exactly the on-chain size.
+    fn load_from_bytes(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() != size_of::<Self>() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut state = Self::zeroed();
+        bytes_of_mut(&mut state).copy_from_slice(data);
+        Ok(state)
+    }
+
+    /// Raw account data of the state, the inverse of `load_from_bytes`.
+    fn pack(&self) -> Vec<u8> {
+        bytes_of(self).to_vec()
     }
 }
 
@@ -375,26 +409,31 @@ pub enum AmmParams {
 }
 impl AmmParams {
     pub fn from_u64(state: u64) -> Self {
+        Self::try_from_u64(state).unwrap_or_else(|| unreachable!())
+    }
+
+    /// None for a value no param has.
+    pub fn try_from_u64(state: u64) -> Option<Self> {
         match state {
-            0u64 => AmmParams::Status,
-            1u64 => AmmParams::State,
-            2u64 => AmmParams::OrderNum,
-            3u64 => AmmParams::Depth,
-            4u64 => AmmParams::AmountWave,
-            5u64 => AmmParams::MinPriceMultiplier,
-            6u64 => AmmParams::MaxPriceMultiplier,
-            7u64 => AmmParams::MinSize,
-            8u64 => AmmParams::VolMaxCutRatio,
-            9u64 => AmmParams::Fees,
-            10u64 => AmmParams::AmmOwner,
-            11u64 => AmmParams::SetOpenTime,
-            12u64 => AmmParams::LastOrderDistance,
-            13u64 => AmmParams::InitOrderDepth,
-            14u64 => AmmParams::SetSwitchTime,
-            15u64 => AmmParams::ClearOpenTime,
-            16u64 => AmmParams::Seperate,
-            17u64 => AmmParams::UpdateOpenOrder,
-            _ => unreachable!(),
+            0u64 => Some(AmmParams::Status),
+            1u64 => Some(AmmParams::State),
+            2u64 => Some(AmmParams::OrderNum),
+            3u64 => Some(AmmParams::Depth),
+            4u64 => Some(AmmParams::AmountWave),
+            5u64 => Some(AmmParams::MinPriceMultiplier),
+            6u64 => Some(AmmParams::MaxPriceMultiplier),
+            7u64 => Some(AmmParams::MinSize),
+            8u64 => Some(AmmParams::VolMaxCutRatio),
+            9u64 => Some(AmmParams::Fees),
+            10u64 => Some(AmmParams::AmmOwner),
+            11u64 => Some(AmmParams::SetOpenTime),
+            12u64 => Some(AmmParams::LastOrderDistance),
+            13u64 => Some(AmmParams::InitOrderDepth),
+            14u64 => Some(AmmParams::SetSwitchTime),
+            15u64 => Some(AmmParams::ClearOpenTime),
+            16u64 => Some(AmmParams::Seperate),
+            17u64 => Some(AmmParams::UpdateOpenOrder),
+            _ => None,
         }
     }
 
@@ -446,7 +485,7 @@ impl AmmResetFlag {
     }
 }
 
-fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), AmmError> {
+pub fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), AmmError> {
     if numerator >= denominator || denominator == 0 {
         Err(AmmError::InvalidFee)
     } else {
@@ -454,6 +493,7 @@ fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), AmmError> {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Fees {
@@ -489,6 +529,12 @@ impl Fees {
         Ok(())
     }
 
+    /// Swap fee charged on `amount`, rounded up so the pool never under-collects.
+    /// None if the swap fee denominator is zero or the product overflows.
+    pub fn trading_fee(&self, amount: u128) -> Option<u128> {
+        ceil_mul_div(amount, self.swap_fee_numerator, self.swap_fee_denominator)
+    }
+
     pub fn initialize(&mut self) -> Result<(), AmmError> {
         // min_separate = 5/10000
         self.min_separate_numerator = 5;
@@ -506,6 +552,15 @@ impl Fees {
     }
 }
 
+fn ceil_mul_div(amount: u128, numerator: u64, denominator: u64) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    let product = amount.checked_mul(numerator.into())?;
+    let denominator = u128::from(denominator);
+    Some(product / denominator + u128::from(product % denominator != 0))
+}
+
 /// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
 impl IsInitialized for Fees {
     fn is_initialized(&self) -> bool {
@@ -564,6 +619,7 @@ impl Pack for Fees {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct StateData {
@@ -577,8 +633,8 @@ pub struct StateData {
     pub total_pnl_coin: u64,
     /// ido pool open time
     pub pool_open_time: u64,
-    /// padding for future updates
-    pub padding: [u64; 2],
+    /// Q64.64 pc per coin price integrated over seconds, little endian limbs of a wrapping u128
+    pub price_cumulative_coin: [u64; 2],
     /// switch from orderbookonly to init
     pub orderbook_to_init_time: u64,
 
@@ -604,7 +660,7 @@ impl StateData {
         self.total_pnl_pc = 0u64;
         self.total_pnl_coin = 0u64;
         self.pool_open_time = open_time;
-        self.padding = Zeroable::zeroed();
+        self.price_cumulative_coin = Zeroable::zeroed();
         self.orderbook_to_init_time = 0u64;
         self.swap_coin_in_amount = 0u128;
         self.swap_pc_out_amount = 0u128;
@@ -615,8 +671,46 @@ impl StateData {
 
         Ok(())
     }
+
+    /// Account a swap of coin to pc, `amount_in` including the `fee` charged in coin.
+    /// The counters saturate rather than fail a swap of a long lived pool.
+    pub fn record_swap_coin_in(&mut self, amount_in: u64, amount_out: u64, fee: u64) {
+        self.swap_coin_in_amount = self.swap_coin_in_amount.saturating_add(amount_in.into());
+        self.swap_pc_out_amount = self.swap_pc_out_amount.saturating_add(amount_out.into());
+        self.swap_acc_coin_fee = self.swap_acc_coin_fee.saturating_add(fee);
+    }
+
+    /// Account a swap of pc to coin, `amount_in` including the `fee` charged in pc.
+    pub fn record_swap_pc_in(&mut self, amount_in: u64, amount_out: u64, fee: u64) {
+        self.swap_pc_in_amount = self.swap_pc_in_amount.saturating_add(amount_in.into());
+        self.swap_coin_out_amount = self.swap_coin_out_amount.saturating_add(amount_out.into());
+        self.swap_acc_pc_fee = self.swap_acc_pc_fee.saturating_add(fee);
+    }
+
+    /// Lifetime trade fee charged in coin, protocol share included.
+    pub fn total_trade_fee_coin(&self) -> u64 {
+        self.swap_acc_coin_fee
+    }
+
+    /// Lifetime trade fee charged in pc, protocol share included.
+    pub fn total_trade_fee_pc(&self) -> u64 {
+        self.swap_acc_pc_fee
+    }
+
+    /// Lifetime coin swapped through the pool in either direction.
+    pub fn total_volume_coin(&self) -> u128 {
+        self.swap_coin_in_amount
+            .saturating_add(self.swap_coin_out_amount)
+    }
+
+    /// Lifetime pc swapped through the pool in either direction.
+    pub fn total_volume_pc(&self) -> u128 {
+        self.swap_pc_in_amount
+            .saturating_add(self.swap_pc_out_amount)
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "client", derive(Debug))]
 #[repr(C)]
 #[derive(Clone, Copy, Default, PartialEq)]
@@ -662,39 +756,223 @@ pub struct AmmInfo {
     /// Statistical data
     pub state_data: StateData,
     /// Coin vault
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub coin_vault: Pubkey,
     /// Pc vault
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub pc_vault: Pubkey,
     /// Coin vault mint
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub coin_vault_mint: Pubkey,
     /// Pc vault mint
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub pc_vault_mint: Pubkey,
     /// lp mint
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub lp_mint: Pubkey,
     /// open_orders key
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub open_orders: Pubkey,
     /// market key
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub market: Pubkey,
     /// market program key
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub market_program: Pubkey,
     /// target_orders key
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub target_orders: Pubkey,
-    /// padding
-    pub padding1: [u64; 8],
-    /// amm owner key
-    pub amm_owner: Pubkey,
+    /// numerator of the swap fee share taken by the protocol
+    pub protocol_fee_numerator: u64,
+    /// denominator of the swap fee share taken by the protocol
+    pub protocol_fee_denominator: u64,
+    /// bitfield of PoolStatusBit, a set bit disables that kind of instruction
+    pub pool_status: u64,
+    /// layout version, see AMM_INFO_VERSION
+    pub version: u64,
+    /// deposits may not grow the coin reserve above this amount, 0 means no cap
+    pub max_coin_reserve: u64,
+    /// deposits may not grow the pc reserve above this amount, 0 means no cap
+    pub max_pc_reserve: u64,
+    /// swap_permissioned of version 3 to 5 pools, see `swap_permissioned`. Version 6 moves it
+    /// into PoolStatusBit::SwapPermissioned and leaves the word zero
+    pub legacy_swap_permissioned: u64,
+    /// unix timestamp of the last price_cumulative update, 0 before the first one
+    pub last_update_timestamp: u64,
+    /// wallet signing the pool scoped admin instructions next to the amm owners, the
+    /// default pubkey leaves them to the amm owners alone
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub pool_admin: Pubkey,
     /// amm subscriber key
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub amm_subscriber: Pubkey,
     /// pool lp amount
     pub lp_amount: u64,
     /// client order id
     pub client_order_id: u64,
-    /// padding
-    pub padding2: [u64; 2],
+    /// Q64.64 coin per pc price integrated over seconds, little endian limbs of a wrapping u128
+    pub price_cumulative_pc: [u64; 2],
 }
 impl_loadable!(AmmInfo);
 
+/// Fields of a pool added once the size of AmmInfo was fixed by the deployed pools. They
+/// follow AmmInfo in the accounts of pools of version 6 on, older pools have none and read
+/// them as zero.
+#[cfg_attr(feature = "client", derive(Debug))]
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct AmmInfoExtension {
+    /// 1 when swaps charge the dynamic fee below instead of fees.swap_fee, 0 for static fees
+    pub dynamic_fee_enabled: u64,
+    /// dynamic fee numerator while the spot price holds at its recent average
+    pub base_fee_numerator: u64,
+    /// dynamic fee numerator once the spot price moved max_fee_deviation_bps from the average
+    pub max_fee_numerator: u64,
+    /// denominator of base_fee_numerator and max_fee_numerator
+    pub dynamic_fee_denominator: u64,
+    /// distance of the spot price from its recent average, in bps of the average, at which
+    /// the dynamic fee reaches max_fee_numerator
+    pub max_fee_deviation_bps: u64,
+    /// price_cumulative_coin at reference_timestamp, the start of the recent average price
+    pub reference_price_cumulative: [u64; 2],
+    /// unix timestamp of reference_price_cumulative, 0 until a swap took it
+    pub reference_timestamp: u64,
+    /// price_cumulative_coin at next_reference_timestamp, the reference once it is a
+    /// DYNAMIC_FEE_WINDOW old
+    pub next_reference_price_cumulative: [u64; 2],
+    /// unix timestamp of next_reference_price_cumulative, 0 until a swap took it
+    pub next_reference_timestamp: u64,
+    /// least coin a swap may pay in, 0 for no minimum
+    pub min_coin_trade_amount: u64,
+    /// least pc a swap may pay in, 0 for no minimum
+    pub min_pc_trade_amount: u64,
+    /// curve of the swaps, 0 for constant product, 1 for stable, see `curve_type`
+    pub curve: u64,
+    /// amplification of the stable curve, 0 on a constant product pool
+    pub amp: u64,
+    /// pending
+    pub pending: [u64; 17],
+}
+impl_loadable!(AmmInfoExtension);
+
+impl AmmInfoExtension {
+    /// Copy of the extension in the raw data of a pool account, e.g. fetched over RPC. Pools
+    /// not migrated to version 6 have none and read the default.
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        if AmmInfo::extended_len(data.len())? {
+            Self::load_from_bytes(&data[size_of::<AmmInfo>()..])
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Swap fees of `amm` at `now`, once its price accumulator has taken the reserves held
+    /// up to `now` (see `AmmInfo::update_price_cumulative`). The pool fees unless the dynamic
+    /// fee is enabled, which grows from base_fee_numerator to max_fee_numerator with the
+    /// distance of the spot price of `coin_reserve` and `pc_reserve` from its average since
+    /// reference_timestamp. Rolls the references, a quote takes the fees of a copy.
+    pub fn swap_fees(
+        &mut self,
+        amm: &AmmInfo,
+        coin_reserve: u64,
+        pc_reserve: u64,
+        now: u64,
+    ) -> Fees {
+        if self.dynamic_fee_enabled == 0 {
+            return amm.fees;
+        }
+        let (price_cumulative, _) = amm.price_cumulative();
+        if self.next_reference_timestamp == 0 {
+            self.next_reference_price_cumulative = u128_to_limbs(price_cumulative);
+            self.next_reference_timestamp = now;
+        } else if now.saturating_sub(self.next_reference_timestamp) >= DYNAMIC_FEE_WINDOW {
+            self.reference_price_cumulative = self.next_reference_price_cumulative;
+            self.reference_timestamp = self.next_reference_timestamp;
+            self.next_reference_price_cumulative = u128_to_limbs(price_cumulative);
+            self.next_reference_timestamp = now;
+        }
+        // the base fee until a reference is a window old
+        let deviation_bps = if self.reference_timestamp == 0 || coin_reserve == 0 {
+            None
+        } else {
+            twap(
+                u128_from_limbs(self.reference_price_cumulative),
+                self.reference_timestamp,
+                price_cumulative,
+                now,
+            )
+            .and_then(|average| {
+                price_deviation_bps(((pc_reserve as u128) << 64) / coin_reserve as u128, average)
+            })
+        };
+        Fees {
+            swap_fee_numerator: dynamic_fee_numerator(
+                self.base_fee_numerator,
+                self.max_fee_numerator,
+                deviation_bps.unwrap_or(0),
+                self.max_fee_deviation_bps,
+            ),
+            swap_fee_denominator: self.dynamic_fee_denominator,
+            ..amm.fees
+        }
+    }
+
+    /// Curve the pool swaps on, fixed at initialization.
+    pub fn curve_type(&self) -> CurveType {
+        match self.curve {
+            1 => CurveType::Stable { amp: self.amp },
+            _ => CurveType::ConstantProduct,
+        }
+    }
+
+    pub fn set_curve_type(&mut self, curve_type: CurveType) {
+        match curve_type {
+            CurveType::ConstantProduct => {
+                self.curve = 0;
+                self.amp = 0;
+            }
+            CurveType::Stable { amp } => {
+                self.curve = 1;
+                self.amp = amp;
+            }
+        }
+    }
+
+    /// Least amount of its input mint a swap in `swap_direction` may pay in, 0 for none.
+    pub fn min_trade_amount(&self, swap_direction: SwapDirection) -> u64 {
+        match swap_direction {
+            SwapDirection::Coin2PC => self.min_coin_trade_amount,
+            SwapDirection::PC2Coin => self.min_pc_trade_amount,
+        }
+    }
+}
+
 impl AmmInfo {
+    /// Whether a pool account of `data_len` bytes carries the AmmInfoExtension, the accounts
+    /// of older pools end with AmmInfo.
+    fn extended_len(data_len: usize) -> Result<bool, AmmError> {
+        if data_len == AMM_INFO_ACCOUNT_LEN {
+            Ok(true)
+        } else if data_len == size_of::<Self>() {
+            Ok(false)
+        } else {
+            Err(AmmError::ExpectedAccount)
+        }
+    }
+
+    fn check_loaded(&self, extended: bool) -> Result<(), AmmError> {
+        if self.status == AmmStatus::Uninitialized as u64 {
+            return Err(AmmError::InvalidStatus);
+        }
+        if self.version > AMM_INFO_VERSION {
+            return Err(AmmError::UnsupportedStateVersion);
+        }
+        if !extended && self.version > AMM_INFO_UNEXTENDED_VERSION {
+            return Err(AmmError::ExpectedAccount);
+        }
+        Ok(())
+    }
+
     /// Helper function to get the more efficient packed size of the struct
     /// load_mut_checked
     #[inline]
@@ -702,16 +980,7 @@ impl AmmInfo {
         account: &'a AccountInfo,
         program_id: &Pubkey,
     ) -> Result<RefMut<'a, Self>, ProgramError> {
-        if account.owner != program_id {
-            return Err(AmmError::InvalidAmmAccountOwner.into());
-        }
-        if account.data_len() != size_of::<Self>() {
-            return Err(AmmError::ExpectedAccount.into());
-        }
-        let data = Self::load_mut(account)?;
-        if data.status == AmmStatus::Uninitialized as u64 {
-            return Err(AmmError::InvalidStatus.into());
-        }
+        let (data, _extension) = Self::load_mut_checked_with_extension(account, program_id)?;
         Ok(data)
     }
 
@@ -724,14 +993,63 @@ impl AmmInfo {
         if account.owner != program_id {
             return Err(AmmError::InvalidAmmAccountOwner.into());
         }
-        if account.data_len() != size_of::<Self>() {
-            return Err(AmmError::ExpectedAccount.into());
+        let extended = Self::extended_len(account.data_len())?;
+        let data = Ref::map(account.try_borrow_data()?, |data| {
+            from_bytes(&data[..size_of::<Self>()])
+        });
+        data.check_loaded(extended)?;
+        Ok(data)
+    }
+
+    /// `load_mut_checked` that also maps the AmmInfoExtension, None for pools not migrated to
+    /// version 6.
+    pub fn load_mut_checked_with_extension<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<(RefMut<'a, Self>, Option<RefMut<'a, AmmInfoExtension>>), ProgramError> {
+        if account.owner != program_id {
+            return Err(AmmError::InvalidAmmAccountOwner.into());
         }
-        let data = Self::load(account)?;
-        if data.status == AmmStatus::Uninitialized as u64 {
-            return Err(AmmError::InvalidStatus.into());
+        let extended = Self::extended_len(account.data_len())?;
+        let (data, extension) = RefMut::map_split(account.try_borrow_mut_data()?, |data| {
+            data.split_at_mut(size_of::<Self>())
+        });
+        let data = RefMut::map(data, |data| from_bytes_mut::<Self>(data));
+        data.check_loaded(extended)?;
+        let extension = if extended {
+            Some(RefMut::map(extension, |extension| {
+                from_bytes_mut::<AmmInfoExtension>(extension)
+            }))
+        } else {
+            None
+        };
+        Ok((data, extension))
+    }
+
+    /// The state of a pool account Initialize2 just created and its zeroed extension, status
+    /// and version are unset.
+    pub fn load_mut_uninitialized<'a>(
+        account: &'a AccountInfo,
+    ) -> Result<(RefMut<'a, Self>, RefMut<'a, AmmInfoExtension>), ProgramError> {
+        if account.data_len() != AMM_INFO_ACCOUNT_LEN {
+            return Err(AmmError::ExpectedAccount.into());
         }
-        Ok(data)
+        let (data, extension) = RefMut::map_split(account.try_borrow_mut_data()?, |data| {
+            data.split_at_mut(size_of::<Self>())
+        });
+        Ok((
+            RefMut::map(data, |data| from_bytes_mut::<Self>(data)),
+            RefMut::map(extension, |extension| {
+                from_bytes_mut::<AmmInfoExtension>(extension)
+            }),
+        ))
+    }
+
+    /// Copy of the pool state in the raw data of a pool account of any version, e.g. fetched
+    /// over RPC, see `AmmInfoExtension::from_account_data` for the fields that follow it.
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::extended_len(data.len())?;
+        Self::load_from_bytes(&data[..size_of::<Self>()])
     }
 
     pub fn initialize(
@@ -822,8 +1140,17 @@ impl AmmInfo {
         self.min_price_multiplier = 1;
         self.max_price_multiplier = 1000000000;
         self.client_order_id = 0;
-        self.padding1 = Zeroable::zeroed();
-        self.padding2 = Zeroable::zeroed();
+        self.protocol_fee_numerator = 0;
+        self.protocol_fee_denominator = 0;
+        self.pool_status = 0;
+        self.version = AMM_INFO_VERSION;
+        self.max_coin_reserve = 0;
+        self.max_pc_reserve = 0;
+        self.legacy_swap_permissioned = 0;
+        self.last_update_timestamp = 0;
+        self.state_data.price_cumulative_coin = Zeroable::zeroed();
+        self.price_cumulative_pc = Zeroable::zeroed();
+        self.pool_admin = Pubkey::default();
 
         Ok(())
     }
@@ -835,18 +1162,212 @@ impl AmmInfo {
         }
         self.client_order_id
     }
+
+    /// Upgrade the state written by an older program, defaulting the fields the older layout
+    /// did not have. Only an `extended` account, grown by MigrateState, goes past
+    /// AMM_INFO_UNEXTENDED_VERSION to AMM_INFO_VERSION.
+    pub fn migrate(&mut self, extended: bool) -> Result<(), AmmError> {
+        if self.version > AMM_INFO_VERSION {
+            return Err(AmmError::UnsupportedStateVersion);
+        }
+        if self.version == 0 {
+            self.protocol_fee_numerator = 0;
+            self.protocol_fee_denominator = 0;
+            self.pool_status = 0;
+            self.version = 1;
+        }
+        if self.version == 1 {
+            self.max_coin_reserve = 0;
+            self.max_pc_reserve = 0;
+            self.version = 2;
+        }
+        if self.version == 2 {
+            self.legacy_swap_permissioned = 0;
+            self.version = 3;
+        }
+        if self.version == 3 {
+            self.last_update_timestamp = 0;
+            self.state_data.price_cumulative_coin = Zeroable::zeroed();
+            self.price_cumulative_pc = Zeroable::zeroed();
+            self.version = 4;
+        }
+        if self.version == 4 {
+            self.pool_admin = Pubkey::default();
+            self.version = 5;
+        }
+        if self.version == 5 && extended {
+            if self.legacy_swap_permissioned != 0 {
+                self.pool_status |= PoolStatusBit::SwapPermissioned.mask();
+            }
+            self.legacy_swap_permissioned = 0;
+            self.version = 6;
+        }
+        Ok(())
+    }
+
+    /// Protocol share of a `trading_fee`, rounded up.
+    /// Pools without a protocol fee (zero denominator, e.g. v0 pools) owe nothing.
+    pub fn owner_trading_fee(&self, trading_fee: u128) -> Option<u128> {
+        if self.protocol_fee_denominator == 0 {
+            return Some(0);
+        }
+        ceil_mul_div(
+            trading_fee,
+            self.protocol_fee_numerator,
+            self.protocol_fee_denominator,
+        )
+    }
+
+    /// Take the trade and protocol fees of the config. The swap fee is only replaced once the
+    /// config sets one, until then pools keep the default fees.
+    pub fn apply_config_fees(&mut self, amm_config: &AmmConfig) {
+        if amm_config.trade_fee_denominator != 0 {
+            self.fees.swap_fee_numerator = amm_config.trade_fee_numerator;
+            self.fees.swap_fee_denominator = amm_config.trade_fee_denominator;
+        }
+        self.protocol_fee_numerator = amm_config.protocol_fee_numerator;
+        self.protocol_fee_denominator = amm_config.protocol_fee_denominator;
+    }
+
+    pub fn is_disabled(&self, bit: PoolStatusBit) -> bool {
+        self.pool_status & bit.mask() != 0
+    }
+
+    /// Whether swaps are restricted to the wallets of the pool SwapWhitelist. Pools before
+    /// version 6 keep the flag in its own word, which was padding before version 3.
+    pub fn swap_permissioned(&self) -> bool {
+        match self.version {
+            0..=2 => false,
+            3..=5 => self.legacy_swap_permissioned != 0,
+            _ => self.pool_status & PoolStatusBit::SwapPermissioned.mask() != 0,
+        }
+    }
+
+    /// Whether the pool has an open orders account on its market. A pool initialized without
+    /// one trades on its vaults alone and never enters a status with order book permission.
+    pub fn uses_open_orders(&self) -> bool {
+        !self.is_disabled(PoolStatusBit::OpenOrders)
+    }
+
+    /// Whether a swap of the pool has to be the first one of it in its transaction.
+    pub fn swap_guarded(&self) -> bool {
+        self.pool_status & PoolStatusBit::SwapGuard.mask() != 0
+    }
+
+    /// Set the flag read by `swap_permissioned`, migrate older layouts first.
+    pub fn set_swap_permissioned(&mut self, permissioned: bool) {
+        if self.version < 6 {
+            self.legacy_swap_permissioned = permissioned as u64;
+        } else if permissioned {
+            self.pool_status |= PoolStatusBit::SwapPermissioned.mask();
+        } else {
+            self.pool_status &= !PoolStatusBit::SwapPermissioned.mask();
+        }
+    }
+
+    /// The delegated pool admin, if any. Pools not migrated to version 5 have none, their
+    /// pool_admin bytes are the unchecked amm_owner of older layouts.
+    pub fn pool_admin(&self) -> Option<Pubkey> {
+        if self.version < 5 || self.pool_admin == Pubkey::default() {
+            return None;
+        }
+        Some(self.pool_admin)
+    }
+
+    /// The (coin, pc) price accumulators, see `math::twap`.
+    pub fn price_cumulative(&self) -> (u128, u128) {
+        (
+            u128_from_limbs(self.state_data.price_cumulative_coin),
+            u128_from_limbs(self.price_cumulative_pc),
+        )
+    }
+
+    /// Accumulate the prices of the reserves held since the last update, call it with the
+    /// reserves before they change. The accumulators wrap, only their differences matter.
+    pub fn update_price_cumulative(&mut self, coin_reserve: u64, pc_reserve: u64, now: u64) {
+        if now <= self.last_update_timestamp {
+            return;
+        }
+        let elapsed = (now - self.last_update_timestamp) as u128;
+        if self.last_update_timestamp != 0 && coin_reserve != 0 && pc_reserve != 0 {
+            let (coin_cumulative, pc_cumulative) = self.price_cumulative();
+            let coin_price = ((pc_reserve as u128) << 64) / coin_reserve as u128;
+            let pc_price = ((coin_reserve as u128) << 64) / pc_reserve as u128;
+            self.state_data.price_cumulative_coin =
+                u128_to_limbs(coin_cumulative.wrapping_add(coin_price.wrapping_mul(elapsed)));
+            self.price_cumulative_pc =
+                u128_to_limbs(pc_cumulative.wrapping_add(pc_price.wrapping_mul(elapsed)));
+        }
+        self.last_update_timestamp = now;
+    }
+
+    /// Whether reserves of `coin` and `pc` would break the admin reserve caps.
+    pub fn exceeds_reserve_cap(&self, coin: u64, pc: u64) -> bool {
+        (self.max_coin_reserve != 0 && coin > self.max_coin_reserve)
+            || (self.max_pc_reserve != 0 && pc > self.max_pc_reserve)
+    }
+}
+
+/// Bits of AmmInfo::pool_status, set by the admin to pause part of the pool or, for
+/// SwapPermissioned, to restrict its swaps to the pool SwapWhitelist and, for SwapGuard, to
+/// one swap of the pool per transaction. OpenOrders is set at initialization for a pool
+/// without an open orders account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolStatusBit {
+    Swap = 0,
+    Deposit = 1,
+    Withdraw = 2,
+    SwapPermissioned = 3,
+    OpenOrders = 4,
+    SwapGuard = 5,
+}
+
+impl PoolStatusBit {
+    /// the pause bits, the ones SetPoolStatus sets
+    pub const PAUSE_MASK: u64 = 0b111;
+
+    pub fn mask(&self) -> u64 {
+        1u64 << (*self as u64)
+    }
 }
 
 /// State of amm config account
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct AmmConfig {
     /// withdraw pnl owner
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub pnl_owner: Pubkey,
     /// admin amm order owner
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
     pub cancel_owner: Pubkey,
+    /// config admin, the built-in amm owner is used while it is unset
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub admin: Pubkey,
+    /// admin proposed by the current admin, set until it is accepted
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub pending_admin: Pubkey,
+    /// numerator of the swap fee of pools, unset keeps the default fees
+    pub trade_fee_numerator: u64,
+    /// denominator of the swap fee of pools
+    pub trade_fee_denominator: u64,
+    /// numerator of the swap fee share taken by the protocol
+    pub protocol_fee_numerator: u64,
+    /// denominator of the swap fee share taken by the protocol
+    pub protocol_fee_denominator: u64,
+    /// owner of the token accounts protocol fees are collected to, the pnl_owner while unset
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub fee_recipient: Pubkey,
+    /// holders of this mint swap at the discount fee, unset disables the discount
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub discount_mint: Pubkey,
+    /// numerator of the swap fee paid by discount mint holders
+    pub discount_fee_numerator: u64,
+    /// denominator of the swap fee paid by discount mint holders
+    pub discount_fee_denominator: u64,
     /// pending
-    pub pending_1: [u64; 28],
+    pub pending_1: [u64; 6],
     /// pending
     pub pending_2: [u64; 31],
     /// init amm pool fee amount
@@ -887,8 +1408,120 @@ impl AmmConfig {
         let data = Self::load(account)?;
         Ok(data)
     }
+
+    /// Owner of the token accounts protocol fees are collected to. Configs created
+    /// before the field carry zero there and keep paying the pnl_owner.
+    pub fn fee_recipient(&self) -> Pubkey {
+        if self.fee_recipient == Pubkey::default() {
+            self.pnl_owner
+        } else {
+            self.fee_recipient
+        }
+    }
 }
 
+/// u128 fields are kept as u64 limbs so the 8 byte aligned layout is the same on every target
+fn u128_from_limbs(limbs: [u64; 2]) -> u128 {
+    (limbs[1] as u128) << 64 | limbs[0] as u128
+}
+
+fn u128_to_limbs(value: u128) -> [u64; 2] {
+    [value as u64, (value >> 64) as u64]
+}
+
+/// Most wallets a SwapWhitelist can hold.
+pub const MAX_WHITELIST_LEN: usize = 64;
+
+/// Wallets allowed to swap in a permissioned pool, a PDA of the pool
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapWhitelist {
+    /// the pool this whitelist belongs to
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey"))]
+    pub amm: Pubkey,
+    /// number of used entries of `wallets`
+    pub len: u64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_pubkey::array"))]
+    pub wallets: [Pubkey; MAX_WHITELIST_LEN],
+}
+impl_loadable!(SwapWhitelist);
+
+impl SwapWhitelist {
+    /// load_mut_checked
+    #[inline]
+    pub fn load_mut_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+        amm: &Pubkey,
+    ) -> Result<RefMut<'a, Self>, ProgramError> {
+        if account.owner != program_id {
+            return Err(AmmError::InvalidOwner.into());
+        }
+        if account.data_len() != size_of::<Self>() {
+            return Err(AmmError::ExpectedAccount.into());
+        }
+        let data = Self::load_mut(account)?;
+        if data.amm != *amm {
+            return Err(AmmError::InvalidInput.into());
+        }
+        Ok(data)
+    }
+
+    /// load_checked
+    #[inline]
+    pub fn load_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+        amm: &Pubkey,
+    ) -> Result<Ref<'a, Self>, ProgramError> {
+        if account.owner != program_id {
+            return Err(AmmError::InvalidOwner.into());
+        }
+        if account.data_len() != size_of::<Self>() {
+            return Err(AmmError::ExpectedAccount.into());
+        }
+        let data = Self::load(account)?;
+        if data.amm != *amm {
+            return Err(AmmError::InvalidInput.into());
+        }
+        Ok(data)
+    }
+
+    pub fn wallets(&self) -> &[Pubkey] {
+        &self.wallets[..(self.len as usize).min(MAX_WHITELIST_LEN)]
+    }
+
+    pub fn contains(&self, wallet: &Pubkey) -> bool {
+        self.wallets().contains(wallet)
+    }
+
+    /// Adding a wallet twice is a no-op.
+    pub fn add(&mut self, wallet: &Pubkey) -> Result<(), AmmError> {
+        if self.contains(wallet) {
+            return Ok(());
+        }
+        let len = self.wallets().len();
+        if len == MAX_WHITELIST_LEN {
+            return Err(AmmError::InvalidInput);
+        }
+        self.wallets[len] = *wallet;
+        self.len = len as u64 + 1;
+        Ok(())
+    }
+
+    /// Removing a missing wallet is a no-op.
+    pub fn remove(&mut self, wallet: &Pubkey) {
+        let len = self.wallets().len();
+        if let Some(index) = self.wallets().iter().position(|w| w == wallet) {
+            self.wallets[index] = self.wallets[len - 1];
+            self.wallets[len - 1] = Pubkey::default();
+            self.len = len as u64 - 1;
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct LastOrderDistance {
@@ -908,12 +1541,17 @@ pub enum SimulateParams {
 }
 impl SimulateParams {
     pub fn from_u64(flag: u64) -> Self {
+        Self::try_from_u64(flag).unwrap_or_else(|| unreachable!())
+    }
+
+    /// None for a value no param has.
+    pub fn try_from_u64(flag: u64) -> Option<Self> {
         match flag {
-            0u64 => SimulateParams::PoolInfo,
-            1u64 => SimulateParams::SwapBaseInInfo,
-            2u64 => SimulateParams::SwapBaseOutInfo,
-            3u64 => SimulateParams::RunCrankInfo,
-            _ => unreachable!(),
+            0u64 => Some(SimulateParams::PoolInfo),
+            1u64 => Some(SimulateParams::SwapBaseInInfo),
+            2u64 => Some(SimulateParams::SwapBaseOutInfo),
+            3u64 => Some(SimulateParams::RunCrankInfo),
+            _ => None,
         }
     }
 
@@ -997,3 +1635,489 @@ impl GetSwapBaseOutData {
         serde_json::from_str(data).unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_amm_info_bytes_round_trip() {
+        let mut amm = AmmInfo::default();
+        amm.status = AmmStatus::SwapOnly.into_u64();
+        amm.nonce = 254;
+        amm.coin_decimals = 9;
+        amm.pc_decimals = 6;
+        amm.fees.swap_fee_numerator = 25;
+        amm.fees.swap_fee_denominator = 10000;
+        amm.state_data.need_take_pnl_coin = 7;
+        amm.state_data.swap_coin_in_amount = u128::MAX;
+        amm.coin_vault = Pubkey::new_unique();
+        amm.lp_amount = u64::MAX;
+
+        let bytes = amm.pack();
+        assert_eq!(bytes.len(), size_of::<AmmInfo>());
+        // data fetched over rpc is not guaranteed to be aligned
+        let mut unaligned = vec![0u8; bytes.len() + 1];
+        unaligned[1..].copy_from_slice(&bytes);
+        let decoded = AmmInfo::load_from_bytes(&unaligned[1..]).unwrap();
+        assert!(decoded == amm);
+        assert!(decoded.pack() == bytes);
+
+        assert!(AmmInfo::load_from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_trading_fees_round_up() {
+        let mut amm = AmmInfo::default();
+        amm.fees.initialize().unwrap();
+        amm.protocol_fee_numerator = 1;
+        amm.protocol_fee_denominator = 6;
+
+        // 25 / 10000
+        assert_eq!(amm.fees.trading_fee(10_000), Some(25));
+        assert_eq!(amm.fees.trading_fee(10_001), Some(26));
+        assert_eq!(amm.fees.trading_fee(1), Some(1));
+        assert_eq!(amm.fees.trading_fee(0), Some(0));
+        assert_eq!(amm.fees.trading_fee(u128::MAX), None);
+        assert_eq!(amm.owner_trading_fee(24), Some(4));
+        assert_eq!(amm.owner_trading_fee(25), Some(5));
+
+        amm.fees.swap_fee_denominator = 0;
+        assert_eq!(amm.fees.trading_fee(10_000), None);
+        amm.protocol_fee_denominator = 0;
+        assert_eq!(amm.owner_trading_fee(25), Some(0));
+    }
+
+    #[test]
+    fn test_migrate_v0_to_current() {
+        let mut amm = AmmInfo::default();
+        amm.status = AmmStatus::Initialized.into_u64();
+        amm.fees.swap_fee_numerator = 25;
+        amm.fees.swap_fee_denominator = 10000;
+        amm.lp_amount = 1_000_000;
+        // garbage in the fields carved out of the v0 padding must not survive the migration
+        amm.protocol_fee_numerator = 3;
+        amm.pool_status = u64::MAX;
+        amm.max_coin_reserve = 7;
+        amm.legacy_swap_permissioned = 1;
+        amm.last_update_timestamp = 9;
+        amm.price_cumulative_pc = [1, 2];
+        amm.pool_admin = Pubkey::new_unique();
+        let v0 = amm.pack();
+
+        let mut migrated = AmmInfo::load_from_bytes(&v0).unwrap();
+        assert_eq!(migrated.version, 0);
+        // an unchecked amm_owner of an older layout grants nothing
+        assert_eq!(migrated.pool_admin(), None);
+        // an account that was not grown stops short of the extension
+        let mut unextended = migrated;
+        unextended.migrate(false).unwrap();
+        assert_eq!(unextended.version, AMM_INFO_UNEXTENDED_VERSION);
+        migrated.migrate(true).unwrap();
+        assert_eq!(migrated.version, AMM_INFO_VERSION);
+        assert_eq!(migrated.protocol_fee_numerator, 0);
+        assert_eq!(migrated.protocol_fee_denominator, 0);
+        assert_eq!(migrated.pool_status, 0);
+        assert_eq!(migrated.max_coin_reserve, 0);
+        assert_eq!(migrated.legacy_swap_permissioned, 0);
+        assert!(!migrated.swap_permissioned());
+        assert_eq!(migrated.last_update_timestamp, 0);
+        assert_eq!(migrated.price_cumulative(), (0, 0));
+        assert_eq!(migrated.pool_admin, Pubkey::default());
+        assert_eq!(migrated.fees.swap_fee_numerator, 25);
+        assert_eq!(migrated.lp_amount, 1_000_000);
+
+        // migrating the current version is a no-op
+        let current = migrated;
+        migrated.migrate(true).unwrap();
+        assert!(migrated == current);
+        migrated.migrate(false).unwrap();
+        assert!(migrated == current);
+
+        migrated.version = AMM_INFO_VERSION + 1;
+        assert_eq!(
+            migrated.migrate(true),
+            Err(AmmError::UnsupportedStateVersion)
+        );
+    }
+
+    #[test]
+    fn test_swap_permissioned_moves_into_pool_status() {
+        let mut amm = AmmInfo::default();
+        amm.version = AMM_INFO_UNEXTENDED_VERSION;
+        amm.pool_status = PoolStatusBit::Deposit.mask();
+        amm.set_swap_permissioned(true);
+        assert_eq!(amm.legacy_swap_permissioned, 1);
+        assert!(amm.swap_permissioned());
+
+        // an account that was not grown keeps the word
+        let mut unextended = amm;
+        unextended.migrate(false).unwrap();
+        assert!(unextended == amm);
+
+        amm.migrate(true).unwrap();
+        assert_eq!(amm.legacy_swap_permissioned, 0);
+        assert_eq!(
+            amm.pool_status,
+            PoolStatusBit::Deposit.mask() | PoolStatusBit::SwapPermissioned.mask()
+        );
+        assert!(amm.swap_permissioned());
+        amm.set_swap_permissioned(false);
+        assert_eq!(amm.pool_status, PoolStatusBit::Deposit.mask());
+        assert!(!amm.swap_permissioned());
+
+        // the word was padding before version 3
+        let mut v2 = AmmInfo::default();
+        v2.version = 2;
+        v2.legacy_swap_permissioned = 1;
+        assert!(!v2.swap_permissioned());
+    }
+
+    #[test]
+    fn test_load_pool_accounts_of_known_sizes() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut amm = AmmInfo::default();
+        amm.status = AmmStatus::SwapOnly.into_u64();
+        amm.version = AMM_INFO_UNEXTENDED_VERSION;
+        amm.lp_amount = 1_000;
+        let mut extension = AmmInfoExtension::default();
+        extension.pending[0] = 7;
+        // u128 backed, so the account data is aligned for AmmInfo on any host
+        let mut words = vec![0u128; AMM_INFO_ACCOUNT_LEN / 16 + 1];
+        let buffer: &mut [u8] = bytemuck::cast_slice_mut(&mut words);
+        buffer[..size_of::<AmmInfo>()].copy_from_slice(&amm.pack());
+        buffer[size_of::<AmmInfo>()..AMM_INFO_ACCOUNT_LEN].copy_from_slice(&extension.pack());
+        let mut lamports = 0;
+
+        // a pool of an older version ends with AmmInfo
+        let (data, _) = buffer.split_at_mut(size_of::<AmmInfo>());
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            data,
+            &program_id,
+            false,
+            0,
+        );
+        assert_eq!(
+            AmmInfo::load_checked(&account, &program_id)
+                .unwrap()
+                .lp_amount,
+            1_000
+        );
+        let (loaded, loaded_extension) =
+            AmmInfo::load_mut_checked_with_extension(&account, &program_id).unwrap();
+        assert!(loaded_extension.is_none());
+        drop(loaded);
+        assert!(
+            AmmInfoExtension::from_account_data(&account.data.borrow()).unwrap()
+                == Default::default()
+        );
+        // but can not claim a version with the extension
+        AmmInfo::load_mut_checked(&account, &program_id)
+            .unwrap()
+            .version = AMM_INFO_VERSION;
+        assert_eq!(
+            AmmInfo::load_checked(&account, &program_id).err(),
+            Some(AmmError::ExpectedAccount.into())
+        );
+        drop(account);
+
+        // a migrated pool carries the extension after AmmInfo
+        let (data, _) = buffer.split_at_mut(AMM_INFO_ACCOUNT_LEN);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            data,
+            &program_id,
+            false,
+            0,
+        );
+        {
+            let (mut loaded, loaded_extension) =
+                AmmInfo::load_mut_checked_with_extension(&account, &program_id).unwrap();
+            assert!(*loaded_extension.unwrap() == extension);
+            loaded.migrate(true).unwrap();
+        }
+        assert_eq!(
+            AmmInfo::load_checked(&account, &program_id)
+                .unwrap()
+                .version,
+            AMM_INFO_VERSION
+        );
+        let data = account.data.borrow();
+        assert_eq!(AmmInfo::from_account_data(&data).unwrap().lp_amount, 1_000);
+        assert!(AmmInfoExtension::from_account_data(&data).unwrap() == extension);
+        drop(data);
+        drop(account);
+
+        // any other size is not a pool
+        let (data, _) = buffer.split_at_mut(AMM_INFO_ACCOUNT_LEN - 8);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            data,
+            &program_id,
+            false,
+            0,
+        );
+        assert_eq!(
+            AmmInfo::load_checked(&account, &program_id).err(),
+            Some(AmmError::ExpectedAccount.into())
+        );
+        assert!(AmmInfo::from_account_data(&account.data.borrow()).is_err());
+    }
+
+    #[test]
+    fn test_price_cumulative_twap() {
+        use crate::math::twap;
+
+        let mut amm = AmmInfo::default();
+        // the first update only starts the clock
+        amm.update_price_cumulative(1_000, 2_000, 1_000);
+        assert_eq!(amm.price_cumulative(), (0, 0));
+        let (start_coin, start_pc) = amm.price_cumulative();
+
+        // a swap 10s later accumulates the initial reserves
+        amm.update_price_cumulative(1_000, 2_000, 1_010);
+        // a second swap 30s after that accumulates the reserves left by the first one
+        amm.update_price_cumulative(1_250, 1_600, 1_040);
+        let (end_coin, end_pc) = amm.price_cumulative();
+
+        let (coin_price1, coin_price2) = ((2_000u128 << 64) / 1_000, (1_600u128 << 64) / 1_250);
+        let (pc_price1, pc_price2) = ((1_000u128 << 64) / 2_000, (1_250u128 << 64) / 1_600);
+        assert_eq!(
+            twap(start_coin, 1_000, end_coin, 1_040),
+            Some((coin_price1 * 10 + coin_price2 * 30) / 40)
+        );
+        assert_eq!(
+            twap(start_pc, 1_000, end_pc, 1_040),
+            Some((pc_price1 * 10 + pc_price2 * 30) / 40)
+        );
+
+        // a later update in the same second accumulates nothing
+        let before = amm;
+        amm.update_price_cumulative(1, 1, 1_040);
+        assert!(amm == before);
+
+        // the accumulator wraps without panicking and the twap survives the wrap
+        amm.state_data.price_cumulative_coin = u128_to_limbs(u128::MAX - 1);
+        let (wrapped_start, _) = amm.price_cumulative();
+        amm.update_price_cumulative(1_250, 1_600, 1_100);
+        let (wrapped_end, _) = amm.price_cumulative();
+        assert!(wrapped_end < wrapped_start);
+        assert_eq!(
+            twap(wrapped_start, 1_040, wrapped_end, 1_100),
+            Some(coin_price2)
+        );
+        assert_eq!(twap(wrapped_end, 1_100, wrapped_start, 1_040), None);
+        assert_eq!(twap(wrapped_start, 1_100, wrapped_end, 1_100), None);
+    }
+
+    #[test]
+    fn test_dynamic_swap_fees() {
+        let mut amm = AmmInfo::default();
+        amm.fees.swap_fee_numerator = 25;
+        amm.fees.swap_fee_denominator = 10_000;
+        let mut extension = AmmInfoExtension::default();
+        // static fees until enabled
+        assert_eq!(extension.swap_fees(&amm, 1_000, 2_000, 1_000), amm.fees);
+        assert!(extension == AmmInfoExtension::default());
+
+        // 20 bps at rest, 100 bps once the price moved 5% from its average
+        extension.dynamic_fee_enabled = 1;
+        extension.base_fee_numerator = 20;
+        extension.max_fee_numerator = 100;
+        extension.dynamic_fee_denominator = 10_000;
+        extension.max_fee_deviation_bps = 500;
+        let swap_fee = |extension: &mut AmmInfoExtension, amm: &mut AmmInfo, coin, pc, now| {
+            amm.update_price_cumulative(coin, pc, now);
+            let fees = extension.swap_fees(amm, coin, pc, now);
+            assert_eq!(fees.swap_fee_denominator, 10_000);
+            assert_eq!(fees.pnl_numerator, amm.fees.pnl_numerator);
+            fees.swap_fee_numerator
+        };
+        // the base fee until a reference is a window old
+        assert_eq!(swap_fee(&mut extension, &mut amm, 1_000, 2_000, 1_000), 20);
+        assert_eq!(extension.reference_timestamp, 0);
+        assert_eq!(extension.next_reference_timestamp, 1_000);
+        assert_eq!(swap_fee(&mut extension, &mut amm, 1_000, 2_000, 1_300), 20);
+
+        // calm, the spot price holds at its average
+        let now = 1_000 + DYNAMIC_FEE_WINDOW;
+        assert_eq!(swap_fee(&mut extension, &mut amm, 1_000, 2_000, now), 20);
+        assert_eq!(extension.reference_timestamp, 1_000);
+        assert_eq!(extension.next_reference_timestamp, now);
+
+        // a 1% move lifts the fee part of the way
+        let mut mild = (extension, amm);
+        let fee = swap_fee(&mut mild.0, &mut mild.1, 1_000, 2_020, now + 10);
+        assert!(fee > 20 && fee < 100);
+
+        // volatile, a 25% move charges the max fee
+        assert_eq!(
+            swap_fee(&mut extension, &mut amm, 800, 2_500, now + 10),
+            100
+        );
+        // until the average catches up with the new price
+        let later = now + DYNAMIC_FEE_WINDOW / 2;
+        assert_eq!(swap_fee(&mut extension, &mut amm, 800, 2_500, later), 100);
+        let later = now + 2 * DYNAMIC_FEE_WINDOW;
+        assert_eq!(swap_fee(&mut extension, &mut amm, 800, 2_500, later), 20);
+        assert_eq!(extension.reference_timestamp, now);
+    }
+
+    #[test]
+    fn test_swap_statistics_accumulate() {
+        let mut state = StateData::default();
+        state.record_swap_coin_in(1_000, 1_900, 3);
+        state.record_swap_pc_in(4_000, 1_990, 10);
+        assert_eq!(state.total_trade_fee_coin(), 3);
+        assert_eq!(state.total_trade_fee_pc(), 10);
+        assert_eq!(state.total_volume_coin(), 1_000 + 1_990);
+        assert_eq!(state.total_volume_pc(), 1_900 + 4_000);
+        state.record_swap_coin_in(500, 900, 2);
+        assert_eq!(state.total_trade_fee_coin(), 5);
+        assert_eq!(state.total_volume_coin(), 1_500 + 1_990);
+        assert_eq!(state.total_volume_pc(), 2_800 + 4_000);
+
+        // saturate instead of failing the swap
+        state.swap_acc_coin_fee = u64::MAX - 1;
+        state.swap_coin_in_amount = u128::MAX;
+        state.record_swap_coin_in(u64::MAX, 1, 2);
+        assert_eq!(state.total_trade_fee_coin(), u64::MAX);
+        assert_eq!(state.swap_coin_in_amount, u128::MAX);
+        assert_eq!(state.total_volume_coin(), u128::MAX);
+    }
+
+    #[test]
+    fn test_reserve_cap() {
+        let mut amm = AmmInfo::default();
+        // uncapped by default
+        assert!(!amm.exceeds_reserve_cap(u64::MAX, u64::MAX));
+
+        amm.max_coin_reserve = 1_000;
+        assert!(!amm.exceeds_reserve_cap(1_000, u64::MAX));
+        assert!(amm.exceeds_reserve_cap(1_001, 0));
+        amm.max_pc_reserve = 5_000;
+        assert!(!amm.exceeds_reserve_cap(1_000, 5_000));
+        assert!(amm.exceeds_reserve_cap(1_000, 5_001));
+    }
+
+    #[test]
+    fn test_apply_config_fees() {
+        let mut amm = AmmInfo::default();
+        amm.fees.initialize().unwrap();
+        let default_fees = amm.fees;
+        let mut amm_config: AmmConfig = Zeroable::zeroed();
+        amm_config.protocol_fee_numerator = 1;
+        amm_config.protocol_fee_denominator = 5;
+        // no config trade fee yet, the default swap fee stays
+        amm.apply_config_fees(&amm_config);
+        assert_eq!(amm.fees.swap_fee_numerator, default_fees.swap_fee_numerator);
+        assert_eq!(
+            amm.fees.swap_fee_denominator,
+            default_fees.swap_fee_denominator
+        );
+        assert_eq!(amm.protocol_fee_numerator, 1);
+        assert_eq!(amm.protocol_fee_denominator, 5);
+
+        amm_config.trade_fee_numerator = 30;
+        amm_config.trade_fee_denominator = 10_000;
+        amm_config.protocol_fee_numerator = 0;
+        amm.apply_config_fees(&amm_config);
+        assert_eq!(amm.fees.swap_fee_numerator, 30);
+        assert_eq!(amm.fees.swap_fee_denominator, 10_000);
+        assert_eq!(
+            amm.fees.trade_fee_numerator,
+            default_fees.trade_fee_numerator
+        );
+        assert_eq!(amm.protocol_fee_numerator, 0);
+        assert_eq!(amm.owner_trading_fee(1_000), Some(0));
+    }
+
+    #[test]
+    fn test_swap_whitelist_add_remove() {
+        let mut whitelist: SwapWhitelist = Zeroable::zeroed();
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        assert!(!whitelist.contains(&alice));
+        // the zeroed entries are not members
+        assert!(!whitelist.contains(&Pubkey::default()));
+
+        whitelist.add(&alice).unwrap();
+        whitelist.add(&alice).unwrap();
+        whitelist.add(&bob).unwrap();
+        assert_eq!(whitelist.wallets(), &[alice, bob]);
+
+        whitelist.remove(&alice);
+        assert_eq!(whitelist.wallets(), &[bob]);
+        assert!(!whitelist.contains(&alice));
+        whitelist.remove(&alice);
+        assert_eq!(whitelist.len, 1);
+
+        for _ in 1..MAX_WHITELIST_LEN {
+            whitelist.add(&Pubkey::new_unique()).unwrap();
+        }
+        assert_eq!(
+            whitelist.add(&Pubkey::new_unique()),
+            Err(AmmError::InvalidInput)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_json_round_trip() {
+        use crate::instruction::{
+            AmmInstruction, InitializeInstruction2, LpMintExtension, UpdateSwapWhitelistInstruction,
+        };
+
+        let mut amm: AmmInfo = Zeroable::zeroed();
+        amm.coin_vault = Pubkey::new_unique();
+        amm.lp_mint = Pubkey::new_unique();
+        amm.state_data.swap_coin_in_amount = u128::MAX;
+        let json = serde_json::to_value(&amm).unwrap();
+        // pubkeys are base58 strings, not byte arrays
+        assert_eq!(json["coin_vault"], amm.coin_vault.to_string());
+        assert!(serde_json::from_value::<AmmInfo>(json).unwrap() == amm);
+
+        let mut config: AmmConfig = Zeroable::zeroed();
+        config.pnl_owner = Pubkey::new_unique();
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<AmmConfig>(&json).unwrap(), config);
+
+        let mut whitelist: SwapWhitelist = Zeroable::zeroed();
+        whitelist.add(&Pubkey::new_unique()).unwrap();
+        let json = serde_json::to_string(&whitelist).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SwapWhitelist>(&json).unwrap(),
+            whitelist
+        );
+
+        for instruction in [
+            AmmInstruction::UpdateSwapWhitelist(UpdateSwapWhitelistInstruction {
+                add: 1,
+                wallet: Pubkey::new_unique(),
+            }),
+            AmmInstruction::Initialize2(InitializeInstruction2 {
+                lp_mint_extension: Some(LpMintExtension::MetadataPointer {
+                    authority: None,
+                    metadata_address: Some(Pubkey::new_unique()),
+                }),
+                ..Default::default()
+            }),
+        ] {
+            let json = serde_json::to_string(&instruction).unwrap();
+            assert_eq!(
+                serde_json::from_str::<AmmInstruction>(&json).unwrap(),
+                instruction
+            );
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! Setup shared by the end-to-end tests: mints, an openbook market, the amm config and a
+//! pool initialized against them, plus helpers to send instructions and read accounts.
+
+// each test crate compiles its own copy and uses only part of it
+#![allow(dead_code)]
+
+use makidex_amm::{
+    error::AmmError,
+    instruction::{self, TradeDirection},
+    processor::{config_feature, get_amm_pdas, AmmPdas},
+    state::{AmmConfig, AmmInfo, Loadable},
+};
+use solana_program_test::{BanksClient, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::AccountSharedData,
+    hash::Hash,
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use std::mem::size_of;
+
+pub const COIN_DECIMALS: u8 = 9;
+pub const PC_DECIMALS: u8 = 6;
+pub const REQUEST_QUEUE_LEN: usize = 5 + 32 + 10 * 80 + 7;
+pub const EVENT_QUEUE_LEN: usize = 5 + 32 + 128 * 88 + 7;
+pub const ORDERBOOK_LEN: usize = 5 + 40 + 128 * 72 + 7;
+
+pub struct Market {
+    pub market: Pubkey,
+    pub event_queue: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub vault_signer: Pubkey,
+}
+
+pub struct TestPool {
+    pub context: ProgramTestContext,
+    pub market: Market,
+    pub pdas: AmmPdas,
+    pub user_token_coin: Pubkey,
+    pub user_token_pc: Pubkey,
+    pub user_token_lp: Pubkey,
+}
+
+pub async fn send(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    instrs: &[Instruction],
+    recent_blockhash: Hash,
+) {
+    let signers = [&[payer], signers].concat();
+    let txn = Transaction::new_signed_with_payer(
+        instrs,
+        Some(&payer.pubkey()),
+        &signers,
+        recent_blockhash,
+    );
+    banks_client.process_transaction(txn).await.unwrap();
+}
+
+pub async fn token_account(
+    banks_client: &mut BanksClient,
+    key: &Pubkey,
+) -> spl_token::state::Account {
+    let account = banks_client.get_account(*key).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data).unwrap()
+}
+
+/// Sends `instr` expecting the amm to reject it with `error`.
+pub async fn assert_amm_error(
+    pool: &mut TestPool,
+    signers: &[&Keypair],
+    instr: Instruction,
+    error: AmmError,
+) {
+    let signers = [&[&pool.context.payer], signers].concat();
+    let txn = Transaction::new_signed_with_payer(
+        &[instr],
+        Some(&pool.context.payer.pubkey()),
+        &signers,
+        pool.context.last_blockhash,
+    );
+    let err = pool
+        .context
+        .banks_client
+        .process_transaction(txn)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+    );
+}
+
+pub async fn load_amm(pool: &mut TestPool) -> AmmInfo {
+    let account = pool
+        .context
+        .banks_client
+        .get_account(pool.pdas.amm_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    AmmInfo::from_account_data(&account.data).unwrap()
+}
+
+/// A deposit of `max_coin_amount` with the coin side as base.
+pub fn deposit(
+    pool: &TestPool,
+    max_coin_amount: u64,
+    max_pc_amount: u64,
+    minimum_lp_amount: u64,
+) -> Instruction {
+    instruction::deposit(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &pool.market.market,
+        &pool.market.event_queue,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &pool.user_token_lp,
+        &pool.context.payer.pubkey(),
+        max_coin_amount,
+        max_pc_amount,
+        0,
+        minimum_lp_amount,
+    )
+    .unwrap()
+}
+
+/// Pc taken and lp minted by a deposit of `coin_amount`, worked out from the vault balances.
+/// No crank places orders in these tests, so before any swap accrues fees the vaults hold
+/// the whole pool.
+pub async fn quote_deposit(pool: &mut TestPool, coin_amount: u64) -> (u64, u64) {
+    let coin = token_account(&mut pool.context.banks_client, &pool.pdas.amm_coin_vault)
+        .await
+        .amount as u128;
+    let pc = token_account(&mut pool.context.banks_client, &pool.pdas.amm_pc_vault)
+        .await
+        .amount as u128;
+    let lp = load_amm(pool).await.lp_amount as u128;
+    let coin_amount = coin_amount as u128;
+    // the pc side is rounded up and the lp down, both in favour of the pool
+    let pc_amount = (coin_amount * pc + coin - 1) / coin;
+    let lp_amount = (coin_amount * lp / coin).min(pc_amount * lp / pc);
+    (pc_amount as u64, lp_amount as u64)
+}
+
+/// Coin, pc and lp balances of the user, then the coin and pc vault balances.
+pub async fn deposit_balances(pool: &mut TestPool) -> [u64; 5] {
+    let mut balances = [0; 5];
+    for (balance, key) in balances.iter_mut().zip([
+        pool.user_token_coin,
+        pool.user_token_pc,
+        pool.user_token_lp,
+        pool.pdas.amm_coin_vault,
+        pool.pdas.amm_pc_vault,
+    ]) {
+        *balance = token_account(&mut pool.context.banks_client, &key)
+            .await
+            .amount;
+    }
+    balances
+}
+
+/// SetPoolAdmin is signed by an amm owner, whose key the tests don't hold, so the pool admin
+/// is written to the pool account directly.
+pub async fn set_pool_admin(pool: &mut TestPool, pool_admin: &Pubkey) {
+    let mut account = pool
+        .context
+        .banks_client
+        .get_account(pool.pdas.amm_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut amm = AmmInfo::from_account_data(&account.data).unwrap();
+    amm.pool_admin = *pool_admin;
+    account.data[..size_of::<AmmInfo>()].copy_from_slice(&amm.pack());
+    pool.context
+        .set_account(&pool.pdas.amm_pool, &AccountSharedData::from(account));
+}
+
+pub async fn set_config_admin(pool: &mut TestPool, admin: &Pubkey) {
+    let mut account = pool
+        .context
+        .banks_client
+        .get_account(pool.pdas.amm_config)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut amm_config = AmmConfig::load_from_bytes(&account.data).unwrap();
+    amm_config.admin = *admin;
+    account.data = amm_config.pack();
+    pool.context
+        .set_account(&pool.pdas.amm_config, &AccountSharedData::from(account));
+}
+
+/// Overwrite the balance of a token account, leaving the mint supply untouched.
+pub async fn set_token_amount(pool: &mut TestPool, key: &Pubkey, amount: u64) {
+    let mut account = pool
+        .context
+        .banks_client
+        .get_account(*key)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut token = spl_token::state::Account::unpack(&account.data).unwrap();
+    token.amount = amount;
+    spl_token::state::Account::pack(token, &mut account.data).unwrap();
+    pool.context
+        .set_account(key, &AccountSharedData::from(account));
+}
+
+pub async fn create_market(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    recent_blockhash: Hash,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+) -> Market {
+    let market_program = config_feature::openbook_program::id();
+    let rent = banks_client.get_rent().await.unwrap();
+    let market = Keypair::new();
+    let request_queue = Keypair::new();
+    let event_queue = Keypair::new();
+    let bids = Keypair::new();
+    let asks = Keypair::new();
+    let instrs = [
+        (&market, size_of::<serum_dex::state::MarketState>() + 12),
+        (&request_queue, REQUEST_QUEUE_LEN),
+        (&event_queue, EVENT_QUEUE_LEN),
+        (&bids, ORDERBOOK_LEN),
+        (&asks, ORDERBOOK_LEN),
+    ]
+    .iter()
+    .map(|(account, len)| {
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(*len),
+            *len as u64,
+            &market_program,
+        )
+    })
+    .collect::<Vec<_>>();
+    send(
+        banks_client,
+        payer,
+        &[&market, &request_queue, &event_queue, &bids, &asks],
+        &instrs,
+        recent_blockhash,
+    )
+    .await;
+
+    let (vault_signer_nonce, vault_signer) = (0..u64::MAX)
+        .find_map(|nonce| {
+            serum_dex::state::gen_vault_signer_key(nonce, &market.pubkey(), &market_program)
+                .ok()
+                .map(|vault_signer| (nonce, vault_signer))
+        })
+        .unwrap();
+    let coin_vault = get_associated_token_address(&vault_signer, coin_mint);
+    let pc_vault = get_associated_token_address(&vault_signer, pc_mint);
+    let coin_lot_size = 10u64.pow((COIN_DECIMALS - 3).into());
+    let pc_lot_size = 1;
+    let mut instrs = [coin_mint, pc_mint]
+        .iter()
+        .map(|mint| {
+            create_associated_token_account_idempotent(
+                &payer.pubkey(),
+                &vault_signer,
+                mint,
+                &spl_token::id(),
+            )
+        })
+        .collect::<Vec<_>>();
+    instrs.push(
+        serum_dex::instruction::initialize_market(
+            &market.pubkey(),
+            &market_program,
+            coin_mint,
+            pc_mint,
+            &coin_vault,
+            &pc_vault,
+            None,
+            None,
+            None,
+            &bids.pubkey(),
+            &asks.pubkey(),
+            &request_queue.pubkey(),
+            &event_queue.pubkey(),
+            coin_lot_size,
+            pc_lot_size,
+            vault_signer_nonce,
+            pc_lot_size,
+        )
+        .unwrap(),
+    );
+    send(banks_client, payer, &[], &instrs, recent_blockhash).await;
+
+    Market {
+        market: market.pubkey(),
+        event_queue: event_queue.pubkey(),
+        bids: bids.pubkey(),
+        asks: asks.pubkey(),
+        coin_vault,
+        pc_vault,
+        vault_signer,
+    }
+}
+
+/// Mints, market, config and a pool holding 1_000 coin against 10_000 pc.
+pub async fn setup() -> TestPool {
+    setup_with_lp_decimals(None).await
+}
+
+/// `setup` with the lp mint decimals requested from initialize2.
+pub async fn setup_with_lp_decimals(lp_decimals: Option<u8>) -> TestPool {
+    setup_pool(lp_decimals, false).await
+}
+
+/// `setup` for a pool trading on its vaults alone, its open orders account is never created.
+pub async fn setup_without_open_orders() -> TestPool {
+    setup_pool(None, true).await
+}
+
+async fn setup_pool(lp_decimals: Option<u8>, without_open_orders: bool) -> TestPool {
+    let mut program_test = ProgramTest::default();
+    program_test.prefer_bpf(true);
+    program_test.add_program("makidex_amm", makidex_amm::id(), None);
+    program_test.add_program("openbook_dex", config_feature::openbook_program::id(), None);
+    let mut context = program_test.start_with_context().await;
+    let recent_blockhash = context.last_blockhash;
+    let rent = context.banks_client.get_rent().await.unwrap();
+
+    let coin_mint = Keypair::new();
+    let pc_mint = Keypair::new();
+    let user_token_coin =
+        get_associated_token_address(&context.payer.pubkey(), &coin_mint.pubkey());
+    let user_token_pc = get_associated_token_address(&context.payer.pubkey(), &pc_mint.pubkey());
+    let mut instrs = Vec::new();
+    for (mint, user_token, decimals) in [
+        (&coin_mint, &user_token_coin, COIN_DECIMALS),
+        (&pc_mint, &user_token_pc, PC_DECIMALS),
+    ] {
+        instrs.push(system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(spl_token::state::Mint::LEN),
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ));
+        instrs.push(
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &context.payer.pubkey(),
+                None,
+                decimals,
+            )
+            .unwrap(),
+        );
+        instrs.push(create_associated_token_account_idempotent(
+            &context.payer.pubkey(),
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            &spl_token::id(),
+        ));
+        instrs.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &mint.pubkey(),
+                user_token,
+                &context.payer.pubkey(),
+                &[],
+                1_000_000 * 10u64.pow(decimals.into()),
+            )
+            .unwrap(),
+        );
+    }
+    send(
+        &mut context.banks_client,
+        &context.payer,
+        &[&coin_mint, &pc_mint],
+        &instrs,
+        recent_blockhash,
+    )
+    .await;
+
+    let market = create_market(
+        &mut context.banks_client,
+        &context.payer,
+        recent_blockhash,
+        &coin_mint.pubkey(),
+        &pc_mint.pubkey(),
+    )
+    .await;
+
+    let pdas = get_amm_pdas(
+        &makidex_amm::id(),
+        &market.market,
+        &coin_mint.pubkey(),
+        &pc_mint.pubkey(),
+    );
+    let user_token_lp = get_associated_token_address(&context.payer.pubkey(), &pdas.amm_lp_mint);
+    let instrs = [
+        instruction::create_config_account(
+            &makidex_amm::id(),
+            &config_feature::amm_owner::id(),
+            &context.payer.pubkey(),
+            &pdas.amm_config,
+            &context.payer.pubkey(),
+        )
+        .unwrap(),
+        instruction::initialize2(
+            &makidex_amm::id(),
+            &pdas.amm_pool,
+            &pdas.amm_authority,
+            &pdas.amm_open_orders,
+            &pdas.amm_lp_mint,
+            &coin_mint.pubkey(),
+            &pc_mint.pubkey(),
+            &pdas.amm_coin_vault,
+            &pdas.amm_pc_vault,
+            &pdas.amm_target_orders,
+            &pdas.amm_config,
+            &config_feature::create_pool_fee_address::id(),
+            &config_feature::openbook_program::id(),
+            &market.market,
+            &context.payer.pubkey(),
+            &user_token_coin,
+            &user_token_pc,
+            &user_token_lp,
+            pdas.nonce,
+            0,
+            10_000 * 10u64.pow(PC_DECIMALS.into()),
+            1_000 * 10u64.pow(COIN_DECIMALS.into()),
+            None,
+            None,
+            lp_decimals,
+            without_open_orders,
+            None,
+        )
+        .unwrap(),
+    ];
+    send(
+        &mut context.banks_client,
+        &context.payer,
+        &[],
+        &instrs,
+        recent_blockhash,
+    )
+    .await;
+
+    TestPool {
+        context,
+        market,
+        pdas,
+        user_token_coin,
+        user_token_pc,
+        user_token_lp,
+    }
+}
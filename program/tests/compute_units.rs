@@ -0,0 +1,144 @@
+//! Compute units used by deposit, swap and withdraw against a real openbook market.
+//!
+//! Runs the BPF builds of both programs, so it needs `cargo build-sbf` first and an
+//! openbook binary at `tests/fixtures/openbook_dex.so`:
+//!
+//! ```sh
+//! solana program dump -u m srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX tests/fixtures/openbook_dex.so
+//! cargo test-sbf --features test-sbf -- --nocapture
+//! ```
+#![cfg(feature = "test-sbf")]
+
+mod common;
+
+use common::*;
+use makidex_amm::{
+    instruction::{self, TradeDirection},
+    processor::config_feature,
+};
+use solana_sdk::{instruction::Instruction, signature::Signer, transaction::Transaction};
+
+/// Upper bound for each measured instruction, a regression past it fails the test.
+const MAX_COMPUTE_UNITS: u64 = 200_000;
+
+/// Simulates `instr` to read its compute units, then executes it.
+async fn measure(pool: &mut TestPool, name: &str, instr: Instruction) -> u64 {
+    let txn = Transaction::new_signed_with_payer(
+        &[instr],
+        Some(&pool.context.payer.pubkey()),
+        &[&pool.context.payer],
+        pool.context.last_blockhash,
+    );
+    let simulation = pool
+        .context
+        .banks_client
+        .simulate_transaction(txn.clone())
+        .await
+        .unwrap();
+    simulation.result.unwrap().unwrap();
+    let units = simulation.simulation_details.unwrap().units_consumed;
+    println!("{}: {} compute units", name, units);
+    pool.context
+        .banks_client
+        .process_transaction(txn)
+        .await
+        .unwrap();
+    units
+}
+
+#[tokio::test]
+async fn test_compute_units() {
+    let mut pool = setup().await;
+    let market_program = config_feature::openbook_program::id();
+    let owner = pool.context.payer.pubkey();
+
+    let deposit = instruction::deposit(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &pool.market.market,
+        &pool.market.event_queue,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &pool.user_token_lp,
+        &owner,
+        10 * 10u64.pow(COIN_DECIMALS.into()),
+        200 * 10u64.pow(PC_DECIMALS.into()),
+        0,
+        1,
+    )
+    .unwrap();
+    let deposit_units = measure(&mut pool, "deposit", deposit).await;
+
+    let swap = instruction::swap_base_in(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &market_program,
+        &pool.market.market,
+        &pool.market.bids,
+        &pool.market.asks,
+        &pool.market.event_queue,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &owner,
+        10u64.pow(COIN_DECIMALS.into()),
+        1,
+        u64::MAX,
+        Some(TradeDirection::CoinToPc),
+    )
+    .unwrap();
+    let swap_units = measure(&mut pool, "swap_base_in", swap).await;
+
+    let withdraw = instruction::withdraw(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &market_program,
+        &pool.market.market,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_lp,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &owner,
+        &pool.market.event_queue,
+        &pool.market.bids,
+        &pool.market.asks,
+        None,
+        10u64.pow(COIN_DECIMALS.into()),
+    )
+    .unwrap();
+    let withdraw_units = measure(&mut pool, "withdraw", withdraw).await;
+
+    for (name, units) in [
+        ("deposit", deposit_units),
+        ("swap_base_in", swap_units),
+        ("withdraw", withdraw_units),
+    ] {
+        assert!(
+            units <= MAX_COMPUTE_UNITS,
+            "{} used {} compute units, budget is {}",
+            name,
+            units,
+            MAX_COMPUTE_UNITS
+        );
+    }
+}
@@ -0,0 +1,1053 @@
+//! End-to-end tests of the pool instructions against a real openbook market: swaps paying
+//! out to another wallet or at the discount fee, lp mint decimals, deposits and their
+//! slippage bound, protocol fee collection, syncing the config fees into a pool, closing
+//! an emptied pool, the swap whitelist flag surviving SetPoolStatus, a pool trading
+//! without an open orders account, the dynamic swap fee, the minimum trade amount and the
+//! guard against a second swap of a pool in one transaction.
+//!
+//! Runs the BPF builds of both programs, see `compute_units.rs` for how to build and run them.
+#![cfg(feature = "test-sbf")]
+
+mod common;
+
+use common::*;
+use makidex_amm::{
+    error::AmmError,
+    instruction::{self, TradeDirection},
+    processor::config_feature,
+    state::{AmmInfoExtension, PoolStatusBit},
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+
+#[tokio::test]
+async fn test_swap_output_to_third_party() {
+    let mut pool = setup().await;
+    let owner = pool.context.payer.pubkey();
+    let pc_mint = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .mint;
+    // never signs, the swap only credits its token account
+    let third_party = Keypair::new().pubkey();
+    let third_party_token_pc = get_associated_token_address(&third_party, &pc_mint);
+    let owner_pc_before = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+
+    let swap = instruction::swap_base_in(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &config_feature::openbook_program::id(),
+        &pool.market.market,
+        &pool.market.bids,
+        &pool.market.asks,
+        &pool.market.event_queue,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_coin,
+        &third_party_token_pc,
+        &owner,
+        10u64.pow(COIN_DECIMALS.into()),
+        1,
+        u64::MAX,
+        Some(TradeDirection::CoinToPc),
+    )
+    .unwrap();
+    let instrs = [
+        create_associated_token_account_idempotent(
+            &owner,
+            &third_party,
+            &pc_mint,
+            &spl_token::id(),
+        ),
+        swap,
+    ];
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &instrs,
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    let third_party_pc = token_account(&mut pool.context.banks_client, &third_party_token_pc).await;
+    assert_eq!(third_party_pc.owner, third_party);
+    assert!(third_party_pc.amount > 0);
+    let owner_pc_after = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+    assert_eq!(owner_pc_after, owner_pc_before);
+}
+
+/// Swap one coin of the payer to pc, passing the config and `discount_account` when given,
+/// and return the pc received.
+/// Swap of one whole coin for at least one unit of pc, paid by the payer.
+fn swap_one_coin_instruction(pool: &TestPool) -> Instruction {
+    let owner = pool.context.payer.pubkey();
+    instruction::swap_base_in(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &config_feature::openbook_program::id(),
+        &pool.market.market,
+        &pool.market.bids,
+        &pool.market.asks,
+        &pool.market.event_queue,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &owner,
+        10u64.pow(COIN_DECIMALS.into()),
+        1,
+        u64::MAX,
+        Some(TradeDirection::CoinToPc),
+    )
+    .unwrap()
+}
+
+async fn swap_one_coin(pool: &mut TestPool, discount_account: Option<Pubkey>) -> u64 {
+    let mut swap = swap_one_coin_instruction(pool);
+    if let Some(discount_account) = discount_account {
+        swap.accounts.extend([
+            AccountMeta::new_readonly(pool.pdas.amm_config, false),
+            AccountMeta::new_readonly(discount_account, false),
+        ]);
+    }
+    let pc_before = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[swap],
+        pool.context.last_blockhash,
+    )
+    .await;
+    token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount
+        - pc_before
+}
+
+#[tokio::test]
+async fn test_swap_discount_with_token_2022_holding() {
+    let mut full_fee_pool = setup().await;
+    let full_fee_out = swap_one_coin(&mut full_fee_pool, None).await;
+
+    let mut pool = setup().await;
+    let owner = pool.context.payer.pubkey();
+    let admin = Keypair::new();
+    set_config_admin(&mut pool, &admin.pubkey()).await;
+    let discount_mint = Keypair::new();
+    let discount_account = get_associated_token_address_with_program_id(
+        &owner,
+        &discount_mint.pubkey(),
+        &spl_token_2022::id(),
+    );
+    let rent = pool.context.banks_client.get_rent().await.unwrap();
+    let instrs = [
+        system_instruction::create_account(
+            &owner,
+            &discount_mint.pubkey(),
+            rent.minimum_balance(spl_token_2022::state::Mint::LEN),
+            spl_token_2022::state::Mint::LEN as u64,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::initialize_mint(
+            &spl_token_2022::id(),
+            &discount_mint.pubkey(),
+            &owner,
+            None,
+            0,
+        )
+        .unwrap(),
+        create_associated_token_account_idempotent(
+            &owner,
+            &owner,
+            &discount_mint.pubkey(),
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::instruction::mint_to(
+            &spl_token_2022::id(),
+            &discount_mint.pubkey(),
+            &discount_account,
+            &owner,
+            &[],
+            1,
+        )
+        .unwrap(),
+        instruction::set_swap_discount(
+            &makidex_amm::id(),
+            &admin.pubkey(),
+            &pool.pdas.amm_config,
+            &discount_mint.pubkey(),
+            10,
+            10_000,
+        )
+        .unwrap(),
+    ];
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&discount_mint, &admin],
+        &instrs,
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    // the same swap on an identical pool pays out more at the discount fee
+    let discounted_out = swap_one_coin(&mut pool, Some(discount_account)).await;
+    assert!(discounted_out > full_fee_out);
+}
+
+#[tokio::test]
+async fn test_lp_decimals() {
+    for (lp_decimals, expected) in [(None, COIN_DECIMALS), (Some(6), 6), (Some(9), 9)] {
+        let mut pool = setup_with_lp_decimals(lp_decimals).await;
+        let lp_mint = pool
+            .context
+            .banks_client
+            .get_account(pool.pdas.amm_lp_mint)
+            .await
+            .unwrap()
+            .unwrap();
+        let lp_mint = spl_token::state::Mint::unpack(&lp_mint.data).unwrap();
+        assert_eq!(lp_mint.decimals, expected);
+        // the locked part of the initial liquidity is never minted
+        let user_lp = token_account(&mut pool.context.banks_client, &pool.user_token_lp).await;
+        assert_eq!(user_lp.amount, lp_mint.supply);
+    }
+}
+
+#[tokio::test]
+async fn test_deposit() {
+    let mut pool = setup().await;
+    let coin_amount = 10 * 10u64.pow(COIN_DECIMALS.into());
+    let (pc_amount, lp_amount) = quote_deposit(&mut pool, coin_amount).await;
+    let [user_coin_before, user_pc_before, user_lp_before, coin_vault_before, pc_vault_before] =
+        deposit_balances(&mut pool).await;
+    let lp_before = load_amm(&mut pool).await.lp_amount;
+
+    // bounded by the exact quote on both the pc taken and the lp minted
+    let instr = deposit(&pool, coin_amount, pc_amount, lp_amount);
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    let [user_coin_after, user_pc_after, user_lp_after, coin_vault_after, pc_vault_after] =
+        deposit_balances(&mut pool).await;
+    let lp_after = load_amm(&mut pool).await.lp_amount;
+    assert_eq!(user_coin_before - user_coin_after, coin_amount);
+    assert_eq!(user_pc_before - user_pc_after, pc_amount);
+    assert_eq!(user_lp_after - user_lp_before, lp_amount);
+    assert_eq!(coin_vault_after - coin_vault_before, coin_amount);
+    assert_eq!(pc_vault_after - pc_vault_before, pc_amount);
+    assert_eq!(lp_after - lp_before, lp_amount);
+    // every lp is backed by at least as much of either side as before the deposit
+    for (reserve_before, reserve_after) in [
+        (coin_vault_before, coin_vault_after),
+        (pc_vault_before, pc_vault_after),
+    ] {
+        assert!(
+            reserve_after as u128 * lp_before as u128 >= reserve_before as u128 * lp_after as u128
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_minimum_lp_amount() {
+    let mut pool = setup().await;
+    let owner = pool.context.payer.pubkey();
+    let coin_amount = 10 * 10u64.pow(COIN_DECIMALS.into());
+    let (pc_amount, lp_amount) = quote_deposit(&mut pool, coin_amount).await;
+
+    // a single sided coin deposit lands first and lowers the lp each coin is worth
+    let front_run = instruction::deposit_single_sided(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &pool.market.market,
+        &pool.market.event_queue,
+        &pool.user_token_coin,
+        &pool.user_token_lp,
+        &owner,
+        50 * 10u64.pow(COIN_DECIMALS.into()),
+        TradeDirection::CoinToPc,
+        0,
+    )
+    .unwrap();
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[front_run],
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    let instr = deposit(&pool, coin_amount, pc_amount, lp_amount);
+    assert_amm_error(&mut pool, &[], instr, AmmError::ExceededSlippage).await;
+
+    let user_lp_before = token_account(&mut pool.context.banks_client, &pool.user_token_lp)
+        .await
+        .amount;
+    let minimum_lp_amount = lp_amount / 10 * 9;
+    let instr = deposit(&pool, coin_amount, pc_amount, minimum_lp_amount);
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let minted = token_account(&mut pool.context.banks_client, &pool.user_token_lp)
+        .await
+        .amount
+        - user_lp_before;
+    assert!(minted >= minimum_lp_amount && minted < lp_amount);
+}
+
+#[tokio::test]
+async fn test_collect_protocol_fees() {
+    let mut pool = setup().await;
+    let owner = pool.context.payer.pubkey();
+    let mut swaps = Vec::new();
+    for (amount_in, direction) in [
+        (
+            10 * 10u64.pow(COIN_DECIMALS.into()),
+            TradeDirection::CoinToPc,
+        ),
+        (
+            100 * 10u64.pow(PC_DECIMALS.into()),
+            TradeDirection::PcToCoin,
+        ),
+    ] {
+        swaps.push(
+            instruction::swap_base_in(
+                &makidex_amm::id(),
+                &pool.pdas.amm_pool,
+                &pool.pdas.amm_authority,
+                &pool.pdas.amm_open_orders,
+                &pool.pdas.amm_coin_vault,
+                &pool.pdas.amm_pc_vault,
+                &config_feature::openbook_program::id(),
+                &pool.market.market,
+                &pool.market.bids,
+                &pool.market.asks,
+                &pool.market.event_queue,
+                &pool.market.coin_vault,
+                &pool.market.pc_vault,
+                &pool.market.vault_signer,
+                &pool.user_token_coin,
+                &pool.user_token_pc,
+                &owner,
+                amount_in,
+                1,
+                u64::MAX,
+                Some(direction),
+            )
+            .unwrap(),
+        );
+    }
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &swaps,
+        pool.context.last_blockhash,
+    )
+    .await;
+    let amm = load_amm(&mut pool).await;
+    let need_take_pnl_coin = amm.state_data.need_take_pnl_coin;
+    let need_take_pnl_pc = amm.state_data.need_take_pnl_pc;
+    assert!(need_take_pnl_coin > 0 && need_take_pnl_pc > 0);
+
+    let collect = |pool: &TestPool,
+                   owner_token_coin: &Pubkey,
+                   owner_token_pc: &Pubkey,
+                   pnl_owner: &Pubkey| {
+        instruction::collect_protocol_fees(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool.pdas.amm_config,
+            &pool.pdas.amm_authority,
+            &pool.pdas.amm_coin_vault,
+            &pool.pdas.amm_pc_vault,
+            owner_token_coin,
+            owner_token_pc,
+            pnl_owner,
+            u64::MAX,
+            u64::MAX,
+        )
+        .unwrap()
+    };
+    // only the config pnl_owner signs for the fees
+    let not_pnl_owner = Keypair::new();
+    let instr = collect(
+        &pool,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &not_pnl_owner.pubkey(),
+    );
+    assert_amm_error(
+        &mut pool,
+        &[&not_pnl_owner],
+        instr,
+        AmmError::InvalidSignAccount,
+    )
+    .await;
+    // and they are only paid to token accounts of the fee recipient
+    let third_party = Keypair::new().pubkey();
+    let mut third_party_tokens = Vec::new();
+    let mut instrs = Vec::new();
+    for user_token in [pool.user_token_coin, pool.user_token_pc] {
+        let mint = token_account(&mut pool.context.banks_client, &user_token)
+            .await
+            .mint;
+        third_party_tokens.push(get_associated_token_address(&third_party, &mint));
+        instrs.push(create_associated_token_account_idempotent(
+            &owner,
+            &third_party,
+            &mint,
+            &spl_token::id(),
+        ));
+    }
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &instrs,
+        pool.context.last_blockhash,
+    )
+    .await;
+    let instr = collect(
+        &pool,
+        &third_party_tokens[0],
+        &third_party_tokens[1],
+        &owner,
+    );
+    assert_amm_error(&mut pool, &[], instr, AmmError::InvalidOwner).await;
+
+    let owner_coin_before = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    let owner_pc_before = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+    let coin_vault_before =
+        token_account(&mut pool.context.banks_client, &pool.pdas.amm_coin_vault)
+            .await
+            .amount;
+    let pc_vault_before = token_account(&mut pool.context.banks_client, &pool.pdas.amm_pc_vault)
+        .await
+        .amount;
+    // the requested amounts are clamped to what the pool owes
+    let instr = collect(&pool, &pool.user_token_coin, &pool.user_token_pc, &owner);
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    let owner_coin_after = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    let owner_pc_after = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+    let coin_vault_after = token_account(&mut pool.context.banks_client, &pool.pdas.amm_coin_vault)
+        .await
+        .amount;
+    let pc_vault_after = token_account(&mut pool.context.banks_client, &pool.pdas.amm_pc_vault)
+        .await
+        .amount;
+    assert_eq!(owner_coin_after - owner_coin_before, need_take_pnl_coin);
+    assert_eq!(owner_pc_after - owner_pc_before, need_take_pnl_pc);
+    assert_eq!(coin_vault_before - coin_vault_after, need_take_pnl_coin);
+    assert_eq!(pc_vault_before - pc_vault_after, need_take_pnl_pc);
+    let amm = load_amm(&mut pool).await;
+    assert_eq!(amm.state_data.need_take_pnl_coin, 0);
+    assert_eq!(amm.state_data.need_take_pnl_pc, 0);
+}
+
+#[tokio::test]
+async fn test_sync_pool_fees() {
+    let mut pool = setup().await;
+    let admin = Keypair::new();
+    set_config_admin(&mut pool, &admin.pubkey()).await;
+    let update_fees = instruction::update_config_fees(
+        &makidex_amm::id(),
+        &admin.pubkey(),
+        &pool.pdas.amm_config,
+        40,
+        10_000,
+        1,
+        4,
+    )
+    .unwrap();
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&admin],
+        &[update_fees],
+        pool.context.last_blockhash,
+    )
+    .await;
+    // the live pool keeps the fees it was created with
+    let amm = load_amm(&mut pool).await;
+    assert_ne!(amm.fees.swap_fee_numerator, 40);
+
+    let sync = |pool: &TestPool, admin: &Pubkey| {
+        instruction::sync_pool_fees(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool.pdas.amm_config,
+            admin,
+        )
+        .unwrap()
+    };
+    let other = Keypair::new();
+    let instr = sync(&pool, &other.pubkey());
+    assert_amm_error(&mut pool, &[&other], instr, AmmError::InvalidSignAccount).await;
+
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&admin],
+        &[sync(&pool, &admin.pubkey())],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let amm = load_amm(&mut pool).await;
+    assert_eq!(amm.fees.swap_fee_numerator, 40);
+    assert_eq!(amm.fees.swap_fee_denominator, 10_000);
+    assert_eq!(amm.protocol_fee_numerator, 1);
+    assert_eq!(amm.protocol_fee_denominator, 4);
+}
+
+#[tokio::test]
+async fn test_close_pool_after_the_vaults_are_emptied() {
+    let mut pool = setup().await;
+    let owner = pool.context.payer.pubkey();
+    let close_pool = |pool: &TestPool, admin: &Pubkey, recipient: &Pubkey| {
+        instruction::close_pool(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool.pdas.amm_authority,
+            &pool.pdas.amm_open_orders,
+            &pool.pdas.amm_coin_vault,
+            &pool.pdas.amm_pc_vault,
+            &pool.pdas.amm_lp_mint,
+            &pool.pdas.amm_target_orders,
+            &config_feature::openbook_program::id(),
+            &pool.market.market,
+            admin,
+            recipient,
+            &pool.pdas.amm_config,
+            &pool.user_token_coin,
+            &pool.user_token_pc,
+        )
+        .unwrap()
+    };
+    let admin = Keypair::new();
+    set_config_admin(&mut pool, &admin.pubkey()).await;
+    let recipient = Keypair::new().pubkey();
+
+    // the pool admin only manages the pool, closing it takes the config admin
+    let pool_admin = Keypair::new();
+    set_pool_admin(&mut pool, &pool_admin.pubkey()).await;
+    let instr = close_pool(&pool, &pool_admin.pubkey(), &recipient);
+    assert_amm_error(
+        &mut pool,
+        &[&pool_admin],
+        instr,
+        AmmError::InvalidSignAccount,
+    )
+    .await;
+
+    // refused while any minted lp is left
+    let instr = close_pool(&pool, &admin.pubkey(), &recipient);
+    assert_amm_error(&mut pool, &[&admin], instr, AmmError::PoolNotEmpty).await;
+
+    let user_lp = token_account(&mut pool.context.banks_client, &pool.user_token_lp)
+        .await
+        .amount;
+    let withdraw = instruction::withdraw(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &config_feature::openbook_program::id(),
+        &pool.market.market,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_lp,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &owner,
+        &pool.market.event_queue,
+        &pool.market.bids,
+        &pool.market.asks,
+        None,
+        user_lp,
+    )
+    .unwrap();
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[withdraw],
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    // every minted lp is burned but the locked liquidity keeps reserves in the vaults
+    let lp_supply = spl_token::state::Mint::unpack(
+        &pool
+            .context
+            .banks_client
+            .get_account(pool.pdas.amm_lp_mint)
+            .await
+            .unwrap()
+            .unwrap()
+            .data,
+    )
+    .unwrap()
+    .supply;
+    assert_eq!(lp_supply, 0);
+    let coin_vault = token_account(&mut pool.context.banks_client, &pool.pdas.amm_coin_vault)
+        .await
+        .amount;
+    let pc_vault = token_account(&mut pool.context.banks_client, &pool.pdas.amm_pc_vault)
+        .await
+        .amount;
+    assert!(coin_vault > 0 && pc_vault > 0);
+    let instr = close_pool(&pool, &admin.pubkey(), &recipient);
+    assert_amm_error(&mut pool, &[&admin], instr, AmmError::PoolNotEmpty).await;
+
+    let (pnl_coin, pnl_pc) = {
+        let amm = load_amm(&mut pool).await;
+        (
+            amm.state_data.need_take_pnl_coin,
+            amm.state_data.need_take_pnl_pc,
+        )
+    };
+    let coin_vault_key = pool.pdas.amm_coin_vault;
+    let pc_vault_key = pool.pdas.amm_pc_vault;
+    set_token_amount(&mut pool, &coin_vault_key, pnl_coin).await;
+    set_token_amount(&mut pool, &pc_vault_key, pnl_pc).await;
+    let owner_coin_before = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    let owner_pc_before = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+
+    // the payer is the config pnl_owner, so its token accounts receive the uncollected pnl
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&admin],
+        &[close_pool(&pool, &admin.pubkey(), &recipient)],
+        pool.context.last_blockhash,
+    )
+    .await;
+
+    for closed in [
+        pool.pdas.amm_pool,
+        pool.pdas.amm_coin_vault,
+        pool.pdas.amm_pc_vault,
+        pool.pdas.amm_open_orders,
+        pool.pdas.amm_target_orders,
+    ] {
+        assert!(pool
+            .context
+            .banks_client
+            .get_account(closed)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    let owner_coin_after = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    let owner_pc_after = token_account(&mut pool.context.banks_client, &pool.user_token_pc)
+        .await
+        .amount;
+    assert_eq!(owner_coin_after - owner_coin_before, pnl_coin);
+    assert_eq!(owner_pc_after - owner_pc_before, pnl_pc);
+    assert!(
+        pool.context
+            .banks_client
+            .get_balance(recipient)
+            .await
+            .unwrap()
+            > 0
+    );
+}
+
+#[tokio::test]
+async fn test_pool_status_keeps_swap_permissioned() {
+    let mut pool = setup().await;
+    let pool_admin = Keypair::new();
+    set_pool_admin(&mut pool, &pool_admin.pubkey()).await;
+    let instrs = [
+        instruction::set_swap_permissioned(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool_admin.pubkey(),
+            true,
+        )
+        .unwrap(),
+        instruction::set_pool_status(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool_admin.pubkey(),
+            PoolStatusBit::Deposit.mask(),
+        )
+        .unwrap(),
+    ];
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &instrs,
+        pool.context.last_blockhash,
+    )
+    .await;
+    let amm = load_amm(&mut pool).await;
+    assert!(amm.swap_permissioned());
+    assert!(amm.is_disabled(PoolStatusBit::Deposit));
+
+    // the pause bits are all SetPoolStatus takes
+    let instr = instruction::set_pool_status(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool_admin.pubkey(),
+        PoolStatusBit::SwapPermissioned.mask(),
+    )
+    .unwrap();
+    assert_amm_error(&mut pool, &[&pool_admin], instr, AmmError::InvalidInput).await;
+    let instr = instruction::set_pool_status(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool_admin.pubkey(),
+        0,
+    )
+    .unwrap();
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let amm = load_amm(&mut pool).await;
+    assert!(amm.swap_permissioned());
+    assert_eq!(amm.pool_status, PoolStatusBit::SwapPermissioned.mask());
+}
+
+#[tokio::test]
+async fn test_pool_without_open_orders() {
+    let mut pool = setup_without_open_orders().await;
+    let owner = pool.context.payer.pubkey();
+    assert!(pool
+        .context
+        .banks_client
+        .get_account(pool.pdas.amm_open_orders)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(!load_amm(&mut pool).await.uses_open_orders());
+
+    // deposits, swaps and withdrawals price off the vaults alone
+    let coin_amount = 10 * 10u64.pow(COIN_DECIMALS.into());
+    let (pc_amount, lp_amount) = quote_deposit(&mut pool, coin_amount).await;
+    let instr = deposit(&pool, coin_amount, pc_amount, lp_amount);
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+    assert!(swap_one_coin(&mut pool, None).await > 0);
+    let [user_coin_before, user_pc_before, ..] = deposit_balances(&mut pool).await;
+    let withdraw = instruction::withdraw(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_lp_mint,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &config_feature::openbook_program::id(),
+        &pool.market.market,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.user_token_lp,
+        &pool.user_token_coin,
+        &pool.user_token_pc,
+        &owner,
+        &pool.market.event_queue,
+        &pool.market.bids,
+        &pool.market.asks,
+        None,
+        lp_amount,
+    )
+    .unwrap();
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[withdraw],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let [user_coin_after, user_pc_after, ..] = deposit_balances(&mut pool).await;
+    assert!(user_coin_after > user_coin_before);
+    assert!(user_pc_after > user_pc_before);
+
+    // the pool has no orders on the market to manage
+    let instr = instruction::admin_cancel_orders(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &pool.pdas.amm_authority,
+        &pool.pdas.amm_open_orders,
+        &pool.pdas.amm_target_orders,
+        &pool.pdas.amm_coin_vault,
+        &pool.pdas.amm_pc_vault,
+        &owner,
+        &pool.pdas.amm_config,
+        &config_feature::openbook_program::id(),
+        &pool.market.market,
+        &pool.market.coin_vault,
+        &pool.market.pc_vault,
+        &pool.market.vault_signer,
+        &pool.market.event_queue,
+        &pool.market.bids,
+        &pool.market.asks,
+        None,
+        None,
+        10,
+    )
+    .unwrap();
+    assert_amm_error(&mut pool, &[], instr, AmmError::NoOpenOrders).await;
+}
+
+#[tokio::test]
+async fn test_dynamic_fee() {
+    let mut pool = setup().await;
+    let pool_admin = Keypair::new();
+    set_pool_admin(&mut pool, &pool_admin.pubkey()).await;
+    let fees = load_amm(&mut pool).await.fees;
+    let set_dynamic_fee = |pool: &TestPool, base_fee_numerator: u64, max_fee_numerator: u64| {
+        instruction::set_dynamic_fee(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool_admin.pubkey(),
+            true,
+            base_fee_numerator,
+            max_fee_numerator,
+            fees.swap_fee_denominator,
+            500,
+        )
+        .unwrap()
+    };
+    // the fee may only grow with the deviation
+    let instr = set_dynamic_fee(&pool, 100, fees.swap_fee_numerator);
+    assert_amm_error(&mut pool, &[&pool_admin], instr, AmmError::InvalidFee).await;
+
+    let instr = set_dynamic_fee(&pool, fees.swap_fee_numerator, 100);
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &[instr],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let extension = load_extension(&mut pool).await;
+    assert_eq!(extension.dynamic_fee_enabled, 1);
+    assert_eq!(extension.max_fee_numerator, 100);
+    assert_eq!(extension.next_reference_timestamp, 0);
+
+    // no average price to compare with yet, the first swap pays the base fee, the same as
+    // on a pool with static fees, and starts one
+    let mut static_pool = setup().await;
+    let static_amount_out = swap_one_coin(&mut static_pool, None).await;
+    assert_eq!(swap_one_coin(&mut pool, None).await, static_amount_out);
+    assert_ne!(load_extension(&mut pool).await.next_reference_timestamp, 0);
+}
+
+async fn load_extension(pool: &mut TestPool) -> AmmInfoExtension {
+    let account = pool
+        .context
+        .banks_client
+        .get_account(pool.pdas.amm_pool)
+        .await
+        .unwrap()
+        .unwrap();
+    AmmInfoExtension::from_account_data(&account.data).unwrap()
+}
+
+#[tokio::test]
+async fn test_min_trade_amount() {
+    let mut pool = setup().await;
+    let pool_admin = Keypair::new();
+    set_pool_admin(&mut pool, &pool_admin.pubkey()).await;
+    let one_coin = 10u64.pow(COIN_DECIMALS.into());
+    let set_min_trade_amount = |pool: &TestPool, min_coin_amount: u64| {
+        instruction::set_min_trade_amount(
+            &makidex_amm::id(),
+            &pool.pdas.amm_pool,
+            &pool_admin.pubkey(),
+            min_coin_amount,
+            0,
+        )
+        .unwrap()
+    };
+    let other = Keypair::new();
+    let instr = instruction::set_min_trade_amount(
+        &makidex_amm::id(),
+        &pool.pdas.amm_pool,
+        &other.pubkey(),
+        one_coin + 1,
+        0,
+    )
+    .unwrap();
+    assert_amm_error(&mut pool, &[&other], instr, AmmError::InvalidSignAccount).await;
+
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &[set_min_trade_amount(&pool, one_coin + 1)],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let swap = swap_one_coin_instruction(&pool);
+    assert_amm_error(&mut pool, &[], swap, AmmError::TradeTooSmall).await;
+
+    // a minimum of exactly the input lets the swap through
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &[set_min_trade_amount(&pool, one_coin)],
+        pool.context.last_blockhash,
+    )
+    .await;
+    assert!(swap_one_coin(&mut pool, None).await > 0);
+}
+
+#[tokio::test]
+async fn test_swap_guard() {
+    let mut pool = setup().await;
+    let pool_admin = Keypair::new();
+    set_pool_admin(&mut pool, &pool_admin.pubkey()).await;
+    let set_swap_guard = |pool: &TestPool, admin: &Pubkey| {
+        instruction::set_swap_guard(&makidex_amm::id(), &pool.pdas.amm_pool, admin, true).unwrap()
+    };
+    let other = Keypair::new();
+    let instr = set_swap_guard(&pool, &other.pubkey());
+    assert_amm_error(&mut pool, &[&other], instr, AmmError::InvalidSignAccount).await;
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[&pool_admin],
+        &[set_swap_guard(&pool, &pool_admin.pubkey())],
+        pool.context.last_blockhash,
+    )
+    .await;
+    assert!(load_amm(&mut pool).await.swap_guarded());
+
+    // a guarded pool cannot tell its earlier swaps without the sysvar
+    let swap = swap_one_coin_instruction(&pool);
+    assert_amm_error(&mut pool, &[], swap, AmmError::InstructionsSysvarMissing).await;
+
+    let mut swap = swap_one_coin_instruction(&pool);
+    swap.accounts
+        .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    let txn = Transaction::new_signed_with_payer(
+        &[swap.clone(), swap.clone()],
+        Some(&pool.context.payer.pubkey()),
+        &[&pool.context.payer],
+        pool.context.last_blockhash,
+    );
+    let err = pool
+        .context
+        .banks_client
+        .process_transaction(txn)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(AmmError::RepeatedSwap as u32)
+        )
+    );
+
+    // one swap per transaction still goes through
+    let coin_before = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    send(
+        &mut pool.context.banks_client,
+        &pool.context.payer,
+        &[],
+        &[swap],
+        pool.context.last_blockhash,
+    )
+    .await;
+    let coin_after = token_account(&mut pool.context.banks_client, &pool.user_token_coin)
+        .await
+        .amount;
+    assert_eq!(coin_before - coin_after, 10u64.pow(COIN_DECIMALS.into()));
+}